@@ -10,55 +10,109 @@ pub enum Error {
     Utf8(#[from] Utf8Error),
 }
 
-/// Read a CSL from bytes
+/// A streaming reader over the entries of a CSL
 ///
-/// # Errors
-/// Returns errors on invalid CSL
-pub fn from_bytes(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
-    let mut v = vec![];
-    // First byte specifies the size of the fixed-length payload
-    let payload_n = usize::from(*bytes.first().ok_or(Error::Parse)?);
-    let mut ptr = 1;
-    // Grab the first string and payload
-    let key_n = usize::from(*bytes.get(ptr).ok_or(Error::Parse)?);
-    ptr += 1;
-    let key_bytes = bytes.get(ptr..(key_n + ptr)).ok_or(Error::Parse)?;
-    ptr += key_n;
-    let key_string = std::str::from_utf8(key_bytes)?;
-    let key_pl = bytes
-        .get(ptr..(payload_n + ptr))
-        .ok_or(Error::Parse)?
-        .to_vec();
-    ptr += payload_n;
-    v.push((key_string.to_string(), key_pl));
+/// Keys are prefix-compressed against the previous key, so each key is only valid until the next
+/// call to [`Entries::next_entry`], which expands the next one into the same buffer. Payloads
+/// borrow from the input. This makes reading a CSL allocation-free, aside from the key buffer
+/// growing to the length of the longest key.
+#[derive(Debug, Clone)]
+pub struct Entries<'a> {
+    bytes: &'a [u8],
+    payload_n: usize,
+    ptr: usize,
+    key: String,
+    first: bool,
+    done: bool,
+}
+
+impl<'a> Entries<'a> {
+    /// Start reading the CSL in `bytes`
+    ///
+    /// # Errors
+    /// Returns an error if `bytes` is empty
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        // First byte specifies the size of the fixed-length payload
+        let payload_n = usize::from(*bytes.first().ok_or(Error::Parse)?);
+        Ok(Self {
+            bytes,
+            payload_n,
+            ptr: 1,
+            key: String::new(),
+            first: true,
+            done: false,
+        })
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.bytes.get(self.ptr..self.ptr + n).ok_or(Error::Parse)?;
+        self.ptr += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<usize, Error> {
+        Ok(usize::from(self.take(1)?[0]))
+    }
+
+    /// Read the next `(key, payload)` entry, or `None` at the end of the list
+    ///
+    /// # Errors
+    /// Returns errors on invalid CSL, after which there are no more entries
+    pub fn next_entry(&mut self) -> Option<Result<(&str, &'a [u8]), Error>> {
+        if self.done {
+            return None;
+        }
+        match self.advance() {
+            Ok(Some(payload)) => Some(Ok((&self.key, payload))),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
 
-    // Now read the rest
-    loop {
-        let header_n = usize::from(*bytes.get(ptr).ok_or(Error::Parse)?);
-        ptr += 1;
-        let tail_n = usize::from(*bytes.get(ptr).ok_or(Error::Parse)?);
-        ptr += 1;
+    fn advance(&mut self) -> Result<Option<&'a [u8]>, Error> {
+        // The first entry is only a whole key, the rest reuse `header_n` chars of the previous
+        let header_n = if self.first { 0 } else { self.byte()? };
+        let tail_n = self.byte()?;
         // Check end condition
-        if header_n == 0 && tail_n == 0 {
-            break;
+        if !self.first && header_n == 0 && tail_n == 0 {
+            return Ok(None);
         }
-        // Pull out `header_n` chars from previous string and append to `tail_n` chars next
-        let head = &v.last().ok_or(Error::Parse)?.0[..header_n];
-        let tail_bytes = bytes.get(ptr..(tail_n + ptr)).ok_or(Error::Parse)?;
-        let tail = std::str::from_utf8(tail_bytes)?;
-        let key = format!("{head}{tail}");
-        ptr += tail_n;
-        // Pull out payload
-        let payload = bytes
-            .get(ptr..(payload_n + ptr))
-            .ok_or(Error::Parse)?
-            .to_vec();
-        ptr += payload_n;
-        // Push
-        v.push((key, payload));
+        self.first = false;
+        if header_n > self.key.len() || !self.key.is_char_boundary(header_n) {
+            return Err(Error::Parse);
+        }
+        self.key.truncate(header_n);
+        let tail = std::str::from_utf8(self.take(tail_n)?)?;
+        self.key.push_str(tail);
+        self.take(self.payload_n).map(Some)
     }
 
-    Ok(v)
+    /// Read every remaining entry into owned keys and payloads
+    ///
+    /// # Errors
+    /// Returns errors on invalid CSL
+    pub fn into_vec(mut self) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let mut v = vec![];
+        while let Some(entry) = self.next_entry() {
+            let (key, payload) = entry?;
+            v.push((key.to_string(), payload.to_vec()));
+        }
+        Ok(v)
+    }
+}
+
+/// Read a CSL from bytes
+///
+/// # Errors
+/// Returns errors on invalid CSL
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    Entries::new(bytes)?.into_vec()
 }
 
 #[cfg(test)]
@@ -86,5 +140,18 @@ mod test {
                 ("eth_0_core".to_string(), vec![0x06])
             ]
         );
+        // The streaming reader sees the same entries, borrowing the payloads
+        let mut entries = Entries::new(&csl).unwrap();
+        let (key, payload) = entries.next_entry().unwrap().unwrap();
+        assert_eq!((key, payload), ("adc16_wb_ram1", &csl[15..16]));
+        let mut keys = vec![];
+        while let Some(entry) = entries.next_entry() {
+            keys.push(entry.unwrap().0.to_string());
+        }
+        assert_eq!(keys.last().unwrap(), "eth_0_core");
+        assert!(entries.next_entry().is_none());
+        // A prefix longer than the previous key is malformed, not a panic
+        assert!(from_bytes(&[0x00, 0x01, b'a', 0x02, 0x01, b'b', 0x00, 0x00]).is_err());
+        assert!(from_bytes(&csl[..20]).is_err());
     }
 }
@@ -0,0 +1,308 @@
+//! Structured differences between two designs, i.e. to check what an upgrade of the gateware
+//! changes or to write its release notes
+//!
+//! [`diff`] compares the devices (their kind, register, and metadata) and the register maps of
+//! two designs. Everything is sorted by name, so the report (and its [`std::fmt::Display`]
+//! rendering) is stable from run to run.
+use super::{
+    Device,
+    DeviceKind,
+    FpgaDesign,
+    Register,
+};
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+/// A value that differs between the old and new design, `None` on the side it's missing from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<V> {
+    pub old: Option<V>,
+    pub new: Option<V>,
+}
+
+/// How a device present in both designs changed
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DeviceChange {
+    /// The old and new kind, if it changed
+    pub kind: Option<(DeviceKind, DeviceKind)>,
+    /// The old and new register, if it moved, resized, appeared, or went away
+    pub register: Option<Change<Register>>,
+    /// Every metadata key that was added, removed, or changed value
+    pub metadata: BTreeMap<String, Change<String>>,
+}
+
+impl DeviceChange {
+    fn between(old: &Device, new: &Device) -> Self {
+        let keys: BTreeSet<_> = old.metadata.keys().chain(new.metadata.keys()).collect();
+        Self {
+            kind: (old.kind != new.kind).then(|| (old.kind.clone(), new.kind.clone())),
+            register: (old.register != new.register).then_some(Change {
+                old: old.register,
+                new: new.register,
+            }),
+            metadata: keys
+                .into_iter()
+                .filter_map(|key| {
+                    let (old, new) = (old.metadata.get(key), new.metadata.get(key));
+                    (old != new).then(|| {
+                        (
+                            key.to_string(),
+                            Change {
+                                old: old.cloned(),
+                                new: new.cloned(),
+                            },
+                        )
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// True if nothing about the device changed
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.kind.is_none() && self.register.is_none() && self.metadata.is_empty()
+    }
+}
+
+/// Everything that differs between two designs, see [`diff`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DesignDiff {
+    /// The old and new md5 strings, if the designs differ at all
+    pub md5: Option<(String, String)>,
+    /// The devices only in the new design, with their kinds
+    pub added: BTreeMap<String, DeviceKind>,
+    /// The devices only in the old design, with their kinds
+    pub removed: BTreeMap<String, DeviceKind>,
+    /// The devices in both designs that changed
+    pub changed: BTreeMap<String, DeviceChange>,
+    /// The registers of the register maps that were added, removed, moved, or resized
+    pub registers: BTreeMap<String, Change<Register>>,
+}
+
+impl DesignDiff {
+    /// True if the designs have the same devices and registers. The bitstreams may still differ,
+    /// see [`DesignDiff::md5`].
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.registers.is_empty()
+    }
+}
+
+/// Compare the design `old` to the design `new`
+pub fn diff<A, B>(old: &A, new: &B) -> DesignDiff
+where
+    A: FpgaDesign,
+    B: FpgaDesign,
+{
+    let (old_devs, new_devs) = (old.devices(), new.devices());
+    let mut report = DesignDiff {
+        md5: (old.md5() != new.md5()).then(|| (old.md5_string(), new.md5_string())),
+        ..Default::default()
+    };
+    for (name, dev) in old_devs {
+        match new_devs.get(name) {
+            Some(new_dev) => {
+                let change = DeviceChange::between(dev, new_dev);
+                if !change.is_empty() {
+                    report.changed.insert(name.to_string(), change);
+                }
+            }
+            None => {
+                report.removed.insert(name.to_string(), dev.kind.clone());
+            }
+        }
+    }
+    for (name, dev) in new_devs {
+        if !old_devs.contains_key(name) {
+            report.added.insert(name.to_string(), dev.kind.clone());
+        }
+    }
+    let (old_regs, new_regs) = (old.registers(), new.registers());
+    let names: BTreeSet<_> = old_regs.keys().chain(new_regs.keys()).collect();
+    for name in names {
+        let (old, new) = (old_regs.get(name), new_regs.get(name));
+        if old != new {
+            report.registers.insert(
+                name.to_string(),
+                Change {
+                    old: old.copied(),
+                    new: new.copied(),
+                },
+            );
+        }
+    }
+    report
+}
+
+fn fmt_register(reg: Option<&Register>) -> String {
+    match reg {
+        Some(reg) => format!("{:#x} ({} bytes)", reg.addr, reg.size),
+        None => "none".to_string(),
+    }
+}
+
+fn fmt_value(value: Option<&String>) -> &str {
+    value.map_or("(unset)", String::as_str)
+}
+
+impl std::fmt::Display for DesignDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some((old, new)) = &self.md5 {
+            writeln!(f, "md5: {old} -> {new}")?;
+        }
+        if self.is_empty() {
+            return writeln!(f, "No changes to the devices or registers");
+        }
+        for (name, kind) in &self.added {
+            writeln!(f, "+ {name} ({kind})")?;
+        }
+        for (name, kind) in &self.removed {
+            writeln!(f, "- {name} ({kind})")?;
+        }
+        for (name, change) in &self.changed {
+            writeln!(f, "~ {name}")?;
+            if let Some((old, new)) = &change.kind {
+                writeln!(f, "    kind: {old} -> {new}")?;
+            }
+            if let Some(reg) = &change.register {
+                writeln!(
+                    f,
+                    "    register: {} -> {}",
+                    fmt_register(reg.old.as_ref()),
+                    fmt_register(reg.new.as_ref())
+                )?;
+            }
+            for (key, value) in &change.metadata {
+                writeln!(
+                    f,
+                    "    {key}: {} -> {}",
+                    fmt_value(value.old.as_ref()),
+                    fmt_value(value.new.as_ref())
+                )?;
+            }
+        }
+        for (name, reg) in &self.registers {
+            writeln!(
+                f,
+                "register {name}: {} -> {}",
+                fmt_register(reg.old.as_ref()),
+                fmt_register(reg.new.as_ref())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::design_sources::raw::RawDesign;
+    use kstring::KString;
+
+    /// A device's name, kind, register, and metadata
+    type Spec<'a> = (&'a str, &'a str, Option<Register>, &'a [(&'a str, &'a str)]);
+
+    fn design(md5: u8, devices: &[Spec]) -> RawDesign {
+        RawDesign {
+            registers: devices
+                .iter()
+                .filter_map(|(name, _, reg, _)| Some((KString::from_ref(name), (*reg)?)))
+                .collect(),
+            devices: devices
+                .iter()
+                .map(|(name, kind, register, meta)| {
+                    (
+                        KString::from_ref(name),
+                        Device {
+                            kind: (*kind).into(),
+                            register: *register,
+                            metadata: meta
+                                .iter()
+                                .map(|(k, v)| (KString::from_ref(k), (*v).to_string()))
+                                .collect(),
+                        },
+                    )
+                })
+                .collect(),
+            bitstream: vec![],
+            md5: [md5; 16],
+            sha256: [0; 32],
+            filename: "design.bin".into(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let reg = |addr| Some(Register { addr, size: 4 });
+        let old = design(
+            1,
+            &[
+                ("sys_clkcounter", "xps:sw_reg", reg(0), &[]),
+                ("gain", "xps:sw_reg", reg(4), &[("bitwidths", "32")]),
+                ("old_snap", "casper:snapshot", reg(8), &[]),
+            ],
+        );
+        let new = design(
+            2,
+            &[
+                ("sys_clkcounter", "xps:sw_reg", reg(0), &[]),
+                (
+                    "gain",
+                    "xps:sw_reg",
+                    reg(0x10),
+                    &[("bitwidths", "16"), ("mode", "wo")],
+                ),
+                ("new_bram", "xps:bram", None, &[]),
+            ],
+        );
+        let report = diff(&old, &new);
+        assert_eq!(
+            report.added,
+            BTreeMap::from([("new_bram".into(), "xps:bram".into())])
+        );
+        assert_eq!(
+            report.removed,
+            BTreeMap::from([("old_snap".into(), "casper:snapshot".into())])
+        );
+        let gain = &report.changed["gain"];
+        assert_eq!(gain.kind, None);
+        assert_eq!(
+            gain.register,
+            Some(Change {
+                old: reg(4),
+                new: reg(0x10)
+            })
+        );
+        assert_eq!(
+            gain.metadata.keys().collect::<Vec<_>>(),
+            vec!["bitwidths", "mode"]
+        );
+        assert_eq!(report.registers.len(), 2);
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "md5: {} -> {}\n\
+                 + new_bram (xps:bram)\n\
+                 - old_snap (casper:snapshot)\n\
+                 ~ gain\n    \
+                 register: 0x4 (4 bytes) -> 0x10 (4 bytes)\n    \
+                 bitwidths: 32 -> 16\n    \
+                 mode: (unset) -> wo\n\
+                 register gain: 0x4 (4 bytes) -> 0x10 (4 bytes)\n\
+                 register old_snap: 0x8 (4 bytes) -> none\n",
+                old.md5_string(),
+                new.md5_string()
+            )
+        );
+
+        let same = diff(&old, &old);
+        assert!(same.is_empty() && same.md5.is_none());
+        assert_eq!(same, DesignDiff::default());
+    }
+}
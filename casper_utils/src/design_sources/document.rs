@@ -0,0 +1,229 @@
+//! Register map documents of a design, i.e. to generate an interface control document (ICD)
+//!
+//! A [`RegisterDocument`] lists every register and device of a design with its kind, address,
+//! size, access, and fpg metadata, sorted by address (then name) so it reads like a memory map.
+//! It can be rendered as JSON with [`RegisterDocument::to_json`] for other tools, or as a markdown
+//! table with [`RegisterDocument::to_markdown`] for humans.
+use super::{
+    Access,
+    DeviceKind,
+    FpgaDesign,
+    Register,
+};
+use crate::json::quote;
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    fmt::Write,
+};
+
+/// One entry of a [`RegisterDocument`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterEntry {
+    pub name: String,
+    /// The kind of the device, `None` for registers without a device entry
+    pub kind: Option<DeviceKind>,
+    /// The register on the bus, `None` for devices without one (i.e. the platform block)
+    pub register: Option<Register>,
+    pub access: Access,
+    /// The fpg metadata of the device, sorted by key
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The documented register map of a design, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterDocument {
+    /// The md5 of the design the document was made from
+    pub md5: String,
+    pub entries: Vec<RegisterEntry>,
+}
+
+impl RegisterDocument {
+    /// Document every register and device of `design`
+    pub fn of<D>(design: &D) -> Self
+    where
+        D: FpgaDesign,
+    {
+        let (devices, registers) = (design.devices(), design.registers());
+        let names: BTreeSet<_> = devices.keys().chain(registers.keys()).collect();
+        let mut entries: Vec<_> = names
+            .into_iter()
+            .map(|name| {
+                let device = devices.get(name);
+                RegisterEntry {
+                    name: name.to_string(),
+                    kind: device.map(|dev| dev.kind.clone()),
+                    register: registers
+                        .get(name)
+                        .copied()
+                        .or_else(|| device.and_then(|dev| dev.register)),
+                    access: device.map_or(Access::ReadWrite, super::Device::access),
+                    metadata: device
+                        .map(|dev| {
+                            dev.metadata
+                                .iter()
+                                .map(|(k, v)| (k.to_string(), v.clone()))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+        // Registers in address order, then everything without one
+        entries.sort_by(|a, b| {
+            let key = |e: &RegisterEntry| e.register.map_or(u64::MAX, |r| u64::from(r.addr));
+            key(a).cmp(&key(b)).then_with(|| a.name.cmp(&b.name))
+        });
+        Self {
+            md5: design.md5_string(),
+            entries,
+        }
+    }
+
+    /// Render the document as a single JSON object
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let kind = entry
+                    .kind
+                    .as_ref()
+                    .map_or_else(|| "null".to_string(), |k| quote(k.as_str()));
+                let (addr, size) = entry.register.map_or_else(
+                    || ("null".to_string(), "null".to_string()),
+                    |r| (r.addr.to_string(), r.size.to_string()),
+                );
+                let metadata: Vec<_> = entry
+                    .metadata
+                    .iter()
+                    .map(|(k, v)| format!("{}:{}", quote(k), quote(v)))
+                    .collect();
+                format!(
+                    r#"{{"name":{},"kind":{kind},"addr":{addr},"size":{size},"access":"{}","metadata":{{{}}}}}"#,
+                    quote(&entry.name),
+                    entry.access,
+                    metadata.join(",")
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"md5":"{}","registers":[{}]}}"#,
+            self.md5,
+            entries.join(",")
+        )
+    }
+
+    /// Render the document as a markdown table, one row per entry
+    #[must_use]
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!(
+            "Register map of the design with md5 `{}`\n\n\
+             | Name | Kind | Address | Size | Access | Metadata |\n\
+             | --- | --- | --- | --- | --- | --- |\n",
+            self.md5
+        );
+        for entry in &self.entries {
+            let (addr, size) = entry.register.map_or_else(
+                || ("-".to_string(), "-".to_string()),
+                |r| (format!("`{:#010x}`", r.addr), r.size.to_string()),
+            );
+            let metadata: Vec<_> = entry
+                .metadata
+                .iter()
+                .map(|(k, v)| format!("{}: `{}`", markdown_cell(k), markdown_cell(v)))
+                .collect();
+            let _ = writeln!(
+                out,
+                "| `{}` | {} | {addr} | {size} | {} | {} |",
+                entry.name,
+                entry
+                    .kind
+                    .as_ref()
+                    .map_or("-".to_string(), |k| format!("`{k}`")),
+                entry.access,
+                metadata.join("<br>")
+            );
+        }
+        out
+    }
+}
+
+/// Keep a value from breaking out of its table cell
+fn markdown_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::design_sources::{
+        raw::RawDesign,
+        Device,
+    };
+    use kstring::KString;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_document() {
+        let reg = |addr| Register { addr, size: 4 };
+        let design = RawDesign {
+            registers: HashMap::from([
+                (KString::from_ref("sys_clkcounter"), reg(0)),
+                (KString::from_ref("gain"), reg(0x10)),
+            ]),
+            devices: HashMap::from([
+                (
+                    KString::from_ref("gain"),
+                    Device {
+                        kind: DeviceKind::SwReg,
+                        register: Some(reg(0x10)),
+                        metadata: HashMap::from([
+                            (KString::from_ref("io_dir"), "To\\_Processor".to_string()),
+                            (KString::from_ref("bitwidths"), "32".to_string()),
+                        ]),
+                    },
+                ),
+                (
+                    KString::from_ref("SNAP"),
+                    Device {
+                        kind: DeviceKind::Xsg,
+                        register: None,
+                        metadata: HashMap::from([(KString::from_ref("clk_rate"), "250".into())]),
+                    },
+                ),
+            ]),
+            bitstream: vec![],
+            md5: [0; 16],
+            sha256: [0; 32],
+            filename: "design.bin".into(),
+        };
+        let doc = RegisterDocument::of(&design);
+        let names: Vec<_> = doc.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["sys_clkcounter", "gain", "SNAP"]);
+        assert_eq!(doc.entries[0].kind, None);
+        assert_eq!(doc.entries[1].access, Access::Read);
+
+        assert_eq!(
+            doc.to_json(),
+            format!(
+                r#"{{"md5":"{}","registers":[{},{},{}]}}"#,
+                design.md5_string(),
+                r#"{"name":"sys_clkcounter","kind":null,"addr":0,"size":4,"access":"read-write","metadata":{}}"#,
+                r#"{"name":"gain","kind":"xps:sw_reg","addr":16,"size":4,"access":"read-only","metadata":{"bitwidths":"32","io_dir":"To\\_Processor"}}"#,
+                r#"{"name":"SNAP","kind":"xps:xsg","addr":null,"size":null,"access":"read-write","metadata":{"clk_rate":"250"}}"#,
+            )
+        );
+        let markdown = doc.to_markdown();
+        assert!(markdown.contains(
+            "| `gain` | `xps:sw_reg` | `0x00000010` | 4 | read-only | bitwidths: `32`<br>io_dir: \
+             `To\\_Processor` |\n"
+        ));
+        assert!(
+            markdown.ends_with("| `SNAP` | `xps:xsg` | - | - | read-write | clk_rate: `250` |\n")
+        );
+    }
+}
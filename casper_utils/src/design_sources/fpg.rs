@@ -2,10 +2,12 @@
 //! As there is no formal specification of this format, the parsing logic here uses the
 //! "implementation as spec"
 use super::{
+    mangle_name,
     Device,
     FpgaDesign,
     Register,
 };
+use crate::digest::sha256;
 use flate2::bufread::GzDecoder;
 use kstring::KString;
 use nom::{
@@ -46,6 +48,7 @@ pub struct File {
     pub devices: HashMap<KString, Device>,
     pub bitstream: Vec<u8>,
     pub md5: [u8; 16],
+    pub sha256: [u8; 32],
     pub filename: OsString,
 }
 
@@ -76,6 +79,10 @@ impl FpgaDesign for File {
         &self.md5
     }
 
+    fn sha256(&self) -> [u8; 32] {
+        self.sha256
+    }
+
     fn devices(&self) -> &super::Devices {
         &self.devices
     }
@@ -118,7 +125,7 @@ fn register(input: &[u8]) -> IResult<&[u8], (&str, u32, u32)> {
 
 type Metadata<'a> = (KString, &'a str, &'a str, &'a str);
 
-fn meta(input: &[u8]) -> IResult<&[u8], Metadata> {
+fn meta(input: &[u8]) -> IResult<&[u8], Metadata<'_>> {
     let (remaining, _) = tag("?meta")(input)?;
     let (remaining, device) =
         map_res(preceded(space1, take_till(is_space)), utf8_string)(remaining)?;
@@ -133,7 +140,7 @@ fn meta(input: &[u8]) -> IResult<&[u8], Metadata> {
     // '_' like the registers list To make them match (for later lookup), we'll replace them.
     Ok((
         remaining,
-        (device.replace('/', "_").into(), kind, meta_key, meta_value),
+        (mangle_name(device).into(), kind, meta_key, meta_value),
     ))
 }
 
@@ -170,7 +177,7 @@ pub(crate) fn fpg_file(input: &[u8]) -> IResult<&[u8], AlmostFile> {
                 devices.insert(
                     name.clone(),
                     Device {
-                        kind: kind.to_owned(),
+                        kind: kind.into(),
                         metadata: HashMap::from_iter([(k.to_owned().into(), v.to_owned())]),
                         register: registers.get(&name).copied(),
                     },
@@ -196,6 +203,7 @@ where
 
     // Calculate the MD5
     let md5 = md5::compute(&contents);
+    let sha256 = sha256(&contents);
 
     let (_, (regs, devs, bs)) = fpg_file(&contents).map_err(|_| Error::ParseMatch)?;
     let mut file = File {
@@ -203,6 +211,7 @@ where
         registers: regs,
         bitstream: bs,
         md5: md5.into(),
+        sha256,
         filename: filename.as_ref().file_name().unwrap().to_owned(),
     };
     // Check if file's bitsream bytes is compressed (Gzip), and if so, decompress
@@ -218,6 +227,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::design_sources::DeviceKind;
 
     #[test]
     fn test_shebang() {
@@ -281,7 +291,7 @@ mod tests {
         assert_eq!(
             *devs.get("SNAP").unwrap(),
             Device {
-                kind: "xps:xsg".to_owned(),
+                kind: DeviceKind::Xsg,
                 register: None,
                 metadata: HashMap::from_iter([("clk_rate".into(), "250".to_owned())])
             }
@@ -289,7 +299,7 @@ mod tests {
         assert_eq!(
             *devs.get("tx_en").unwrap(),
             Device {
-                kind: "xps:sw_reg".to_owned(),
+                kind: DeviceKind::SwReg,
                 register: Some(Register {
                     addr: 217_404,
                     size: 4
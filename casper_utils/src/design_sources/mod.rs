@@ -6,7 +6,13 @@ use std::{
     fmt::Write,
 };
 
+pub mod diff;
+pub mod document;
 pub mod fpg;
+pub mod raw;
+
+pub use diff::diff;
+pub use document::RegisterDocument;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 /// A register on the FPGA bus described by its 32-bit address and size in bytes
@@ -19,15 +25,131 @@ pub struct Register {
 /// An enumeratable "device" described by it's kind, potential corresponding register, and any
 /// (String,String) metadata
 pub struct Device {
-    pub kind: String,
+    pub kind: DeviceKind,
     pub register: Option<Register>,
     pub metadata: HashMap<KString, String>,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[non_exhaustive]
+/// The kind of a [`Device`], i.e. `xps:sw_reg`, parsed so matching on it catches typos at compile
+/// time. Kinds without a variant are kept as [`DeviceKind::Unknown`], and should be built with
+/// [`DeviceKind::from`] so they never shadow a known kind.
+pub enum DeviceKind {
+    SwReg,
+    Bram,
+    TenGbe,
+    SnapAdc,
+    Snapshot,
+    KatAdc,
+    IAdc,
+    Xsg,
+    Unknown(String),
+}
+
+impl DeviceKind {
+    /// The kind as written in the design, i.e. `xps:sw_reg`
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::SwReg => "xps:sw_reg",
+            Self::Bram => "xps:bram",
+            Self::TenGbe => "xps:ten_gbe",
+            Self::SnapAdc => "xps:snap_adc",
+            Self::Snapshot => "casper:snapshot",
+            Self::KatAdc => "xps:katadc",
+            Self::IAdc => "xps:iadc",
+            Self::Xsg => "xps:xsg",
+            Self::Unknown(kind) => kind,
+        }
+    }
+}
+
+impl From<&str> for DeviceKind {
+    fn from(kind: &str) -> Self {
+        match kind {
+            "xps:sw_reg" => Self::SwReg,
+            "xps:bram" => Self::Bram,
+            "xps:ten_gbe" => Self::TenGbe,
+            "xps:snap_adc" => Self::SnapAdc,
+            "casper:snapshot" => Self::Snapshot,
+            "xps:katadc" => Self::KatAdc,
+            "xps:iadc" => Self::IAdc,
+            "xps:xsg" => Self::Xsg,
+            other => Self::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+/// What client applications may do with a register
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    /// Parse a `mode` metadata hint, i.e. `r`, `wo`, or `read-write`
+    #[must_use]
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode.to_ascii_lowercase().replace(['_', ' '], "-").as_str() {
+            "r" | "ro" | "read" | "read-only" => Some(Self::Read),
+            "w" | "wo" | "write" | "write-only" => Some(Self::Write),
+            "rw" | "read-write" => Some(Self::ReadWrite),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn readable(self) -> bool {
+        self != Self::Write
+    }
+
+    #[must_use]
+    pub fn writable(self) -> bool {
+        self != Self::Read
+    }
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Read => "read-only",
+            Self::Write => "write-only",
+            Self::ReadWrite => "read-write",
+        })
+    }
+}
+
 impl Device {
     fn add_meta(&mut self, k: KString, v: String) {
         self.metadata.insert(k, v);
     }
+
+    /// The access clients have to this device's register. This comes from the `mode` metadata hint
+    /// if there is one, else software registers that only go to the processor are read-only.
+    /// Everything else is read-write.
+    #[must_use]
+    pub fn access(&self) -> Access {
+        if let Some(access) = self.metadata.get("mode").and_then(|m| Access::from_mode(m)) {
+            return access;
+        }
+        match self.metadata.get("io_dir") {
+            Some(dir)
+                if self.kind == DeviceKind::SwReg && dir.replace('\\', "") == "To_Processor" =>
+            {
+                Access::Read
+            }
+            _ => Access::ReadWrite,
+        }
+    }
 }
 
 /// A map from device name (corresponding with a register name) to [`Device`]
@@ -36,9 +158,33 @@ pub type Devices = HashMap<KString, Device>;
 /// A map from register name to [`Register`]
 pub type Registers = HashMap<KString, Register>;
 
+/// Converts a nested Simulink block path (i.e. `adc/snap`) into the flat register name used on
+/// the bus (i.e. `adc_snap`). The toolflow uses '/' for nesting in metadata but '_' in the
+/// register list, so everything should go through here to agree on names.
+#[must_use]
+pub fn mangle_name(path: &str) -> String {
+    path.replace('/', "_")
+}
+
+/// Checks detached signatures of designs. With the `ed25519` feature, ed25519 public keys
+/// ([`ed25519_dalek::VerifyingKey`]) implement this, other schemes can wrap the verifying key of
+/// whichever implementation the site uses.
+pub trait SignatureVerifier {
+    /// Returns true if `signature` is a valid signature of `message`
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+#[cfg(feature = "ed25519")]
+impl SignatureVerifier for ed25519_dalek::VerifyingKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        ed25519_dalek::Signature::from_slice(signature)
+            .is_ok_and(|signature| self.verify_strict(message, &signature).is_ok())
+    }
+}
+
 /// Any type that provides all the information to concretly describe a CASPER design must implement
-/// the [`FpgaDesign`] trait. Right now this is just FPG files, but could be extended to bitstream +
-/// device tree, etc.
+/// the [`FpgaDesign`] trait. Right now this is FPG files and raw bitstreams with a separate
+/// register map, but could be extended to bitstream + device tree, etc.
 pub trait FpgaDesign {
     /// Get the uncompressed bitstream for a given design as bytes, ready to program
     fn bitstream(&self) -> &Vec<u8>;
@@ -54,9 +200,155 @@ pub trait FpgaDesign {
         })
     }
 
+    /// Get the SHA-256 of the design, for sites that need a stronger check than md5. This is of
+    /// the bitstream unless the design says otherwise, the designs read from files here use the
+    /// file as read.
+    fn sha256(&self) -> [u8; 32] {
+        crate::digest::sha256(self.bitstream())
+    }
+
+    /// Get a hex string representation of the SHA-256 hash
+    fn sha256_string(&self) -> String {
+        crate::digest::to_hex(&self.sha256())
+    }
+
+    /// Check a detached `signature` of the design with `verifier`. The signed message is the
+    /// 32-byte SHA-256 of the design as read (see [`FpgaDesign::sha256`]), so the signature can be
+    /// made from the output of `sha256sum` without the design in hand.
+    fn verify_signature<V>(&self, signature: &[u8], verifier: &V) -> bool
+    where
+        V: SignatureVerifier + ?Sized,
+        Self: Sized,
+    {
+        verifier.verify(&self.sha256(), signature)
+    }
+
     /// Get the list of potentially constructable devices
     fn devices(&self) -> &Devices;
 
     /// Get the list of system regisers
     fn registers(&self) -> &Registers;
+
+    /// Get the fingerprint of the register map, which a board running this design reports from
+    /// `listdev` too, see [`crate::digest::register_fingerprint`]
+    fn register_fingerprint(&self) -> String {
+        crate::digest::register_fingerprint(
+            self.registers()
+                .iter()
+                .map(|(name, reg)| (name.as_str(), u64::from(reg.addr), u64::from(reg.size))),
+        )
+    }
+
+    /// Get the access mode of every device with a register, see [`Device::access`]
+    fn access_map(&self) -> HashMap<KString, Access> {
+        self.devices()
+            .iter()
+            .filter(|(_, dev)| dev.register.is_some())
+            .map(|(name, dev)| (name.clone(), dev.access()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(kind: &str, metadata: &[(&str, &str)]) -> Device {
+        Device {
+            kind: kind.into(),
+            register: Some(Register { addr: 0, size: 4 }),
+            metadata: metadata
+                .iter()
+                .map(|(k, v)| (KString::from_ref(k), (*v).to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_access() {
+        assert_eq!(Access::from_mode("RW"), Some(Access::ReadWrite));
+        assert_eq!(Access::from_mode("read_only"), Some(Access::Read));
+        assert_eq!(Access::from_mode("sideways"), None);
+        assert_eq!(
+            device("xps:sw_reg", &[("io_dir", "To\\_Processor")]).access(),
+            Access::Read
+        );
+        assert_eq!(
+            device("xps:sw_reg", &[("io_dir", "From\\_Processor")]).access(),
+            Access::ReadWrite
+        );
+        // The mode hint wins
+        assert_eq!(
+            device(
+                "xps:sw_reg",
+                &[("io_dir", "To\\_Processor"), ("mode", "wo")]
+            )
+            .access(),
+            Access::Write
+        );
+        assert_eq!(device("xps:bram", &[]).access(), Access::ReadWrite);
+        assert!(Access::Read.readable() && !Access::Read.writable());
+    }
+
+    /// A design with nothing but a bitstream
+    struct Bitstream(Vec<u8>, Devices, Registers);
+
+    impl FpgaDesign for Bitstream {
+        fn bitstream(&self) -> &Vec<u8> {
+            &self.0
+        }
+
+        fn md5(&self) -> &[u8; 16] {
+            &[0; 16]
+        }
+
+        fn devices(&self) -> &Devices {
+            &self.1
+        }
+
+        fn registers(&self) -> &Registers {
+            &self.2
+        }
+    }
+
+    #[test]
+    fn test_default_sha256() {
+        let design = Bitstream(b"abc".to_vec(), Devices::new(), Registers::new());
+        assert_eq!(
+            design.sha256_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[cfg(feature = "ed25519")]
+    #[test]
+    fn test_ed25519() {
+        use ed25519_dalek::{
+            Signer,
+            SigningKey,
+        };
+        let design = Bitstream(b"abc".to_vec(), Devices::new(), Registers::new());
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signature = key.sign(&design.sha256()).to_bytes();
+        assert!(design.verify_signature(&signature, &key.verifying_key()));
+        // Signed by someone else, signing something else, or not a signature at all
+        let other = SigningKey::from_bytes(&[8; 32]);
+        assert!(!design.verify_signature(&signature, &other.verifying_key()));
+        let wrong = key.sign(design.bitstream()).to_bytes();
+        assert!(!design.verify_signature(&wrong, &key.verifying_key()));
+        assert!(!design.verify_signature(&signature[..32], &key.verifying_key()));
+    }
+
+    #[test]
+    fn test_device_kind() {
+        assert_eq!(DeviceKind::from("xps:ten_gbe"), DeviceKind::TenGbe);
+        assert_eq!(
+            DeviceKind::from("casper:snapshot").as_str(),
+            "casper:snapshot"
+        );
+        let dram = DeviceKind::from("xps:dram");
+        assert_eq!(dram, DeviceKind::Unknown("xps:dram".to_string()));
+        assert_eq!(dram.to_string(), "xps:dram");
+        assert_eq!(device("xps:sw_reg", &[]).kind, DeviceKind::SwReg);
+    }
 }
@@ -0,0 +1,180 @@
+//! Designs made of a raw bitstream (i.e. a legacy `.bin` or `.bof`) and a separate register
+//! listing, for when there is no fpg file.
+//!
+//! The register listing is CSV with one register per line as `name,addr,size[,kind]`, where the
+//! address and size may be decimal or `0x` prefixed hex. Blank lines, lines starting with `#`, and
+//! a `name,addr,size` header are skipped. Every register becomes a [`Device`] of the given kind
+//! (empty if not given) with no metadata, as there is nothing to derive it from.
+use super::{
+    mangle_name,
+    Device,
+    Devices,
+    FpgaDesign,
+    Register,
+    Registers,
+};
+use crate::digest::sha256;
+use flate2::bufread::GzDecoder;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io::Read,
+    path::Path,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Malformed register map on line {line} - {reason}")]
+    RegisterMap { line: usize, reason: String },
+    #[error("The bitstream file has no file name")]
+    NoFilename,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// A raw bitstream with an externally supplied register map
+pub struct RawDesign {
+    pub registers: Registers,
+    pub devices: Devices,
+    pub bitstream: Vec<u8>,
+    /// The MD5 of the bitstream file as read
+    pub md5: [u8; 16],
+    /// The SHA-256 of the bitstream file as read
+    pub sha256: [u8; 32],
+    pub filename: OsString,
+}
+
+impl FpgaDesign for RawDesign {
+    fn bitstream(&self) -> &Vec<u8> {
+        &self.bitstream
+    }
+
+    fn md5(&self) -> &[u8; 16] {
+        &self.md5
+    }
+
+    fn sha256(&self) -> [u8; 32] {
+        self.sha256
+    }
+
+    fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Parse a CSV register listing into the registers and (metadata-less) devices it describes
+/// # Errors
+/// Returns an error on malformed lines
+pub fn parse_register_map(contents: &str) -> Result<(Registers, Devices), Error> {
+    let mut registers = HashMap::new();
+    let mut devices = HashMap::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("name,") {
+            continue;
+        }
+        let err = |reason: &str| Error::RegisterMap {
+            line: idx + 1,
+            reason: reason.to_string(),
+        };
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let (name, addr, size, kind) = match fields[..] {
+            [name, addr, size] => (name, addr, size, ""),
+            [name, addr, size, kind] => (name, addr, size, kind),
+            _ => return Err(err("expected `name,addr,size[,kind]`")),
+        };
+        if name.is_empty() {
+            return Err(err("empty register name"));
+        }
+        let register = Register {
+            addr: parse_number(addr).ok_or_else(|| err("invalid address"))?,
+            size: parse_number(size).ok_or_else(|| err("invalid size"))?,
+        };
+        let name = mangle_name(name);
+        registers.insert(name.clone().into(), register);
+        devices.insert(
+            name.into(),
+            Device {
+                kind: kind.into(),
+                register: Some(register),
+                metadata: HashMap::new(),
+            },
+        );
+    }
+    Ok((registers, devices))
+}
+
+/// Reads a raw bitstream from `bitstream` (which may be gzipped) and its CSV register listing from
+/// `register_map`
+/// # Errors
+/// Returns an error if either file couldn't be read or the register map is malformed
+pub fn read_raw_design<B, R>(bitstream: B, register_map: R) -> Result<RawDesign, Error>
+where
+    B: AsRef<Path>,
+    R: AsRef<Path>,
+{
+    let filename = bitstream
+        .as_ref()
+        .file_name()
+        .ok_or(Error::NoFilename)?
+        .to_owned();
+    let contents = std::fs::read(bitstream)?;
+    let md5 = md5::compute(&contents);
+    let sha256 = sha256(&contents);
+    let (registers, devices) = parse_register_map(&std::fs::read_to_string(register_map)?)?;
+    let bitstream = if contents.starts_with(&[0x1F, 0x8B, 0x08]) {
+        let mut decompressed = vec![];
+        GzDecoder::new(&contents[..]).read_to_end(&mut decompressed)?;
+        decompressed
+    } else {
+        contents
+    };
+    Ok(RawDesign {
+        registers,
+        devices,
+        bitstream,
+        md5: md5.into(),
+        sha256,
+        filename,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::design_sources::DeviceKind;
+
+    #[test]
+    fn test_parse_register_map() {
+        let csv = "name,addr,size\n# comment\nsys_clkcounter,0x0,4\nadc/snap_bram, 4096 ,0x400,xps:bram\n\n";
+        let (registers, devices) = parse_register_map(csv).unwrap();
+        assert_eq!(registers.len(), 2);
+        assert_eq!(
+            registers["adc_snap_bram"],
+            Register {
+                addr: 4096,
+                size: 0x400
+            }
+        );
+        assert_eq!(devices["adc_snap_bram"].kind, DeviceKind::Bram);
+        assert_eq!(devices["sys_clkcounter"].kind.as_str(), "");
+        assert!(matches!(
+            parse_register_map("sys_clkcounter,0xZZ,4"),
+            Err(Error::RegisterMap { line: 1, .. })
+        ));
+        assert!(parse_register_map("sys_clkcounter,0").is_err());
+    }
+}
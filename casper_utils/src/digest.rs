@@ -0,0 +1,63 @@
+//! Checksums for validating designs, beyond the md5 the toolflow has always used
+use sha2::{
+    Digest,
+    Sha256,
+};
+use std::fmt::Write;
+
+/// Compute the SHA-256 digest of `data`
+#[must_use]
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Format a digest as lowercase hex, two digits per byte
+#[must_use]
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().fold(String::new(), |mut output, v| {
+        let _ = write!(output, "{v:02x}");
+        output
+    })
+}
+
+/// A fingerprint of a register map of (name, address, size) entries that doesn't depend on their
+/// order, so the `listdev` of a board can be compared to the register map of a design without any
+/// metadata in flash
+pub fn register_fingerprint<'a, I>(registers: I) -> String
+where
+    I: IntoIterator<Item = (&'a str, u64, u64)>,
+{
+    let mut registers: Vec<_> = registers.into_iter().collect();
+    registers.sort_unstable();
+    let listing = registers
+        .iter()
+        .fold(String::new(), |mut listing, (name, addr, size)| {
+            let _ = writeln!(listing, "{name}\t{addr:#x}\t{size}");
+            listing
+        });
+    to_hex(&sha256(listing.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            to_hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            to_hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two blocks of padding
+        assert_eq!(
+            to_hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+}
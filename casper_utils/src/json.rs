@@ -0,0 +1,37 @@
+//! The small amount of JSON writing the design reports and transaction logs need, without pulling
+//! in a serializer
+
+use std::fmt::Write;
+
+/// Quote `s` as a JSON string, escaping quotes, backslashes, and control characters
+#[must_use]
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("adc_snap"), r#""adc_snap""#);
+        assert_eq!(quote("a \"b\"\\\n\t\u{1}é"), r#""a \"b\"\\\n\t\u0001é""#);
+    }
+}
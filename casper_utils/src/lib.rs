@@ -7,3 +7,5 @@
 
 pub mod csl;
 pub mod design_sources;
+pub mod digest;
+pub mod json;
@@ -2,6 +2,7 @@
 //! setup the 10 GbE core.
 
 use casperfpga::{
+    config::Profiles,
     prelude::*,
     yellow_blocks::snapadc::{
         controller::ChannelInput,
@@ -13,48 +14,70 @@ use std::net::Ipv4Addr;
 fpga_from_fpg!(GrexFpga, "casperfpga/examples/grex_gateware.fpg");
 
 fn main() -> anyhow::Result<()> {
-    // Create the transport and connect
-    let mut fpga = GrexFpga::new(Tapcp::connect(
-        "192.168.0.3:69".parse()?,
-        tapcp::Platform::SNAP,
-    )?)?;
+    // Connect to the board of `--profile <name>` (see `casperfpga::config`) or the default address
+    let args: Vec<_> = std::env::args().collect();
+    let profile = match args.iter().position(|a| a == "--profile") {
+        Some(i) => {
+            let name = args
+                .get(i + 1)
+                .ok_or(anyhow::anyhow!("Missing profile name"))?;
+            Some(Profiles::from_env()?.get(name)?)
+        }
+        None => None,
+    };
+    let transport = match &profile {
+        Some(profile) => profile.connect_tapcp()?,
+        None => Tapcp::connect("192.168.0.3:69".parse()?, tapcp::Platform::SNAP)?,
+    };
+    // Track the bringup so the steps can't run out of order
+    let mut bringup = GrexFpga::new(transport)?.bringup();
 
     // Program the design
-    let design = read_fpg_file("casperfpga/examples/grex_gateware.fpg")?;
-    fpga.transport.lock().unwrap().program(&design, true)?;
+    let design = read_fpg_file(
+        profile
+            .and_then(|p| p.fpg)
+            .unwrap_or("casperfpga/examples/grex_gateware.fpg".into()),
+    )?;
+    bringup.program(|fpga| fpga.transport.lock().unwrap().program(&design, true))?;
 
     // Setup the ADCs
-    fpga.snap_adc.initialize()?;
-    fpga.snap_adc
-        .select_inputs(ChannelInput::Dual(InputSelect::_1, InputSelect::_1))?;
+    bringup.init_adc(|fpga| {
+        fpga.snap_adc.initialize()?;
+        fpga.snap_adc
+            .select_inputs(ChannelInput::Dual(InputSelect::_1, InputSelect::_1))
+    })?;
 
     // Configure the 10 GbE core
     let dest_ip: Ipv4Addr = "192.168.0.1".parse()?;
-    let dest_mac = [0x98, 0xb7, 0x85, 0xa7, 0xec, 0x78];
+    let dest_mac = "98:b7:85:a7:ec:78".parse()?;
     let dest_port = 60000u16;
 
-    // Disable
-    fpga.tx_en.write(false)?;
-    // Reset
-    fpga.master_rst.write(false)?;
-    fpga.master_rst.write(true)?;
-    fpga.master_rst.write(false)?;
+    bringup.configure_network(|fpga| -> anyhow::Result<()> {
+        // Disable
+        fpga.tx_en.write(false)?;
+        // Reset
+        fpga.master_rst.write(false)?;
+        fpga.master_rst.write(true)?;
+        fpga.master_rst.write(false)?;
 
-    fpga.gbe1.set_ip("192.168.0.20".parse()?)?;
-    fpga.gbe1.set_gateway(dest_ip)?;
-    fpga.gbe1.set_netmask("255.255.255.0".parse()?)?;
-    fpga.gbe1.set_port(dest_port)?;
-    fpga.gbe1.set_mac(&[0x02, 0x2E, 0x46, 0xE0, 0x64, 0xA1])?;
-    fpga.gbe1.set_enable(true)?;
-    fpga.gbe1.toggle_reset()?;
+        fpga.gbe1.set_ip("192.168.0.20".parse()?)?;
+        fpga.gbe1.set_gateway(dest_ip)?;
+        fpga.gbe1.set_netmask("255.255.255.0".parse()?)?;
+        fpga.gbe1.set_port(dest_port)?;
+        fpga.gbe1.set_mac("02:2e:46:e0:64:a1".parse()?)?;
+        fpga.gbe1.set_enable(true)?;
+        fpga.gbe1.toggle_reset()?;
 
-    // Set destination registers
-    fpga.dest_port.write(dest_port.into())?;
-    fpga.dest_ip.write(u32::from(dest_ip).into())?;
-    fpga.gbe1.set_single_arp_entry(dest_ip, &dest_mac)?;
+        // Set destination registers
+        fpga.dest_port.write(dest_port.into())?;
+        fpga.dest_ip.write(u32::from(dest_ip).into())?;
+        fpga.gbe1.set_single_arp_entry(dest_ip, dest_mac)?;
+        Ok(())
+    })?;
 
     // Turn on the core
-    fpga.tx_en.write(true)?;
+    bringup.enable_tx(|fpga| fpga.tx_en.write(true))?;
+    let fpga = bringup.fpga();
 
     // Check the link
     assert!(fpga.gbe1_linkup.read()?, "10GbE Link Failed to come up");
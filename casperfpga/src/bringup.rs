@@ -0,0 +1,226 @@
+//! Enforcing the order of the steps of bringing up a board
+//!
+//! Bringing up a design has to happen in order - program the FPGA, initialize the ADCs, configure
+//! the network, and only then enable the transmitters - and getting it wrong usually doesn't fail,
+//! it just streams garbage. A [`Bringup`] wraps a generated FPGA struct (see `bringup()` on the
+//! structs from `fpga_from_fpg!`) and tracks which [`Stage`] it has reached. Each step is a closure
+//! run against the FPGA, which only runs if the previous stage was reached, and only advances the
+//! stage if it succeeds.
+//!
+//! Steps can be repeated, and repeating an earlier one goes back to that stage, i.e.
+//! reprogramming means the ADCs have to be initialized again before transmitting.
+use thiserror::Error;
+
+/// How far along the bringup of a board is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    Unprogrammed,
+    Programmed,
+    AdcInitialized,
+    NetworkConfigured,
+    Transmitting,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Unprogrammed => "unprogrammed",
+            Self::Programmed => "programmed",
+            Self::AdcInitialized => "ADC initialized",
+            Self::NetworkConfigured => "network configured",
+            Self::Transmitting => "transmitting",
+        })
+    }
+}
+
+impl Stage {
+    /// The stage that has to be reached before this one
+    fn previous(self) -> Option<Self> {
+        match self {
+            Self::Unprogrammed => None,
+            Self::Programmed => Some(Self::Unprogrammed),
+            Self::AdcInitialized => Some(Self::Programmed),
+            Self::NetworkConfigured => Some(Self::AdcInitialized),
+            Self::Transmitting => Some(Self::NetworkConfigured),
+        }
+    }
+}
+
+/// The error of a failed step, of whatever type the step returned
+pub type StepError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("The board has to be {required} before it can be {attempted}, but it is {current}")]
+    OutOfOrder {
+        attempted: Stage,
+        required: Stage,
+        current: Stage,
+    },
+    #[error("Failed to get the board {stage}")]
+    Step {
+        stage: Stage,
+        #[source]
+        source: StepError,
+    },
+}
+
+/// A FPGA with the stage of its bringup, see the module docs
+#[derive(Debug)]
+pub struct Bringup<F> {
+    fpga: F,
+    stage: Stage,
+}
+
+impl<F> Bringup<F> {
+    /// Start the bringup of `fpga`, which is assumed to be unprogrammed
+    pub fn new(fpga: F) -> Self {
+        Self {
+            fpga,
+            stage: Stage::Unprogrammed,
+        }
+    }
+
+    /// Start the bringup of `fpga` from `stage`, i.e. for a board that was already programmed by
+    /// some other process
+    pub fn resume(fpga: F, stage: Stage) -> Self {
+        Self { fpga, stage }
+    }
+
+    /// The stage the bringup has reached
+    #[must_use]
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// The FPGA, i.e. to read status without going through a step
+    #[must_use]
+    pub fn fpga(&self) -> &F {
+        &self.fpga
+    }
+
+    /// Give up on tracking the bringup, returning the FPGA
+    pub fn into_inner(self) -> F {
+        self.fpga
+    }
+
+    /// Run the step `f` that gets the board to `stage`
+    /// # Errors
+    /// Returns an error if the previous stage wasn't reached or if `f` fails, in which case the
+    /// board stays at the previous stage
+    pub fn step<E, S>(&mut self, stage: Stage, f: S) -> Result<(), Error>
+    where
+        S: FnOnce(&mut F) -> Result<(), E>,
+        E: Into<StepError>,
+    {
+        if let Some(required) = stage.previous() {
+            if self.stage < required {
+                return Err(Error::OutOfOrder {
+                    attempted: stage,
+                    required,
+                    current: self.stage,
+                });
+            }
+            // Whatever happens, anything past the previous stage no longer holds
+            self.stage = required;
+        }
+        f(&mut self.fpga).map_err(|e| Error::Step {
+            stage,
+            source: e.into(),
+        })?;
+        self.stage = stage;
+        Ok(())
+    }
+
+    /// Program the FPGA with `f`
+    /// # Errors
+    /// Returns an error if `f` fails
+    pub fn program<E, S>(&mut self, f: S) -> Result<(), Error>
+    where
+        S: FnOnce(&mut F) -> Result<(), E>,
+        E: Into<StepError>,
+    {
+        self.step(Stage::Programmed, f)
+    }
+
+    /// Initialize (and calibrate) the ADCs with `f`
+    /// # Errors
+    /// Returns an error if the FPGA isn't programmed or if `f` fails
+    pub fn init_adc<E, S>(&mut self, f: S) -> Result<(), Error>
+    where
+        S: FnOnce(&mut F) -> Result<(), E>,
+        E: Into<StepError>,
+    {
+        self.step(Stage::AdcInitialized, f)
+    }
+
+    /// Configure the network cores with `f`
+    /// # Errors
+    /// Returns an error if the ADCs weren't initialized or if `f` fails
+    pub fn configure_network<E, S>(&mut self, f: S) -> Result<(), Error>
+    where
+        S: FnOnce(&mut F) -> Result<(), E>,
+        E: Into<StepError>,
+    {
+        self.step(Stage::NetworkConfigured, f)
+    }
+
+    /// Start transmitting with `f`
+    /// # Errors
+    /// Returns an error if the network wasn't configured or if `f` fails
+    pub fn enable_tx<E, S>(&mut self, f: S) -> Result<(), Error>
+    where
+        S: FnOnce(&mut F) -> Result<(), E>,
+        E: Into<StepError>,
+    {
+        self.step(Stage::Transmitting, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bringup_order() {
+        let mut bringup = Bringup::new(vec![]);
+        let ok = |step| {
+            move |log: &mut Vec<&str>| {
+                log.push(step);
+                Ok::<_, StepError>(())
+            }
+        };
+        assert!(matches!(
+            bringup.enable_tx(ok("tx")),
+            Err(Error::OutOfOrder {
+                attempted: Stage::Transmitting,
+                required: Stage::NetworkConfigured,
+                current: Stage::Unprogrammed,
+            })
+        ));
+        bringup.program(ok("program")).unwrap();
+        assert!(bringup.configure_network(ok("network")).is_err());
+        bringup.init_adc(ok("adc")).unwrap();
+        // A failed step stays at the previous stage
+        match bringup.configure_network(|_| Err("no link")) {
+            Err(Error::Step {
+                stage: Stage::NetworkConfigured,
+                source,
+            }) => assert_eq!(source.to_string(), "no link"),
+            other => panic!("Expected a failed step, got {other:?}"),
+        }
+        assert_eq!(bringup.stage(), Stage::AdcInitialized);
+        bringup.configure_network(ok("network")).unwrap();
+        bringup.enable_tx(ok("tx")).unwrap();
+        assert_eq!(bringup.stage(), Stage::Transmitting);
+
+        // Reprogramming goes back to the start
+        bringup.program(ok("program")).unwrap();
+        assert_eq!(bringup.stage(), Stage::Programmed);
+        assert!(bringup.enable_tx(ok("tx")).is_err());
+        assert_eq!(
+            bringup.into_inner(),
+            vec!["program", "adc", "network", "tx", "program"]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Checkpointing and restoring the register state of a board
+//!
+//! A [`Checkpoint`] holds the contents of a set of registers, captured from the register map of a
+//! transport (optionally filtered). It can be written out as [plain text](crate::text), one
+//! register per line as `<name> <hex bytes>`, and later restored to the board with a readback
+//! verification, or just diffed against the current state as a dry run.
+//!
+//! The register map doesn't say which registers are writable, so the filter should exclude
+//! read-only registers (status, counters) and any large memories that don't need saving.
+
+use crate::{
+    core::Register,
+    text::{
+        from_hex,
+        records,
+        to_hex,
+    },
+    transport::{
+        Transport,
+        TransportResult,
+    },
+};
+use std::{
+    collections::BTreeMap,
+    io::{
+        BufRead,
+        Write,
+    },
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("Internal system IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed checkpoint on line {line} - {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+/// A register whose value on the board differs from the checkpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterDiff {
+    /// The name of the register
+    pub name: String,
+    /// The value currently on the board
+    pub current: Vec<u8>,
+    /// The value in the checkpoint
+    pub saved: Vec<u8>,
+}
+
+/// The saved contents of a set of registers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Register names and their contents, read from offset zero
+    pub registers: BTreeMap<String, Vec<u8>>,
+}
+
+impl Checkpoint {
+    /// Read every register in the transport's register map that passes `filter`
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn capture<T, F>(transport: &mut T, mut filter: F) -> TransportResult<Self>
+    where
+        T: Transport,
+        F: FnMut(&str, &Register) -> bool,
+    {
+        let mut registers = BTreeMap::new();
+        for (name, reg) in transport.listdev()? {
+            if !filter(&name, &reg) {
+                continue;
+            }
+            let data = transport.read_n_bytes(&name, 0, reg.length)?;
+            registers.insert(name.to_string(), data);
+        }
+        Ok(Self { registers })
+    }
+
+    /// Compare the checkpoint against the board without writing anything, returning the registers
+    /// that differ in name order
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn diff<T>(&self, transport: &mut T) -> TransportResult<Vec<RegisterDiff>>
+    where
+        T: Transport,
+    {
+        let mut diffs = vec![];
+        for (name, saved) in &self.registers {
+            let current = transport.read_n_bytes(name, 0, saved.len())?;
+            if &current != saved {
+                diffs.push(RegisterDiff {
+                    name: name.clone(),
+                    current,
+                    saved: saved.clone(),
+                });
+            }
+        }
+        Ok(diffs)
+    }
+
+    /// Write back every register that differs from the checkpoint, verifying each write with a
+    /// readback (retrying up to `retries` times), and return what was changed
+    /// # Errors
+    /// Returns an error on bad transport or if a register never read back as its saved value
+    pub fn restore<T>(
+        &self,
+        transport: &mut T,
+        retries: usize,
+    ) -> TransportResult<Vec<RegisterDiff>>
+    where
+        T: Transport,
+    {
+        let diffs = self.diff(transport)?;
+        for diff in &diffs {
+            transport.verified_write_bytes(&diff.name, 0, &diff.saved, retries)?;
+        }
+        Ok(diffs)
+    }
+
+    /// Write the checkpoint as text to `writer`
+    /// # Errors
+    /// Returns an error on IO failures
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        for (name, data) in &self.registers {
+            writeln!(writer, "{name} {}", to_hex(data))?;
+        }
+        Ok(())
+    }
+
+    /// Read a checkpoint from text written by [`Checkpoint::write_to`]
+    /// # Errors
+    /// Returns an error on IO failures or malformed lines
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut registers = BTreeMap::new();
+        for record in records(reader) {
+            let (line, record) = record?;
+            let parse_err = |reason: &str| Error::Parse {
+                line,
+                reason: reason.to_string(),
+            };
+            let (name, hex) = record
+                .split_once(' ')
+                .ok_or_else(|| parse_err("expected `<name> <hex bytes>`"))?;
+            let data = from_hex(hex.trim()).map_err(|e| parse_err(&e.to_string()))?;
+            registers.insert(name.to_string(), data);
+        }
+        Ok(Self { registers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::Mock;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_checkpoint_restore() {
+        let mut transport = Mock::new(HashMap::from([
+            ("gain".into(), Register { addr: 0, length: 4 }),
+            ("sel".into(), Register { addr: 4, length: 4 }),
+            ("status".into(), Register { addr: 8, length: 4 }),
+        ]));
+        transport.write("gain", 0, &10u32).unwrap();
+        transport.write("sel", 0, &2u32).unwrap();
+        let checkpoint = Checkpoint::capture(&mut transport, |name, _| name != "status").unwrap();
+        assert_eq!(checkpoint.registers.len(), 2);
+
+        // Round trip through the text format
+        let mut text = vec![];
+        checkpoint.write_to(&mut text).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&text).unwrap(),
+            "gain 0000000a\nsel 00000002\n"
+        );
+        let checkpoint = Checkpoint::read_from(text.as_slice()).unwrap();
+
+        transport.write("gain", 0, &99u32).unwrap();
+        transport.write("status", 0, &1u32).unwrap();
+        let diffs = checkpoint.diff(&mut transport).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].name, "gain");
+        assert_eq!(diffs[0].current, vec![0, 0, 0, 99]);
+        // A dry run doesn't touch anything
+        assert_eq!(transport.read::<u32, 4>("gain", 0).unwrap(), 99);
+
+        assert_eq!(checkpoint.restore(&mut transport, 0).unwrap(), diffs);
+        assert_eq!(transport.read::<u32, 4>("gain", 0).unwrap(), 10);
+        assert_eq!(transport.read::<u32, 4>("status", 0).unwrap(), 1);
+        assert!(checkpoint.diff(&mut transport).unwrap().is_empty());
+
+        assert!(matches!(
+            Checkpoint::read_from("gain 0a0\n".as_bytes()),
+            Err(Error::Parse { line: 1, .. })
+        ));
+    }
+}
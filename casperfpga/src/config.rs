@@ -0,0 +1,578 @@
+//! Named connection profiles, so tools can take a profile name instead of raw addresses
+//!
+//! A [`Profile`] is the host, platform, retries, timeouts, and design of one board. Profiles are
+//! read from a TOML file, with one table per profile:
+//!
+//! ```toml
+//! # The lab SNAPs
+//! [profiles.snap03]
+//! host = "192.168.0.3"
+//! platform = "snap"
+//! retries = 5
+//! timeout = 0.5
+//! fpg = "gateware/grex.fpg"
+//! ```
+//!
+//! or from environment variables named `CASPERFPGA_PROFILE_<NAME>_<KEY>`, i.e.
+//! `CASPERFPGA_PROFILE_SNAP03_HOST=192.168.0.3` (the name is lowercased). [`Profiles::from_env`]
+//! reads the file at `CASPERFPGA_CONFIG` (if set) and then applies the variables on top, key by
+//! key, which is usually all a tool needs to do before [`Profile::connect`].
+//!
+//! The `platform` is one of `snap` or `snap2` (over TAPCP, needing the `tapcp` feature) or `uio`
+//! (needing the `uio` feature). A host without a port uses the TAPCP port, and timeouts are in
+//! seconds.
+#[cfg(feature = "tapcp")]
+use crate::transport::tapcp::{
+    Platform,
+    RetryPolicy,
+    Tapcp,
+    TAPCP_PORT,
+};
+#[cfg(all(target_os = "linux", feature = "uio"))]
+use crate::transport::uio::Uio;
+use crate::{
+    core::RegisterMap,
+    transport::{
+        Transport,
+        TransportResult,
+    },
+};
+use casper_utils::design_sources::FpgaDesign;
+use std::{
+    collections::BTreeMap,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+
+/// The environment variable holding the path of the profiles file
+pub const CONFIG_VAR: &str = "CASPERFPGA_CONFIG";
+/// The prefix of the environment variables defining profiles
+pub const PROFILE_VAR_PREFIX: &str = "CASPERFPGA_PROFILE_";
+/// The keys a profile can have. `flash_timeout` is before `timeout` so the environment variables
+/// are matched on the longest key.
+const KEYS: [&str; 6] = [
+    "host",
+    "platform",
+    "retries",
+    "flash_timeout",
+    "timeout",
+    "fpg",
+];
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("Line {line} of the profiles file - {msg}")]
+    Parse { line: usize, msg: String },
+    #[error("The profiles file has a `{0}` outside of the `[profiles.<name>]` tables")]
+    NotProfile(String),
+    #[error("There is no profile named `{0}`")]
+    UnknownProfile(String),
+    #[error("Profile `{profile}` has an unknown key `{key}`")]
+    UnknownKey { profile: String, key: String },
+    #[error("Profile `{profile}` is missing the `{key}` entry")]
+    MissingKey { profile: String, key: String },
+    #[error("Profile `{profile}` has a bad `{key}` of `{value}`")]
+    BadValue {
+        profile: String,
+        key: String,
+        value: String,
+    },
+    #[error("The `{platform}` platform needs casperfpga's `{feature}` feature")]
+    FeatureDisabled {
+        platform: ProfilePlatform,
+        feature: &'static str,
+    },
+}
+
+/// The kind of board a profile connects to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProfilePlatform {
+    /// A SNAP over TAPCP
+    Snap,
+    /// A SNAP2 over TAPCP
+    Snap2,
+    /// The local UIO devices of a system-on-chip platform
+    Uio,
+}
+
+impl std::fmt::Display for ProfilePlatform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Snap => "snap",
+            Self::Snap2 => "snap2",
+            Self::Uio => "uio",
+        })
+    }
+}
+
+impl std::str::FromStr for ProfilePlatform {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "snap" => Ok(Self::Snap),
+            "snap2" => Ok(Self::Snap2),
+            "uio" => Ok(Self::Uio),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How to connect to one board
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// The name of the profile
+    pub name: String,
+    /// The address (or hostname), with an optional port
+    pub host: Option<String>,
+    pub platform: ProfilePlatform,
+    /// The number of attempts of every operation, the transport's default if `None`
+    pub retries: Option<usize>,
+    /// The per-attempt timeout of register operations, the transport's default if `None`
+    pub timeout: Option<Duration>,
+    /// The per-attempt timeout of flash writes, the transport's default if `None`
+    pub flash_timeout: Option<Duration>,
+    /// The design the board is meant to run
+    pub fpg: Option<PathBuf>,
+}
+
+/// A transport built from a [`Profile`], forwarding to the transport of its platform
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Connection {
+    #[cfg(feature = "tapcp")]
+    Tapcp(Tapcp),
+    #[cfg(all(target_os = "linux", feature = "uio"))]
+    Uio(Uio),
+}
+
+impl Profile {
+    /// Build the profile `name` from its raw string entries
+    fn from_entries(name: &str, entries: &BTreeMap<String, String>) -> Result<Self, Error> {
+        let bad = |key: &str, value: &str| Error::BadValue {
+            profile: name.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        if let Some(key) = entries.keys().find(|k| !KEYS.contains(&k.as_str())) {
+            return Err(Error::UnknownKey {
+                profile: name.to_string(),
+                key: key.clone(),
+            });
+        }
+        let seconds = |key: &str| {
+            entries
+                .get(key)
+                .map(|v| {
+                    v.parse()
+                        .ok()
+                        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                        .ok_or_else(|| bad(key, v))
+                })
+                .transpose()
+        };
+        let platform = entries.get("platform").ok_or_else(|| Error::MissingKey {
+            profile: name.to_string(),
+            key: "platform".to_string(),
+        })?;
+        Ok(Self {
+            name: name.to_string(),
+            host: entries.get("host").cloned(),
+            platform: platform.parse().map_err(|()| bad("platform", platform))?,
+            retries: entries
+                .get("retries")
+                .map(|v| match v.parse() {
+                    Ok(n) if n > 0 => Ok(n),
+                    _ => Err(bad("retries", v)),
+                })
+                .transpose()?,
+            timeout: seconds("timeout")?,
+            flash_timeout: seconds("flash_timeout")?,
+            fpg: entries.get("fpg").map(PathBuf::from),
+        })
+    }
+
+    /// The host of the profile, or an error if it has none
+    /// # Errors
+    /// Returns [`Error::MissingKey`] if there's no host
+    pub fn host(&self) -> Result<&str, Error> {
+        self.host.as_deref().ok_or_else(|| Error::MissingKey {
+            profile: self.name.clone(),
+            key: "host".to_string(),
+        })
+    }
+
+    /// Connect to the board over TAPCP with the retries and timeouts of the profile
+    /// # Errors
+    /// Returns an error if the profile isn't for a TAPCP platform, has no (resolvable) host, or
+    /// the connection fails
+    #[cfg(feature = "tapcp")]
+    pub fn connect_tapcp(&self) -> Result<Tapcp, Error> {
+        use std::net::{
+            SocketAddr,
+            ToSocketAddrs,
+        };
+        let platform = match self.platform {
+            ProfilePlatform::Snap => Platform::SNAP,
+            ProfilePlatform::Snap2 => Platform::SNAP2,
+            ProfilePlatform::Uio => {
+                return Err(Error::BadValue {
+                    profile: self.name.clone(),
+                    key: "platform".to_string(),
+                    value: self.platform.to_string(),
+                })
+            }
+        };
+        let host = self.host()?;
+        let addr = match host.parse::<SocketAddr>() {
+            Ok(addr) => Some(addr),
+            Err(_) => (host, TAPCP_PORT).to_socket_addrs()?.next(),
+        }
+        .ok_or_else(|| Error::BadValue {
+            profile: self.name.clone(),
+            key: "host".to_string(),
+            value: host.to_string(),
+        })?;
+        let mut tapcp = Tapcp::connect(addr, platform)?;
+        if let Some(retries) = self.retries {
+            tapcp.set_retry_policy(RetryPolicy {
+                attempts: retries,
+                ..*tapcp.retry_policy()
+            });
+        }
+        if let Some(timeout) = self.timeout {
+            tapcp.set_timeout(timeout)?;
+        }
+        if let Some(timeout) = self.flash_timeout {
+            tapcp.set_flash_timeout(timeout);
+        }
+        Ok(tapcp)
+    }
+
+    /// Connect to the board with the transport of its platform
+    /// # Errors
+    /// Returns an error if the platform's feature isn't enabled, the profile is missing entries
+    /// the platform needs, or the connection fails
+    pub fn connect(&self) -> Result<Connection, Error> {
+        match self.platform {
+            #[cfg(feature = "tapcp")]
+            ProfilePlatform::Snap | ProfilePlatform::Snap2 => {
+                Ok(Connection::Tapcp(self.connect_tapcp()?))
+            }
+            #[cfg(not(feature = "tapcp"))]
+            platform @ (ProfilePlatform::Snap | ProfilePlatform::Snap2) => {
+                Err(Error::FeatureDisabled {
+                    platform,
+                    feature: "tapcp",
+                })
+            }
+            #[cfg(all(target_os = "linux", feature = "uio"))]
+            ProfilePlatform::Uio => Ok(Connection::Uio(
+                Uio::new().map_err(crate::transport::Error::from)?,
+            )),
+            #[cfg(not(all(target_os = "linux", feature = "uio")))]
+            platform @ ProfilePlatform::Uio => Err(Error::FeatureDisabled {
+                platform,
+                feature: "uio",
+            }),
+        }
+    }
+}
+
+/// A set of named profiles, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profiles {
+    /// The raw entries of every profile, by name
+    entries: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Profiles {
+    /// Parse profiles from the contents of a TOML file
+    /// # Errors
+    /// Returns an error on malformed TOML, anything outside of the `[profiles.<name>]` tables, or
+    /// entries that aren't strings or numbers
+    pub fn from_toml(contents: &str) -> Result<Self, Error> {
+        let table: toml::Table = contents.parse().map_err(|e: toml::de::Error| {
+            let offset = e.span().map_or(0, |span| span.start);
+            Error::Parse {
+                line: contents[..offset].matches('\n').count() + 1,
+                msg: e.message().to_string(),
+            }
+        })?;
+        let mut profiles = Self::default();
+        for (key, value) in table {
+            let tables = match value {
+                toml::Value::Table(tables) if key == "profiles" => tables,
+                _ => return Err(Error::NotProfile(key)),
+            };
+            for (name, profile) in tables {
+                let toml::Value::Table(profile) = profile else {
+                    return Err(Error::NotProfile(format!("profiles.{name}")));
+                };
+                let entries = profile
+                    .into_iter()
+                    .map(|(key, value)| match value {
+                        toml::Value::String(s) => Ok((key, s)),
+                        toml::Value::Integer(n) => Ok((key, n.to_string())),
+                        toml::Value::Float(x) => Ok((key, x.to_string())),
+                        other => Err(Error::BadValue {
+                            profile: name.clone(),
+                            key,
+                            value: other.to_string(),
+                        }),
+                    })
+                    .collect::<Result<_, _>>()?;
+                profiles.entries.insert(name, entries);
+            }
+        }
+        Ok(profiles)
+    }
+
+    /// Read profiles from the TOML file at `path`
+    /// # Errors
+    /// Returns an error if the file can't be read or parsed
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Collect profiles from `CASPERFPGA_PROFILE_<NAME>_<KEY>` variables, ignoring all others
+    pub fn from_vars<I, K, V>(vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let mut profiles = Self::default();
+        for (var, value) in vars {
+            let Some(rest) = var.as_ref().strip_prefix(PROFILE_VAR_PREFIX) else {
+                continue;
+            };
+            let rest = rest.to_lowercase();
+            let found = KEYS.iter().find_map(|key| {
+                let name = rest.strip_suffix(key)?.strip_suffix('_')?;
+                (!name.is_empty()).then_some((name, key))
+            });
+            if let Some((name, key)) = found {
+                profiles
+                    .entries
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert((*key).to_string(), value.into());
+            }
+        }
+        profiles
+    }
+
+    /// The profiles of the file at `CASPERFPGA_CONFIG` (if it's set), overridden by the profile
+    /// environment variables
+    /// # Errors
+    /// Returns an error if the file can't be read or parsed
+    pub fn from_env() -> Result<Self, Error> {
+        let mut profiles = match std::env::var_os(CONFIG_VAR) {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        profiles.merge(Self::from_vars(std::env::vars()));
+        Ok(profiles)
+    }
+
+    /// Add the profiles of `other`, its entries replacing any of the same profile and key
+    pub fn merge(&mut self, other: Self) {
+        for (name, entries) in other.entries {
+            self.entries.entry(name).or_default().extend(entries);
+        }
+    }
+
+    /// The names of every profile
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Get the profile `name`
+    /// # Errors
+    /// Returns an error if there's no such profile or its entries are bad
+    pub fn get(&self, name: &str) -> Result<Profile, Error> {
+        let entries = self
+            .entries
+            .get(name)
+            .ok_or_else(|| Error::UnknownProfile(name.to_string()))?;
+        Profile::from_entries(name, entries)
+    }
+}
+
+/// Forward to the transport of the connection
+macro_rules! dispatch {
+    ($self:ident, $t:ident => $e:expr) => {
+        match *$self {
+            #[cfg(feature = "tapcp")]
+            Connection::Tapcp(ref mut $t) => $e,
+            #[cfg(all(target_os = "linux", feature = "uio"))]
+            Connection::Uio(ref mut $t) => $e,
+        }
+    };
+}
+
+// Without any transport features there are no connections to forward to
+#[cfg_attr(
+    not(any(feature = "tapcp", all(target_os = "linux", feature = "uio"))),
+    allow(unused_variables)
+)]
+impl Transport for Connection {
+    fn is_running(&mut self) -> TransportResult<bool> {
+        dispatch!(self, t => t.is_running())
+    }
+
+    fn read_n_bytes(&mut self, device: &str, offset: usize, n: usize) -> TransportResult<Vec<u8>> {
+        dispatch!(self, t => t.read_n_bytes(device, offset, n))
+    }
+
+    fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+        dispatch!(self, t => t.write_bytes(device, offset, data))
+    }
+
+    fn listdev(&mut self) -> TransportResult<RegisterMap> {
+        dispatch!(self, t => t.listdev())
+    }
+
+    fn program<D>(&mut self, design: &D, force: bool) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        dispatch!(self, t => t.program(design, force))
+    }
+
+    fn program_cancellable<D>(
+        &mut self,
+        design: &D,
+        force: bool,
+        cancel: &crate::transport::CancelToken,
+    ) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        dispatch!(self, t => t.program_cancellable(design, force, cancel))
+    }
+
+    fn deprogram(&mut self) -> TransportResult<()> {
+        dispatch!(self, t => t.deprogram())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+# The lab boards
+[profiles.snap03]
+host = "192.168.0.3" # the one by the door
+platform = "snap"
+retries = 3
+timeout = 0.25
+fpg = "gateware/grex#2.fpg"
+
+[profiles."soc"]
+platform = "uio"
+"#;
+
+    #[test]
+    fn test_profiles() {
+        let mut profiles = Profiles::from_toml(PROFILES).unwrap();
+        assert_eq!(profiles.names().collect::<Vec<_>>(), vec!["snap03", "soc"]);
+        let snap03 = profiles.get("snap03").unwrap();
+        assert_eq!(
+            snap03,
+            Profile {
+                name: "snap03".to_string(),
+                host: Some("192.168.0.3".to_string()),
+                platform: ProfilePlatform::Snap,
+                retries: Some(3),
+                timeout: Some(Duration::from_millis(250)),
+                flash_timeout: None,
+                fpg: Some("gateware/grex#2.fpg".into()),
+            }
+        );
+        let soc = profiles.get("soc").unwrap();
+        assert_eq!(soc.platform, ProfilePlatform::Uio);
+        assert!(matches!(soc.host(), Err(Error::MissingKey { .. })));
+        assert!(matches!(
+            profiles.get("snap04"),
+            Err(Error::UnknownProfile(_))
+        ));
+
+        // Environment variables override (and add) entries key by key
+        profiles.merge(Profiles::from_vars([
+            ("CASPERFPGA_PROFILE_SNAP03_FLASH_TIMEOUT", "2"),
+            ("CASPERFPGA_PROFILE_SNAP03_HOST", "snap03.lab:6969"),
+            ("CASPERFPGA_PROFILE_NEW_BOARD_PLATFORM", "SNAP2"),
+            ("CASPERFPGA_PROFILE_HOST", "nameless"),
+            ("HOME", "/root"),
+        ]));
+        let snap03 = profiles.get("snap03").unwrap();
+        assert_eq!(snap03.host.as_deref(), Some("snap03.lab:6969"));
+        assert_eq!(snap03.flash_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(snap03.retries, Some(3));
+        assert_eq!(
+            profiles.get("new_board").unwrap().platform,
+            ProfilePlatform::Snap2
+        );
+        assert_eq!(profiles.names().count(), 3);
+    }
+
+    #[test]
+    fn test_bad_profiles() {
+        let parse = |s: &str| Profiles::from_toml(s).and_then(|p| p.get("a"));
+        assert!(matches!(
+            parse("host = \"x\""),
+            Err(Error::NotProfile(key)) if key == "host"
+        ));
+        assert!(matches!(
+            parse("[profiles.a]\nplatform = snap"),
+            Err(Error::Parse { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse("[profiles.a]\nplatform = \"snap\"\n[profiles.a]"),
+            Err(Error::Parse { line: 3, .. })
+        ));
+        assert!(matches!(
+            parse("[boards.a]"),
+            Err(Error::NotProfile(key)) if key == "boards"
+        ));
+        assert!(matches!(
+            parse("[profiles]\na = 1"),
+            Err(Error::NotProfile(key)) if key == "profiles.a"
+        ));
+        assert!(matches!(
+            parse("[profiles.a]\nplatform = \"snap\"\nretries = true"),
+            Err(Error::BadValue { .. })
+        ));
+        assert!(matches!(
+            parse("[profiles.a]\nhost = \"x\""),
+            Err(Error::MissingKey { .. })
+        ));
+        assert!(matches!(
+            parse("[profiles.a]\nplatform = \"roach\""),
+            Err(Error::BadValue { .. })
+        ));
+        assert!(matches!(
+            parse("[profiles.a]\nplatform = \"snap\"\nretries = 0"),
+            Err(Error::BadValue { .. })
+        ));
+        assert!(matches!(
+            parse("[profiles.a]\nplatform = \"snap\"\ntimeout = -1"),
+            Err(Error::BadValue { .. })
+        ));
+        assert!(matches!(
+            parse("[profiles.a]\nplatform = \"snap\"\nport = 69"),
+            Err(Error::UnknownKey { .. })
+        ));
+    }
+}
@@ -1,10 +1,17 @@
 //! The core types and functions for interacting with casperfpga objects
-use crate::transport::Transport;
+use crate::transport::{
+    Deserialize,
+    Transport,
+};
+use casper_utils::design_sources::mangle_name;
+pub use casper_utils::design_sources::Access;
 use kstring::KString;
 use std::{
     collections::HashMap,
+    fmt::Debug,
     time::{
         Duration,
+        Instant,
         SystemTime,
     },
 };
@@ -22,13 +29,234 @@ pub struct Register {
 /// The mapping from register names and their data (address and size)
 pub type RegisterMap = HashMap<KString, Register>;
 
+/// The mapping from register names to what clients may do with them
+pub type AccessMap = HashMap<KString, Access>;
+
+/// The fingerprint of a register map, i.e. from `listdev`, which matches
+/// [`FpgaDesign::register_fingerprint`](casper_utils::design_sources::FpgaDesign::register_fingerprint)
+/// of the design that produced it
+#[must_use]
+pub fn register_fingerprint(registers: &RegisterMap) -> String {
+    casper_utils::digest::register_fingerprint(
+        registers
+            .iter()
+            .map(|(name, reg)| (name.as_str(), reg.addr as u64, reg.length as u64)),
+    )
+}
+
+/// The register name prefix of a (potentially composite) block, used to build the names of its
+/// child registers consistently.
+/// # Example
+/// ```
+/// # use casperfpga::core::RegisterNamespace;
+/// let snap = RegisterNamespace::new("adc/snap");
+/// assert_eq!(snap.reg("ctrl"), "adc_snap_ctrl");
+/// assert_eq!(snap.sub("trig").reg("offset"), "adc_snap_trig_offset");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RegisterNamespace {
+    prefix: String,
+}
+
+impl RegisterNamespace {
+    /// Create a namespace from a block name or nested Simulink path
+    #[must_use]
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: mangle_name(prefix),
+        }
+    }
+
+    /// The (mangled) prefix of this namespace, which is also the name of the block's own register
+    /// if it has one
+    #[must_use]
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The full register name of the child `name` of this block
+    #[must_use]
+    pub fn reg(&self, name: &str) -> String {
+        let name = mangle_name(name);
+        if self.prefix.is_empty() {
+            name
+        } else {
+            format!("{}_{name}", self.prefix)
+        }
+    }
+
+    /// A namespace for the nested block `name`
+    #[must_use]
+    pub fn sub(&self, name: &str) -> Self {
+        Self {
+            prefix: self.reg(name),
+        }
+    }
+}
+
+impl std::fmt::Display for RegisterNamespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.prefix)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {}
 
+#[derive(Debug, Error)]
+/// Errors from waiting on a register to reach some state
+pub enum WaitError<T: Debug> {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("Timed out after {elapsed:?} waiting on `{device}`, last value read was {last:?}")]
+    Timeout {
+        device: String,
+        elapsed: Duration,
+        last: T,
+    },
+}
+
+impl<T: Debug> WaitError<T> {
+    /// The error for polling `device` having run out of time
+    #[must_use]
+    pub fn timeout(device: &str, t: PollTimeout<T>) -> Self {
+        Self::Timeout {
+            device: device.to_string(),
+            elapsed: t.elapsed,
+            last: t.last,
+        }
+    }
+}
+
+/// A poll that ran out of time
+#[derive(Debug)]
+pub struct PollTimeout<T> {
+    /// How long was spent polling
+    pub elapsed: Duration,
+    /// The last value read
+    pub last: T,
+}
+
+/// Call `read` every `poll_interval` until the value it returns satisfies `predicate`, returning
+/// the value that did. The timeout is checked after every read, and `read` is expected to only
+/// hold the transport for as long as it needs to so others can use it in between polls.
+/// # Errors
+/// Returns the first error from `read`, otherwise the inner result is a [`PollTimeout`] (with the
+/// last value read) if the predicate wasn't satisfied within `timeout`
+pub fn poll<T, E, R, P>(
+    mut read: R,
+    mut predicate: P,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<Result<T, PollTimeout<T>>, E>
+where
+    R: FnMut() -> Result<T, E>,
+    P: FnMut(&T) -> bool,
+{
+    let start = Instant::now();
+    loop {
+        let val = read()?;
+        if predicate(&val) {
+            return Ok(Ok(val));
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return Ok(Err(PollTimeout { elapsed, last: val }));
+        }
+        std::thread::sleep(poll_interval.min(timeout.saturating_sub(elapsed)));
+    }
+}
+
+/// Poll `device` at `offset` every `poll_interval` until the value read satisfies `predicate`,
+/// returning the value that did.
+/// # Errors
+/// Returns an error on bad transport or [`WaitError::Timeout`] (with the last value read) if the
+/// predicate wasn't satisfied within `timeout`
+pub fn wait_for<T, U, P, const N: usize>(
+    transport: &mut U,
+    device: &str,
+    offset: usize,
+    predicate: P,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<T, WaitError<T>>
+where
+    U: Transport,
+    T: Deserialize<Chunk = [u8; N]> + Debug,
+    P: FnMut(&T) -> bool,
+    crate::transport::Error: std::convert::From<<T as Deserialize>::Error>,
+{
+    poll(
+        || transport.read(device, offset),
+        predicate,
+        timeout,
+        poll_interval,
+    )?
+    .map_err(|t| WaitError::timeout(device, t))
+}
+
+/// Poll the 32-bit register `device` until bit `bit` (zero being the LSB) reads as `state`.
+/// # Errors
+/// Returns an error on bad transport or [`WaitError::Timeout`] if the bit never reached `state`
+pub fn wait_for_bit<U>(
+    transport: &mut U,
+    device: &str,
+    bit: u32,
+    state: bool,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<u32, WaitError<u32>>
+where
+    U: Transport,
+{
+    wait_for(
+        transport,
+        device,
+        0,
+        |v: &u32| ((v >> bit) & 1 == 1) == state,
+        timeout,
+        poll_interval,
+    )
+}
+
+/// The number of ticks from `first` to `second` of a free-running 32-bit counter, assuming it
+/// wrapped at most once in between
+#[must_use]
+pub fn counter_delta(first: u32, second: u32) -> u32 {
+    second.wrapping_sub(first)
+}
+
+/// Read a 64-bit counter split across the two 32-bit registers `msb` and `lsb`, i.e. the sample
+/// counters of timekeeping and packetizer blocks.
+///
+/// The halves can't be read atomically, so if the low word rolls over between the two reads the
+/// naive result is off by 2^32. The high word is read again afterwards and, if it changed, the low
+/// word is read again to match, so the value is always one the counter actually held during the
+/// call.
+/// # Errors
+/// Returns an error on bad transport
+pub fn read_u64_counter<T>(
+    transport: &mut T,
+    msb: &str,
+    lsb: &str,
+) -> Result<u64, crate::transport::Error>
+where
+    T: Transport,
+{
+    let high = transport.read::<u32, 4>(msb, 0)?;
+    let low = transport.read::<u32, 4>(lsb, 0)?;
+    let high_again = transport.read::<u32, 4>(msb, 0)?;
+    let low = if high == high_again {
+        low
+    } else {
+        transport.read::<u32, 4>(lsb, 0)?
+    };
+    Ok(u64::from(high_again) << 32 | u64::from(low))
+}
+
 /// Read the `sys_clkcounter` register a few times to estimate the clock rate in megahertz
 /// # Errors
 /// Returns an error on bad transport
-#[allow(clippy::cast_precision_loss)]
 #[allow(clippy::missing_panics_doc)]
 pub fn estimate_fpga_clock<T>(transport: &mut T) -> Result<f64, crate::transport::Error>
 where
@@ -36,16 +264,162 @@ where
 {
     let delay_s = 2f64;
     let earlier = SystemTime::now();
-    let first_count = u64::from(transport.read::<u32, 4>("sys_clkcounter", 0)?);
+    let first_count = transport.read::<u32, 4>("sys_clkcounter", 0)?;
     let later = SystemTime::now();
     std::thread::sleep(Duration::from_secs_f64(delay_s));
-    let mut second_count = u64::from(transport.read::<u32, 4>("sys_clkcounter", 0)?);
-    if first_count > second_count {
-        second_count += 2u64.pow(32);
-    }
+    let second_count = transport.read::<u32, 4>("sys_clkcounter", 0)?;
+    let ticks = counter_delta(first_count, second_count);
     let transport_elapsed = later
         .duration_since(earlier)
         .expect("Earlier and later are not properly ordered");
     let transport_delay = transport_elapsed.as_secs_f64();
-    Ok((second_count - first_count) as f64 / ((delay_s - transport_delay) * 1_000_000_f64))
+    Ok(f64::from(ticks) / ((delay_s - transport_delay) * 1_000_000_f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::Mock;
+
+    fn scratchpad() -> Mock {
+        Mock::new(HashMap::from([(
+            "sys_scratchpad".into(),
+            Register { addr: 0, length: 4 },
+        )]))
+    }
+
+    #[test]
+    fn test_wait_for_satisfied() {
+        let mut transport = scratchpad();
+        transport.write("sys_scratchpad", 0, &0b100u32).unwrap();
+        let v = wait_for_bit(
+            &mut transport,
+            "sys_scratchpad",
+            2,
+            true,
+            Duration::from_millis(10),
+            Duration::from_millis(1),
+        )
+        .unwrap();
+        assert_eq!(v, 0b100);
+    }
+
+    #[test]
+    fn test_wait_for_timeout() {
+        let mut transport = scratchpad();
+        transport.write("sys_scratchpad", 0, &7u32).unwrap();
+        match wait_for(
+            &mut transport,
+            "sys_scratchpad",
+            0,
+            |v: &u32| *v > 10,
+            Duration::from_millis(10),
+            Duration::from_millis(2),
+        ) {
+            Err(WaitError::Timeout { last, elapsed, .. }) => {
+                assert_eq!(last, 7);
+                assert!(elapsed >= Duration::from_millis(10));
+            }
+            r => panic!("Expected a timeout, got {r:?}"),
+        }
+    }
+
+    /// A 64-bit counter that ticks `step` on every read, exposed as `msb` and `lsb` registers
+    struct Ticking {
+        count: u64,
+        step: u64,
+    }
+
+    impl Transport for Ticking {
+        fn is_running(&mut self) -> crate::transport::TransportResult<bool> {
+            Ok(true)
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        fn read_n_bytes(
+            &mut self,
+            device: &str,
+            _offset: usize,
+            _n: usize,
+        ) -> crate::transport::TransportResult<Vec<u8>> {
+            self.count += self.step;
+            let word = match device {
+                "msb" => (self.count >> 32) as u32,
+                "lsb" => self.count as u32,
+                _ => return Err(crate::transport::Error::DeviceNotFound(device.to_string())),
+            };
+            Ok(word.to_be_bytes().to_vec())
+        }
+
+        fn write_bytes(
+            &mut self,
+            _device: &str,
+            _offset: usize,
+            _data: &[u8],
+        ) -> crate::transport::TransportResult<()> {
+            Ok(())
+        }
+
+        fn listdev(&mut self) -> crate::transport::TransportResult<RegisterMap> {
+            Ok(HashMap::new())
+        }
+
+        fn program<D>(&mut self, _design: &D, _force: bool) -> crate::transport::TransportResult<()>
+        where
+            D: casper_utils::design_sources::FpgaDesign,
+        {
+            Ok(())
+        }
+
+        fn deprogram(&mut self) -> crate::transport::TransportResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_u64_counter() {
+        // No rollover
+        let mut transport = Ticking {
+            count: 0x0000_0005_0000_0000,
+            step: 1,
+        };
+        assert_eq!(
+            read_u64_counter(&mut transport, "msb", "lsb").unwrap(),
+            0x0000_0005_0000_0002
+        );
+        // The low word rolls over between reading the two halves, which naively reads as
+        // 0x0000_0000_0000_0000
+        let start = 0xFFFF_FFFE;
+        let mut transport = Ticking {
+            count: start,
+            step: 1,
+        };
+        let value = read_u64_counter(&mut transport, "msb", "lsb").unwrap();
+        assert!(value > start && value <= transport.count);
+        assert_eq!(value, 0x0000_0001_0000_0002);
+        // Rolling over right before the first read is consistent from the start
+        let mut transport = Ticking {
+            count: 0x0000_0001_FFFF_FFFF,
+            step: 1,
+        };
+        assert_eq!(
+            read_u64_counter(&mut transport, "msb", "lsb").unwrap(),
+            0x0000_0002_0000_0001
+        );
+    }
+
+    #[test]
+    fn test_counter_delta() {
+        assert_eq!(counter_delta(10, 25), 15);
+        assert_eq!(counter_delta(u32::MAX - 1, 3), 5);
+    }
+
+    #[test]
+    fn test_register_namespace() {
+        let ns = RegisterNamespace::new("pfb");
+        assert_eq!(ns.prefix(), "pfb");
+        assert_eq!(ns.reg("fft/shift"), "pfb_fft_shift");
+        assert_eq!(ns.sub("fft").reg("of_cnt"), "pfb_fft_of_cnt");
+        assert_eq!(RegisterNamespace::new("").reg("acc_len"), "acc_len");
+    }
 }
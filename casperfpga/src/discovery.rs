@@ -0,0 +1,177 @@
+//! Discovery of TAPCP-capable boards on the local network
+//!
+//! Every host in a subnet is probed with a lightweight `/help` request. Hosts that respond are
+//! then queried for their temperature and, if a [`Platform`] is given, the md5 of the design
+//! stored in their flash metadata.
+use crate::transport::tapcp::Platform;
+use std::{
+    net::{
+        Ipv4Addr,
+        SocketAddr,
+        SocketAddrV4,
+        UdpSocket,
+    },
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Mutex,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Internal system IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Subnet prefix length {0} is larger than 32")]
+    BadPrefix(u8),
+}
+
+/// Options controlling how a subnet is probed
+#[derive(Debug, Clone, Copy)]
+pub struct DiscoveryConfig {
+    /// The remote TAPCP (TFTP) port
+    pub port: u16,
+    /// How long to wait on each host before giving up
+    pub timeout: Duration,
+    /// Number of hosts to probe at once
+    pub parallelism: usize,
+    /// If set, the flash metadata location of this platform is used to read the programmed md5
+    pub platform: Option<Platform>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            port: 69,
+            timeout: Duration::from_millis(200),
+            parallelism: 32,
+            platform: None,
+        }
+    }
+}
+
+/// A board that responded to a TAPCP probe
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredBoard {
+    /// The address the board responded on
+    pub addr: SocketAddr,
+    /// The board temperature in Celsius, if it could be read
+    pub temperature: Option<f32>,
+    /// The md5 of the programmed design from the flash metadata, if it could be read
+    pub md5: Option<String>,
+}
+
+// Retries are counted inclusively by the tapcp layer, so 1 is a single attempt
+const PROBE_RETRIES: usize = 1;
+
+/// Probe a single address for a TAPCP responder, returning `None` if nothing answered
+/// # Errors
+/// Returns an error if we couldn't set up the local socket
+pub fn probe(addr: SocketAddr, config: &DiscoveryConfig) -> Result<Option<DiscoveredBoard>, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(config.timeout))?;
+    socket.set_write_timeout(Some(config.timeout))?;
+    socket.connect(addr)?;
+    // Anything that doesn't answer `/help` isn't a TAPCP server
+    if tapcp::help(&socket, PROBE_RETRIES).is_err() {
+        return Ok(None);
+    }
+    let temperature = tapcp::temp(&socket, PROBE_RETRIES).ok();
+    let md5 = config.platform.and_then(|platform| {
+        tapcp::get_metadata(&socket, platform.flash_location(), PROBE_RETRIES)
+            .ok()
+            .and_then(|mut meta| meta.remove("md5"))
+    });
+    Ok(Some(DiscoveredBoard {
+        addr,
+        temperature,
+        md5,
+    }))
+}
+
+/// Enumerate the host addresses of the subnet `network/prefix`, skipping the network and
+/// broadcast addresses where they exist
+/// # Errors
+/// Returns an error if the prefix is invalid
+pub fn subnet_hosts(network: Ipv4Addr, prefix: u8) -> Result<Vec<Ipv4Addr>, Error> {
+    if prefix > 32 {
+        return Err(Error::BadPrefix(prefix));
+    }
+    let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+    let base = u32::from(network) & mask;
+    let last = base | !mask;
+    Ok(match prefix {
+        // Point-to-point and single host subnets have no network or broadcast address
+        31 | 32 => (base..=last).map(Ipv4Addr::from).collect(),
+        _ => (base + 1..last).map(Ipv4Addr::from).collect(),
+    })
+}
+
+/// Probe every host in the subnet `network/prefix` for TAPCP responders, returning the boards that
+/// answered sorted by address
+/// # Errors
+/// Returns an error on an invalid subnet or if local sockets couldn't be created
+#[allow(clippy::missing_panics_doc)]
+pub fn discover(
+    network: Ipv4Addr,
+    prefix: u8,
+    config: &DiscoveryConfig,
+) -> Result<Vec<DiscoveredBoard>, Error> {
+    let hosts = subnet_hosts(network, prefix)?;
+    let next = AtomicUsize::new(0);
+    let found = Mutex::new(vec![]);
+    let workers = config.parallelism.clamp(1, hosts.len().max(1));
+    std::thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                s.spawn(|| -> Result<(), Error> {
+                    while let Some(host) = hosts.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let addr = SocketAddr::V4(SocketAddrV4::new(*host, config.port));
+                        if let Some(board) = probe(addr, config)? {
+                            found.lock().unwrap().push(board);
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|h| h.join().expect("Probe thread panicked"))
+    })?;
+    let mut found = found.into_inner().unwrap();
+    found.sort_by_key(|b| b.addr);
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet_hosts() {
+        let hosts = subnet_hosts("192.168.0.17".parse().unwrap(), 24).unwrap();
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts[0], Ipv4Addr::new(192, 168, 0, 1));
+        assert_eq!(hosts[253], Ipv4Addr::new(192, 168, 0, 254));
+        let hosts = subnet_hosts("10.0.0.5".parse().unwrap(), 32).unwrap();
+        assert_eq!(hosts, vec![Ipv4Addr::new(10, 0, 0, 5)]);
+        assert!(subnet_hosts("10.0.0.0".parse().unwrap(), 33).is_err());
+    }
+
+    #[test]
+    fn test_discover_nothing() {
+        // Nothing should be listening for TFTP on localhost
+        let config = DiscoveryConfig {
+            port: 1,
+            timeout: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let found = discover(Ipv4Addr::LOCALHOST, 32, &config).unwrap();
+        assert!(found.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+//! Archiving the whole register space of a board
+//!
+//! A [`RegisterDump`] is a read-only snapshot of every register in the register map of a transport,
+//! taken for debugging after an anomaly. Unlike a [`crate::checkpoint::Checkpoint`] it's never
+//! written back, so it keeps going past registers that fail to read, recording the error in their
+//! place, and stamps every register with the time it was read. Dumps are written as
+//! [plain text](crate::text), one register per line as `<name> <microseconds since the epoch> <hex
+//! bytes>` (or `! <error>` in place of the bytes), and two dumps can be compared with
+//! [`RegisterDump::compare`].
+//!
+//! Big memories make for big dumps, so [`dump_registers`] takes a filter, and [`readable`] builds
+//! one that skips the write-only devices of a design.
+
+use crate::{
+    core::Register,
+    text::{
+        from_hex,
+        records,
+        to_hex,
+    },
+    transport::{
+        Transport,
+        TransportResult,
+    },
+};
+use casper_utils::design_sources::Devices;
+use std::{
+    collections::BTreeMap,
+    io::{
+        BufRead,
+        Write,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Internal system IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed register dump on line {line} - {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+/// One register of a dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpedRegister {
+    /// When the register was read
+    pub time: SystemTime,
+    /// The contents of the register from offset zero, or why they couldn't be read
+    pub contents: Result<Vec<u8>, String>,
+}
+
+/// A register that differs between two dumps, `None` where a dump doesn't have it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpDiff {
+    pub name: String,
+    pub before: Option<Result<Vec<u8>, String>>,
+    pub after: Option<Result<Vec<u8>, String>>,
+}
+
+/// The contents of a board's registers, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterDump {
+    /// Register names and what was read from them
+    pub registers: BTreeMap<String, DumpedRegister>,
+}
+
+/// Read every register in the transport's register map, see [`dump_registers`]
+/// # Errors
+/// Returns an error if the register map couldn't be read
+pub fn dump_all_registers<T>(transport: &mut T) -> TransportResult<RegisterDump>
+where
+    T: Transport,
+{
+    dump_registers(transport, |_, _| true)
+}
+
+/// Read every register in the transport's register map that passes `filter`. Registers that fail
+/// to read are kept with their error.
+/// # Errors
+/// Returns an error if the register map couldn't be read
+pub fn dump_registers<T, F>(transport: &mut T, mut filter: F) -> TransportResult<RegisterDump>
+where
+    T: Transport,
+    F: FnMut(&str, &Register) -> bool,
+{
+    let mut registers = BTreeMap::new();
+    for (name, reg) in transport.listdev()? {
+        if !filter(&name, &reg) {
+            continue;
+        }
+        let contents = transport
+            .read_n_bytes(&name, 0, reg.length)
+            .map_err(|e| e.to_string());
+        registers.insert(
+            name.to_string(),
+            DumpedRegister {
+                time: SystemTime::now(),
+                contents,
+            },
+        );
+    }
+    Ok(RegisterDump { registers })
+}
+
+/// A filter for [`dump_registers`] that skips the write-only devices of `devices`, keeping
+/// registers the design doesn't describe
+pub fn readable(devices: &Devices) -> impl FnMut(&str, &Register) -> bool + '_ {
+    |name, _| {
+        devices
+            .get(name)
+            .map_or(true, |dev| dev.access().readable())
+    }
+}
+
+impl RegisterDump {
+    /// The registers whose contents (or errors) differ between this dump and a later one, in name
+    /// order. Read times are ignored.
+    #[must_use]
+    pub fn compare(&self, later: &Self) -> Vec<DumpDiff> {
+        let mut names: Vec<_> = self
+            .registers
+            .keys()
+            .chain(later.registers.keys())
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let before = self.registers.get(name).map(|r| r.contents.clone());
+                let after = later.registers.get(name).map(|r| r.contents.clone());
+                (before != after).then(|| DumpDiff {
+                    name: name.clone(),
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+
+    /// Write the dump as text to `writer`
+    /// # Errors
+    /// Returns an error on IO failures
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        for (name, reg) in &self.registers {
+            let micros = reg
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros();
+            match &reg.contents {
+                Ok(data) => writeln!(writer, "{name} {micros} {}", to_hex(data))?,
+                // Keep the error on one line
+                Err(e) => writeln!(writer, "{name} {micros} ! {}", e.replace('\n', " "))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a dump from text written by [`RegisterDump::write_to`]
+    /// # Errors
+    /// Returns an error on IO failures or malformed lines
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut registers = BTreeMap::new();
+        for record in records(reader) {
+            let (line, record) = record?;
+            let parse_err = |reason: &str| Error::Parse {
+                line,
+                reason: reason.to_string(),
+            };
+            let mut fields = record.splitn(3, ' ');
+            let (Some(name), Some(micros)) = (fields.next(), fields.next()) else {
+                return Err(parse_err("expected `<name> <time> <hex bytes>`"));
+            };
+            let micros: u64 = micros.parse().map_err(|_| parse_err("invalid time"))?;
+            let rest = fields.next().unwrap_or_default().trim();
+            let contents = if let Some(e) = rest.strip_prefix('!') {
+                Err(e.trim().to_string())
+            } else {
+                Ok(from_hex(rest).map_err(|e| parse_err(&e.to_string()))?)
+            };
+            registers.insert(
+                name.to_string(),
+                DumpedRegister {
+                    time: UNIX_EPOCH + Duration::from_micros(micros),
+                    contents,
+                },
+            );
+        }
+        Ok(Self { registers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::Mock;
+    use casper_utils::design_sources::Access;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_register_dump() {
+        let mut transport = Mock::new(HashMap::from([
+            ("count".into(), Register { addr: 0, length: 4 }),
+            ("gain".into(), Register { addr: 4, length: 4 }),
+        ]));
+        transport.write("gain", 0, &0x1234u32).unwrap();
+        let before = dump_all_registers(&mut transport).unwrap();
+        assert_eq!(
+            before.registers["gain"].contents,
+            Ok(vec![0, 0, 0x12, 0x34])
+        );
+
+        // Round trip through the text format, which keeps times to the microsecond
+        let mut text = vec![];
+        before.write_to(&mut text).unwrap();
+        let loaded = RegisterDump::read_from(text.as_slice()).unwrap();
+        assert_eq!(loaded.compare(&before), vec![]);
+        let micros = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap().as_micros();
+        assert_eq!(
+            micros(loaded.registers["count"].time),
+            micros(before.registers["count"].time)
+        );
+
+        transport.write("count", 0, &7u32).unwrap();
+        let after = dump_registers(&mut transport, |name, _| name == "count").unwrap();
+        assert_eq!(
+            before.compare(&after),
+            vec![
+                DumpDiff {
+                    name: "count".into(),
+                    before: Some(Ok(vec![0; 4])),
+                    after: Some(Ok(vec![0, 0, 0, 7])),
+                },
+                DumpDiff {
+                    name: "gain".into(),
+                    before: Some(Ok(vec![0, 0, 0x12, 0x34])),
+                    after: None,
+                },
+            ]
+        );
+
+        // Failed reads are kept with their error and survive the text format
+        transport.set_access_map(HashMap::from([("gain".into(), Access::Write)]));
+        let failed = dump_all_registers(&mut transport).unwrap();
+        assert!(failed.registers["gain"].contents.is_err());
+        let mut text = vec![];
+        failed.write_to(&mut text).unwrap();
+        let loaded = RegisterDump::read_from(text.as_slice()).unwrap();
+        assert_eq!(loaded.compare(&failed), vec![]);
+        assert!(matches!(
+            RegisterDump::read_from("gain soon 00\n".as_bytes()),
+            Err(Error::Parse { line: 1, .. })
+        ));
+    }
+}
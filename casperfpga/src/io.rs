@@ -0,0 +1,192 @@
+//! Exporting captured data (snapshot and BRAM reads) for analysis elsewhere
+//!
+//! Data is written in the numpy `.npy` format (version 1.0) so it can be loaded directly with
+//! `numpy.load`, with the dtype and shape recorded in the header.
+//!
+//! Snapshot reads are raw bytes, so they are written as `uint8` by default - reinterpret them with
+//! the right integer type and shape first (i.e. `u16` samples as `(n, 2)` for two interleaved
+//! inputs). Fixed point BRAM reads are converted to `float64` by [`write_npy_fixed`].
+use fixed::traits::Fixed;
+use std::{
+    io::Write,
+    path::Path,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Internal system IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Shape {shape:?} doesn't match the {len} elements of data")]
+    ShapeMismatch { len: usize, shape: Vec<usize> },
+}
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Element types that can be written to a `.npy` file
+pub trait NpyElement: Copy {
+    /// The numpy dtype description string, i.e. `<u2`
+    const DESCR: &'static str;
+    /// Write the element in the byte order given by [`NpyElement::DESCR`]
+    /// # Errors
+    /// Returns an error on IO failures
+    fn write_element<W: Write>(&self, writer: &mut W) -> std::io::Result<()>;
+}
+
+macro_rules! npy_element {
+    ($ty:ty, $descr:literal) => {
+        impl NpyElement for $ty {
+            const DESCR: &'static str = $descr;
+            fn write_element<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+                writer.write_all(&self.to_le_bytes())
+            }
+        }
+    };
+}
+
+npy_element!(u8, "|u1");
+npy_element!(u16, "<u2");
+npy_element!(u32, "<u4");
+npy_element!(u64, "<u8");
+npy_element!(i8, "|i1");
+npy_element!(i16, "<i2");
+npy_element!(i32, "<i4");
+npy_element!(i64, "<i8");
+npy_element!(f32, "<f4");
+npy_element!(f64, "<f8");
+
+fn header(descr: &str, shape: &[usize]) -> Vec<u8> {
+    let shape = match shape {
+        [n] => format!("({n},)"),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut dict = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+    // The magic, version, and header length take 10 bytes and the whole header (ending in a
+    // newline) is padded with spaces so the data starts on a 64 byte boundary
+    let unpadded = MAGIC.len() + 4 + dict.len() + 1;
+    dict.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    dict.push('\n');
+    let mut out = Vec::with_capacity(MAGIC.len() + 4 + dict.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&[1, 0]);
+    out.extend_from_slice(
+        &u16::try_from(dict.len())
+            .expect("npy header too long")
+            .to_le_bytes(),
+    );
+    out.extend_from_slice(dict.as_bytes());
+    out
+}
+
+fn check_shape(len: usize, shape: &[usize]) -> Result<(), Error> {
+    if shape.iter().product::<usize>() == len {
+        Ok(())
+    } else {
+        Err(Error::ShapeMismatch {
+            len,
+            shape: shape.to_vec(),
+        })
+    }
+}
+
+/// Write `data` in C (row-major) order with the given `shape` as a `.npy` file to `writer`
+/// # Errors
+/// Returns an error on IO failures or if `shape` doesn't cover exactly `data.len()` elements
+#[allow(clippy::missing_panics_doc)]
+pub fn write_npy<W, E>(writer: &mut W, data: &[E], shape: &[usize]) -> Result<(), Error>
+where
+    W: Write,
+    E: NpyElement,
+{
+    check_shape(data.len(), shape)?;
+    writer.write_all(&header(E::DESCR, shape))?;
+    for element in data {
+        element.write_element(writer)?;
+    }
+    Ok(())
+}
+
+/// Write fixed point `data` (i.e. from a BRAM read) as `float64` in a `.npy` file. The conversion
+/// is exact for types with up to 53 significant bits.
+/// # Errors
+/// Returns an error on IO failures or if `shape` doesn't cover exactly `data.len()` elements
+pub fn write_npy_fixed<W, F>(writer: &mut W, data: &[F], shape: &[usize]) -> Result<(), Error>
+where
+    W: Write,
+    F: Fixed,
+{
+    let floats: Vec<f64> = data.iter().map(|v| v.to_num()).collect();
+    write_npy(writer, &floats, shape)
+}
+
+/// Save `data` with the given `shape` to a `.npy` file at `path`
+/// # Errors
+/// Returns an error on IO failures or if `shape` doesn't cover exactly `data.len()` elements
+pub fn save_npy<P, E>(path: P, data: &[E], shape: &[usize]) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    E: NpyElement,
+{
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_npy(&mut file, data, shape)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Save fixed point `data` as `float64` to a `.npy` file at `path`, see [`write_npy_fixed`]
+/// # Errors
+/// Returns an error on IO failures or if `shape` doesn't cover exactly `data.len()` elements
+pub fn save_npy_fixed<P, F>(path: P, data: &[F], shape: &[usize]) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    F: Fixed,
+{
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_npy_fixed(&mut file, data, shape)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixed::types::I16F16;
+
+    #[test]
+    fn test_write_npy() {
+        let mut out = vec![];
+        write_npy(&mut out, &[1u16, 2, 3, 4, 5, 6], &[2, 3]).unwrap();
+        assert_eq!(&out[..6], MAGIC);
+        let header_len = u16::from_le_bytes([out[8], out[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let dict = std::str::from_utf8(&out[10..10 + header_len]).unwrap();
+        assert!(dict.starts_with("{'descr': '<u2', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(dict.ends_with('\n'));
+        assert_eq!(&out[10 + header_len..12 + header_len], &[1, 0]);
+        assert_eq!(out.len(), 10 + header_len + 12);
+    }
+
+    #[test]
+    fn test_write_npy_fixed() {
+        let mut out = vec![];
+        let data = [I16F16::from_num(-1.5), I16F16::from_num(0.25)];
+        write_npy_fixed(&mut out, &data, &[2]).unwrap();
+        let header_len = u16::from_le_bytes([out[8], out[9]]) as usize;
+        assert!(std::str::from_utf8(&out[10..10 + header_len])
+            .unwrap()
+            .contains("'descr': '<f8', 'fortran_order': False, 'shape': (2,)"));
+        let first = f64::from_le_bytes(out[10 + header_len..18 + header_len].try_into().unwrap());
+        assert!((first + 1.5).abs() < f64::EPSILON);
+        assert!(matches!(
+            write_npy(&mut vec![], &[1u8, 2, 3], &[2, 2]),
+            Err(Error::ShapeMismatch { len: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+//! Interrupt emulation on latch registers
+//!
+//! The gateware can't interrupt us, so designs latch events (an overflow, a lost packet, a PPS)
+//! into registers that software polls and then clears. An [`Irq`] manages a set of these latches:
+//! it polls them at a fixed rate, clears whatever bits it saw with the [`Clear`] of the latch, and
+//! then calls the handler registered for the latch with the latched value as an [`IrqEvent`].
+//!
+//! Latches are cleared before their handlers run, so an event that happens while a handler is busy
+//! is latched again and seen on the next poll. Clearing only ever touches the bits that were seen
+//! set.
+use crate::transport::{
+    Transport,
+    TransportResult,
+};
+use std::{
+    ops::ControlFlow,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+/// How the bits of a latch are cleared once they've been seen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Clear {
+    /// Nothing is written, the register clears itself or holds a level. Only bits that went from
+    /// zero to one since the last poll are reported.
+    Never,
+    /// Writing ones to the set bits clears them (write-one-to-clear)
+    WriteOne,
+    /// Writing zeros to the set bits clears them, keeping every other bit of the word
+    WriteZero,
+    /// The set bits are pulsed high then low on the word at byte `offset` of `device`, keeping
+    /// every other bit of that word
+    Pulse { device: String, offset: usize },
+}
+
+/// A latch that had bits set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrqEvent {
+    /// The latch register
+    pub device: String,
+    /// The byte offset of the word in the register
+    pub offset: usize,
+    /// The (masked) latched value
+    pub value: u32,
+    /// The bits that are new since the last poll, the same as `value` unless the latch is never
+    /// cleared
+    pub rising: u32,
+    /// When the latch was read
+    pub time: SystemTime,
+}
+
+type Handler = Box<dyn FnMut(&IrqEvent) -> ControlFlow<()> + Send>;
+
+/// A latch register word and its handler
+struct Latch {
+    device: String,
+    offset: usize,
+    mask: u32,
+    clear: Clear,
+    last: u32,
+    handler: Handler,
+}
+
+impl std::fmt::Debug for Latch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Latch")
+            .field("device", &self.device)
+            .field("offset", &self.offset)
+            .field("mask", &self.mask)
+            .field("clear", &self.clear)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A set of latch registers polled for events, see the module docs
+#[derive(Debug)]
+pub struct Irq {
+    latches: Vec<Latch>,
+    interval: Duration,
+}
+
+impl Irq {
+    /// Create an empty set of latches polled every `interval`
+    #[must_use]
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            latches: vec![],
+            interval,
+        }
+    }
+
+    /// Handle every bit of the first word of `device`, cleared with `clear`
+    #[must_use]
+    pub fn latch<F>(self, device: &str, clear: Clear, handler: F) -> Self
+    where
+        F: FnMut(&IrqEvent) -> ControlFlow<()> + Send + 'static,
+    {
+        self.masked(device, 0, u32::MAX, clear, handler)
+    }
+
+    /// Handle only the bits set in `mask` of the word at byte `offset` of `device`, cleared with
+    /// `clear`
+    #[must_use]
+    pub fn masked<F>(
+        mut self,
+        device: &str,
+        offset: usize,
+        mask: u32,
+        clear: Clear,
+        handler: F,
+    ) -> Self
+    where
+        F: FnMut(&IrqEvent) -> ControlFlow<()> + Send + 'static,
+    {
+        self.latches.push(Latch {
+            device: device.to_string(),
+            offset,
+            mask,
+            clear,
+            last: 0,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Read and clear every latch once, dispatching the ones with bits set to their handlers.
+    /// Returns the number of events dispatched, or `Break` if a handler asked to stop (in which
+    /// case the remaining events of this poll are dropped).
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn poll<T>(&mut self, transport: &mut T) -> TransportResult<ControlFlow<(), usize>>
+    where
+        T: Transport,
+    {
+        let mut events = vec![];
+        for (idx, latch) in self.latches.iter_mut().enumerate() {
+            let raw = transport.read::<u32, 4>(&latch.device, latch.offset)?;
+            let value = raw & latch.mask;
+            let rising = match latch.clear {
+                Clear::Never => value & !latch.last,
+                _ => value,
+            };
+            latch.last = value;
+            if rising == 0 {
+                continue;
+            }
+            match &latch.clear {
+                Clear::Never => {}
+                Clear::WriteOne => transport.write(&latch.device, latch.offset, &value)?,
+                Clear::WriteZero => {
+                    transport.write(&latch.device, latch.offset, &(raw & !value))?;
+                }
+                Clear::Pulse { device, offset } => {
+                    let word = transport.read::<u32, 4>(device, *offset)?;
+                    transport.write(device, *offset, &(word | value))?;
+                    transport.write(device, *offset, &(word & !value))?;
+                }
+            }
+            events.push((
+                idx,
+                IrqEvent {
+                    device: latch.device.clone(),
+                    offset: latch.offset,
+                    value,
+                    rising,
+                    time: SystemTime::now(),
+                },
+            ));
+        }
+        let dispatched = events.len();
+        for (idx, event) in events {
+            if (self.latches[idx].handler)(&event).is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(dispatched))
+    }
+
+    /// Poll until a handler returns [`ControlFlow::Break`]
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn run<T>(&mut self, transport: &mut T) -> TransportResult<()>
+    where
+        T: Transport,
+    {
+        loop {
+            let start = Instant::now();
+            if self.poll(transport)?.is_break() {
+                return Ok(());
+            }
+            std::thread::sleep(self.interval.saturating_sub(start.elapsed()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc,
+            Mutex,
+        },
+    };
+
+    type Seen = Arc<Mutex<Vec<(String, u32, u32)>>>;
+
+    #[test]
+    fn test_irq() {
+        let mut transport = Mock::new(HashMap::from([
+            ("overflow".into(), Register { addr: 0, length: 4 }),
+            ("pps".into(), Register { addr: 4, length: 4 }),
+            ("status".into(), Register { addr: 8, length: 4 }),
+            (
+                "clear".into(),
+                Register {
+                    addr: 12,
+                    length: 4,
+                },
+            ),
+        ]));
+        let seen = Seen::default();
+        let log = |seen: &Seen, stop| {
+            let seen = seen.clone();
+            move |event: &IrqEvent| {
+                seen.lock()
+                    .unwrap()
+                    .push((event.device.clone(), event.value, event.rising));
+                if stop {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            }
+        };
+        let mut irq = Irq::new(Duration::from_millis(1))
+            .masked("overflow", 0, 0xFF, Clear::WriteZero, log(&seen, false))
+            .latch("status", Clear::Never, log(&seen, false))
+            .latch(
+                "pps",
+                Clear::Pulse {
+                    device: "clear".into(),
+                    offset: 0,
+                },
+                log(&seen, true),
+            );
+        assert_eq!(irq.poll(&mut transport).unwrap(), ControlFlow::Continue(0));
+
+        // Latched bits are cleared, leaving the bits outside the mask alone
+        transport.write("overflow", 0, &0x105u32).unwrap();
+        transport.write("status", 0, &1u32).unwrap();
+        assert_eq!(irq.poll(&mut transport).unwrap(), ControlFlow::Continue(2));
+        assert_eq!(transport.read::<u32, 4>("overflow", 0).unwrap(), 0x100);
+        // Levels only fire on their rising edges
+        transport.write("status", 0, &3u32).unwrap();
+        assert_eq!(irq.poll(&mut transport).unwrap(), ControlFlow::Continue(1));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                ("overflow".to_string(), 5, 5),
+                ("status".to_string(), 1, 1),
+                ("status".to_string(), 3, 2),
+            ]
+        );
+
+        // A handler can stop the loop, and pulsing the clear leaves its other bits as they were
+        transport.write("pps", 0, &1u32).unwrap();
+        transport.write("clear", 0, &0x8000_0010u32).unwrap();
+        irq.run(&mut transport).unwrap();
+        assert_eq!(seen.lock().unwrap().last().unwrap().0, "pps");
+        assert_eq!(transport.read::<u32, 4>("clear", 0).unwrap(), 0x8000_0010);
+    }
+}
@@ -1,7 +1,23 @@
-#![deny(clippy::all)]
-#![warn(clippy::pedantic)]
-
-pub mod core;
-pub mod prelude;
-pub mod transport;
-pub mod yellow_blocks;
+#![deny(clippy::all)]
+#![warn(clippy::pedantic)]
+
+pub mod bringup;
+pub mod checkpoint;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod core;
+#[cfg(feature = "tapcp")]
+pub mod discovery;
+pub mod dump;
+pub mod io;
+pub mod irq;
+pub mod monitor;
+pub mod pps;
+pub mod prelude;
+pub mod register;
+pub mod system;
+pub mod text;
+pub mod thermal;
+pub mod transport;
+pub mod watch;
+pub mod yellow_blocks;
@@ -0,0 +1,247 @@
+//! A background sampler for building monitoring services
+//!
+//! A [`Monitor`] owns a thread that periodically runs a set of [`Probe`]s against a shared
+//! transport and publishes every result as a [`Sample`] over a channel. Probes are isolated from
+//! one another, a probe that errors only produces an errored sample and never stops the others.
+//!
+//! Like the yellow blocks, the monitor only holds a `Weak` pointer to the transport, so the thread
+//! exits on its own once the owning struct is dropped (or when the receiver hangs up).
+use crate::transport::{
+    Deserialize,
+    Transport,
+    TransportResult,
+};
+use std::{
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc::{
+            channel,
+            Receiver,
+        },
+        Arc,
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+/// The longest the sampling thread will sleep before checking if it was asked to stop
+const MAX_SLEEP: Duration = Duration::from_millis(50);
+
+type ProbeFn<T, S> = Box<dyn FnMut(&mut T) -> TransportResult<S> + Send>;
+
+/// A named, periodic measurement against a transport
+pub struct Probe<T, S> {
+    name: String,
+    interval: Duration,
+    f: ProbeFn<T, S>,
+}
+
+impl<T, S> std::fmt::Debug for Probe<T, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Probe")
+            .field("name", &self.name)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, S> Probe<T, S>
+where
+    T: Transport,
+{
+    /// Create a probe that runs an arbitrary closure every `interval`
+    pub fn new<F>(name: &str, interval: Duration, f: F) -> Self
+    where
+        F: FnMut(&mut T) -> TransportResult<S> + Send + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            interval,
+            f: Box::new(f),
+        }
+    }
+
+    /// Create a probe that reads `device` at `offset` as a `S` every `interval`
+    #[must_use]
+    pub fn register<const N: usize>(
+        name: &str,
+        device: &str,
+        offset: usize,
+        interval: Duration,
+    ) -> Self
+    where
+        S: Deserialize<Chunk = [u8; N]> + 'static,
+        crate::transport::Error: std::convert::From<<S as Deserialize>::Error>,
+    {
+        let device = device.to_string();
+        Self::new(name, interval, move |t: &mut T| t.read(&device, offset))
+    }
+}
+
+/// The result of running a single probe
+#[derive(Debug)]
+pub struct Sample<S> {
+    /// The name of the probe that produced this sample
+    pub probe: String,
+    /// When the probe was run
+    pub time: SystemTime,
+    /// The value (or error) the probe produced
+    pub value: TransportResult<S>,
+}
+
+/// Handle to a running sampling thread, which is stopped (and joined) on drop
+#[derive(Debug)]
+pub struct Monitor {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Monitor {
+    /// Spawn a sampling thread running `probes` against `transport`, returning the handle to the
+    /// thread and the receiving end of its sample channel
+    #[must_use]
+    pub fn spawn<T, S>(
+        transport: &Arc<Mutex<T>>,
+        probes: Vec<Probe<T, S>>,
+    ) -> (Self, Receiver<Sample<S>>)
+    where
+        T: Transport + Send + 'static,
+        S: Send + 'static,
+    {
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let transport = Arc::downgrade(transport);
+        let handle = std::thread::spawn(move || {
+            let mut probes = probes;
+            let now = Instant::now();
+            let mut due = vec![now; probes.len()];
+            while !thread_stop.load(Ordering::Relaxed) {
+                let Some(tarc) = transport.upgrade() else {
+                    return;
+                };
+                let now = Instant::now();
+                for (probe, due) in probes.iter_mut().zip(due.iter_mut()) {
+                    if *due > now {
+                        continue;
+                    }
+                    // A poisoned lock means some other user of the transport panicked, which we
+                    // can't recover from here
+                    let Ok(mut t) = tarc.lock() else {
+                        return;
+                    };
+                    let value = (probe.f)(&mut t);
+                    drop(t);
+                    let sample = Sample {
+                        probe: probe.name.clone(),
+                        time: SystemTime::now(),
+                        value,
+                    };
+                    if tx.send(sample).is_err() {
+                        // Nobody is listening anymore
+                        return;
+                    }
+                    *due += probe.interval;
+                    // Don't try to catch up if we fell behind
+                    if *due < now {
+                        *due = now + probe.interval;
+                    }
+                }
+                drop(tarc);
+                let next = due.iter().min().copied().unwrap_or(now + MAX_SLEEP);
+                std::thread::sleep(
+                    next.saturating_duration_since(Instant::now())
+                        .min(MAX_SLEEP),
+                );
+            }
+        });
+        (
+            Self {
+                stop,
+                handle: Some(handle),
+            },
+            rx,
+        )
+    }
+
+    /// Whether the sampling thread is still running
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|h| !h.is_finished())
+    }
+
+    /// Stop the sampling thread and wait for it to exit
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_monitor() {
+        let transport = Mock::new(HashMap::from([(
+            "sys_scratchpad".into(),
+            Register { addr: 0, length: 4 },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        transport
+            .lock()
+            .unwrap()
+            .write("sys_scratchpad", 0, &42u32)
+            .unwrap();
+        let probes = vec![
+            Probe::register("scratch", "sys_scratchpad", 0, Duration::from_millis(1)),
+            Probe::register("missing", "not_a_register", 0, Duration::from_millis(1)),
+        ];
+        let (monitor, rx) = Monitor::spawn(&transport, probes);
+        let samples: Vec<Sample<u32>> = rx.iter().take(6).collect();
+        monitor.stop();
+        assert!(samples
+            .iter()
+            .filter(|s| s.probe == "scratch")
+            .all(|s| matches!(s.value, Ok(42))));
+        assert!(samples
+            .iter()
+            .filter(|s| s.probe == "missing")
+            .all(|s| s.value.is_err()));
+        assert!(samples.iter().any(|s| s.probe == "missing"));
+    }
+
+    #[test]
+    fn test_monitor_exits_on_drop() {
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::new())));
+        let (monitor, _rx) = Monitor::spawn::<_, u32>(&transport, vec![]);
+        drop(transport);
+        std::thread::sleep(MAX_SLEEP * 2);
+        assert!(!monitor.is_running());
+    }
+}
@@ -0,0 +1,291 @@
+//! Scheduling triggers on a pulse-per-second (PPS) edge, i.e. to start captures or syncs in the
+//! same wall-clock second across many boards
+//!
+//! The gateware is expected to have a register counting PPS edges and a register that, when
+//! toggled high, arms a trigger on the next PPS edge. A [`PpsScheduler`] watches the counter tick
+//! over to find where the board's PPS edges fall against the host clock, so the host only has to
+//! be within half a second of the PPS (i.e. NTP). It then sleeps until [`PpsScheduler::lead`]
+//! before the edge nearest the target time, arms, and checks the counter afterwards to confirm
+//! the trigger landed on the right edge.
+//!
+//! Designs that also keep a 64-bit sample counter (split across two registers) can have it read
+//! alongside the PPS count with [`PpsScheduler::with_sample_counter`], to line up the samples of
+//! many boards against the same second.
+use crate::{
+    core::read_u64_counter,
+    transport::Transport,
+};
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+};
+use thiserror::Error;
+
+/// The default time to arm ahead of the target edge
+pub const DEFAULT_LEAD: Duration = Duration::from_millis(250);
+/// How long to wait for the PPS counter to tick before giving up on there being a PPS
+const PPS_TIMEOUT: Duration = Duration::from_millis(1500);
+const PPS_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const SECOND: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("The PPS counter didn't tick within {0:?}, is the PPS connected?")]
+    NoPps(Duration),
+    #[error("The target time is too soon (or already passed) to arm ahead of it")]
+    TooLate,
+    #[error(
+        "Expected a PPS count of {expected} but found {found}, so the trigger missed its second"
+    )]
+    MissedSecond { expected: u32, found: u32 },
+}
+
+/// The PPS edge a trigger fired on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Scheduled {
+    /// The host time of the edge
+    pub edge: SystemTime,
+    /// The PPS count right after the edge
+    pub count: u32,
+    /// The sample count right after the edge, if the scheduler has a sample counter
+    pub samples: Option<u64>,
+}
+
+/// Schedules triggers on the PPS edge of a given second, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PpsScheduler {
+    pps_count: String,
+    arm: String,
+    lead: Duration,
+    /// The high and low words of the sample counter
+    sample_count: Option<(String, String)>,
+}
+
+impl PpsScheduler {
+    /// Schedule with the PPS counter `pps_count` and the arming register `arm`
+    #[must_use]
+    pub fn new(pps_count: &str, arm: &str) -> Self {
+        Self {
+            pps_count: pps_count.to_string(),
+            arm: arm.to_string(),
+            lead: DEFAULT_LEAD,
+            sample_count: None,
+        }
+    }
+
+    /// Also read the 64-bit sample counter split across the registers `msb` and `lsb` after every
+    /// edge, see [`read_u64_counter`]
+    #[must_use]
+    pub fn with_sample_counter(mut self, msb: &str, lsb: &str) -> Self {
+        self.sample_count = Some((msb.to_string(), lsb.to_string()));
+        self
+    }
+
+    /// Arm `lead` ahead of the target edge (and check the result `lead` after it). This has to
+    /// cover the latency of a register write and the host's scheduling jitter, and is clamped
+    /// to under half a second so it can't reach the neighboring edges.
+    #[must_use]
+    pub fn with_lead(mut self, lead: Duration) -> Self {
+        self.lead = lead.min(Duration::from_millis(499));
+        self
+    }
+
+    /// The time triggers are armed ahead of their edge
+    #[must_use]
+    pub fn lead(&self) -> Duration {
+        self.lead
+    }
+
+    /// Wait for the next PPS edge, returning its host time and the count after it
+    /// # Errors
+    /// Returns an error on bad transport or if the counter doesn't tick
+    pub fn next_edge<T>(&self, transport: &mut T) -> Result<Scheduled, Error>
+    where
+        T: Transport,
+    {
+        let start = Instant::now();
+        let first: u32 = transport.read(&self.pps_count, 0)?;
+        loop {
+            let count: u32 = transport.read(&self.pps_count, 0)?;
+            if count != first {
+                let edge = SystemTime::now();
+                return Ok(Scheduled {
+                    edge,
+                    count,
+                    samples: self.samples(transport)?,
+                });
+            }
+            if start.elapsed() >= PPS_TIMEOUT {
+                return Err(Error::NoPps(PPS_TIMEOUT));
+            }
+            std::thread::sleep(PPS_POLL_INTERVAL);
+        }
+    }
+
+    /// Arm the trigger to fire on the PPS edge nearest `target`, blocking until it has
+    /// # Errors
+    /// Returns an error on bad transport, if `target` is less than a second and a half away, or if
+    /// the PPS count shows the trigger was armed after (or landed on something other than) the
+    /// target edge
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn schedule<T>(&self, transport: &mut T, target: SystemTime) -> Result<Scheduled, Error>
+    where
+        T: Transport,
+    {
+        let reference = self.next_edge(transport)?;
+        // Round to the nearest edge, which only needs the host clock to be within half a second
+        let seconds = target
+            .duration_since(reference.edge)
+            .map_err(|_| Error::TooLate)?
+            .saturating_add(SECOND / 2)
+            .as_secs();
+        if seconds == 0 {
+            return Err(Error::TooLate);
+        }
+        let edge = reference.edge + SECOND * seconds as u32;
+        // The counter wraps, and so do the expected values
+        let expected = reference.count.wrapping_add(seconds as u32);
+        sleep_until(edge - self.lead);
+        let before: u32 = transport.read(&self.pps_count, 0)?;
+        if before != expected.wrapping_sub(1) {
+            return Err(Error::MissedSecond {
+                expected: expected.wrapping_sub(1),
+                found: before,
+            });
+        }
+        // A rising edge arms the trigger for the next PPS
+        transport.write(&self.arm, 0, &0u32)?;
+        transport.write(&self.arm, 0, &1u32)?;
+        sleep_until(edge + self.lead);
+        let after: u32 = transport.read(&self.pps_count, 0)?;
+        let samples = self.samples(transport)?;
+        transport.write(&self.arm, 0, &0u32)?;
+        if after != expected {
+            return Err(Error::MissedSecond {
+                expected,
+                found: after,
+            });
+        }
+        Ok(Scheduled {
+            edge,
+            count: after,
+            samples,
+        })
+    }
+
+    fn samples<T>(&self, transport: &mut T) -> Result<Option<u64>, Error>
+    where
+        T: Transport,
+    {
+        self.sample_count
+            .as_ref()
+            .map(|(msb, lsb)| read_u64_counter(transport, msb, lsb))
+            .transpose()
+            .map_err(Error::from)
+    }
+}
+
+fn sleep_until(time: SystemTime) {
+    if let Ok(remaining) = time.duration_since(SystemTime::now()) {
+        std::thread::sleep(remaining);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::sim::SimulatedFpga;
+    use casper_utils::design_sources::{
+        fpg::read_fpg_file,
+        raw::RawDesign,
+        Register as DesignRegister,
+    };
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::UNIX_EPOCH,
+    };
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    fn test_schedule() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        // A PPS on the host's whole seconds
+        let now = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        sim.on_read("pps_cnt", move |mem, _, _| {
+            mem.write("pps_cnt", 0, &(now().as_secs() as u32))
+        });
+        // Record the second the trigger was armed in
+        let armed = Arc::new(Mutex::new(vec![]));
+        let record = armed.clone();
+        sim.on_write("pps_trig", move |_, _, data| {
+            if data[3] == 1 {
+                record.lock().unwrap().push(now().as_secs());
+            }
+            Ok(())
+        });
+        let scheduler = PpsScheduler::new("pps_cnt", "pps_trig");
+        assert!(matches!(
+            scheduler.schedule(&mut sim, SystemTime::now()),
+            Err(Error::TooLate)
+        ));
+        let target = SystemTime::now() + Duration::from_millis(2500);
+        let edge = scheduler.schedule(&mut sim, target).unwrap();
+        // The edge is the one nearest the target, and we armed in the second before it
+        let landed = edge.edge.duration_since(UNIX_EPOCH).unwrap();
+        let target = target.duration_since(UNIX_EPOCH).unwrap();
+        let error = landed.as_secs_f64() - target.as_secs_f64();
+        assert!(error.abs() <= 0.55);
+        assert_eq!(u64::from(edge.count), landed.as_secs());
+        assert_eq!(*armed.lock().unwrap(), vec![landed.as_secs() - 1]);
+        assert_eq!(edge.samples, None);
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    fn test_sample_counter() {
+        let register = |addr| DesignRegister { addr, size: 4 };
+        let mut sim = SimulatedFpga::new(&RawDesign {
+            registers: HashMap::from([
+                ("pps_cnt".into(), register(0)),
+                ("pps_trig".into(), register(4)),
+                ("sample_msb".into(), register(8)),
+                ("sample_lsb".into(), register(12)),
+            ]),
+            devices: HashMap::new(),
+            bitstream: vec![],
+            md5: [0; 16],
+            sha256: [0; 32],
+            filename: "design.bin".into(),
+        });
+        // A PPS edge between every read, and a sample counter ticking on every read of its low
+        // word, which is just about to roll over
+        sim.on_read("pps_cnt", |mem, _, _| {
+            let count: u32 = mem.read("pps_cnt", 0)?;
+            mem.write("pps_cnt", 0, &(count + 1))
+        });
+        sim.on_read("sample_lsb", |mem, _, _| {
+            let msb: u32 = mem.read("sample_msb", 0)?;
+            let lsb: u32 = mem.read("sample_lsb", 0)?;
+            let samples = (u64::from(msb) << 32 | u64::from(lsb)) + 1;
+            mem.write("sample_msb", 0, &((samples >> 32) as u32))?;
+            mem.write("sample_lsb", 0, &(samples as u32))
+        });
+        sim.memory().write("sample_msb", 0, &1u32).unwrap();
+        sim.memory().write("sample_lsb", 0, &u32::MAX).unwrap();
+
+        let scheduler = PpsScheduler::new("pps_cnt", "pps_trig")
+            .with_sample_counter("sample_msb", "sample_lsb");
+        let edge = scheduler.next_edge(&mut sim).unwrap();
+        assert_eq!(edge.count, 2);
+        // The low word rolled over mid-read, so it was read again to match the new high word
+        assert_eq!(edge.samples, Some(0x2_0000_0001));
+    }
+}
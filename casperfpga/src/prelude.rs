@@ -1,12 +1,27 @@
 //! Prelude (helpful reexports) for this package
 
-pub use crate::transport::{
-    tapcp::{
-        self,
-        Tapcp,
+#[cfg(feature = "tapcp")]
+pub use crate::transport::tapcp::{
+    self,
+    Tapcp,
+};
+pub use crate::{
+    core::{
+        Register,
+        RegisterMap,
+    },
+    register::Registers,
+    transport::{
+        mock::Mock,
+        Transport,
     },
-    Transport,
 };
-pub use casper_utils::design_sources::fpg::read_fpg_file;
-pub use casperfpga_derive::fpga_from_fpg;
+pub use casper_utils::design_sources::{
+    fpg::read_fpg_file,
+    FpgaDesign,
+};
+pub use casperfpga_derive::{
+    fpga_from_fpg,
+    fpga_personalities,
+};
 pub use fixed::prelude::*;
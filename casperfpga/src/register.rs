@@ -0,0 +1,284 @@
+//! Typed handles on registers looked up at runtime, for scripts and exploratory work
+//!
+//! The structs generated from fpg files know every register at compile time. When poking at a
+//! board by hand, [`TypedRegister`] gets the same type safety from just a name:
+//! ```
+//! # use casperfpga::core::Register;
+//! # use casperfpga::transport::mock::Mock;
+//! # use std::{collections::HashMap, sync::{Arc, Mutex}};
+//! use casperfpga::register::Registers;
+//! # let fpga = Arc::new(Mutex::new(Mock::new(HashMap::from([(
+//! #     "sys_scratchpad".into(),
+//! #     Register { addr: 0, length: 4 },
+//! # )]))));
+//! let reg = fpga.register::<u32>("sys_scratchpad").unwrap();
+//! reg.write(&0xdead_beef).unwrap();
+//! assert_eq!(reg.read().unwrap(), 0xdead_beef);
+//! ```
+//! The register is found in the transport's `listdev` when the handle is made, so a misspelled
+//! name or a type wider than the register fails right away instead of on the first access.
+use crate::{
+    core::{
+        poll,
+        WaitError,
+    },
+    transport::{
+        check_bounds,
+        Deserialize,
+        Error,
+        Serialize,
+        Transport,
+        TransportResult,
+    },
+    yellow_blocks::TransportHandle,
+};
+use std::{
+    fmt::Debug,
+    marker::PhantomData,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// The types a [`TypedRegister`] can hold, which is every type that serializes to and from the
+/// same fixed number of bytes (i.e. the builtin numbers and byte arrays)
+pub trait RegisterValue: Sized {
+    /// The number of bytes the value takes up in the register
+    const SIZE: usize;
+
+    /// The big-endian bytes of the value
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// The value from exactly [`RegisterValue::SIZE`] bytes
+    /// # Errors
+    /// Returns an error if the bytes aren't a valid value
+    fn from_bytes(bytes: Vec<u8>) -> TransportResult<Self>;
+}
+
+impl<V> RegisterValue for V
+where
+    V: Serialize + Deserialize<Chunk = <V as Serialize>::Chunk>,
+    <V as Serialize>::Chunk: AsRef<[u8]> + TryFrom<Vec<u8>>,
+    Error: From<<V as Deserialize>::Error>,
+{
+    const SIZE: usize = std::mem::size_of::<<V as Serialize>::Chunk>();
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.serialize().as_ref().to_vec()
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> TransportResult<Self> {
+        let n = bytes.len();
+        let chunk = bytes.try_into().map_err(|_| {
+            Error::Packing(packed_struct::PackingError::BufferSizeMismatch {
+                expected: Self::SIZE,
+                actual: n,
+            })
+        })?;
+        Ok(V::deserialize(chunk)?)
+    }
+}
+
+/// A handle on a value of type `V` at a fixed offset of a register, checked against the
+/// transport's `listdev` when it's made
+#[derive(Debug)]
+pub struct TypedRegister<T, V> {
+    /// Upwards pointer to the shared transport
+    transport: TransportHandle<T>,
+    /// The name of the register
+    name: String,
+    /// The byte offset of the value in the register
+    offset: usize,
+    /// Marker for the value type
+    phantom: PhantomData<fn() -> V>,
+}
+
+impl<T, V> TypedRegister<T, V>
+where
+    T: Transport,
+    V: RegisterValue,
+{
+    /// The value at byte `offset` of the register `name` on `transport`
+    /// # Errors
+    /// Returns errors on bad transport, [`Error::DeviceNotFound`] if the board doesn't list the
+    /// register, or [`Error::OutOfBounds`] if the value doesn't fit in it at `offset`
+    pub fn new(transport: &Arc<Mutex<T>>, name: &str, offset: usize) -> TransportResult<Self> {
+        let transport = TransportHandle::new(Arc::downgrade(transport), name);
+        transport.with_transport(|t| check_bounds(&t.listdev()?, name, offset, V::SIZE))?;
+        Ok(Self {
+            transport,
+            name: name.to_string(),
+            offset,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The name of the register
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The byte offset of the value in the register
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Reads the value
+    /// # Errors
+    /// Returns errors on bad transport or deserialization
+    pub fn read(&self) -> TransportResult<V> {
+        let bytes = self
+            .transport
+            .with_transport(|t| t.read_n_bytes(&self.name, self.offset, V::SIZE))?;
+        V::from_bytes(bytes)
+    }
+
+    /// Writes `value`
+    /// # Errors
+    /// Returns errors on bad transport
+    pub fn write(&self, value: &V) -> TransportResult<()> {
+        self.transport
+            .with_transport(|t| t.write_bytes(&self.name, self.offset, &value.to_bytes()))
+    }
+
+    /// Writes `value` and then puts back whatever was there before, i.e. to strobe a reset or arm
+    /// bit
+    /// # Errors
+    /// Returns errors on bad transport
+    pub fn pulse(&self, value: &V) -> TransportResult<()> {
+        self.transport.with_transport(|t| {
+            let before = t.read_n_bytes(&self.name, self.offset, V::SIZE)?;
+            t.write_bytes(&self.name, self.offset, &value.to_bytes())?;
+            t.write_bytes(&self.name, self.offset, &before)
+        })
+    }
+
+    /// Poll the value every `poll_interval` until it satisfies `predicate`, returning the value
+    /// that did. The transport is free for other users in between polls.
+    /// # Errors
+    /// Returns an error on bad transport or [`WaitError::Timeout`] (with the last value read) if
+    /// the predicate wasn't satisfied within `timeout`
+    pub fn wait_for<P>(
+        &self,
+        predicate: P,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<V, WaitError<V>>
+    where
+        V: Debug,
+        P: FnMut(&V) -> bool,
+    {
+        poll(|| self.read(), predicate, timeout, poll_interval)?
+            .map_err(|t| WaitError::timeout(&self.name, t))
+    }
+}
+
+/// Making [`TypedRegister`]s straight from a shared transport
+pub trait Registers<T> {
+    /// A handle on the register `name` as a `V`, see [`TypedRegister::new`]
+    /// # Errors
+    /// Returns errors on bad transport or if the register doesn't exist or is too small for `V`
+    fn register<V>(&self, name: &str) -> TransportResult<TypedRegister<T, V>>
+    where
+        V: RegisterValue;
+}
+
+impl<T> Registers<T> for Arc<Mutex<T>>
+where
+    T: Transport,
+{
+    fn register<V>(&self, name: &str) -> TransportResult<TypedRegister<T, V>>
+    where
+        V: RegisterValue,
+    {
+        TypedRegister::new(self, name, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::collections::HashMap;
+
+    fn board() -> Arc<Mutex<Mock>> {
+        Arc::new(Mutex::new(Mock::new(HashMap::from([
+            ("sys_scratchpad".into(), Register { addr: 0, length: 4 }),
+            ("counters".into(), Register { addr: 4, length: 8 }),
+        ]))))
+    }
+
+    #[test]
+    fn test_typed_register() {
+        let fpga = board();
+        let reg = fpga.register::<u32>("sys_scratchpad").unwrap();
+        reg.write(&0x1234_5678).unwrap();
+        assert_eq!(reg.read().unwrap(), 0x1234_5678);
+        let float = fpga.register::<f32>("sys_scratchpad").unwrap();
+        float.write(&1.5).unwrap();
+        assert!((float.read().unwrap() - 1.5).abs() < f32::EPSILON);
+
+        // Values can sit at an offset, as long as they fit
+        let high = TypedRegister::<_, u32>::new(&fpga, "counters", 4).unwrap();
+        high.write(&7).unwrap();
+        let whole = fpga.register::<u64>("counters").unwrap();
+        assert_eq!(whole.read().unwrap(), 7);
+        assert_eq!((high.name(), high.offset()), ("counters", 4));
+
+        assert!(matches!(
+            fpga.register::<u64>("sys_scratchpad"),
+            Err(Error::OutOfBounds { size: 4, n: 8, .. })
+        ));
+        assert!(matches!(
+            TypedRegister::<_, u16>::new(&fpga, "counters", 7),
+            Err(Error::OutOfBounds { offset: 7, .. })
+        ));
+        assert!(matches!(
+            fpga.register::<u32>("sys_scratchpadd"),
+            Err(Error::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_pulse_and_wait() {
+        let fpga = board();
+        let reg = fpga.register::<u32>("sys_scratchpad").unwrap();
+        reg.write(&0b10).unwrap();
+        reg.pulse(&0b11).unwrap();
+        assert_eq!(reg.read().unwrap(), 0b10);
+
+        assert_eq!(
+            reg.wait_for(
+                |v| v & 0b10 != 0,
+                Duration::from_millis(10),
+                Duration::from_millis(1)
+            )
+            .unwrap(),
+            0b10
+        );
+        match reg.wait_for(
+            |v| *v > 10,
+            Duration::from_millis(10),
+            Duration::from_millis(2),
+        ) {
+            Err(WaitError::Timeout { last, device, .. }) => {
+                assert_eq!((last, device.as_str()), (0b10, "sys_scratchpad"));
+            }
+            r => panic!("Expected a timeout, got {r:?}"),
+        }
+
+        // Handles fail cleanly once the transport is gone
+        drop(fpga);
+        assert!(matches!(
+            reg.read(),
+            Err(Error::TransportDropped | Error::TransportDroppedEarly { .. })
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+//! The standard `sys_*` registers every CASPER design has, for a quick smoke test after programming
+//!
+//! The toolflow's `sys_block` puts the board id, the gateware revision, a scratchpad, and a free
+//! running clock counter at the same names in every design. [`SystemInfo::read`] decodes them on
+//! any transport, and [`smoke_test`] additionally checks that the scratchpad holds what's written
+//! to it and that the clock is running, which catches most "programmed but not really" boards.
+use crate::{
+    core::counter_delta,
+    transport::Transport,
+};
+use std::time::Duration;
+use thiserror::Error;
+
+/// The patterns written to the scratchpad by [`scratchpad_test`], chosen to toggle every bit
+const SCRATCHPAD_PATTERNS: [u32; 4] = [0xDEAD_BEEF, 0x2152_4110, 0xAAAA_AAAA, 0x5555_5555];
+/// How long [`smoke_test`] waits between two reads of the clock counter
+const CLOCK_CHECK_DELAY: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("Wrote {wrote:#010x} to the scratchpad but read back {read:#010x}")]
+    Scratchpad { wrote: u32, read: u32 },
+    #[error("The clock counter is stuck at {0:#010x}")]
+    ClockStopped(u32),
+}
+
+/// The decoded contents of the standard `sys_*` registers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SystemInfo {
+    /// The board id the design was built for (`sys_board_id`)
+    pub board_id: u32,
+    /// The major gateware revision, the top half of `sys_rev`
+    pub rev_major: u16,
+    /// The minor gateware revision, the third byte of `sys_rev`
+    pub rev_minor: u8,
+    /// The bottom byte of `sys_rev`
+    pub rev_build: u8,
+    /// The revision control (i.e. git) hash the gateware was built from (`sys_rev_rcs`)
+    pub rev_rcs: u32,
+    /// The value of the free running `sys_clkcounter`
+    pub clock_count: u32,
+}
+
+impl SystemInfo {
+    /// Read and decode the `sys_*` registers
+    /// # Errors
+    /// Returns an error on bad transport, i.e. if the board isn't running a design
+    pub fn read<T>(transport: &mut T) -> Result<Self, Error>
+    where
+        T: Transport,
+    {
+        let rev: u32 = transport.read("sys_rev", 0)?;
+        let [major_hi, major_lo, rev_minor, rev_build] = rev.to_be_bytes();
+        Ok(Self {
+            board_id: transport.read("sys_board_id", 0)?,
+            rev_major: u16::from_be_bytes([major_hi, major_lo]),
+            rev_minor,
+            rev_build,
+            rev_rcs: transport.read("sys_rev_rcs", 0)?,
+            clock_count: transport.read("sys_clkcounter", 0)?,
+        })
+    }
+}
+
+impl std::fmt::Display for SystemInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "board {:#x}, revision {}.{}.{} ({:08x})",
+            self.board_id, self.rev_major, self.rev_minor, self.rev_build, self.rev_rcs
+        )
+    }
+}
+
+/// Write a few patterns to `sys_scratchpad` and check each one reads back, restoring the original
+/// contents afterwards
+/// # Errors
+/// Returns an error on bad transport or [`Error::Scratchpad`] on the first pattern that didn't
+/// read back
+pub fn scratchpad_test<T>(transport: &mut T) -> Result<(), Error>
+where
+    T: Transport,
+{
+    let original: u32 = transport.read("sys_scratchpad", 0)?;
+    let mut res = Ok(());
+    for wrote in SCRATCHPAD_PATTERNS {
+        transport.write("sys_scratchpad", 0, &wrote)?;
+        let read: u32 = transport.read("sys_scratchpad", 0)?;
+        if read != wrote {
+            res = Err(Error::Scratchpad { wrote, read });
+            break;
+        }
+    }
+    transport.write("sys_scratchpad", 0, &original)?;
+    res
+}
+
+/// Check that the board is running a design that responds sanely - the `sys_*` registers decode,
+/// the scratchpad holds what's written to it, and the clock counter is running
+/// # Errors
+/// Returns an error on bad transport or the first check that failed
+pub fn smoke_test<T>(transport: &mut T) -> Result<SystemInfo, Error>
+where
+    T: Transport,
+{
+    let info = SystemInfo::read(transport)?;
+    scratchpad_test(transport)?;
+    std::thread::sleep(CLOCK_CHECK_DELAY);
+    let count: u32 = transport.read("sys_clkcounter", 0)?;
+    if counter_delta(info.clock_count, count) == 0 {
+        return Err(Error::ClockStopped(count));
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::sim::SimulatedFpga;
+    use casper_utils::design_sources::fpg::read_fpg_file;
+
+    #[test]
+    fn test_smoke_test() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        sim.memory().write("sys_rev", 0, &0x0002_0103u32).unwrap();
+        sim.memory().write("sys_scratchpad", 0, &42u32).unwrap();
+        let info = smoke_test(&mut sim).unwrap();
+        assert_eq!((info.rev_major, info.rev_minor, info.rev_build), (2, 1, 3));
+        assert_eq!(sim.read::<u32, 4>("sys_scratchpad", 0).unwrap(), 42);
+
+        // A scratchpad with a stuck bit
+        sim.on_read("sys_scratchpad", |mem, _, _| {
+            let value: u32 = mem.read("sys_scratchpad", 0)?;
+            mem.write("sys_scratchpad", 0, &(value | 1))
+        });
+        assert!(matches!(
+            scratchpad_test(&mut sim),
+            Err(Error::Scratchpad {
+                wrote: 0x2152_4110,
+                ..
+            })
+        ));
+        // A clock that isn't running
+        let mut stopped = SimulatedFpga::new(&design).with_clock_rate(0.0);
+        assert!(matches!(
+            smoke_test(&mut stopped),
+            Err(Error::ClockStopped(_))
+        ));
+    }
+}
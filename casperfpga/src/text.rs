@@ -0,0 +1,71 @@
+//! The plain text formats of checkpoints, journals, and register dumps
+//!
+//! Each of these is saved one record per line, with the fields separated by spaces and any bytes
+//! written as lowercase hex, two digits per byte. When reading them back, blank lines and lines
+//! starting with `#` are skipped, so saved files can be annotated by hand.
+
+pub use casper_utils::digest::to_hex;
+use std::io::BufRead;
+use thiserror::Error;
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum HexError {
+    #[error("odd number of hex digits")]
+    OddLength,
+    #[error("invalid hex digit")]
+    InvalidDigit,
+}
+
+/// Parse bytes written by [`to_hex`]
+/// # Errors
+/// Returns an error if `hex` isn't an even number of hex digits
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, HexError> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(HexError::OddLength);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| HexError::InvalidDigit))
+        .collect()
+}
+
+/// The records of `reader` with their (one-based) line numbers, trimmed, skipping blank lines and
+/// comments
+pub fn records<R: BufRead>(reader: R) -> impl Iterator<Item = std::io::Result<(usize, String)>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, line)| match line {
+            Ok(line) => {
+                let line = line.trim();
+                (!line.is_empty() && !line.starts_with('#'))
+                    .then(|| Ok((idx + 1, line.to_string())))
+            }
+            Err(e) => Some(Err(e)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0, 0x0a, 0xff]), "000aff");
+        assert_eq!(from_hex("000aFF"), Ok(vec![0, 0x0a, 0xff]));
+        assert_eq!(from_hex(""), Ok(vec![]));
+        assert_eq!(from_hex("abc"), Err(HexError::OddLength));
+        assert_eq!(from_hex("éa"), Err(HexError::OddLength));
+        assert_eq!(from_hex("0g"), Err(HexError::InvalidDigit));
+    }
+
+    #[test]
+    fn test_records() {
+        let text = "# a comment\n\nfirst 00\n  second 01  \n#another\n";
+        let records: Vec<_> = records(text.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(
+            records,
+            vec![(3, "first 00".to_string()), (4, "second 01".to_string())]
+        );
+    }
+}
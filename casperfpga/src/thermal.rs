@@ -0,0 +1,391 @@
+//! A thermal watchdog, for boards that overheat in closed racks
+//!
+//! A [`ThermalWatchdog`] owns a thread that reads the board temperature every
+//! [`ThermalPolicy::period`], keeps rolling statistics over the last [`ThermalPolicy::window`]
+//! samples, and calls back whenever the board changes [`ThermalLevel`]. A level is only entered
+//! once [`ThermalPolicy::consecutive`] samples in a row agree, so a single noisy reading doesn't
+//! trip anything. On entering [`ThermalLevel::Critical`] the watchdog can also deprogram the FPGA,
+//! which is the only thing software can do to cool it down.
+//!
+//! Like the [`Monitor`](crate::monitor::Monitor), the watchdog only holds a `Weak` pointer to the
+//! transport, so the thread exits on its own once the owning struct is dropped.
+use crate::transport::{
+    Transport,
+    TransportResult,
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+/// The longest the watchdog thread will sleep before checking if it was asked to stop
+const MAX_SLEEP: Duration = Duration::from_millis(50);
+
+/// What the watchdog considers too hot, and what it does about it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermalPolicy {
+    /// How often to read the temperature
+    pub period: Duration,
+    /// The temperature in Celsius at and above which the board is [`ThermalLevel::Warning`]
+    pub warning: f32,
+    /// The temperature in Celsius at and above which the board is [`ThermalLevel::Critical`]
+    pub critical: f32,
+    /// How many samples in a row have to be at a level before the board is considered at it
+    pub consecutive: usize,
+    /// How many of the latest samples the statistics are over
+    pub window: usize,
+    /// Deprogram the FPGA on entering [`ThermalLevel::Critical`]
+    pub deprogram_on_critical: bool,
+}
+
+impl Default for ThermalPolicy {
+    /// Sample every 5 seconds, warn at 80°C, and deprogram at 90°C, after three samples in a row
+    fn default() -> Self {
+        Self {
+            period: Duration::from_secs(5),
+            warning: 80.0,
+            critical: 90.0,
+            consecutive: 3,
+            window: 60,
+            deprogram_on_critical: true,
+        }
+    }
+}
+
+/// How hot the board is, against the thresholds of a [`ThermalPolicy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ThermalLevel {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl ThermalLevel {
+    fn of(temperature: f32, policy: &ThermalPolicy) -> Self {
+        if temperature >= policy.critical {
+            Self::Critical
+        } else if temperature >= policy.warning {
+            Self::Warning
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+/// A change of the board's thermal level
+#[derive(Debug)]
+pub struct ThermalEvent {
+    /// The level the board is now at
+    pub level: ThermalLevel,
+    /// The level the board was at before
+    pub previous: ThermalLevel,
+    /// The temperature in Celsius of the sample that made the change
+    pub temperature: f32,
+    /// When that sample was taken
+    pub time: SystemTime,
+    /// The result of deprogramming the FPGA, if the policy did on this event
+    pub deprogrammed: Option<TransportResult<()>>,
+}
+
+/// Rolling statistics of the temperature, in Celsius
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ThermalStats {
+    /// The latest temperature read, `None` before the first good sample
+    pub last: Option<f32>,
+    /// The lowest temperature in the window
+    pub min: f32,
+    /// The highest temperature in the window
+    pub max: f32,
+    /// The mean temperature over the window
+    pub mean: f32,
+    /// The number of samples in the window
+    pub samples: usize,
+    /// The number of reads that failed since the watchdog started
+    pub errors: usize,
+    /// The level the board is at
+    pub level: Option<ThermalLevel>,
+}
+
+/// The sample history and level tracking of the watchdog, separate from the thread
+#[derive(Debug, Clone)]
+struct ThermalState {
+    policy: ThermalPolicy,
+    window: VecDeque<f32>,
+    errors: usize,
+    level: ThermalLevel,
+    /// The level of the latest samples, and how many in a row there were
+    candidate: (ThermalLevel, usize),
+}
+
+impl ThermalState {
+    fn new(policy: ThermalPolicy) -> Self {
+        Self {
+            policy,
+            window: VecDeque::with_capacity(policy.window),
+            errors: 0,
+            level: ThermalLevel::Normal,
+            candidate: (ThermalLevel::Normal, 0),
+        }
+    }
+
+    /// Record a sample, returning the previous level if the board changed level
+    fn update(&mut self, temperature: f32) -> Option<ThermalLevel> {
+        if self.window.len() == self.policy.window.max(1) {
+            self.window.pop_front();
+        }
+        self.window.push_back(temperature);
+        let level = ThermalLevel::of(temperature, &self.policy);
+        if level == self.candidate.0 {
+            self.candidate.1 += 1;
+        } else {
+            self.candidate = (level, 1);
+        }
+        if level != self.level && self.candidate.1 >= self.policy.consecutive.max(1) {
+            Some(std::mem::replace(&mut self.level, level))
+        } else {
+            None
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn stats(&self) -> ThermalStats {
+        let (min, max, sum) = self.window.iter().fold(
+            (f32::INFINITY, f32::NEG_INFINITY, 0.0),
+            |(min, max, sum), &t| (min.min(t), max.max(t), sum + t),
+        );
+        let samples = self.window.len();
+        if samples == 0 {
+            return ThermalStats {
+                errors: self.errors,
+                ..ThermalStats::default()
+            };
+        }
+        ThermalStats {
+            last: self.window.back().copied(),
+            min,
+            max,
+            mean: sum / samples as f32,
+            samples,
+            errors: self.errors,
+            level: Some(self.level),
+        }
+    }
+}
+
+type ReadFn<T> = Box<dyn FnMut(&mut T) -> TransportResult<f32> + Send>;
+type EventFn = Box<dyn FnMut(&ThermalEvent) + Send>;
+
+/// Handle to a running watchdog thread, which is stopped (and joined) on drop
+#[derive(Debug)]
+pub struct ThermalWatchdog {
+    stop: Arc<AtomicBool>,
+    state: Arc<Mutex<ThermalState>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ThermalWatchdog {
+    /// Spawn a watchdog thread taking the temperature of `transport` with `read` and calling
+    /// `on_event` on every change of level
+    pub fn spawn<T, R, E>(
+        transport: &Arc<Mutex<T>>,
+        policy: ThermalPolicy,
+        read: R,
+        on_event: E,
+    ) -> Self
+    where
+        T: Transport + Send + 'static,
+        R: FnMut(&mut T) -> TransportResult<f32> + Send + 'static,
+        E: FnMut(&ThermalEvent) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let state = Arc::new(Mutex::new(ThermalState::new(policy)));
+        let handle = std::thread::spawn({
+            let stop = stop.clone();
+            let state = state.clone();
+            let transport = Arc::downgrade(transport);
+            let mut read: ReadFn<T> = Box::new(read);
+            let mut on_event: EventFn = Box::new(on_event);
+            move || {
+                let mut due = Instant::now();
+                while !stop.load(Ordering::Relaxed) {
+                    let now = Instant::now();
+                    if due > now {
+                        std::thread::sleep((due - now).min(MAX_SLEEP));
+                        continue;
+                    }
+                    due += policy.period;
+                    // Don't try to catch up if we fell behind
+                    if due < now {
+                        due = now + policy.period;
+                    }
+                    let Some(tarc) = transport.upgrade() else {
+                        return;
+                    };
+                    // A poisoned lock means some other user of the transport panicked, which we
+                    // can't recover from here
+                    let Ok(mut t) = tarc.lock() else {
+                        return;
+                    };
+                    let sample = read(&mut t);
+                    let Ok(mut s) = state.lock() else {
+                        return;
+                    };
+                    let Ok(temperature) = sample else {
+                        s.errors += 1;
+                        continue;
+                    };
+                    let Some(previous) = s.update(temperature) else {
+                        continue;
+                    };
+                    let level = s.level;
+                    drop(s);
+                    let deprogrammed = (level == ThermalLevel::Critical
+                        && policy.deprogram_on_critical)
+                        .then(|| t.deprogram());
+                    drop(t);
+                    on_event(&ThermalEvent {
+                        level,
+                        previous,
+                        temperature,
+                        time: SystemTime::now(),
+                        deprogrammed,
+                    });
+                }
+            }
+        });
+        Self {
+            stop,
+            state,
+            handle: Some(handle),
+        }
+    }
+
+    /// Spawn a watchdog thread on a TAPCP board, using its [`temperature`] endpoint
+    ///
+    /// [`temperature`]: crate::transport::tapcp::Tapcp::temperature
+    #[cfg(feature = "tapcp")]
+    pub fn spawn_tapcp<E>(
+        transport: &Arc<Mutex<crate::transport::tapcp::Tapcp>>,
+        policy: ThermalPolicy,
+        on_event: E,
+    ) -> Self
+    where
+        E: FnMut(&ThermalEvent) + Send + 'static,
+    {
+        Self::spawn(transport, policy, |t| Ok(t.temperature()?), on_event)
+    }
+
+    /// The statistics of the latest samples
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn stats(&self) -> ThermalStats {
+        // The thread never panics while holding the state
+        self.state.lock().unwrap().stats()
+    }
+
+    /// Whether the watchdog thread is still running
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|h| !h.is_finished())
+    }
+
+    /// Stop the watchdog thread and wait for it to exit
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ThermalWatchdog {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::sim::SimulatedFpga;
+    use casper_utils::design_sources::fpg::read_fpg_file;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_thermal_state() {
+        let mut state = ThermalState::new(ThermalPolicy {
+            consecutive: 2,
+            window: 4,
+            ..ThermalPolicy::default()
+        });
+        // A single hot sample doesn't count
+        assert_eq!(state.update(85.0), None);
+        assert_eq!(state.update(70.0), None);
+        assert_eq!(state.update(85.0), None);
+        assert_eq!(state.update(95.0), None);
+        assert_eq!(state.update(96.0), Some(ThermalLevel::Normal));
+        assert_eq!(state.level, ThermalLevel::Critical);
+        assert_eq!(state.update(60.0), None);
+        assert_eq!(state.update(60.0), Some(ThermalLevel::Critical));
+
+        let summary = state.stats();
+        assert_eq!(summary.samples, 4);
+        assert_eq!(summary.last, Some(60.0));
+        assert_eq!((summary.min, summary.max), (60.0, 96.0));
+        assert!((summary.mean - 77.75).abs() < 1e-4);
+        assert_eq!(summary.level, Some(ThermalLevel::Normal));
+    }
+
+    #[test]
+    fn test_watchdog_deprograms() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let transport = Arc::new(Mutex::new(SimulatedFpga::new(&design)));
+        // A board that heats up by a degree a sample
+        let mut temperature = 85.0;
+        let (tx, rx) = channel();
+        let watchdog = ThermalWatchdog::spawn(
+            &transport,
+            ThermalPolicy {
+                period: Duration::from_millis(1),
+                ..ThermalPolicy::default()
+            },
+            move |_| {
+                temperature += 1.0;
+                Ok(temperature)
+            },
+            move |event| {
+                let _ = tx.send((event.previous, event.level, event.deprogrammed.is_some()));
+            },
+        );
+        assert_eq!(
+            rx.recv().unwrap(),
+            (ThermalLevel::Normal, ThermalLevel::Warning, false)
+        );
+        assert_eq!(
+            rx.recv().unwrap(),
+            (ThermalLevel::Warning, ThermalLevel::Critical, true)
+        );
+        assert!(!transport.lock().unwrap().is_running().unwrap());
+        let stats = watchdog.stats();
+        assert!(stats.samples >= 7 && stats.max >= 92.0);
+        assert_eq!(stats.level, Some(ThermalLevel::Critical));
+        watchdog.stop();
+    }
+}
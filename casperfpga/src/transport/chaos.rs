@@ -0,0 +1,613 @@
+//! A transport wrapper that injects faults, for testing how code copes with a misbehaving link
+//!
+//! [`Chaos`] forwards to the transport it wraps, but on every read and write it may (with the
+//! probabilities in its [`ChaosConfig`]) delay, drop the request, drop the response, answer a read
+//! with the stale response of an earlier identical read (or repeat a write), hold a write back
+//! until after the next one, or flip a bit of the data. Faults are drawn from a seeded generator,
+//! so a failing test replays exactly. The same generator is available on its own as [`Faults`],
+//! and drives the datagrams of a [`LossyLink`], a UDP relay for running the real network
+//! transports over a lossy link.
+//!
+//! This is behind the `chaos` feature so downstream crates can enable it for their tests only,
+//! i.e. with `casperfpga = { version = "*", features = ["chaos"] }` in their dev-dependencies.
+use super::{
+    Transport,
+    TransportResult,
+};
+use crate::core::RegisterMap;
+use casper_utils::design_sources::FpgaDesign;
+use std::{
+    collections::HashMap,
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("The request to `{0}` was dropped")]
+    DroppedRequest(String),
+    #[error("The response from `{0}` was dropped")]
+    DroppedResponse(String),
+}
+
+/// How often each kind of fault happens, as probabilities per transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChaosConfig {
+    /// The seed of the fault generator
+    pub seed: u64,
+    /// The transaction never reaches the inner transport
+    pub drop_request: f64,
+    /// The transaction is made, but its result is lost
+    pub drop_response: f64,
+    /// A read returns the response of the previous identical read, a write is made twice
+    pub duplicate: f64,
+    /// A random bit of the data read or written is flipped
+    pub bit_flip: f64,
+    /// A read returns the response of the previous identical read as it arrives late, a write is
+    /// made after the next one
+    pub reorder: f64,
+    /// Every transaction is delayed by a random time up to this
+    pub max_delay: Duration,
+}
+
+impl Default for ChaosConfig {
+    /// No faults at all
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            drop_request: 0.0,
+            drop_response: 0.0,
+            duplicate: 0.0,
+            bit_flip: 0.0,
+            reorder: 0.0,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// A fault to inject into one transaction
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Fault {
+    DropRequest,
+    DropResponse,
+    Duplicate,
+    /// Flip the bit with this index, modulo the number of bits in the data
+    BitFlip(usize),
+    Reorder,
+}
+
+/// A seeded source of faults, drawing at most one per transaction
+#[derive(Debug, Clone)]
+pub struct Faults {
+    config: ChaosConfig,
+    state: u64,
+}
+
+impl Faults {
+    /// Draw faults with the probabilities (and seed) of `config`
+    #[must_use]
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            config,
+            state: config.seed,
+        }
+    }
+
+    /// The next value of the generator (splitmix64)
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A uniform value in `[0, 1)`
+    #[allow(clippy::cast_precision_loss)]
+    fn unit(&mut self) -> f64 {
+        // The top 53 bits are exactly representable
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Draw the fault for the next transaction, if any
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn roll(&mut self) -> Option<Fault> {
+        let config = self.config;
+        let draw = self.unit();
+        let mut threshold = 0.0;
+        for (p, fault) in [
+            (config.drop_request, Fault::DropRequest),
+            (config.drop_response, Fault::DropResponse),
+            (config.duplicate, Fault::Duplicate),
+            (config.bit_flip, Fault::BitFlip(0)),
+            (config.reorder, Fault::Reorder),
+        ] {
+            threshold += p;
+            if draw < threshold {
+                return Some(match fault {
+                    Fault::BitFlip(_) => Fault::BitFlip(self.next_u64() as usize),
+                    fault => fault,
+                });
+            }
+        }
+        None
+    }
+
+    /// Draw the delay for the next transaction
+    pub fn delay(&mut self) -> Duration {
+        if self.config.max_delay.is_zero() {
+            Duration::ZERO
+        } else {
+            self.config.max_delay.mul_f64(self.unit())
+        }
+    }
+}
+
+/// How many of each fault a [`Chaos`] transport or [`LossyLink`] has injected
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub transactions: usize,
+    pub dropped_requests: usize,
+    pub dropped_responses: usize,
+    pub duplicates: usize,
+    pub bit_flips: usize,
+    pub reordered: usize,
+}
+
+/// A transport that forwards to `T`, injecting faults along the way, see the module docs
+#[derive(Debug)]
+pub struct Chaos<T> {
+    inner: T,
+    faults: Faults,
+    max_delay: Duration,
+    /// The last response of every read, for stale duplicates
+    responses: HashMap<(String, usize, usize), Vec<u8>>,
+    /// A reordered write waiting for the next one, as the device, offset, and data
+    held: Option<(String, usize, Vec<u8>)>,
+    stats: ChaosStats,
+}
+
+fn flip(data: &mut [u8], bit: usize) {
+    if !data.is_empty() {
+        let bit = bit % (data.len() * 8);
+        data[bit / 8] ^= 1 << (bit % 8);
+    }
+}
+
+impl<T> Chaos<T>
+where
+    T: Transport,
+{
+    /// Wrap `inner`, injecting faults according to `config`
+    pub fn new(inner: T, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            faults: Faults::new(config),
+            max_delay: config.max_delay,
+            responses: HashMap::new(),
+            held: None,
+            stats: ChaosStats::default(),
+        }
+    }
+
+    /// Get a reference to the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped transport, bypassing the faults
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwrap the chaos, returning the transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// The faults injected so far
+    pub fn stats(&self) -> &ChaosStats {
+        &self.stats
+    }
+
+    /// Delay and draw the fault for the next transaction
+    fn next_fault(&mut self) -> Option<Fault> {
+        self.stats.transactions += 1;
+        if !self.max_delay.is_zero() {
+            std::thread::sleep(self.faults.delay());
+        }
+        let fault = self.faults.roll();
+        match fault {
+            Some(Fault::DropRequest) => self.stats.dropped_requests += 1,
+            Some(Fault::DropResponse) => self.stats.dropped_responses += 1,
+            Some(Fault::Duplicate) => self.stats.duplicates += 1,
+            Some(Fault::BitFlip(_)) => self.stats.bit_flips += 1,
+            Some(Fault::Reorder) => self.stats.reordered += 1,
+            None => {}
+        }
+        fault
+    }
+}
+
+impl<T> Transport for Chaos<T>
+where
+    T: Transport,
+{
+    fn is_running(&mut self) -> TransportResult<bool> {
+        self.inner.is_running()
+    }
+
+    fn read_n_bytes(&mut self, device: &str, offset: usize, n: usize) -> TransportResult<Vec<u8>> {
+        let key = (device.to_string(), offset, n);
+        let fault = self.next_fault();
+        if fault == Some(Fault::DropRequest) {
+            return Err(Error::DroppedRequest(device.to_string()).into());
+        }
+        let mut bytes = self.inner.read_n_bytes(device, offset, n)?;
+        let stale = self.responses.insert(key, bytes.clone());
+        match fault {
+            Some(Fault::DropResponse) => Err(Error::DroppedResponse(device.to_string()).into()),
+            // Without an earlier response there's nothing to duplicate (or arrive late)
+            Some(Fault::Duplicate | Fault::Reorder) => Ok(stale.unwrap_or(bytes)),
+            Some(Fault::BitFlip(bit)) => {
+                flip(&mut bytes, bit);
+                Ok(bytes)
+            }
+            _ => Ok(bytes),
+        }
+    }
+
+    fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+        let held = self.held.take();
+        let res = match self.next_fault() {
+            Some(Fault::DropRequest) => Err(Error::DroppedRequest(device.to_string()).into()),
+            Some(Fault::DropResponse) => {
+                self.inner.write_bytes(device, offset, data)?;
+                Err(Error::DroppedResponse(device.to_string()).into())
+            }
+            Some(Fault::Duplicate) => {
+                self.inner.write_bytes(device, offset, data)?;
+                self.inner.write_bytes(device, offset, data)
+            }
+            Some(Fault::BitFlip(bit)) => {
+                let mut data = data.to_vec();
+                flip(&mut data, bit);
+                self.inner.write_bytes(device, offset, &data)
+            }
+            Some(Fault::Reorder) => {
+                self.held = Some((device.to_string(), offset, data.to_vec()));
+                Ok(())
+            }
+            None => self.inner.write_bytes(device, offset, data),
+        };
+        // The write held back from before lands after this one
+        if let Some((device, offset, data)) = held {
+            self.inner.write_bytes(&device, offset, &data)?;
+        }
+        res
+    }
+
+    fn listdev(&mut self) -> TransportResult<RegisterMap> {
+        self.inner.listdev()
+    }
+
+    fn program<D>(&mut self, design: &D, force: bool) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        self.inner.program(design, force)
+    }
+
+    fn deprogram(&mut self) -> TransportResult<()> {
+        self.inner.deprogram()
+    }
+}
+
+/// Which way a datagram is going through a [`LossyLink`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    ToTarget,
+    ToClient,
+}
+
+/// The state of a [`LossyLink`] relay
+struct Relay {
+    front: UdpSocket,
+    target: SocketAddr,
+    faults: Faults,
+    /// A socket to the target for every client, so the target can tell them apart
+    clients: Vec<(SocketAddr, UdpSocket)>,
+    /// A reordered datagram waiting for the next one going the same way, with its client
+    held: [Option<(SocketAddr, Vec<u8>)>; 2],
+    stats: Arc<Mutex<ChaosStats>>,
+}
+
+impl Relay {
+    /// Send `datagram` on for `client` going `direction`, unless a fault gets in the way
+    fn forward(&mut self, client: SocketAddr, datagram: &[u8], direction: Direction) {
+        let fault = self.faults.roll();
+        if !self.faults.config.max_delay.is_zero() {
+            std::thread::sleep(self.faults.delay());
+        }
+        let mut stats = self
+            .stats
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        stats.transactions += 1;
+        let times = match (fault, direction) {
+            (Some(Fault::DropRequest), Direction::ToTarget) => {
+                stats.dropped_requests += 1;
+                0
+            }
+            (Some(Fault::DropResponse), Direction::ToClient) => {
+                stats.dropped_responses += 1;
+                0
+            }
+            // A corrupt datagram fails its checksum, so it's lost all the same
+            (Some(Fault::BitFlip(_)), _) => {
+                stats.bit_flips += 1;
+                0
+            }
+            (Some(Fault::Duplicate), _) => {
+                stats.duplicates += 1;
+                2
+            }
+            (Some(Fault::Reorder), _) => {
+                stats.reordered += 1;
+                let slot = &mut self.held[direction as usize];
+                let earlier = slot.replace((client, datagram.to_vec()));
+                drop(stats);
+                if let Some((client, earlier)) = earlier {
+                    self.send(client, &earlier, direction);
+                }
+                return;
+            }
+            _ => 1,
+        };
+        drop(stats);
+        for _ in 0..times {
+            self.send(client, datagram, direction);
+        }
+        if let Some((client, held)) = self.held[direction as usize].take() {
+            self.send(client, &held, direction);
+        }
+    }
+
+    fn send(&mut self, client: SocketAddr, datagram: &[u8], direction: Direction) {
+        match direction {
+            Direction::ToClient => {
+                self.front.send_to(datagram, client).ok();
+            }
+            Direction::ToTarget => {
+                if let Some((_, socket)) = self.clients.iter().find(|(c, _)| *c == client) {
+                    socket.send_to(datagram, self.target).ok();
+                }
+            }
+        }
+    }
+
+    /// Relay whatever datagrams are waiting, returning false if there weren't any
+    fn poll(&mut self) -> bool {
+        let mut buf = [0; 2048];
+        let mut busy = false;
+        while let Ok((n, client)) = self.front.recv_from(&mut buf) {
+            busy = true;
+            if !self.clients.iter().any(|(c, _)| *c == client) {
+                let Ok(socket) = UdpSocket::bind("127.0.0.1:0") else {
+                    continue;
+                };
+                socket.set_nonblocking(true).ok();
+                self.clients.push((client, socket));
+            }
+            self.forward(client, &buf[..n], Direction::ToTarget);
+        }
+        for idx in 0..self.clients.len() {
+            while let Ok(n) = self.clients[idx].1.recv(&mut buf) {
+                busy = true;
+                let client = self.clients[idx].0;
+                self.forward(client, &buf[..n], Direction::ToClient);
+            }
+        }
+        busy
+    }
+}
+
+/// A UDP relay on localhost to `target`, injecting the faults of a [`ChaosConfig`] into every
+/// datagram that passes through it. Requests are dropped on their way to the target and responses
+/// on their way back, corrupt datagrams are lost (as they'd fail their checksum), and reordered
+/// ones are sent after the next datagram going the same way. Point a network transport at
+/// [`LossyLink::addr`] instead of the target to run it over the link. The relay runs until the
+/// link is dropped.
+#[derive(Debug)]
+pub struct LossyLink {
+    addr: SocketAddr,
+    stats: Arc<Mutex<ChaosStats>>,
+    stop: Arc<AtomicBool>,
+    relay: Option<JoinHandle<()>>,
+}
+
+impl LossyLink {
+    /// Start relaying to `target` with the faults of `config`
+    /// # Errors
+    /// Returns an error if the relay's socket can't be set up
+    pub fn start(target: SocketAddr, config: ChaosConfig) -> std::io::Result<Self> {
+        let front = UdpSocket::bind("127.0.0.1:0")?;
+        front.set_nonblocking(true)?;
+        let addr = front.local_addr()?;
+        let stats = Arc::new(Mutex::new(ChaosStats::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut relay = Relay {
+            front,
+            target,
+            faults: Faults::new(config),
+            clients: vec![],
+            held: [None, None],
+            stats: stats.clone(),
+        };
+        let relay = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if !relay.poll() {
+                        std::thread::sleep(Duration::from_micros(100));
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            stats,
+            stop,
+            relay: Some(relay),
+        })
+    }
+
+    /// The address to send to instead of the target
+    #[must_use]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The faults injected so far, counting every datagram as a transaction
+    #[must_use]
+    pub fn stats(&self) -> ChaosStats {
+        self.stats
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Drop for LossyLink {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(relay) = self.relay.take() {
+            relay.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+
+    fn scratchpad() -> Mock {
+        Mock::new(HashMap::from([(
+            "sys_scratchpad".into(),
+            Register { addr: 0, length: 4 },
+        )]))
+    }
+
+    #[test]
+    fn test_faults_are_seeded() {
+        let config = ChaosConfig {
+            seed: 7,
+            drop_request: 0.1,
+            drop_response: 0.1,
+            duplicate: 0.1,
+            bit_flip: 0.1,
+            ..ChaosConfig::default()
+        };
+        let draws = |n| {
+            let mut faults = Faults::new(config);
+            (0..n).map(|_| faults.roll()).collect::<Vec<_>>()
+        };
+        let first = draws(1000);
+        assert_eq!(first, draws(1000));
+        // Roughly the configured rates
+        let clean = first.iter().filter(|f| f.is_none()).count();
+        assert!((500..700).contains(&clean));
+        assert_eq!(Faults::new(ChaosConfig::default()).roll(), None);
+    }
+
+    #[test]
+    fn test_verified_write_under_chaos() {
+        let mut chaos = Chaos::new(
+            scratchpad(),
+            ChaosConfig {
+                seed: 42,
+                bit_flip: 0.3,
+                ..ChaosConfig::default()
+            },
+        );
+        // Flipped writes and reads are caught by the readback and retried. Stale duplicates aren't
+        // in here as they can fool a readback, which is as true of a real link.
+        for value in 0u32..50 {
+            chaos
+                .verified_write_bytes("sys_scratchpad", 0, &value.to_be_bytes(), 20)
+                .unwrap();
+            assert_eq!(
+                chaos
+                    .inner_mut()
+                    .read::<u32, 4>("sys_scratchpad", 0)
+                    .unwrap(),
+                value
+            );
+        }
+        assert!(chaos.stats().bit_flips > 0);
+
+        // Drops surface as errors
+        let mut chaos = Chaos::new(
+            scratchpad(),
+            ChaosConfig {
+                drop_response: 1.0,
+                ..ChaosConfig::default()
+            },
+        );
+        assert!(chaos.write("sys_scratchpad", 0, &7u32).is_err());
+        // but the write still landed
+        assert_eq!(
+            chaos
+                .inner_mut()
+                .read::<u32, 4>("sys_scratchpad", 0)
+                .unwrap(),
+            7
+        );
+    }
+
+    #[test]
+    fn test_reordered_writes() {
+        let mut chaos = Chaos::new(
+            scratchpad(),
+            ChaosConfig {
+                reorder: 1.0,
+                ..ChaosConfig::default()
+            },
+        );
+        // Every write is held back until the next one, which overtakes it
+        chaos.write("sys_scratchpad", 0, &1u32).unwrap();
+        assert_eq!(
+            chaos
+                .inner_mut()
+                .read::<u32, 4>("sys_scratchpad", 0)
+                .unwrap(),
+            0
+        );
+        chaos.write("sys_scratchpad", 0, &2u32).unwrap();
+        assert_eq!(
+            chaos
+                .inner_mut()
+                .read::<u32, 4>("sys_scratchpad", 0)
+                .unwrap(),
+            1
+        );
+        assert_eq!(chaos.stats().reordered, 2);
+    }
+}
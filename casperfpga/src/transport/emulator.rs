@@ -0,0 +1,567 @@
+//! An in-process TAPCP server standing in for a SNAP board, for testing the TAPCP transport and
+//! the code built on it without hardware
+//!
+//! The [`Emulator`] speaks just enough TFTP to serve the files the TAPCP client asks for:
+//! `/listdev`, `/dev` and `/flash` reads and writes, `/progdev`, `/help`, and `/temp`. Its flash
+//! starts blank (aside from an empty metadata dictionary) and its gateware is a [`SimulatedFpga`],
+//! booted from the flash when `/progdev` points at a user image holding the bitstream of the
+//! [`Board::with_design`] design. Like the real server, every device read or write of an
+//! unprogrammed board fails with [`ErrorCode::NoFile`]. The rest of the [`Board`] sets how the
+//! server behaves, i.e. which TFTP options it accepts, and records what the client asked of it.
+//!
+//! This is behind the `emulator` feature so downstream crates can enable it for their tests only,
+//! i.e. with `casperfpga = { version = "*", features = ["emulator"] }` in their dev-dependencies.
+use super::{
+    sim::SimulatedFpga,
+    tapcp::Platform,
+    Transport,
+};
+use casper_utils::design_sources::FpgaDesign;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        MutexGuard,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use tftp_client::parser::ErrorCode;
+
+/// The size of the emulated flash
+pub const FLASH_SIZE: usize = 0x0100_0000;
+/// The TFTP block size without a `blksize` option
+const BLOCK: usize = 512;
+/// The commands the board lists in `/help` unless told otherwise
+const HELP: &str = "/dev /flash /help /listdev /progdev /temp\n";
+
+type Setup = Box<dyn Fn(&mut SimulatedFpga) + Send>;
+
+/// How the board answers TFTP options
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptionSupport {
+    /// Acknowledge the options it knows with an OACK, like a server implementing RFC 2347
+    Accept,
+    /// Carry on as if there were no options, like the real server
+    #[default]
+    Ignore,
+    /// Fail any request with options
+    Refuse,
+}
+
+/// A read or write request the board got
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// Where it came from
+    pub client: SocketAddr,
+    /// The whole packet, options and all
+    pub packet: Vec<u8>,
+}
+
+impl Request {
+    /// True for write requests
+    #[must_use]
+    pub fn is_write(&self) -> bool {
+        self.packet.get(1) == Some(&2)
+    }
+
+    /// The file the request is for
+    #[must_use]
+    pub fn filename(&self) -> String {
+        let name = self.packet[2..]
+            .split(|&b| b == 0)
+            .next()
+            .unwrap_or_default();
+        String::from_utf8_lossy(name).into_owned()
+    }
+}
+
+/// The state of the emulated board
+pub struct Board {
+    /// The contents of the flash
+    pub flash: Vec<u8>,
+    /// The running gateware, if the board was programmed
+    pub fpga: Option<SimulatedFpga>,
+    /// Every address `/progdev` was written with, in order
+    pub progdevs: Vec<u32>,
+    /// Files read back and overwritten as they are, taking the place of anything the board would
+    /// otherwise serve under the same name (i.e. `/help`)
+    pub files: HashMap<String, Vec<u8>>,
+    /// Files every request for is refused with an access violation
+    pub refused: HashSet<String>,
+    /// Every read and write request, in order
+    pub requests: Vec<Request>,
+    /// How TFTP options are answered
+    pub options: OptionSupport,
+    /// The largest `blksize` accepted
+    pub max_block_size: usize,
+    /// Send blocks of this many bytes no matter what was negotiated, like a broken server
+    pub block_size: Option<usize>,
+    design: Option<(Box<dyn FpgaDesign + Send>, Setup)>,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Board")
+            .field("fpga", &self.fpga)
+            .field("progdevs", &self.progdevs)
+            .field("files", &self.files.keys().collect::<Vec<_>>())
+            .field("refused", &self.refused)
+            .field("requests", &self.requests.len())
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Board {
+    /// An unprogrammed board that nothing can be booted on, taking TFTP options like the real
+    /// server (by ignoring them)
+    #[must_use]
+    pub fn new() -> Self {
+        let mut flash = vec![0xFF; FLASH_SIZE];
+        let meta = Platform::SNAP.spec().flash_location as usize;
+        flash[meta..meta + 4].copy_from_slice(b"?end");
+        Self {
+            flash,
+            fpga: None,
+            progdevs: vec![],
+            files: HashMap::new(),
+            refused: HashSet::new(),
+            requests: vec![],
+            options: OptionSupport::default(),
+            max_block_size: BLOCK,
+            block_size: None,
+            design: None,
+        }
+    }
+
+    /// An unprogrammed board that can be programmed with `design`, running `setup` on the
+    /// simulated gateware every time it boots (i.e. to install hooks playing the part of the
+    /// design)
+    #[must_use]
+    pub fn with_design<D, F>(design: D, setup: F) -> Self
+    where
+        D: FpgaDesign + Send + 'static,
+        F: Fn(&mut SimulatedFpga) + Send + 'static,
+    {
+        Self {
+            design: Some((Box::new(design), Box::new(setup))),
+            ..Self::new()
+        }
+    }
+
+    /// The running gateware, panicking if the board wasn't programmed
+    /// # Panics
+    /// Panics if nothing is running
+    pub fn fpga(&mut self) -> &mut SimulatedFpga {
+        self.fpga.as_mut().expect("The board isn't programmed")
+    }
+
+    /// The filenames of every write request, in order
+    #[must_use]
+    pub fn writes(&self) -> Vec<String> {
+        self.requests
+            .iter()
+            .filter(|r| r.is_write())
+            .map(Request::filename)
+            .collect()
+    }
+
+    fn boot(&mut self, addr: u32) {
+        self.progdevs.push(addr);
+        let spec = Platform::SNAP.spec();
+        let image = spec.program_location as usize;
+        self.fpga = self.design.as_ref().and_then(|(design, setup)| {
+            let bitstream = design.bitstream();
+            (addr == spec.progdev_address(spec.program_location)
+                && self.flash[image..image + bitstream.len()] == bitstream[..])
+                .then(|| {
+                    let mut fpga = SimulatedFpga::new(design.as_ref());
+                    setup(&mut fpga);
+                    fpga
+                })
+        });
+    }
+
+    fn read(&mut self, filename: &str) -> Result<Vec<u8>, String> {
+        if let Some(file) = self.files.get(filename) {
+            return Ok(file.clone());
+        }
+        match filename {
+            "/help" => Ok(HELP.as_bytes().to_vec()),
+            "/temp" => Ok(42.5f32.to_be_bytes().to_vec()),
+            "/listdev" => {
+                let fpga = self.fpga.as_mut().ok_or("Not programmed")?;
+                let mut devices: Vec<_> = fpga
+                    .listdev()
+                    .map_err(|e| e.to_string())?
+                    .into_iter()
+                    .collect();
+                devices.sort_by(|a, b| a.0.cmp(&b.0));
+                Ok(csl(devices.iter().map(|(name, reg)| {
+                    let addr = u32::try_from(reg.addr).unwrap_or(u32::MAX);
+                    let length = u32::try_from(reg.length).unwrap_or(u32::MAX);
+                    (
+                        name.as_str(),
+                        [addr.to_be_bytes(), length.to_be_bytes()].concat(),
+                    )
+                })))
+            }
+            _ => {
+                if let Some(args) = filename.strip_prefix("/flash.") {
+                    let (offset, n) = args.split_once('.').ok_or("Bad flash read")?;
+                    let start = 4 * word(offset)?;
+                    let end = start + 4 * word(n)?;
+                    return self
+                        .flash
+                        .get(start..end)
+                        .map(<[u8]>::to_vec)
+                        .ok_or_else(|| "Read past the flash".into());
+                }
+                let args = filename.strip_prefix("/dev/").ok_or("No such file")?;
+                let mut parts = args.split('.');
+                let device = parts.next().unwrap_or_default();
+                let offset = 4 * parts.next().map_or(Ok(0), word)?;
+                let n = 4 * parts.next().map_or(Ok(0), word)?;
+                let fpga = self.fpga.as_mut().ok_or("Not programmed")?;
+                let n = if n == 0 {
+                    let regs = fpga.listdev().map_err(|e| e.to_string())?;
+                    regs.get(device).ok_or("No such device")?.length - offset
+                } else {
+                    n
+                };
+                fpga.read_n_bytes(device, offset, n)
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    fn write(&mut self, filename: &str, at: usize, data: &[u8]) -> Result<(), String> {
+        if let Some(file) = self.files.get_mut(filename) {
+            file.truncate(at);
+            file.extend_from_slice(data);
+            return Ok(());
+        }
+        if filename == "/progdev" {
+            let addr = data.get(..4).ok_or("Short progdev")?;
+            self.boot(u32::from_be_bytes([addr[0], addr[1], addr[2], addr[3]]));
+            return Ok(());
+        }
+        if let Some(offset) = filename.strip_prefix("/flash.") {
+            let start = 4 * word(offset)? + at;
+            self.flash
+                .get_mut(start..start + data.len())
+                .ok_or("Write past the flash")?
+                .copy_from_slice(data);
+            return Ok(());
+        }
+        if let Some(args) = filename.strip_prefix("/dev/") {
+            let (device, offset) = args.split_once('.').unwrap_or((args, "0"));
+            let offset = 4 * word(offset)? + at;
+            return self
+                .fpga
+                .as_mut()
+                .ok_or("Not programmed")?
+                .write_bytes(device, offset, data)
+                .map_err(|e| e.to_string());
+        }
+        Err("No such file".into())
+    }
+}
+
+/// A hexadecimal word count or offset from a filename
+fn word(hex: &str) -> Result<usize, String> {
+    usize::from_str_radix(hex, 16).map_err(|_| format!("Bad number `{hex}`"))
+}
+
+/// Pack sorted `(key, payload)` entries into a compact sorted list, reusing the common prefix of
+/// consecutive keys like the real server
+fn csl<'a, I>(entries: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = (&'a str, Vec<u8>)>,
+{
+    let mut bytes = vec![8];
+    let mut last: Option<&str> = None;
+    for (key, payload) in entries {
+        let shared = last.map_or(0, |last| {
+            last.bytes()
+                .zip(key.bytes())
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        if last.is_some() {
+            bytes.push(u8::try_from(shared).unwrap_or(u8::MAX));
+        }
+        let tail = &key[shared..];
+        bytes.push(u8::try_from(tail.len()).unwrap_or(u8::MAX));
+        bytes.extend_from_slice(tail.as_bytes());
+        bytes.extend_from_slice(&payload);
+        last = Some(key);
+    }
+    bytes.extend_from_slice(&[0, 0]);
+    bytes
+}
+
+/// An open transfer with one client
+enum Transfer {
+    Read {
+        data: Vec<u8>,
+        block_size: usize,
+    },
+    Write {
+        filename: String,
+        last: u16,
+        block_size: usize,
+    },
+}
+
+/// A TAPCP server on localhost, running until it's dropped
+#[derive(Debug)]
+pub struct Emulator {
+    addr: SocketAddr,
+    board: Arc<Mutex<Board>>,
+    stop: Arc<AtomicBool>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl Emulator {
+    /// Start serving `board`
+    /// # Panics
+    /// Panics if the server's socket can't be bound
+    #[must_use]
+    pub fn start(board: Board) -> Self {
+        let board = Arc::new(Mutex::new(board));
+        let socket = UdpSocket::bind("127.0.0.1:0").expect("Binding the emulator's socket");
+        socket
+            .set_read_timeout(Some(Duration::from_millis(20)))
+            .expect("Setting the emulator's timeout");
+        let addr = socket.local_addr().expect("Getting the emulator's address");
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = {
+            let board = board.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || serve(&socket, &board, &stop))
+        };
+        Self {
+            addr,
+            board,
+            stop,
+            server: Some(server),
+        }
+    }
+
+    /// The address to connect to
+    #[must_use]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The state of the board, holding off the server while it's borrowed
+    /// # Panics
+    /// Panics if the server panicked
+    pub fn board(&self) -> MutexGuard<'_, Board> {
+        self.board.lock().expect("The emulator panicked")
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(server) = self.server.take() {
+            server.join().ok();
+        }
+    }
+}
+
+fn error(code: ErrorCode, msg: &str) -> Vec<u8> {
+    [&[0, 5, 0, code as u8], msg.as_bytes(), &[0]].concat()
+}
+
+fn data(block: u16, data: &[u8]) -> Vec<u8> {
+    [&[0, 3], &block.to_be_bytes()[..], data].concat()
+}
+
+fn ack(block: u16) -> Vec<u8> {
+    [&[0, 4], &block.to_be_bytes()[..]].concat()
+}
+
+/// The block `block` (counting from one) of `data`
+fn chunk(data: &[u8], block: u16, block_size: usize) -> &[u8] {
+    let start = ((usize::from(block) - 1) * block_size).min(data.len());
+    &data[start..data.len().min(start + block_size)]
+}
+
+/// The option acknowledgement for the `(name, value)` options of a request for `size` bytes (if
+/// it's a read), and the block size it settles on
+fn oack(
+    options: &[(String, String)],
+    size: Option<usize>,
+    max_block_size: usize,
+) -> (Vec<u8>, usize) {
+    let mut reply = vec![0, 6];
+    let mut block_size = BLOCK;
+    for (name, value) in options {
+        let value = match name.to_ascii_lowercase().as_str() {
+            "tsize" => size.map_or_else(|| value.clone(), |size| size.to_string()),
+            "timeout" => value.clone(),
+            "blksize" => {
+                block_size = value.parse().unwrap_or(BLOCK).min(max_block_size);
+                block_size.to_string()
+            }
+            _ => continue,
+        };
+        for s in [name, &value] {
+            reply.extend_from_slice(s.as_bytes());
+            reply.push(0);
+        }
+    }
+    (reply, block_size)
+}
+
+/// Handle the read or write request `packet`
+fn request(
+    board: &mut Board,
+    transfers: &mut HashMap<SocketAddr, Transfer>,
+    client: SocketAddr,
+    packet: &[u8],
+) -> Vec<u8> {
+    board.requests.push(Request {
+        client,
+        packet: packet.to_vec(),
+    });
+    let mut fields = packet[2..]
+        .split(|&b| b == 0)
+        .map(|f| String::from_utf8_lossy(f).into_owned());
+    let filename = fields.next().unwrap_or_default();
+    let _mode = fields.next();
+    let fields: Vec<_> = fields.filter(|f| !f.is_empty()).collect();
+    let options: Vec<_> = fields
+        .chunks_exact(2)
+        .map(|kv| (kv[0].clone(), kv[1].clone()))
+        .collect();
+    if board.refused.contains(&filename) {
+        return error(ErrorCode::Access, "Refused");
+    }
+    let negotiate = !options.is_empty() && board.options == OptionSupport::Accept;
+    if !options.is_empty() && board.options == OptionSupport::Refuse {
+        return error(ErrorCode::BadOpt, "Bad option");
+    }
+    if packet[1] == 1 {
+        let bytes = match board.read(&filename) {
+            Ok(bytes) => bytes,
+            Err(msg) => return error(ErrorCode::NoFile, &msg),
+        };
+        let (reply, block_size) = if negotiate {
+            oack(&options, Some(bytes.len()), board.max_block_size)
+        } else {
+            (vec![], BLOCK)
+        };
+        let block_size = board.block_size.unwrap_or(block_size);
+        // Without an OACK to acknowledge, the data starts right away
+        let reply = if reply.is_empty() {
+            data(1, chunk(&bytes, 1, block_size))
+        } else {
+            reply
+        };
+        transfers.insert(
+            client,
+            Transfer::Read {
+                data: bytes,
+                block_size,
+            },
+        );
+        reply
+    } else {
+        let (reply, block_size) = if negotiate {
+            oack(&options, None, board.max_block_size)
+        } else {
+            (ack(0), BLOCK)
+        };
+        transfers.insert(
+            client,
+            Transfer::Write {
+                filename,
+                last: 0,
+                block_size,
+            },
+        );
+        reply
+    }
+}
+
+fn serve(socket: &UdpSocket, board: &Mutex<Board>, stop: &AtomicBool) {
+    let mut buf = vec![0; 65536];
+    let mut transfers: HashMap<SocketAddr, Transfer> = HashMap::new();
+    while !stop.load(Ordering::Relaxed) {
+        let Ok((n, client)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        let Ok(mut board) = board.lock() else {
+            return;
+        };
+        let reply = match buf[..n] {
+            [0, 1 | 2, ..] => request(&mut board, &mut transfers, client, &buf[..n]),
+            [0, 3, hi, lo, ref bytes @ ..] => {
+                let block = u16::from_be_bytes([hi, lo]);
+                match transfers.get_mut(&client) {
+                    Some(Transfer::Write {
+                        filename,
+                        last,
+                        block_size,
+                    }) => {
+                        // Retransmits are acknowledged again but only applied once
+                        if block == *last + 1 {
+                            let at = (usize::from(block) - 1) * *block_size;
+                            if let Err(msg) = board.write(filename, at, bytes) {
+                                transfers.remove(&client);
+                                socket.send_to(&error(ErrorCode::NoFile, &msg), client).ok();
+                                continue;
+                            }
+                            *last = block;
+                        }
+                        ack(block)
+                    }
+                    _ => error(ErrorCode::BadId, "No write in progress"),
+                }
+            }
+            [0, 4, hi, lo] => {
+                let block = u16::from_be_bytes([hi, lo]);
+                match transfers.get(&client) {
+                    // The last block was short, so the read is done
+                    Some(Transfer::Read {
+                        data: bytes,
+                        block_size,
+                    }) if block > 0 && usize::from(block) * block_size > bytes.len() => {
+                        transfers.remove(&client);
+                        continue;
+                    }
+                    Some(Transfer::Read {
+                        data: bytes,
+                        block_size,
+                    }) => data(block + 1, chunk(bytes, block + 1, *block_size)),
+                    _ => continue,
+                }
+            }
+            _ => error(ErrorCode::Op, "Unexpected packet"),
+        };
+        socket.send_to(&reply, client).ok();
+    }
+}
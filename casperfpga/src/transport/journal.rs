@@ -0,0 +1,307 @@
+//! Journaling register writes for idempotent re-initialization
+//!
+//! Re-running a whole bringup script against a board that's already up resets cores and drops
+//! links for no reason. Instead, run the bringup once through a [`Journaling`] transport, which
+//! forwards everything to the transport it wraps and keeps the last value written to every
+//! register location in a [`Journal`]. The journal can be saved as [plain text](crate::text), one
+//! write per line as `<device> <hex offset> <hex bytes>`, and on later runs [`Journal::reconcile`]
+//! compares it against the board and only rewrites the locations that drifted.
+//!
+//! Only the final state of each location is kept, so strobes (i.e. a reset toggled high then low)
+//! aren't repeated when reconciling. Write-only registers never read back as written and are
+//! rewritten every time, so leave them out with [`Journal::retain`].
+
+use super::{
+    Transport,
+    TransportResult,
+};
+use crate::{
+    core::RegisterMap,
+    text::{
+        from_hex,
+        records,
+        to_hex,
+    },
+};
+use casper_utils::design_sources::FpgaDesign;
+use std::{
+    collections::BTreeMap,
+    io::{
+        BufRead,
+        Write,
+    },
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Internal system IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed journal on line {line} - {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+/// A journaled location whose value on the board differs from what was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Drift {
+    pub device: String,
+    /// The byte offset of the write
+    pub offset: usize,
+    /// The value currently on the board
+    pub current: Vec<u8>,
+    /// The value in the journal
+    pub intended: Vec<u8>,
+}
+
+/// The intended state of a set of register locations, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Journal {
+    /// The last bytes written, by device and byte offset
+    pub writes: BTreeMap<(String, usize), Vec<u8>>,
+}
+
+impl Journal {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `data` was written to `device` at `offset`, replacing any earlier write to the
+    /// same location
+    pub fn record(&mut self, device: &str, offset: usize, data: &[u8]) {
+        self.writes
+            .insert((device.to_string(), offset), data.to_vec());
+    }
+
+    /// Only keep the writes to the devices for which `keep` returns true
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&str) -> bool,
+    {
+        self.writes.retain(|(device, _), _| keep(device));
+    }
+
+    /// Compare the journal against the board without writing anything, returning the locations
+    /// that differ in device and offset order
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn diff<T>(&self, transport: &mut T) -> TransportResult<Vec<Drift>>
+    where
+        T: Transport,
+    {
+        let mut drifts = vec![];
+        for ((device, offset), intended) in &self.writes {
+            let current = transport.read_n_bytes(device, *offset, intended.len())?;
+            if &current != intended {
+                drifts.push(Drift {
+                    device: device.clone(),
+                    offset: *offset,
+                    current,
+                    intended: intended.clone(),
+                });
+            }
+        }
+        Ok(drifts)
+    }
+
+    /// Rewrite every location that differs from the journal, verifying each write with a readback
+    /// (retrying up to `retries` times), and return what was fixed
+    /// # Errors
+    /// Returns an error on bad transport or if a location never read back as intended
+    pub fn reconcile<T>(&self, transport: &mut T, retries: usize) -> TransportResult<Vec<Drift>>
+    where
+        T: Transport,
+    {
+        let drifts = self.diff(transport)?;
+        for drift in &drifts {
+            transport.verified_write_bytes(
+                &drift.device,
+                drift.offset,
+                &drift.intended,
+                retries,
+            )?;
+        }
+        Ok(drifts)
+    }
+
+    /// Write the journal as text to `writer`
+    /// # Errors
+    /// Returns an error on IO failures
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        for ((device, offset), data) in &self.writes {
+            writeln!(writer, "{device} {offset:x} {}", to_hex(data))?;
+        }
+        Ok(())
+    }
+
+    /// Read a journal from text written by [`Journal::write_to`]
+    /// # Errors
+    /// Returns an error on IO failures or malformed lines
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut journal = Self::new();
+        for record in records(reader) {
+            let (line, record) = record?;
+            let parse_err = |reason: &str| Error::Parse {
+                line,
+                reason: reason.to_string(),
+            };
+            let mut fields = record.split_whitespace();
+            let (Some(device), Some(offset), Some(hex), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(parse_err("expected `<device> <hex offset> <hex bytes>`"));
+            };
+            let offset =
+                usize::from_str_radix(offset, 16).map_err(|_| parse_err("invalid offset"))?;
+            let data = from_hex(hex).map_err(|e| parse_err(&e.to_string()))?;
+            journal.record(device, offset, &data);
+        }
+        Ok(journal)
+    }
+}
+
+/// A transport that forwards to `T`, journaling every successful write
+#[derive(Debug)]
+pub struct Journaling<T> {
+    inner: T,
+    journal: Journal,
+}
+
+impl<T> Journaling<T>
+where
+    T: Transport,
+{
+    /// Wrap `inner` with an empty journal
+    pub fn new(inner: T) -> Self {
+        Self::with_journal(inner, Journal::new())
+    }
+
+    /// Wrap `inner`, adding to `journal`
+    pub fn with_journal(inner: T, journal: Journal) -> Self {
+        Self { inner, journal }
+    }
+
+    /// The writes journaled so far
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Get a reference to the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped transport. Writes made through this reference are
+    /// not journaled.
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwrap the transport, returning it and the journal
+    pub fn into_parts(self) -> (T, Journal) {
+        (self.inner, self.journal)
+    }
+}
+
+impl<T> Transport for Journaling<T>
+where
+    T: Transport,
+{
+    fn is_running(&mut self) -> TransportResult<bool> {
+        self.inner.is_running()
+    }
+
+    fn read_n_bytes(&mut self, device: &str, offset: usize, n: usize) -> TransportResult<Vec<u8>> {
+        self.inner.read_n_bytes(device, offset, n)
+    }
+
+    fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+        self.inner.write_bytes(device, offset, data)?;
+        self.journal.record(device, offset, data);
+        Ok(())
+    }
+
+    fn listdev(&mut self) -> TransportResult<RegisterMap> {
+        self.inner.listdev()
+    }
+
+    fn program<D>(&mut self, design: &D, force: bool) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        self.inner.program(design, force)
+    }
+
+    fn deprogram(&mut self) -> TransportResult<()> {
+        self.inner.deprogram()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_journal_reconcile() {
+        let mock = Mock::new(HashMap::from([
+            ("gain".into(), Register { addr: 0, length: 8 }),
+            ("rst".into(), Register { addr: 8, length: 4 }),
+            (
+                "status".into(),
+                Register {
+                    addr: 12,
+                    length: 4,
+                },
+            ),
+        ]));
+        // The bringup run
+        let mut transport = Journaling::new(mock);
+        transport.write("gain", 0, &10u32).unwrap();
+        transport.write("gain", 4, &20u32).unwrap();
+        transport.write("rst", 0, &1u32).unwrap();
+        transport.write("rst", 0, &0u32).unwrap();
+        let (mut mock, journal) = transport.into_parts();
+        assert_eq!(journal.writes.len(), 3);
+
+        // Round trip through the text format
+        let mut text = vec![];
+        journal.write_to(&mut text).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&text).unwrap(),
+            "gain 0 0000000a\ngain 4 00000014\nrst 0 00000000\n"
+        );
+        let journal = Journal::read_from(text.as_slice()).unwrap();
+
+        // Nothing to do on a board that's still up
+        assert!(journal.reconcile(&mut mock, 0).unwrap().is_empty());
+        // Only the drifted location is rewritten
+        mock.write("gain", 4, &99u32).unwrap();
+        mock.write("status", 0, &1u32).unwrap();
+        let fixed = journal.reconcile(&mut mock, 0).unwrap();
+        assert_eq!(
+            fixed,
+            vec![Drift {
+                device: "gain".into(),
+                offset: 4,
+                current: vec![0, 0, 0, 99],
+                intended: vec![0, 0, 0, 20],
+            }]
+        );
+        assert_eq!(mock.read::<u32, 4>("gain", 4).unwrap(), 20);
+        assert_eq!(mock.read::<u32, 4>("status", 0).unwrap(), 1);
+
+        assert!(matches!(
+            Journal::read_from("gain 0a0\n".as_bytes()),
+            Err(Error::Parse { line: 1, .. })
+        ));
+        assert!(matches!(
+            Journal::read_from("gain zz 00\n".as_bytes()),
+            Err(Error::Parse { line: 1, .. })
+        ));
+    }
+}
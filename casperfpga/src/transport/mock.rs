@@ -1,10 +1,13 @@
 //! Mock transport implementations used in testing the interface
 
 use super::{
+    check_access,
+    check_bounds,
     Transport,
     TransportResult,
 };
 use crate::core::{
+    AccessMap,
     Register,
     RegisterMap,
 };
@@ -17,6 +20,8 @@ use thiserror::Error;
 pub struct Mock {
     memory: HashMap<usize, u8>,
     registers: RegisterMap,
+    raw: bool,
+    access: AccessMap,
 }
 
 #[derive(Debug, Error)]
@@ -38,7 +43,23 @@ impl Mock {
                 memory.insert(addr + i, 0u8);
             }
         }
-        Self { memory, registers }
+        Self {
+            memory,
+            registers,
+            raw: false,
+            access: AccessMap::new(),
+        }
+    }
+
+    /// Allow reads and writes that run past the end of a register into neighboring memory
+    pub fn set_raw_access(&mut self, raw: bool) {
+        self.raw = raw;
+    }
+
+    /// Reject reads and writes the registers' access modes don't allow, i.e. from
+    /// [`FpgaDesign::access_map`]. Registers not in the map are unrestricted.
+    pub fn set_access_map(&mut self, access: AccessMap) {
+        self.access = access;
     }
 }
 
@@ -48,6 +69,10 @@ impl Transport for Mock {
     }
 
     fn read_n_bytes(&mut self, device: &str, offset: usize, n: usize) -> TransportResult<Vec<u8>> {
+        check_access(&self.access, device, false)?;
+        if !self.raw {
+            check_bounds(&self.registers, device, offset, n)?;
+        }
         // Get the address in memory
         let dev = self
             .registers
@@ -78,11 +103,16 @@ impl Transport for Mock {
             .registers
             .get(device)
             .ok_or_else(|| super::Error::DeviceNotFound(device.to_string()))?;
-        if dev.length - offset < data.len() {
-            return Err(Error::Addressing.into());
+        check_access(&self.access, device, true)?;
+        if !self.raw {
+            check_bounds(&self.registers, device, offset, data.len())?;
         }
         for (i, byte) in data.iter().enumerate() {
-            self.memory.insert(dev.addr + i + offset, *byte);
+            let byte_ref = self
+                .memory
+                .get_mut(&(dev.addr + i + offset))
+                .ok_or(Error::Addressing)?;
+            *byte_ref = *byte;
         }
         Ok(())
     }
@@ -204,6 +234,97 @@ mod tests {
         assert_eq!(read_bytes, write_bytes);
     }
 
+    #[test]
+    fn test_verified_write() {
+        let mut transport = Mock::new(HashMap::from([(
+            "sys_scratchpad".into(),
+            Register { addr: 0, length: 4 },
+        )]));
+        transport
+            .verified_write("sys_scratchpad", 0, &0xDEAD_BEEFu32, 0)
+            .unwrap();
+        let read_num: u32 = transport.read("sys_scratchpad", 0).unwrap();
+        assert_eq!(read_num, 0xDEAD_BEEF);
+    }
+
+    /// A mock where the top bits of every byte are stuck low
+    struct StuckBits(Mock);
+
+    impl Transport for StuckBits {
+        fn is_running(&mut self) -> TransportResult<bool> {
+            self.0.is_running()
+        }
+
+        fn read_n_bytes(
+            &mut self,
+            device: &str,
+            offset: usize,
+            n: usize,
+        ) -> TransportResult<Vec<u8>> {
+            self.0.read_n_bytes(device, offset, n)
+        }
+
+        fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+            let masked: Vec<_> = data.iter().map(|b| b & 0x0F).collect();
+            self.0.write_bytes(device, offset, &masked)
+        }
+
+        fn listdev(&mut self) -> TransportResult<RegisterMap> {
+            self.0.listdev()
+        }
+
+        fn program<D>(&mut self, design: &D, force: bool) -> TransportResult<()>
+        where
+            D: FpgaDesign,
+        {
+            self.0.program(design, force)
+        }
+
+        fn deprogram(&mut self) -> TransportResult<()> {
+            self.0.deprogram()
+        }
+    }
+
+    #[test]
+    fn test_verified_write_mismatch() {
+        let mut transport = StuckBits(Mock::new(HashMap::from([(
+            "sys_scratchpad".into(),
+            Register { addr: 0, length: 4 },
+        )])));
+        match transport.verified_write_bytes("sys_scratchpad", 0, &[0xFF, 0x01, 0x02, 0xF3], 2) {
+            Err(super::super::Error::VerifyMismatch {
+                expected, actual, ..
+            }) => {
+                assert_eq!(expected, vec![0xFF, 0x01, 0x02, 0xF3]);
+                assert_eq!(actual, vec![0x0F, 0x01, 0x02, 0x03]);
+            }
+            r => panic!("Expected a verification mismatch, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let mut transport = Mock::new(HashMap::from([
+            ("sys_scratchpad".into(), Register { addr: 0, length: 4 }),
+            ("sys_clkcounter".into(), Register { addr: 4, length: 4 }),
+        ]));
+        assert!(matches!(
+            transport.read_n_bytes("sys_scratchpad", 0, 4096),
+            Err(super::super::Error::OutOfBounds { size: 4, .. })
+        ));
+        assert!(matches!(
+            transport.write_bytes("sys_scratchpad", 2, &[1, 2, 3]),
+            Err(super::super::Error::OutOfBounds { .. })
+        ));
+        // Opting out lets us walk into the neighboring register
+        transport.set_raw_access(true);
+        transport
+            .write_bytes("sys_scratchpad", 2, &[1, 2, 3])
+            .unwrap();
+        let bytes = transport.read_bytes("sys_clkcounter", 0).unwrap();
+        assert_eq!(bytes, [3, 0, 0, 0]);
+    }
+
     test_rw_num!(u8, 42);
     test_rw_num!(u16, 0xDEAD);
     test_rw_num!(u32, 0xDEAD_BEEF);
@@ -1,12 +1,38 @@
 //! Defines all the transport mechanisms for which all casperfpga transports must implement
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(all(feature = "tapcp", any(test, feature = "emulator")))]
+pub mod emulator;
+pub mod journal;
 pub mod mock;
+#[cfg(feature = "tapcp")]
+pub mod pool;
+pub mod recorder;
+pub mod sim;
+#[cfg(feature = "tapcp")]
 pub mod tapcp;
+#[cfg(all(target_os = "linux", feature = "uio"))]
+pub mod uio;
 
 use crate::{
-    core::RegisterMap,
+    core::{
+        Access,
+        AccessMap,
+        RegisterMap,
+    },
     yellow_blocks::Address,
 };
-use casper_utils::design_sources::FpgaDesign;
+use casper_utils::design_sources::{
+    FpgaDesign,
+    SignatureVerifier,
+};
+use std::sync::{
+    atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    Arc,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -16,12 +42,151 @@ pub enum Error {
     Infallible(#[from] std::convert::Infallible),
     #[error("Trying to transport through a packed struct yeilded a packing error")]
     Packing(#[from] packed_struct::PackingError),
+    #[error("The operation was cancelled")]
+    Cancelled,
+    #[error("The transport was dropped along with the struct that owned it")]
+    TransportDropped,
+    #[error("The transport was dropped while these blocks still used it: {}", blocks.join(", "))]
+    TransportDroppedEarly { blocks: Vec<String> },
+    #[error("The transport's lock was poisoned by a panic while it was held")]
+    TransportPoisoned,
+    #[error("The signature of the design with SHA-256 `{0}` didn't verify")]
+    BadSignature(String),
+    #[error("The {n} byte multi-word access at offset {offset} of `{device}` isn't word aligned")]
+    Misaligned {
+        device: String,
+        offset: usize,
+        n: usize,
+    },
     #[error("The requested device was not found - `{0}`")]
     DeviceNotFound(String),
+    #[error("`{device}` is {access}")]
+    AccessDenied { device: String, access: Access },
     #[error(transparent)]
     Mock(#[from] mock::Error),
+    #[cfg(feature = "chaos")]
+    #[error(transparent)]
+    Chaos(#[from] chaos::Error),
+    #[cfg(feature = "tapcp")]
     #[error(transparent)]
     Tapcp(#[from] tapcp::Error),
+    #[error(transparent)]
+    Recorder(#[from] recorder::Error),
+    #[cfg(all(target_os = "linux", feature = "uio"))]
+    #[error(transparent)]
+    Uio(#[from] uio::Error),
+    #[error("Readback of `{device}` at offset {offset} didn't match - expected {expected:02x?}, read {actual:02x?}")]
+    VerifyMismatch {
+        device: String,
+        offset: usize,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    #[error(
+        "Accessing {n} bytes at offset {offset} walks off the end of `{device}` ({size} bytes)"
+    )]
+    OutOfBounds {
+        device: String,
+        offset: usize,
+        n: usize,
+        size: usize,
+    },
+}
+
+/// Checks that accessing `n` bytes at `offset` of `device` stays within the register's bounds as
+/// given by `registers`
+/// # Errors
+/// Returns [`Error::DeviceNotFound`] if the device isn't in the map and [`Error::OutOfBounds`] if
+/// the access would run past the end of the register
+pub fn check_bounds(
+    registers: &RegisterMap,
+    device: &str,
+    offset: usize,
+    n: usize,
+) -> TransportResult<()> {
+    let reg = registers
+        .get(device)
+        .ok_or_else(|| Error::DeviceNotFound(device.to_string()))?;
+    match offset.checked_add(n) {
+        Some(end) if end <= reg.length => Ok(()),
+        _ => Err(Error::OutOfBounds {
+            device: device.to_string(),
+            offset,
+            n,
+            size: reg.length,
+        }),
+    }
+}
+
+/// Checks that `device` may be written (if `write`) or read according to `access`. Devices that
+/// aren't in the map are unrestricted.
+/// # Errors
+/// Returns [`Error::AccessDenied`] if the access isn't allowed
+pub fn check_access(access: &AccessMap, device: &str, write: bool) -> TransportResult<()> {
+    match access.get(device) {
+        Some(&mode) if (write && !mode.writable()) || (!write && !mode.readable()) => {
+            Err(Error::AccessDenied {
+                device: device.to_string(),
+                access: mode,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A flag shared between a long-running operation and whoever supervises it. Cancelling is
+/// cooperative, the operation checks the token between steps (i.e. flash sectors) and returns
+/// [`Error::Cancelled`] at the next one. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// A new token that hasn't been cancelled
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request that every operation holding this token stops at its next check
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [`CancelToken::cancel`] has been called
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Bail out if the token was cancelled
+    /// # Errors
+    /// Returns [`Error::Cancelled`] if the token was cancelled
+    pub fn check(&self) -> TransportResult<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The width of a word on the register bus. Packed registers wider than this are transferred one
+/// word at a time.
+pub const WORD_BYTES: usize = 4;
+
+/// Checks that a multi-word access of `n` bytes at `offset` is made of whole, aligned words
+/// # Errors
+/// Returns [`Error::Misaligned`] if it isn't
+pub fn check_word_aligned(device: &str, offset: usize, n: usize) -> TransportResult<()> {
+    if offset % WORD_BYTES == 0 && n % WORD_BYTES == 0 {
+        Ok(())
+    } else {
+        Err(Error::Misaligned {
+            device: device.to_string(),
+            offset,
+            n,
+        })
+    }
 }
 
 /// All methods involving transports will have this signature
@@ -164,15 +329,28 @@ pub trait Transport {
     }
 
     /// Generically read a `Deserializable` + `Address` type `T` from the connected platform at
-    /// `device` and offset specified in the type's address.
+    /// `device` and offset specified in the type's address. Types wider than a word are read one
+    /// word at a time, lowest address first.
     /// # Errors
-    /// Returns errors on bad transport or deserialization
+    /// Returns errors on bad transport or deserialization, or [`Error::Misaligned`] if a multi-word
+    /// type isn't made of whole, aligned words
     fn read_addr<T, const N: usize>(&mut self, device: &str) -> TransportResult<T>
     where
         T: Deserialize<Chunk = [u8; N]> + Address,
         Error: std::convert::From<<T as Deserialize>::Error>,
     {
-        let bytes: [u8; N] = self.read_bytes(device, T::addr() as usize)?;
+        let offset = T::addr() as usize;
+        let bytes: [u8; N] = if N > WORD_BYTES {
+            check_word_aligned(device, offset, N)?;
+            let mut bytes = [0u8; N];
+            for (i, word) in bytes.chunks_exact_mut(WORD_BYTES).enumerate() {
+                let chunk: [u8; WORD_BYTES] = self.read_bytes(device, offset + i * WORD_BYTES)?;
+                word.copy_from_slice(&chunk);
+            }
+            bytes
+        } else {
+            self.read_bytes(device, offset)?
+        };
         Ok(T::deserialize(bytes)?)
     }
 
@@ -209,15 +387,119 @@ pub trait Transport {
     }
 
     /// Generically write a `Deserializable` + `Address` type `T` from the connected platform at
-    /// `device` and offset specified in the type's address.
+    /// `device` and offset specified in the type's address. Types wider than a word are written
+    /// one word at a time, lowest address first.
     /// # Errors
-    /// Returns errors on bad transport
+    /// Returns errors on bad transport, or [`Error::Misaligned`] if a multi-word type isn't made of
+    /// whole, aligned words
     fn write_addr<T, const N: usize>(&mut self, device: &str, data: &T) -> TransportResult<()>
     where
         T: Serialize<Chunk = [u8; N]> + Address,
     {
         // Create bytes from the data and write with `write_bytes`
-        self.write_bytes(device, T::addr() as usize, &data.serialize())
+        let offset = T::addr() as usize;
+        let bytes = data.serialize();
+        if N > WORD_BYTES {
+            check_word_aligned(device, offset, N)?;
+            for (i, word) in bytes.chunks_exact(WORD_BYTES).enumerate() {
+                self.write_bytes(device, offset + i * WORD_BYTES, word)?;
+            }
+            Ok(())
+        } else {
+            self.write_bytes(device, offset, &bytes)
+        }
+    }
+
+    /// Write `data` to `device` from byte offset `offset`, reading back afterwards to verify the
+    /// write landed. On a mismatch, the write is attempted again up to `retries` more times.
+    /// # Errors
+    /// Returns errors on bad transport or [`Error::VerifyMismatch`] if the readback never matched
+    fn verified_write_bytes(
+        &mut self,
+        device: &str,
+        offset: usize,
+        data: &[u8],
+        retries: usize,
+    ) -> TransportResult<()> {
+        let mut attempt = 0;
+        loop {
+            self.write_bytes(device, offset, data)?;
+            let actual = self.read_n_bytes(device, offset, data.len())?;
+            if actual == data {
+                return Ok(());
+            }
+            if attempt == retries {
+                return Err(Error::VerifyMismatch {
+                    device: device.to_string(),
+                    offset,
+                    expected: data.to_vec(),
+                    actual,
+                });
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Generically write a `Serializable` type `T` to `device` at `offset`, verifying the write
+    /// with a readback. See [`Transport::verified_write_bytes`].
+    /// # Errors
+    /// Returns errors on bad transport or [`Error::VerifyMismatch`] if the readback never matched
+    fn verified_write<T, const N: usize>(
+        &mut self,
+        device: &str,
+        offset: usize,
+        data: &T,
+        retries: usize,
+    ) -> TransportResult<()>
+    where
+        T: Serialize<Chunk = [u8; N]>,
+    {
+        self.verified_write_bytes(device, offset, &data.serialize(), retries)
+    }
+
+    /// Generically write a `Serializable` + `Address` type `T` to `device` at the offset specified
+    /// in the type's address, verifying the write with a readback. Types wider than a word are
+    /// written and read back one word at a time, as with [`Transport::write_addr`] and
+    /// [`Transport::read_addr`]. See [`Transport::verified_write_bytes`] for the retries.
+    /// # Errors
+    /// Returns errors on bad transport, [`Error::Misaligned`] if a multi-word type isn't made of
+    /// whole, aligned words, or [`Error::VerifyMismatch`] if the readback never matched
+    fn verified_write_addr<T, const N: usize>(
+        &mut self,
+        device: &str,
+        data: &T,
+        retries: usize,
+    ) -> TransportResult<()>
+    where
+        T: Serialize<Chunk = [u8; N]> + Address,
+    {
+        let offset = T::addr() as usize;
+        let expected = data.serialize();
+        let mut attempt = 0;
+        loop {
+            self.write_addr(device, data)?;
+            let actual = if N > WORD_BYTES {
+                let mut actual = Vec::with_capacity(N);
+                for word in (0..N).step_by(WORD_BYTES) {
+                    actual.extend(self.read_n_bytes(device, offset + word, WORD_BYTES)?);
+                }
+                actual
+            } else {
+                self.read_n_bytes(device, offset, N)?
+            };
+            if actual == expected {
+                return Ok(());
+            }
+            if attempt == retries {
+                return Err(Error::VerifyMismatch {
+                    device: device.to_string(),
+                    offset,
+                    expected: expected.to_vec(),
+                    actual,
+                });
+            }
+            attempt += 1;
+        }
     }
 
     /// Retrieve a list of available devices on the (potentially programmed) connected platform
@@ -225,6 +507,19 @@ pub trait Transport {
     /// Returns errors on bad transport
     fn listdev(&mut self) -> TransportResult<RegisterMap>;
 
+    /// Check whether `design` is the one running by comparing the fingerprint of the `listdev`
+    /// register map to the design's, see [`crate::core::register_fingerprint`]. Unlike checking
+    /// the md5 in flash metadata, this also works for boards programmed behind our back (i.e. over
+    /// JTAG).
+    /// # Errors
+    /// Returns errors on bad transport
+    fn is_design_loaded<D>(&mut self, design: &D) -> TransportResult<bool>
+    where
+        D: FpgaDesign,
+    {
+        Ok(crate::core::register_fingerprint(&self.listdev()?) == design.register_fingerprint())
+    }
+
     /// Program a bitstream file from `filename` to the connected platform.
     /// Some transports can cache programed bitstreams, so the `force` variable turns off noop-ing
     /// if the bitstream is already programmed.
@@ -234,6 +529,45 @@ pub trait Transport {
     where
         D: FpgaDesign;
 
+    /// Program `design` like [`Transport::program`], stopping early if `cancel` is cancelled.
+    /// Transports that can't be interrupted part way through only check before starting, see the
+    /// individual transports for the state a cancelled programming leaves the board in.
+    /// # Errors
+    /// Returns [`Error::Cancelled`] if cancelled and errors on bad transport
+    fn program_cancellable<D>(
+        &mut self,
+        design: &D,
+        force: bool,
+        cancel: &CancelToken,
+    ) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        cancel.check()?;
+        self.program(design, force)
+    }
+
+    /// Program `design` like [`Transport::program`], but only after checking its detached
+    /// `signature` with `verifier`, so only signed gateware ever reaches the board
+    /// # Errors
+    /// Returns [`Error::BadSignature`] if the signature doesn't verify and errors on bad transport
+    fn program_signed<D, V>(
+        &mut self,
+        design: &D,
+        signature: &[u8],
+        verifier: &V,
+        force: bool,
+    ) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+        V: SignatureVerifier + ?Sized,
+    {
+        if !design.verify_signature(signature, verifier) {
+            return Err(Error::BadSignature(design.sha256_string()));
+        }
+        self.program(design, force)
+    }
+
     /// Deprograms the connected platform
     /// # Errors
     /// Returns errors on bad transport
@@ -0,0 +1,288 @@
+//! A pool of UDP sockets shared by many [`Tapcp`] connections
+//!
+//! Every [`Tapcp`] owns a socket bound to its own local port, so opening one per board from a
+//! thread pool runs out of ephemeral ports on big arrays. A [`TapcpPool`] caps the number of
+//! sockets with [`PoolConfig::max_sockets`] instead. [`TapcpPool::connect`] hands out a [`Tapcp`]
+//! on an idle socket (preferring the one last used for the same board, so the board sees the same
+//! source port), binds a new one if we're under the limit, or reconnects the idle socket of another
+//! board. When every socket is in use, it waits for one to come back. Dropping the [`Tapcp`] gives
+//! its socket back to the pool.
+//!
+//! Each TFTP transfer waits for its answer on a connected socket, so a socket is only ever used for
+//! one board at a time.
+use super::{
+    tapcp::{
+        ConnectConfig,
+        Error,
+        Platform,
+        Tapcp,
+        TAPCP_PORT,
+    },
+    TransportResult,
+};
+use std::{
+    io,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        MutexGuard,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// Limits of a [`TapcpPool`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// The most sockets open at once, i.e. the most boards talked to at the same time
+    pub max_sockets: usize,
+    /// The local address new sockets are bound to, port 0 picks an ephemeral port
+    pub local: SocketAddr,
+    /// The remote TAPCP (TFTP) port
+    pub remote_port: u16,
+    /// How long to wait for a socket when all of them are in use, `None` to wait forever
+    pub wait: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_sockets: 64,
+            local: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            remote_port: TAPCP_PORT,
+            wait: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Sockets {
+    idle: Vec<UdpSocket>,
+    /// Sockets handed out and idle
+    open: usize,
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: PoolConfig,
+    sockets: Mutex<Sockets>,
+    returned: Condvar,
+}
+
+impl Shared {
+    fn sockets(&self) -> MutexGuard<'_, Sockets> {
+        // The socket list is valid no matter where another thread panicked
+        self.sockets
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// The claim of a [`Tapcp`] on a socket of the pool
+#[derive(Debug)]
+pub(crate) struct Lease(Arc<Shared>);
+
+impl Lease {
+    /// Give the socket back, or `None` if it couldn't be kept
+    pub(crate) fn release(self, socket: Option<UdpSocket>) {
+        let mut sockets = self.0.sockets();
+        match socket {
+            Some(socket) => sockets.idle.push(socket),
+            None => sockets.open -= 1,
+        }
+        self.0.returned.notify_one();
+    }
+}
+
+/// A bounded pool of sockets for [`Tapcp`] connections, see the module docs
+///
+/// Clones share the same sockets.
+#[derive(Debug, Clone)]
+pub struct TapcpPool {
+    shared: Arc<Shared>,
+}
+
+impl TapcpPool {
+    #[must_use]
+    pub fn new(config: PoolConfig) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                config,
+                sockets: Mutex::default(),
+                returned: Condvar::new(),
+            }),
+        }
+    }
+
+    /// The limits of the pool
+    #[must_use]
+    pub fn config(&self) -> &PoolConfig {
+        &self.shared.config
+    }
+
+    /// The number of sockets open, in use or idle
+    #[must_use]
+    pub fn open_sockets(&self) -> usize {
+        self.shared.sockets().open
+    }
+
+    /// The number of sockets waiting to be handed out
+    #[must_use]
+    pub fn idle_sockets(&self) -> usize {
+        self.shared.sockets().idle.len()
+    }
+
+    /// Connect to the board at `host` on a socket from the pool, waiting up to
+    /// [`PoolConfig::wait`] for one if they're all in use
+    /// # Errors
+    /// Returns [`Error::PoolExhausted`] if no socket came back in time, or an error if a socket
+    /// fails to bind or connect
+    pub fn connect(&self, host: IpAddr, platform: Platform) -> TransportResult<Tapcp> {
+        let config = self.shared.config;
+        let remote = SocketAddr::new(host, config.remote_port);
+        let socket = self.checkout(remote)?;
+        let config = ConnectConfig {
+            local: socket.local_addr().map_err(Error::from)?,
+            remote_port: config.remote_port,
+            pin_source_port: true,
+        };
+        let mut tapcp = Tapcp::from_socket(socket, remote, platform, config)?;
+        tapcp.lease_to(Lease(self.shared.clone()));
+        Ok(tapcp)
+    }
+
+    /// Take a socket connected to `remote` from the pool
+    fn checkout(&self, remote: SocketAddr) -> Result<UdpSocket, Error> {
+        let start = Instant::now();
+        let mut sockets = self.shared.sockets();
+        loop {
+            if let Some(idx) = sockets
+                .idle
+                .iter()
+                .position(|s| s.peer_addr().ok() == Some(remote))
+            {
+                return Ok(sockets.idle.swap_remove(idx));
+            }
+            if sockets.open < self.shared.config.max_sockets {
+                sockets.open += 1;
+                drop(sockets);
+                return self.bind(remote).map_err(|e| {
+                    Lease(self.shared.clone()).release(None);
+                    e
+                });
+            }
+            if let Some(socket) = sockets.idle.pop() {
+                drop(sockets);
+                return retarget(&socket, remote).map(|()| socket).map_err(|e| {
+                    Lease(self.shared.clone()).release(None);
+                    e.into()
+                });
+            }
+            sockets = match self.shared.config.wait {
+                None => self
+                    .shared
+                    .returned
+                    .wait(sockets)
+                    .unwrap_or_else(std::sync::PoisonError::into_inner),
+                Some(wait) => {
+                    let left = wait
+                        .checked_sub(start.elapsed())
+                        .ok_or(Error::PoolExhausted {
+                            max: self.shared.config.max_sockets,
+                            waited: wait,
+                        })?;
+                    self.shared
+                        .returned
+                        .wait_timeout(sockets, left)
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+
+    fn bind(&self, remote: SocketAddr) -> Result<UdpSocket, Error> {
+        let socket = UdpSocket::bind(self.shared.config.local)?;
+        socket.set_nonblocking(false)?;
+        socket.connect(remote)?;
+        Ok(socket)
+    }
+}
+
+/// Point an idle `socket` at a different board, dropping anything the last one still sent
+fn retarget(socket: &UdpSocket, remote: SocketAddr) -> io::Result<()> {
+    socket.connect(remote)?;
+    socket.set_nonblocking(true)?;
+    let mut buf = [0; 1024];
+    while socket.recv(&mut buf).is_ok() {}
+    socket.set_nonblocking(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_limits() {
+        let boards: Vec<_> = (0..2)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let remote = |idx: usize| boards[idx].local_addr().unwrap();
+        let pool = TapcpPool::new(PoolConfig {
+            max_sockets: 2,
+            local: "127.0.0.1:0".parse().unwrap(),
+            remote_port: remote(0).port(),
+            wait: Some(Duration::from_millis(20)),
+        });
+        let localhost = remote(0).ip();
+        let a = pool.connect(localhost, Platform::SNAP).unwrap();
+        let b = pool.connect(localhost, Platform::SNAP).unwrap();
+        assert_eq!(pool.open_sockets(), 2);
+        assert!(matches!(
+            pool.connect(localhost, Platform::SNAP),
+            Err(crate::transport::Error::Tapcp(Error::PoolExhausted {
+                max: 2,
+                ..
+            }))
+        ));
+        // Dropped connections give their sockets back
+        let (a_local, b_local) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        drop(a);
+        drop(b);
+        assert_eq!(pool.idle_sockets(), 2);
+
+        // A board gets the socket it used last, another board takes over whichever is idle
+        let other = pool.checkout(remote(1)).unwrap();
+        assert_eq!(other.peer_addr().unwrap(), remote(1));
+        assert_eq!(other.local_addr().unwrap(), b_local);
+        let again = pool.connect(localhost, Platform::SNAP).unwrap();
+        assert_eq!(again.local_addr().unwrap(), a_local);
+        Lease(pool.shared.clone()).release(Some(other));
+        assert_eq!(pool.open_sockets(), 2);
+
+        // Waiting threads get the next socket that comes back
+        let pool = TapcpPool::new(PoolConfig {
+            wait: None,
+            ..*pool.config()
+        });
+        let held = pool.connect(localhost, Platform::SNAP).unwrap();
+        let _held_too = pool.connect(localhost, Platform::SNAP).unwrap();
+        let waiter = {
+            let pool = pool.clone();
+            std::thread::spawn(move || pool.connect(localhost, Platform::SNAP).map(|_| ()))
+        };
+        std::thread::sleep(Duration::from_millis(10));
+        drop(held);
+        waiter.join().unwrap().unwrap();
+        assert_eq!(pool.open_sockets(), 2);
+    }
+}
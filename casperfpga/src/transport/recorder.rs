@@ -0,0 +1,437 @@
+//! A transport wrapper that records every register transaction for post-mortem debugging
+//!
+//! The [`Recorder`] forwards everything to the transport it wraps and appends each read and write
+//! to a log as a line of JSON (JSONL), i.e.
+//! ```text
+//! {"time":1700000000.123456,"op":"write","device":"sys_scratchpad","offset":0,"data":"deadbeef","error":null}
+//! ```
+//! A recorded log can be applied to another transport with [`replay`], which repeats every
+//! successful write in order.
+
+use super::{
+    Transport,
+    TransportResult,
+};
+use crate::{
+    core::RegisterMap,
+    text::{
+        from_hex,
+        to_hex,
+    },
+};
+use casper_utils::{
+    design_sources::FpgaDesign,
+    json::quote,
+};
+use std::{
+    collections::HashMap,
+    io::{
+        BufRead,
+        Write,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Internal system IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed transaction on line {line} - {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+/// The kind of a recorded transaction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    Read,
+    Write,
+}
+
+/// A single recorded register transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    /// When the transaction completed
+    pub time: SystemTime,
+    /// Whether this was a read or a write
+    pub op: Op,
+    /// The device that was accessed
+    pub device: String,
+    /// The byte offset into the device
+    pub offset: usize,
+    /// The bytes written, or the bytes read back (empty if the read failed)
+    pub data: Vec<u8>,
+    /// The error message if the transaction failed
+    pub error: Option<String>,
+}
+
+impl Transaction {
+    /// Serialize this transaction as a single line of JSON (without the newline)
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let time = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let op = match self.op {
+            Op::Read => "read",
+            Op::Write => "write",
+        };
+        let error = self
+            .error
+            .as_ref()
+            .map_or_else(|| "null".to_string(), |e| quote(e));
+        format!(
+            r#"{{"time":{time:.6},"op":"{op}","device":{},"offset":{},"data":"{}","error":{error}}}"#,
+            quote(&self.device),
+            self.offset,
+            to_hex(&self.data),
+        )
+    }
+
+    /// Parse a transaction from a line of JSON as written by [`Transaction::to_json`]
+    /// # Errors
+    /// Returns an error with `reason` describing what was wrong with the line
+    pub fn from_json(line: &str) -> Result<Self, String> {
+        let mut fields = parse_object(line)?;
+        let mut take = |key: &str| {
+            fields
+                .remove(key)
+                .ok_or_else(|| format!("missing field `{key}`"))
+        };
+        let time = match take("time")? {
+            Value::Number(n) => n
+                .parse::<f64>()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .map(|d| UNIX_EPOCH + d)
+                .ok_or("invalid `time`")?,
+            _ => return Err("`time` should be a number".to_string()),
+        };
+        let op = match take("op")? {
+            Value::String(s) if s == "read" => Op::Read,
+            Value::String(s) if s == "write" => Op::Write,
+            _ => return Err("`op` should be \"read\" or \"write\"".to_string()),
+        };
+        let Value::String(device) = take("device")? else {
+            return Err("`device` should be a string".to_string());
+        };
+        let offset = match take("offset")? {
+            Value::Number(n) => n.parse().map_err(|_| "invalid `offset`")?,
+            _ => return Err("`offset` should be a number".to_string()),
+        };
+        let data = match take("data")? {
+            Value::String(s) => from_hex(&s).map_err(|_| "invalid hex in `data`")?,
+            _ => return Err("`data` should be a hex string".to_string()),
+        };
+        let error = match take("error")? {
+            Value::String(s) => Some(s),
+            Value::Null => None,
+            Value::Number(_) => return Err("`error` should be a string or null".to_string()),
+        };
+        Ok(Self {
+            time,
+            op,
+            device,
+            offset,
+            data,
+            error,
+        })
+    }
+}
+
+/// A transport that forwards to `T`, logging every read and write to `W`
+#[derive(Debug)]
+pub struct Recorder<T, W> {
+    inner: T,
+    log: W,
+}
+
+impl<T, W> Recorder<T, W>
+where
+    T: Transport,
+    W: Write,
+{
+    /// Wrap `inner`, appending transactions to `log`
+    pub fn new(inner: T, log: W) -> Self {
+        Self { inner, log }
+    }
+
+    /// Get a reference to the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped transport. Transactions made through this reference
+    /// are not recorded.
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwrap the recorder, returning the transport and the log
+    pub fn into_parts(self) -> (T, W) {
+        (self.inner, self.log)
+    }
+
+    fn record(
+        &mut self,
+        op: Op,
+        device: &str,
+        offset: usize,
+        data: &[u8],
+        error: Option<String>,
+    ) -> TransportResult<()> {
+        let transaction = Transaction {
+            time: SystemTime::now(),
+            op,
+            device: device.to_string(),
+            offset,
+            data: data.to_vec(),
+            error,
+        };
+        writeln!(self.log, "{}", transaction.to_json()).map_err(Error::from)?;
+        Ok(())
+    }
+}
+
+impl<T, W> Transport for Recorder<T, W>
+where
+    T: Transport,
+    W: Write,
+{
+    fn is_running(&mut self) -> TransportResult<bool> {
+        self.inner.is_running()
+    }
+
+    fn read_n_bytes(&mut self, device: &str, offset: usize, n: usize) -> TransportResult<Vec<u8>> {
+        let res = self.inner.read_n_bytes(device, offset, n);
+        match &res {
+            Ok(bytes) => self.record(Op::Read, device, offset, bytes, None)?,
+            Err(e) => self.record(Op::Read, device, offset, &[], Some(e.to_string()))?,
+        }
+        res
+    }
+
+    fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+        let res = self.inner.write_bytes(device, offset, data);
+        let error = res.as_ref().err().map(ToString::to_string);
+        self.record(Op::Write, device, offset, data, error)?;
+        res
+    }
+
+    fn listdev(&mut self) -> TransportResult<RegisterMap> {
+        self.inner.listdev()
+    }
+
+    fn program<D>(&mut self, design: &D, force: bool) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        self.inner.program(design, force)
+    }
+
+    fn deprogram(&mut self) -> TransportResult<()> {
+        self.inner.deprogram()
+    }
+}
+
+/// Read a transaction log from `log`
+/// # Errors
+/// Returns an error on IO failures or malformed lines
+pub fn read_log<R>(log: R) -> Result<Vec<Transaction>, Error>
+where
+    R: BufRead,
+{
+    let mut transactions = vec![];
+    for (idx, line) in log.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        transactions.push(
+            Transaction::from_json(&line).map_err(|reason| Error::Parse {
+                line: idx + 1,
+                reason,
+            })?,
+        );
+    }
+    Ok(transactions)
+}
+
+/// Apply every successful write recorded in `log` to `transport`, in order, returning the number
+/// of writes performed. Reads and failed writes are skipped.
+/// # Errors
+/// Returns an error on a malformed log or if any write fails
+pub fn replay<T, R>(transport: &mut T, log: R) -> TransportResult<usize>
+where
+    T: Transport,
+    R: BufRead,
+{
+    let mut writes = 0;
+    for transaction in read_log(log)? {
+        if transaction.op == Op::Write && transaction.error.is_none() {
+            transport.write_bytes(&transaction.device, transaction.offset, &transaction.data)?;
+            writes += 1;
+        }
+    }
+    Ok(writes)
+}
+
+/// The subset of JSON values that appear in a transaction
+enum Value {
+    String(String),
+    Number(String),
+    Null,
+}
+
+/// Parse a flat JSON object of strings, numbers, and nulls
+fn parse_object(line: &str) -> Result<HashMap<String, Value>, String> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = HashMap::new();
+    let skip_ws = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+    if chars.next() != Some('{') {
+        return Err("expected `{`".to_string());
+    }
+    skip_ws(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(fields);
+    }
+    loop {
+        skip_ws(&mut chars);
+        let key = parse_string(&mut chars)?;
+        skip_ws(&mut chars);
+        if chars.next() != Some(':') {
+            return Err("expected `:`".to_string());
+        }
+        skip_ws(&mut chars);
+        let value = match chars.peek() {
+            Some('"') => Value::String(parse_string(&mut chars)?),
+            Some('n') => {
+                if chars.by_ref().take(4).collect::<String>() != "null" {
+                    return Err("expected `null`".to_string());
+                }
+                Value::Null
+            }
+            Some(_) => {
+                let mut num = String::new();
+                while let Some(c) = chars
+                    .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+                {
+                    num.push(c);
+                }
+                if num.is_empty() {
+                    return Err(format!("unexpected value for `{key}`"));
+                }
+                Value::Number(num)
+            }
+            None => return Err("unexpected end of line".to_string()),
+        };
+        fields.insert(key, value);
+        skip_ws(&mut chars);
+        match chars.next() {
+            Some(',') => {}
+            Some('}') => break,
+            _ => return Err("expected `,` or `}`".to_string()),
+        }
+    }
+    Ok(fields)
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    if chars.next() != Some('"') {
+        return Err("expected a string".to_string());
+    }
+    let mut out = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(out),
+            '\\' => match chars.next().ok_or("unterminated string")? {
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or("invalid unicode escape")?;
+                    out.push(c);
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::collections::HashMap;
+
+    fn registers() -> RegisterMap {
+        HashMap::from([
+            ("sys_scratchpad".into(), Register { addr: 0, length: 4 }),
+            ("sys_clkcounter".into(), Register { addr: 4, length: 4 }),
+        ])
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut recorder = Recorder::new(Mock::new(registers()), vec![]);
+        recorder
+            .write("sys_scratchpad", 0, &0xDEAD_BEEFu32)
+            .unwrap();
+        recorder.write("sys_clkcounter", 2, &0x1234u16).unwrap();
+        assert_eq!(
+            recorder.read::<u32, 4>("sys_scratchpad", 0).unwrap(),
+            0xDEAD_BEEF
+        );
+        assert!(recorder.write("not_a_register", 0, &1u8).is_err());
+        let (_, log) = recorder.into_parts();
+
+        let transactions = read_log(log.as_slice()).unwrap();
+        assert_eq!(transactions.len(), 4);
+        assert_eq!(transactions[0].op, Op::Write);
+        assert_eq!(transactions[0].data, vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(transactions[2].op, Op::Read);
+        assert!(transactions[3].error.is_some());
+
+        let mut fresh = Mock::new(registers());
+        assert_eq!(replay(&mut fresh, log.as_slice()).unwrap(), 2);
+        assert_eq!(
+            fresh.read::<u32, 4>("sys_scratchpad", 0).unwrap(),
+            0xDEAD_BEEF
+        );
+        assert_eq!(fresh.read::<u16, 2>("sys_clkcounter", 2).unwrap(), 0x1234);
+    }
+
+    #[test]
+    fn test_transaction_roundtrip() {
+        let transaction = Transaction {
+            time: UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456),
+            op: Op::Read,
+            device: "weird \"name\"\\".to_string(),
+            offset: 12,
+            data: vec![],
+            error: Some("line one\nline two".to_string()),
+        };
+        let parsed = Transaction::from_json(&transaction.to_json()).unwrap();
+        assert_eq!(parsed.device, transaction.device);
+        assert_eq!(parsed.error, transaction.error);
+        assert_eq!(parsed.offset, 12);
+        assert!(Transaction::from_json("{\"op\":\"read\"}").is_err());
+        assert!(read_log("not json\n".as_bytes()).is_err());
+    }
+}
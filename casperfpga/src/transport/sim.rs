@@ -0,0 +1,319 @@
+//! A simulated FPGA for end-to-end testing of bringup sequences without hardware
+//!
+//! [`SimulatedFpga`] is a [`Mock`] built from the register map of an [`FpgaDesign`], so every
+//! register has its real size and bounds. On top of plain memory it emulates a free-running
+//! `sys_clkcounter` and lets tests attach behavior to individual devices with
+//! [`SimulatedFpga::on_read`] and [`SimulatedFpga::on_write`], i.e. a fake ADC controller that
+//! reports lock once it was initialized.
+
+use super::{
+    check_access,
+    mock::Mock,
+    Transport,
+    TransportResult,
+};
+use crate::core::{
+    AccessMap,
+    Register,
+    RegisterMap,
+};
+use casper_utils::design_sources::FpgaDesign;
+use std::{
+    collections::HashMap,
+    time::Instant,
+};
+
+/// The default rate of the simulated `sys_clkcounter` in MHz
+pub const DEFAULT_CLOCK_MHZ: f64 = 250.0;
+
+type ReadHook = Box<dyn FnMut(&mut Mock, usize, usize) -> TransportResult<()> + Send>;
+type WriteHook = Box<dyn FnMut(&mut Mock, usize, &[u8]) -> TransportResult<()> + Send>;
+
+/// A transport that simulates a programmed FPGA running some design
+pub struct SimulatedFpga {
+    memory: Mock,
+    start: Instant,
+    clock_mhz: f64,
+    running: bool,
+    md5: String,
+    access: AccessMap,
+    read_hooks: HashMap<String, ReadHook>,
+    write_hooks: HashMap<String, WriteHook>,
+}
+
+impl std::fmt::Debug for SimulatedFpga {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimulatedFpga")
+            .field("memory", &self.memory)
+            .field("clock_mhz", &self.clock_mhz)
+            .field("running", &self.running)
+            .field("md5", &self.md5)
+            .field("read_hooks", &self.read_hooks.keys().collect::<Vec<_>>())
+            .field("write_hooks", &self.write_hooks.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SimulatedFpga {
+    /// Construct a simulated FPGA that is already running `design`
+    #[must_use]
+    pub fn new<D>(design: &D) -> Self
+    where
+        D: FpgaDesign + ?Sized,
+    {
+        let registers: RegisterMap = design
+            .registers()
+            .iter()
+            .map(|(name, reg)| {
+                (
+                    name.clone(),
+                    Register {
+                        addr: reg.addr as usize,
+                        length: reg.size as usize,
+                    },
+                )
+            })
+            .collect();
+        Self {
+            memory: Mock::new(registers),
+            start: Instant::now(),
+            clock_mhz: DEFAULT_CLOCK_MHZ,
+            running: true,
+            md5: design.md5_string(),
+            access: AccessMap::new(),
+            read_hooks: HashMap::new(),
+            write_hooks: HashMap::new(),
+        }
+    }
+
+    /// Set the rate of the simulated `sys_clkcounter` in MHz
+    #[must_use]
+    pub fn with_clock_rate(mut self, clock_mhz: f64) -> Self {
+        self.clock_mhz = clock_mhz;
+        self
+    }
+
+    /// Reject reads and writes the design's access modes don't allow, like hardware where writes
+    /// to read-only registers are lost. The hooks and [`SimulatedFpga::memory`] are unrestricted,
+    /// so they can still play the part of the design.
+    #[must_use]
+    pub fn with_access_checks<D>(mut self, design: &D) -> Self
+    where
+        D: FpgaDesign,
+    {
+        self.access = design.access_map();
+        self
+    }
+
+    /// Run `f` before every read of `device` with the simulated memory, the offset, and the number
+    /// of bytes being read, i.e. to update a status register. Replaces any previous read hook.
+    pub fn on_read<F>(&mut self, device: &str, f: F)
+    where
+        F: FnMut(&mut Mock, usize, usize) -> TransportResult<()> + Send + 'static,
+    {
+        self.read_hooks.insert(device.to_string(), Box::new(f));
+    }
+
+    /// Run `f` after every successful write to `device` with the simulated memory, the offset, and
+    /// the bytes that were written, i.e. to react to a control register. Replaces any previous
+    /// write hook.
+    pub fn on_write<F>(&mut self, device: &str, f: F)
+    where
+        F: FnMut(&mut Mock, usize, &[u8]) -> TransportResult<()> + Send + 'static,
+    {
+        self.write_hooks.insert(device.to_string(), Box::new(f));
+    }
+
+    /// Direct access to the simulated memory, bypassing the hooks
+    pub fn memory(&mut self) -> &mut Mock {
+        &mut self.memory
+    }
+
+    /// The md5 of the design the simulation is "running"
+    #[must_use]
+    pub fn md5(&self) -> &str {
+        &self.md5
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn clkcounter(&self) -> u32 {
+        let ticks = self.start.elapsed().as_secs_f64() * self.clock_mhz * 1e6;
+        // The counter wraps at 32 bits
+        (ticks as u64 & u64::from(u32::MAX)) as u32
+    }
+}
+
+impl Transport for SimulatedFpga {
+    fn is_running(&mut self) -> TransportResult<bool> {
+        Ok(self.running)
+    }
+
+    fn read_n_bytes(&mut self, device: &str, offset: usize, n: usize) -> TransportResult<Vec<u8>> {
+        check_access(&self.access, device, false)?;
+        if device == "sys_clkcounter" {
+            let count = self.clkcounter();
+            self.memory.write(device, 0, &count)?;
+        }
+        if let Some(hook) = self.read_hooks.get_mut(device) {
+            hook(&mut self.memory, offset, n)?;
+        }
+        self.memory.read_n_bytes(device, offset, n)
+    }
+
+    fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+        check_access(&self.access, device, true)?;
+        self.memory.write_bytes(device, offset, data)?;
+        if let Some(hook) = self.write_hooks.get_mut(device) {
+            hook(&mut self.memory, offset, data)?;
+        }
+        Ok(())
+    }
+
+    fn listdev(&mut self) -> TransportResult<RegisterMap> {
+        self.memory.listdev()
+    }
+
+    fn program<D>(&mut self, design: &D, _force: bool) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        self.md5 = design.md5_string();
+        self.running = true;
+        Ok(())
+    }
+
+    fn deprogram(&mut self) -> TransportResult<()> {
+        self.running = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Access,
+        yellow_blocks::snapadc::controller::Adc16,
+    };
+    use casper_utils::design_sources::{
+        fpg::read_fpg_file,
+        SignatureVerifier,
+    };
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    #[test]
+    fn test_simulated_fpga() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        assert_eq!(sim.md5(), design.md5_string());
+        assert!(sim.is_running().unwrap());
+
+        // The clock counter ticks on its own
+        let first: u32 = sim.read("sys_clkcounter", 0).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second: u32 = sim.read("sys_clkcounter", 0).unwrap();
+        assert_ne!(first, second);
+
+        // Register sizes come from the design
+        assert!(sim.read_n_bytes("sys_clkcounter", 0, 8).is_err());
+
+        // A fake ADC16 controller that reports three chips, all locked
+        sim.on_read("adc16_controller", |mem, _, _| {
+            mem.write_bytes("adc16_controller", 0, &[0b11, 0x30])
+        });
+        let transport = Arc::new(Mutex::new(sim));
+        let adc = Adc16::new(Arc::downgrade(&transport));
+        assert_eq!(adc.supported_chips().unwrap(), 3);
+        assert!(adc.locked().unwrap());
+
+        // Write hooks see every write after it lands
+        let mut sim = transport.lock().unwrap();
+        sim.on_write("pps_trig", |mem, _, data| {
+            let count: u32 = mem.read("pps_cnt", 0)?;
+            if data[3] & 1 == 1 {
+                mem.write("pps_cnt", 0, &(count + 1))?;
+            }
+            Ok(())
+        });
+        sim.write("pps_trig", 0, &1u32).unwrap();
+        sim.write("pps_trig", 0, &0u32).unwrap();
+        assert_eq!(sim.read::<u32, 4>("pps_cnt", 0).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_is_design_loaded() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        assert!(sim.is_design_loaded(&design).unwrap());
+        // A design with a register moved doesn't match, whatever its metadata says
+        let mut other = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        other.registers.get_mut("sys_clkcounter").unwrap().addr += 4;
+        assert!(!sim.is_design_loaded(&other).unwrap());
+    }
+
+    /// Accepts signatures that are the message reversed
+    struct Reversed;
+
+    impl SignatureVerifier for Reversed {
+        fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+            message.iter().rev().eq(signature)
+        }
+    }
+
+    #[test]
+    fn test_program_signed() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        assert_eq!(
+            design.sha256(),
+            casper_utils::digest::sha256(&std::fs::read("examples/grex_gateware.fpg").unwrap())
+        );
+        let mut sim = SimulatedFpga::new(&design);
+        sim.deprogram().unwrap();
+        let mut signature = design.sha256().to_vec();
+        assert!(matches!(
+            sim.program_signed(&design, &signature, &Reversed, false),
+            Err(crate::transport::Error::BadSignature(_))
+        ));
+        assert!(!sim.is_running().unwrap());
+        signature.reverse();
+        sim.program_signed(&design, &signature, &Reversed, false)
+            .unwrap();
+        assert!(sim.is_running().unwrap());
+    }
+
+    #[test]
+    fn test_access_checks() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        assert_eq!(design.access_map()["adc_snap_status"], Access::Read);
+        let mut sim = SimulatedFpga::new(&design).with_access_checks(&design);
+        assert!(matches!(
+            sim.write("adc_snap_status", 0, &1u32),
+            Err(crate::transport::Error::AccessDenied {
+                access: Access::Read,
+                ..
+            })
+        ));
+        // The design itself can still update it
+        sim.memory().write("adc_snap_status", 0, &1u32).unwrap();
+        assert_eq!(sim.read::<u32, 4>("adc_snap_status", 0).unwrap(), 1);
+        sim.write("adc_snap_ctrl", 0, &1u32).unwrap();
+    }
+
+    #[test]
+    fn test_program_cancelled() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        sim.deprogram().unwrap();
+        let cancel = crate::transport::CancelToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            sim.program_cancellable(&design, false, &cancel),
+            Err(crate::transport::Error::Cancelled)
+        ));
+        assert!(!sim.is_running().unwrap());
+    }
+}
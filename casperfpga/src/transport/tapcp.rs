@@ -1,18 +1,63 @@
 //! The casperfpga transport implementations for TAPCP
-use super::{Transport, TransportResult};
-use crate::core::{Register, RegisterMap};
-use casper_utils::design_sources::FpgaDesign;
+use super::{
+    check_access,
+    check_bounds,
+    pool::Lease,
+    CancelToken,
+    Transport,
+    TransportResult,
+};
+use crate::core::{
+    register_fingerprint,
+    AccessMap,
+    Register,
+    RegisterMap,
+};
+use casper_utils::{
+    design_sources::FpgaDesign,
+    digest::sha256,
+};
+#[cfg(feature = "progress")]
 use indicatif::ProgressBar;
 use kstring::KString;
 use std::{
-    collections::HashMap,
-    net::{SocketAddr, UdpSocket},
+    borrow::Cow,
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+        UdpSocket,
+    },
+    ops::Range,
+    sync::atomic::{
+        AtomicBool,
+        AtomicUsize,
+        Ordering,
+    },
     time::Duration,
 };
 use thiserror::Error;
 
+pub use tapcp::{
+    FlashAddr,
+    PlatformCommand,
+    RetryPolicy,
+    SocketStats,
+    TransferOptions,
+};
+
 const DEFAULT_TIMEOUT: f32 = 0.5;
 const DEFAULT_RETRIES: usize = 5;
+// Flash writes can take up to 1s
+const DEFAULT_FLASH_TIMEOUT: f32 = 1.5;
+const FLASH_RETRIES: usize = 8;
+/// The well-known TFTP port that TAPCP servers listen on
+pub const TAPCP_PORT: u16 = 69;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -20,45 +65,585 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("Error from the lower-level TAPCP library")]
     Lower(#[from] tapcp::Error),
+    #[error("Writing {len} bytes at {start:#x} would overlap the golden image region {golden_start:#x}..{golden_end:#x}")]
+    GoldenOverlap {
+        start: u32,
+        len: usize,
+        golden_start: u32,
+        golden_end: u32,
+    },
+    #[error("The golden image is {len} bytes, but the golden region only holds {size}")]
+    GoldenTooLarge { len: usize, size: u32 },
+    #[error(
+        "The {len} byte metadata dictionary doesn't fit in the {region} bytes before the bitstream"
+    )]
+    MetadataTooLarge { len: usize, region: usize },
+    #[error("Slot {slot} doesn't exist, the platform has {slots} design slots")]
+    BadSlot { slot: u32, slots: u32 },
+    #[error("The {len} byte bitstream doesn't fit in the {capacity} bytes of slot {slot}")]
+    SlotTooSmall {
+        slot: u32,
+        len: usize,
+        capacity: u32,
+    },
+    #[error("Slot {0} doesn't hold a completely written design")]
+    EmptySlot(u32),
+    #[error("Expected the board to boot the image with {expected}, but it's running {found}")]
+    WrongImageBooted {
+        expected: ImageIdentity,
+        found: ImageIdentity,
+    },
+    #[error("All {max} sockets of the pool were still in use after {waited:?}")]
+    PoolExhausted { max: usize, waited: Duration },
+    #[error("Bad board inventory entry - {0}")]
+    BadInventory(String),
+    #[error("The board inventory is version {0}, newer than the supported {INVENTORY_VERSION}")]
+    InventoryVersion(u32),
+    #[error("The board came back running the design with md5 {found:?} instead of `{expected}`")]
+    DesignChanged {
+        expected: String,
+        found: Option<String>,
+    },
+    #[error("The board's TAPCP server doesn't support the `{0}` command")]
+    Unsupported(PlatformCommand),
+}
+
+/// The metadata key marking a programming attempt that hasn't finished, holding the md5 of the
+/// design being written
+const PROGRAMMING_KEY: &str = "programming";
+/// The metadata key holding the number of sectors written so far by an unfinished programming
+const PROGRESS_KEY: &str = "programmed_sectors";
+/// How many sectors are written between updates of the progress in the metadata. Each update
+/// rewrites the metadata sector, so this trades the time it takes against how much an interrupted
+/// programming has to check again.
+const PROGRESS_SECTORS: usize = 16;
+
+/// The prefix of the metadata keys reserved for the [`BoardInventory`]
+const INVENTORY_PREFIX: &str = "inventory.";
+/// The version of the inventory layout written by this crate
+pub const INVENTORY_VERSION: u32 = 1;
+
+/// A board's description of itself, stored in its own section of the flash metadata dictionary
+/// so it survives programming
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BoardInventory {
+    /// The board's serial number
+    pub serial: Option<String>,
+    /// The antenna the board is connected to
+    pub antenna: Option<String>,
+    /// Cable lengths in meters, by cable name
+    pub cable_lengths: BTreeMap<String, f64>,
+    /// Any other site-specific entries
+    pub extra: BTreeMap<String, String>,
+}
+
+impl BoardInventory {
+    /// Encode the inventory as metadata entries, all under [`INVENTORY_PREFIX`]
+    fn to_metadata(&self) -> Result<HashMap<KString, String>, Error> {
+        let mut entries = vec![("version".to_string(), INVENTORY_VERSION.to_string())];
+        entries.extend(
+            self.serial
+                .iter()
+                .map(|v| ("serial".to_string(), v.clone())),
+        );
+        entries.extend(
+            self.antenna
+                .iter()
+                .map(|v| ("antenna".to_string(), v.clone())),
+        );
+        entries.extend(
+            self.cable_lengths
+                .iter()
+                .map(|(k, v)| (format!("cable.{k}"), v.to_string())),
+        );
+        for (k, v) in &self.extra {
+            if ["version", "serial", "antenna"].contains(&k.as_str()) || k.starts_with("cable.") {
+                return Err(Error::BadInventory(format!("`{k}` is a reserved name")));
+            }
+            entries.push((k.clone(), v.clone()));
+        }
+        entries
+            .into_iter()
+            .map(|(k, v)| {
+                // The dictionary is stored as ?<key>\t<value> pairs
+                if k.is_empty() || k.contains(['?', '\t']) || v.contains(['?', '\t']) {
+                    return Err(Error::BadInventory(format!(
+                        "`{k}` can't be stored in the flash dictionary"
+                    )));
+                }
+                Ok((format!("{INVENTORY_PREFIX}{k}").into(), v))
+            })
+            .collect()
+    }
+
+    /// Decode the inventory from the metadata, `None` if the board has never had one written
+    fn from_metadata(meta: &HashMap<KString, String>) -> Result<Option<Self>, Error> {
+        let entries = meta
+            .iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix(INVENTORY_PREFIX)?, v)));
+        let mut inventory = Self::default();
+        let mut version = None;
+        for (k, v) in entries {
+            match k {
+                "version" => {
+                    version = Some(v.parse().map_err(|_| {
+                        Error::BadInventory(format!("version `{v}` isn't a number"))
+                    })?);
+                }
+                "serial" => inventory.serial = Some(v.clone()),
+                "antenna" => inventory.antenna = Some(v.clone()),
+                _ => match k.strip_prefix("cable.") {
+                    Some(cable) => {
+                        let length = v.parse().map_err(|_| {
+                            Error::BadInventory(format!("cable `{cable}` length `{v}`"))
+                        })?;
+                        inventory.cable_lengths.insert(cable.to_string(), length);
+                    }
+                    None => {
+                        inventory.extra.insert(k.to_string(), v.clone());
+                    }
+                },
+            }
+        }
+        match version {
+            None => Ok(None),
+            Some(v) if v > INVENTORY_VERSION => Err(Error::InventoryVersion(v)),
+            Some(_) => Ok(Some(inventory)),
+        }
+    }
+}
+
+/// Runtime diagnostics from the board's TAPCP server, see [`Tapcp::server_stats`]. The server
+/// only reports the temperature, so the rest of what it can tell us is which commands it has.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerStats {
+    /// The FPGA temperature in Celsius, `None` if the server doesn't have `/temp` (or failed to
+    /// report it)
+    pub temperature: Option<f32>,
+    /// Every endpoint the server advertised in `/help`
+    pub endpoints: Vec<String>,
+}
+
+impl ServerStats {
+    /// Collect the stats from whichever of the diagnostic `endpoints` exist, reading each with
+    /// `read`, which returns `None` on failure
+    fn collect<F>(endpoints: Vec<String>, mut read: F) -> Self
+    where
+        F: FnMut(&str) -> Option<Vec<u8>>,
+    {
+        let temperature = endpoints
+            .iter()
+            .any(|e| e == "/temp")
+            .then(|| read("/temp"))
+            .flatten()
+            .and_then(|b| Some(f32::from_be_bytes(b.get(..4)?.try_into().ok()?)));
+        Self {
+            temperature,
+            endpoints,
+        }
+    }
+}
+
+/// What the flash metadata says about the user image, see [`Tapcp::verify_programmed`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgramState {
+    /// The last programming finished, leaving the hashes of its design
+    Programmed {
+        md5: Option<String>,
+        sha256: Option<String>,
+    },
+    /// Programming the design with this md5 started but never finished, so the user image is
+    /// probably corrupt past the first `sectors` sectors
+    Interrupted { md5: String, sectors: usize },
+    /// There is no record of a programmed design
+    Unknown,
+}
+
+impl ProgramState {
+    /// The state recorded in a metadata dictionary
+    fn from_metadata(mut meta: HashMap<KString, String>) -> Self {
+        if let Some(md5) = meta.remove(PROGRAMMING_KEY) {
+            let sectors = meta
+                .get(PROGRESS_KEY)
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(0);
+            return Self::Interrupted { md5, sectors };
+        }
+        let md5 = meta.remove("md5");
+        let sha256 = meta.remove("sha256");
+        if md5.is_none() && sha256.is_none() {
+            Self::Unknown
+        } else {
+            Self::Programmed { md5, sha256 }
+        }
+    }
+}
+
+/// A design slot in flash and what it holds, see [`Tapcp::list_stored_designs`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredDesign {
+    /// The index of the slot
+    pub slot: u32,
+    /// The flash address of the slot's bitstream
+    pub location: u32,
+    /// What the slot's metadata says about the design in it
+    pub state: ProgramState,
+}
+
+/// What identifies the image a board is running, see [`Tapcp::verify_boot`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageIdentity {
+    /// The md5 recorded in the metadata of the booted slot, if it has one
+    pub md5: Option<String>,
+    /// The fingerprint of the register map, see [`crate::core::register_fingerprint`]
+    pub registers: String,
+}
+
+impl ImageIdentity {
+    /// The identity the board should report once `design` booted
+    pub fn of<D>(design: &D) -> Self
+    where
+        D: FpgaDesign,
+    {
+        Self {
+            md5: Some(design.md5_string()),
+            registers: design.register_fingerprint(),
+        }
+    }
+}
+
+impl std::fmt::Display for ImageIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "md5 {} and register map {}",
+            self.md5.as_deref().unwrap_or("(none)"),
+            self.registers.get(..16).unwrap_or(&self.registers),
+        )
+    }
+}
+
+/// What [`Tapcp::repair`] had to do
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RepairOutcome {
+    /// Programming wasn't interrupted, so nothing was done
+    Intact,
+    /// Programming was interrupted, so the design was programmed again
+    Reprogrammed,
+}
+
+/// Which image a board ended up running after a recovery attempt
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BootOutcome {
+    /// The user image came up on its own
+    User,
+    /// The user image never came up, so we rebooted into the golden image
+    Golden,
+}
+
+/// The flash layout and boot behavior of a TAPCP platform
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PlatformSpec {
+    /// Flash address of the user metadata dictionary
+    pub flash_location: u32,
+    /// Flash address user bitstreams are written to
+    pub program_location: u32,
+    /// Flash address of the golden (fallback) image
+    pub golden_location: u32,
+    /// Number of bytes reserved for the golden image
+    pub golden_size: u32,
+    /// Right shift applied to flash addresses passed to `progdev` (the "mystery bitshift")
+    pub progdev_shift: u32,
+    /// Number of bytes between consecutive design slots, see [`PlatformSpec::slot_location`]
+    pub slot_size: u32,
+    /// Number of design slots, the first of which is the usual user image
+    pub slots: u32,
+}
+
+impl PlatformSpec {
+    /// Build a spec in the usual layout, where the bitstream starts one flash sector after the
+    /// metadata dictionary and the golden image lives at the start of flash
+    #[must_use]
+    pub const fn with_flash_location(flash_location: u32, progdev_shift: u32) -> Self {
+        Self {
+            flash_location,
+            program_location: flash_location + tapcp::FLASH_SECTOR_SIZE,
+            golden_location: 0,
+            golden_size: flash_location,
+            progdev_shift,
+            slot_size: flash_location,
+            slots: 1,
+        }
+    }
+
+    /// The flash address of the metadata dictionary of design slot `slot`. Every slot has the
+    /// layout of the user image: its metadata, followed by the bitstream at the same offset as
+    /// [`PlatformSpec::program_location`]. Slot 0 is the user image itself.
+    /// # Errors
+    /// Returns [`Error::BadSlot`] if the platform doesn't have that many slots
+    pub fn slot_location(&self, slot: u32) -> Result<u32, Error> {
+        let location = u64::from(self.flash_location) + u64::from(slot) * u64::from(self.slot_size);
+        match u32::try_from(location) {
+            Ok(location) if slot < self.slots => Ok(location),
+            _ => Err(Error::BadSlot {
+                slot,
+                slots: self.slots,
+            }),
+        }
+    }
+
+    /// The flash address of the bitstream of design slot `slot`
+    /// # Errors
+    /// Returns [`Error::BadSlot`] if the platform doesn't have that many slots
+    pub fn slot_program_location(&self, slot: u32) -> Result<u32, Error> {
+        let offset = self.program_location - self.flash_location;
+        self.slot_location(slot)?
+            .checked_add(offset)
+            .ok_or(Error::BadSlot {
+                slot,
+                slots: self.slots,
+            })
+    }
+
+    /// Checks that a `len` byte bitstream fits in design slot `slot`, clear of the next slot and
+    /// the golden image
+    /// # Errors
+    /// Returns an error if the bitstream doesn't fit or the slot doesn't exist
+    pub fn check_slot(&self, slot: u32, len: usize) -> Result<(), Error> {
+        let start = self.slot_program_location(slot)?;
+        let capacity = self
+            .slot_size
+            .saturating_sub(self.program_location - self.flash_location);
+        if len > capacity as usize {
+            return Err(Error::SlotTooSmall {
+                slot,
+                len,
+                capacity,
+            });
+        }
+        self.check_golden_overlap(start, len)
+    }
+
+    /// Checks that writing `len` bytes at the flash address `start` stays clear of the golden image
+    /// # Errors
+    /// Returns [`Error::GoldenOverlap`] if the two regions intersect
+    pub fn check_golden_overlap(&self, start: u32, len: usize) -> Result<(), Error> {
+        let golden_end = u64::from(self.golden_location) + u64::from(self.golden_size);
+        let end = u64::from(start) + len as u64;
+        if len > 0 && u64::from(start) < golden_end && end > u64::from(self.golden_location) {
+            #[allow(clippy::cast_possible_truncation)]
+            return Err(Error::GoldenOverlap {
+                start,
+                len,
+                golden_start: self.golden_location,
+                golden_end: golden_end as u32,
+            });
+        }
+        Ok(())
+    }
+
+    /// Translate a flash address into the argument `progdev` expects for this platform
+    #[must_use]
+    pub const fn progdev_address(&self, flash_addr: u32) -> u32 {
+        flash_addr >> self.progdev_shift
+    }
 }
 
-/// Platforms that support TAPCP
-#[derive(Debug, Copy, Clone)]
+/// Platforms that support TAPCP.
+///
+/// Only the SNAP family has a built-in flash layout, the same two the TAPCP transport of the
+/// Python casperfpga knows about. Other boards running the TAPCP server (i.e. the VCU128) aren't
+/// supported out of the box, as their flash layouts aren't documented anywhere we can check
+/// against - they can still be programmed by describing their layout with [`Platform::Custom`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Platform {
+    /// The SNAP, whose user image starts at 8 MiB into flash
     SNAP,
+    /// The SNAP2, whose user image starts at 12 MiB into flash
     SNAP2,
+    /// Any other board, with an explicit flash layout
+    Custom(PlatformSpec),
 }
 
 impl Platform {
-    fn flash_location(self) -> u32 {
+    /// The flash layout of this platform
+    #[must_use]
+    pub const fn spec(self) -> PlatformSpec {
         match self {
-            Platform::SNAP => 0x0080_0000,
-            Platform::SNAP2 => 0x00C0_0000,
+            Platform::SNAP => PlatformSpec::with_flash_location(0x0080_0000, 8),
+            Platform::SNAP2 => PlatformSpec::with_flash_location(0x00C0_0000, 0),
+            Platform::Custom(spec) => spec,
+        }
+    }
+
+    pub(crate) const fn flash_location(self) -> u32 {
+        self.spec().flash_location
+    }
+}
+
+/// When a [`Tapcp`] connection gives up on a board and re-establishes it, see
+/// [`Tapcp::set_reconnect_policy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Reconnect once this many operations in a row gave up with a timeout (after all of their
+    /// retries)
+    pub after_timeouts: usize,
+    /// The md5 the board's metadata has to report after reconnecting, i.e. the design it was
+    /// running. With `None`, whatever it reports is accepted.
+    pub expected_md5: Option<String>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            after_timeouts: 3,
+            expected_md5: None,
+        }
+    }
+}
+
+/// A connection that was re-established by the [`ReconnectPolicy`], see
+/// [`Tapcp::take_reconnect_events`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectEvent {
+    /// The number of operations in a row that timed out
+    pub timeouts: usize,
+    /// The md5 in the board's metadata after reconnecting, if it has one
+    pub md5: Option<String>,
+    /// Whether the register map used for bounds checking was fetched again
+    pub registers_reloaded: bool,
+}
+
+/// Options controlling the UDP socket of a [`Tapcp`] connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectConfig {
+    /// The local address (and port) to bind to, port 0 picks an ephemeral port
+    pub local: SocketAddr,
+    /// The remote TAPCP (TFTP) port
+    pub remote_port: u16,
+    /// Keep the same source port when the connection is re-established with
+    /// [`Tapcp::reconnect`]. Some tcpborphserver versions only answer the transfer ID (source
+    /// port) they first saw, and firewalls often only allow a fixed one.
+    pub pin_source_port: bool,
+}
+
+impl Default for ConnectConfig {
+    fn default() -> Self {
+        Self {
+            local: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            remote_port: TAPCP_PORT,
+            pin_source_port: true,
         }
     }
+}
+
+/// How bitstreams are written to flash, see [`Tapcp::set_flash_write_config`]
+///
+/// Every TFTP transfer waits for an acknowledgement of each block, so on high-latency links most
+/// of the programming time is spent waiting on round trips rather than on the flash itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashWriteConfig {
+    /// The number of sectors sent in each transfer (at least one). Fewer, larger transfers save
+    /// the request round trips, but boards that only take one sector per write need this at 1.
+    pub sectors_per_write: usize,
+    /// How long to wait after each transfer, for boards that need time to commit a sector
+    pub sector_delay: Duration,
+    /// The number of transfers in flight at once (at least one), each on its own socket. This
+    /// overlaps the round trips of one transfer with the next, but only helps on boards whose
+    /// server handles concurrent transfers. The extra sockets use ephemeral source ports.
+    pub pipeline: usize,
+}
 
-    fn program_location(self) -> u32 {
-        self.flash_location() + tapcp::FLASH_SECTOR_SIZE
+impl Default for FlashWriteConfig {
+    fn default() -> Self {
+        Self {
+            sectors_per_write: 1,
+            sector_delay: Duration::ZERO,
+            pipeline: 1,
+        }
     }
 }
 
 #[derive(Debug)]
 /// A TAPCP Connection (newtype for a [`UdpSocket`])
+///
+/// Every request (and every retry of a request) is sent from the same socket, so the source port
+/// only ever changes through [`Tapcp::reconnect`] with an unpinned [`ConnectConfig`].
 pub struct Tapcp {
     socket: UdpSocket,
-    retries: usize,
+    remote: SocketAddr,
+    config: ConnectConfig,
+    retry: RetryPolicy,
+    /// The TFTP options negotiated on flash reads
+    transfer_options: TransferOptions,
+    /// The most words read per request
+    read_chunk_words: usize,
+    /// Per-attempt socket timeout for register operations
+    timeout: Duration,
+    /// Per-attempt socket timeout for flash sector writes
+    flash_timeout: Duration,
+    /// How bitstreams are split and pipelined when writing flash
+    flash_write: FlashWriteConfig,
+    /// How long to wait for the FPGA to reboot after `progdev`
+    reboot_wait: Duration,
     platform: Platform,
+    /// Register map used to bounds check reads and writes, if we have one
+    registers: Option<RegisterMap>,
+    /// Skip bounds checking even if we have a register map
+    raw: bool,
+    /// Access modes used to reject reads and writes, empty for no restrictions
+    access: AccessMap,
+    /// The last `listdev` response, until something changes what the board is running
+    listdev_cache: Option<RegisterMap>,
+    /// Check devices exist in the `listdev` response before reading or writing them
+    validate_devices: bool,
+    /// Reconnect after persistent timeouts, if set
+    reconnect: Option<ReconnectPolicy>,
+    /// The number of operations in a row that gave up with a timeout
+    timeouts: usize,
+    /// Reconnects the caller hasn't seen yet
+    reconnect_events: Vec<ReconnectEvent>,
+    /// The pool the socket goes back to when we're dropped, if it came from one
+    lease: Option<Lease>,
 }
 
 impl Tapcp {
-    /// Create and connect to a TAPCP transport
+    /// Create and connect to a TAPCP transport, binding to an ephemeral local port
     /// # Errors
     /// Will return an error if the UDP socket fails to connect
     pub fn connect(host: SocketAddr, platform: Platform) -> TransportResult<Self> {
-        let socket = UdpSocket::bind("0.0.0.0:0").map_err(Error::from)?;
-        // Set explicit nonblocking
-        socket.set_nonblocking(false).map_err(Error::from)?;
-        // Set a default timeout
+        let config = ConnectConfig {
+            local: SocketAddr::new(unspecified(host.ip()), 0),
+            remote_port: host.port(),
+            ..ConnectConfig::default()
+        };
+        Self::connect_with(host.ip(), platform, config)
+    }
+
+    /// Create and connect to a TAPCP transport on `host` with explicit socket options
+    /// # Errors
+    /// Will return an error if the UDP socket fails to bind or connect
+    pub fn connect_with(
+        host: IpAddr,
+        platform: Platform,
+        config: ConnectConfig,
+    ) -> TransportResult<Self> {
+        let remote = SocketAddr::new(host, config.remote_port);
+        let socket = open_socket(
+            config.local,
+            remote,
+            Duration::from_secs_f32(DEFAULT_TIMEOUT),
+        )?;
+        Self::from_socket(socket, remote, platform, config)
+    }
+
+    /// Wrap the already connected `socket`, resetting its timeouts to the defaults
+    pub(super) fn from_socket(
+        socket: UdpSocket,
+        remote: SocketAddr,
+        platform: Platform,
+        config: ConnectConfig,
+    ) -> TransportResult<Self> {
         let timeout = Duration::from_secs_f32(DEFAULT_TIMEOUT);
         socket
             .set_write_timeout(Some(timeout))
@@ -66,53 +651,304 @@ impl Tapcp {
         socket
             .set_read_timeout(Some(timeout))
             .map_err(Error::from)?;
-        // Connect
-        socket.connect(host).map_err(Error::from)?;
-        // And return
         Ok(Self {
             socket,
-            retries: DEFAULT_RETRIES,
+            remote,
+            config,
+            retry: RetryPolicy::with_attempts(DEFAULT_RETRIES),
+            transfer_options: TransferOptions::default(),
+            read_chunk_words: tapcp::DEFAULT_READ_CHUNK_WORDS,
+            timeout,
+            flash_timeout: Duration::from_secs_f32(DEFAULT_FLASH_TIMEOUT),
+            flash_write: FlashWriteConfig::default(),
+            reboot_wait: tapcp::DEFAULT_REBOOT_WAIT,
             platform,
+            registers: None,
+            raw: false,
+            access: AccessMap::new(),
+            listdev_cache: None,
+            validate_devices: false,
+            reconnect: None,
+            timeouts: 0,
+            reconnect_events: vec![],
+            lease: None,
         })
     }
-}
 
-// Transport trait implementations
+    /// Give the socket back to the pool it came from when this is dropped
+    pub(super) fn lease_to(&mut self, lease: Lease) {
+        self.lease = Some(lease);
+    }
 
-impl Transport for Tapcp {
-    fn is_running(&mut self) -> TransportResult<bool> {
-        // Check if sys_clkcounter exists
-        match tapcp::read_device("sys_clkcounter", 0, 1, &self.socket, self.retries) {
-            Ok(_) => Ok(true),
-            // In the case we get back a file not found error,
-            // that implies the device is not running a user program.
-            // Any other error is actually an error
-            Err(e) => match e {
-                tapcp::Error::Tftp(tftp_client::Error::Protocol {
-                    code: tftp_client::parser::ErrorCode::NoFile,
-                    msg: _,
-                }) => Ok(false),
-                _ => Err(Error::Lower(e).into()),
-            },
-        }
+    /// The local address of the underlying socket
+    /// # Errors
+    /// Returns an error if the socket address couldn't be determined
+    pub fn local_addr(&self) -> TransportResult<SocketAddr> {
+        Ok(self.socket.local_addr().map_err(Error::from)?)
     }
 
-    fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
-        // The inverted version of `read_vec`. The problem here is if we are not writing a 4 byte
-        // chunk (which we need to), we have to read the bytes that are already there and include
-        // them. Because we don't want to do this read when we don't have to, we will branch
-        if (offset % 4) == 0 && (data.len() % 4) == 0 {
-            // Just do the write
-            tapcp::write_device(device, offset / 4, data, &self.socket, self.retries)
-                .map_err(Error::from)?;
+    /// Tear down and re-establish the UDP socket, i.e. after the board rebooted. With
+    /// [`ConnectConfig::pin_source_port`] set, the new socket is bound to the same local port as
+    /// the old one, otherwise it's bound to [`ConnectConfig::local`] again.
+    /// # Errors
+    /// Returns an error if the new socket fails to bind or connect, in which case the transport is
+    /// left unconnected
+    pub fn reconnect(&mut self) -> TransportResult<()> {
+        let local = if self.config.pin_source_port {
+            self.socket.local_addr().map_err(Error::from)?
         } else {
-            unimplemented!()
-        }
+            self.config.local
+        };
+        // The old socket has to be closed before its port can be bound again
+        let placeholder =
+            UdpSocket::bind(SocketAddr::new(unspecified(local.ip()), 0)).map_err(Error::from)?;
+        drop(std::mem::replace(&mut self.socket, placeholder));
+        // Whatever is on the other end now may not be running the same design
+        self.listdev_cache = None;
+        self.socket = open_socket(local, self.remote, self.timeout)?;
         Ok(())
     }
 
-    fn listdev(&mut self) -> TransportResult<RegisterMap> {
-        let devices = tapcp::listdev(&self.socket, self.retries).map_err(Error::from)?;
+    /// Set the register map used to bounds check reads and writes. With no map (the default),
+    /// bounds are left up to the device.
+    pub fn set_register_map(&mut self, registers: Option<RegisterMap>) {
+        self.registers = registers;
+    }
+
+    /// Fetch the register map from the device with `listdev` and use it for bounds checking
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn load_register_map(&mut self) -> TransportResult<()> {
+        self.registers = Some(self.listdev()?);
+        Ok(())
+    }
+
+    /// Fetch the device list with `listdev` again, replacing the cached one. The cache is dropped
+    /// on its own whenever we program, deprogram, or reconnect, so this is only needed if the
+    /// board was changed behind our back.
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn refresh_listdev(&mut self) -> TransportResult<RegisterMap> {
+        self.listdev_cache = None;
+        self.listdev()
+    }
+
+    /// Check that devices are in the (cached) `listdev` response before reading or writing them,
+    /// so a typo is a [`DeviceNotFound`](super::Error::DeviceNotFound) instead of whatever error
+    /// the board sends back. The first checked access fetches the device list if it isn't cached.
+    pub fn set_validate_devices(&mut self, validate: bool) {
+        self.validate_devices = validate;
+    }
+
+    /// Allow reads and writes that run past the end of a register, even with a register map
+    pub fn set_raw_access(&mut self, raw: bool) {
+        self.raw = raw;
+    }
+
+    /// Reject reads and writes the registers' access modes don't allow, i.e. from
+    /// [`FpgaDesign::access_map`] of the programmed design. Registers not in the map (and every
+    /// register with an empty map, the default) are unrestricted.
+    pub fn set_access_map(&mut self, access: AccessMap) {
+        self.access = access;
+    }
+
+    /// Recover from the board going away (i.e. a power cycle) without the caller's help. Once
+    /// [`ReconnectPolicy::after_timeouts`] register operations in a row gave up with a timeout,
+    /// the socket is re-established with [`Tapcp::reconnect`], `listdev` is re-run (replacing the
+    /// register map if there is one), the design md5 in the metadata is checked, and the operation
+    /// is tried once more. Every reconnect is recorded for [`Tapcp::take_reconnect_events`].
+    /// With `None` (the default), timeouts are returned as they happen.
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect = policy;
+        self.timeouts = 0;
+    }
+
+    /// The reconnects since the last call, oldest first. Register contents (and anything else the
+    /// gateware was configured with) should be assumed lost across each of them.
+    pub fn take_reconnect_events(&mut self) -> Vec<ReconnectEvent> {
+        std::mem::take(&mut self.reconnect_events)
+    }
+
+    /// Set the per-attempt timeout for register operations
+    /// # Errors
+    /// Returns an error if `timeout` is zero
+    pub fn set_timeout(&mut self, timeout: Duration) -> TransportResult<()> {
+        self.apply_timeout(timeout)?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// Set the per-attempt timeout for flash sector writes while programming
+    pub fn set_flash_timeout(&mut self, timeout: Duration) {
+        self.flash_timeout = timeout;
+    }
+
+    /// Set how long to wait for the FPGA to come back after rebooting it (when programming,
+    /// deprogramming, or booting another image), [`tapcp::DEFAULT_REBOOT_WAIT`] by default
+    pub fn set_reboot_wait(&mut self, wait: Duration) {
+        self.reboot_wait = wait;
+    }
+
+    /// Set how bitstreams are written to flash while programming, one sector per transfer and one
+    /// transfer at a time by default
+    pub fn set_flash_write_config(&mut self, config: FlashWriteConfig) {
+        self.flash_write = FlashWriteConfig {
+            sectors_per_write: config.sectors_per_write.max(1),
+            pipeline: config.pipeline.max(1),
+            ..config
+        };
+    }
+
+    /// How bitstreams are written to flash
+    #[must_use]
+    pub fn flash_write_config(&self) -> &FlashWriteConfig {
+        &self.flash_write
+    }
+
+    /// Set how failed requests are retried. The flash writes while programming keep their own
+    /// (larger) number of attempts but otherwise follow this policy.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Set the TFTP options (`tsize`, `timeout`, and `blksize`) to negotiate on flash reads, none
+    /// by default. Boards that don't support them are read without them.
+    pub fn set_transfer_options(&mut self, options: TransferOptions) {
+        self.transfer_options = options;
+    }
+
+    /// The datagram counts of the socket to the board (see [`tapcp::stats`]), which carries every
+    /// request but the extra flash writes of a pipelined [`FlashWriteConfig`]. Reconnecting can
+    /// bind a new socket, which has counts of its own.
+    #[must_use]
+    pub fn socket_stats(&self) -> SocketStats {
+        tapcp::stats::get(&self.socket)
+    }
+
+    /// Start the datagram counts of the socket to the board over
+    pub fn reset_socket_stats(&self) {
+        tapcp::stats::reset(&self.socket);
+    }
+
+    /// Set the most words read per request, larger reads are split into requests of this many
+    /// words (and at least one). Each request is retried on its own.
+    pub fn set_read_chunk_words(&mut self, words: usize) {
+        self.read_chunk_words = words.max(1);
+    }
+
+    /// The policy failed requests are retried with
+    #[must_use]
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Run `f` with the per-attempt timeout temporarily set to `timeout`, restoring the configured
+    /// timeout afterwards (even if `f` fails)
+    /// # Errors
+    /// Returns the error from `f` or an error if the timeout couldn't be set
+    pub fn with_timeout<R, E, F>(&mut self, timeout: Duration, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Self) -> Result<R, E>,
+        E: From<Error>,
+    {
+        self.apply_timeout(timeout)?;
+        let res = f(self);
+        self.apply_timeout(self.timeout)?;
+        res
+    }
+
+    /// Run `f` such that no single operation in it (including all of its retries) takes longer
+    /// than roughly `deadline`, so a stuck board fails fast
+    /// # Errors
+    /// Returns the error from `f` or an error if the timeout couldn't be set
+    pub fn with_deadline<R, E, F>(&mut self, deadline: Duration, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Self) -> Result<R, E>,
+        E: From<Error>,
+    {
+        // Attempts are inclusive of the first one and each one backs off by the policy's
+        // multiplier, so split the deadline across the whole backoff series
+        let multiplier = self.retry.multiplier.max(1.0);
+        let series = (0..self.retry.attempts.max(1))
+            .fold((0.0, 1.0), |(sum, term), _| (sum + term, term * multiplier))
+            .0;
+        let per_attempt = deadline.div_f64(series).max(Duration::from_millis(1));
+        self.with_timeout(per_attempt, f)
+    }
+
+    fn apply_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        self.socket.set_write_timeout(Some(timeout))?;
+        Ok(())
+    }
+
+    /// Run `op`, reconnecting and running it again if it pushed us over the reconnect policy's
+    /// limit of timeouts
+    fn recovering<R, F>(&mut self, mut op: F) -> TransportResult<R>
+    where
+        F: FnMut(&Self) -> Result<R, Error>,
+    {
+        let res = match op(self) {
+            Err(e) if self.count_timeout(&e) && self.reconnect_due() => {
+                self.restore_connection()?;
+                op(self).map_err(|e| {
+                    self.count_timeout(&e);
+                    e
+                })
+            }
+            res => res,
+        };
+        if res.is_ok() {
+            self.timeouts = 0;
+        }
+        Ok(res?)
+    }
+
+    /// Count `e` towards the reconnect policy, returning whether it was a timeout. Any other
+    /// error means the board answered, which breaks the run of timeouts.
+    fn count_timeout(&mut self, e: &Error) -> bool {
+        let timeout = matches!(e, Error::Lower(tapcp::Error::Timeout { .. }));
+        self.timeouts = if timeout { self.timeouts + 1 } else { 0 };
+        timeout
+    }
+
+    fn reconnect_due(&self) -> bool {
+        matches!(&self.reconnect, Some(policy) if self.timeouts >= policy.after_timeouts)
+    }
+
+    fn restore_connection(&mut self) -> TransportResult<()> {
+        self.reconnect()?;
+        let registers = self.fetch_registers()?;
+        let registers_reloaded = self.registers.is_some();
+        if registers_reloaded {
+            self.registers = Some(registers.clone());
+        }
+        self.listdev_cache = Some(registers);
+        let md5 = self
+            .find_metadata(self.platform.flash_location())?
+            .and_then(|mut meta| meta.remove("md5"));
+        self.reconnect_events.push(ReconnectEvent {
+            timeouts: self.timeouts,
+            md5: md5.clone(),
+            registers_reloaded,
+        });
+        self.timeouts = 0;
+        match self
+            .reconnect
+            .as_ref()
+            .and_then(|p| p.expected_md5.as_ref())
+        {
+            Some(expected) if md5.as_ref() != Some(expected) => Err(Error::DesignChanged {
+                expected: expected.clone(),
+                found: md5,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn fetch_registers(&self) -> Result<RegisterMap, Error> {
+        let devices = tapcp::listdev(&self.socket, self.retry)?;
         Ok(devices
             .iter()
             .map(|(k, (addr, len))| {
@@ -127,6 +963,137 @@ impl Transport for Tapcp {
             .collect())
     }
 
+    fn check_device(&mut self, device: &str) -> TransportResult<()> {
+        if !self.validate_devices {
+            return Ok(());
+        }
+        if self.listdev_cache.is_none() {
+            self.listdev()?;
+        }
+        match &self.listdev_cache {
+            Some(devices) if devices.contains_key(device) => Ok(()),
+            _ => Err(super::Error::DeviceNotFound(device.to_string())),
+        }
+    }
+
+    fn check_bounds(&self, device: &str, offset: usize, n: usize) -> TransportResult<()> {
+        match &self.registers {
+            Some(registers) if !self.raw => check_bounds(registers, device, offset, n),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The unspecified address of the same family as `ip`
+fn unspecified(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+/// The number of flash sectors `bitstream` takes up
+fn sector_count(bitstream: &[u8]) -> usize {
+    let sector = tapcp::FLASH_SECTOR_SIZE as usize;
+    (bitstream.len() + sector - 1) / sector
+}
+
+impl Drop for Tapcp {
+    fn drop(&mut self) {
+        if let Some(lease) = self.lease.take() {
+            // A duplicate handle keeps the port bound after our socket closes
+            lease.release(self.socket.try_clone().ok());
+        }
+    }
+}
+
+/// Bind a blocking UDP socket to `local` with `timeout` and connect it to `remote`
+fn open_socket(
+    local: SocketAddr,
+    remote: SocketAddr,
+    timeout: Duration,
+) -> Result<UdpSocket, Error> {
+    let socket = UdpSocket::bind(local)?;
+    // Set explicit nonblocking
+    socket.set_nonblocking(false)?;
+    socket.set_write_timeout(Some(timeout))?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(remote)?;
+    Ok(socket)
+}
+
+// Transport trait implementations
+
+impl Transport for Tapcp {
+    fn is_running(&mut self) -> TransportResult<bool> {
+        // Check if sys_clkcounter exists
+        self.recovering(|t| {
+            match tapcp::read_device("sys_clkcounter", 0, 1, &t.socket, t.retry) {
+                Ok(_) => Ok(true),
+                // In the case we get back a file not found error,
+                // that implies the device is not running a user program.
+                // Any other error is actually an error
+                Err(e) => match e.tftp() {
+                    Some(tftp_client::Error::Protocol {
+                        code: tftp_client::parser::ErrorCode::NoFile,
+                        msg: _,
+                    }) => Ok(false),
+                    _ => Err(Error::Lower(e)),
+                },
+            }
+        })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "tapcp::write",
+            level = "trace",
+            skip(self, data),
+            fields(n = data.len()),
+            err,
+        )
+    )]
+    fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+        // The inverted version of `read_vec`. The problem here is if we are not writing a 4 byte
+        // chunk (which we need to), we have to read the bytes that are already there and include
+        // them. Because we don't want to do this read when we don't have to, we will branch
+        check_access(&self.access, device, true)?;
+        self.check_device(device)?;
+        self.check_bounds(device, offset, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let (first_word, words) = if (offset % 4) == 0 && (data.len() % 4) == 0 {
+            // Just do the write
+            (offset / 4, Cow::Borrowed(data))
+        } else {
+            // Read every word the write touches and write them back with the new bytes in place
+            let first_word = offset / 4;
+            let last_word = (offset + data.len() + 3) / 4;
+            let mut words =
+                self.read_n_bytes(device, first_word * 4, (last_word - first_word) * 4)?;
+            let start_idx = offset % 4;
+            words[start_idx..start_idx + data.len()].copy_from_slice(data);
+            (first_word, Cow::Owned(words))
+        };
+        self.recovering(|t| {
+            Ok(tapcp::write_device(
+                device, first_word, &words, &t.socket, t.retry,
+            )?)
+        })
+    }
+
+    /// The device list is cached after the first call, see [`Tapcp::refresh_listdev`]
+    fn listdev(&mut self) -> TransportResult<RegisterMap> {
+        if let Some(devices) = &self.listdev_cache {
+            return Ok(devices.clone());
+        }
+        let devices = self.recovering(Self::fetch_registers)?;
+        self.listdev_cache = Some(devices.clone());
+        Ok(devices)
+    }
+
     #[allow(clippy::cast_sign_loss)]
     #[allow(clippy::cast_possible_truncation)]
     #[allow(clippy::cast_precision_loss)]
@@ -134,72 +1101,75 @@ impl Transport for Tapcp {
     where
         D: FpgaDesign,
     {
-        // First check to see if we even need to program by comparing the hashes
-        let meta = self.metadata()?;
-        if let Some(hash) = meta.get("md5") {
-            if hash == &design.md5_string() && !force {
-                return Ok(());
-            }
-        }
-        // Else we're programming!
-        // Set the timeout high as flash writes can take up to 1s
-        self.socket
-            .set_read_timeout(Some(Duration::from_secs_f32(1.5)))
-            .unwrap();
-        self.socket
-            .set_write_timeout(Some(Duration::from_secs_f32(1.5)))
-            .unwrap();
-        // And we'll also set the retries higher
-        let retries = 8;
+        self.program_cancellable(design, force, &CancelToken::new())
+    }
 
+    /// Programming is checked for cancellation between every flash sector. A cancelled programming
+    /// leaves the board running whatever it was running before (the FPGA is only rebooted once
+    /// the whole bitstream is written), but with a partially written user image that
+    /// [`Tapcp::verify_programmed`] reports as interrupted. Don't reboot into the user image until
+    /// it has been programmed again.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "tapcp::program", skip(self, design, cancel), err)
+    )]
+    fn program_cancellable<D>(
+        &mut self,
+        design: &D,
+        force: bool,
+        cancel: &CancelToken,
+    ) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        cancel.check()?;
         // The bitstream will start one tapcp::FLASH_SECTOR_SIZE away from the platform-specific
         // flash location. We don't care about recording the header and this makes the program
         // location consistent.
-        // We have to write in chunks of FLASH_SECTOR_SIZE as well
-        let bar = ProgressBar::new(
-            (design.bitstream().len() as f64 / f64::from(tapcp::FLASH_SECTOR_SIZE)).ceil() as u64,
-        );
-        bar.set_message("Writting bitstream");
-        for (idx, chunk) in design
-            .bitstream()
-            .chunks(tapcp::FLASH_SECTOR_SIZE as usize)
-            .enumerate()
-        {
-            tapcp::write_flash(
-                self.platform.program_location() as usize + tapcp::FLASH_SECTOR_SIZE as usize * idx,
-                chunk,
-                &self.socket,
-                retries,
-            )
-            .map_err(Error::from)?;
-            bar.inc(1);
+        let spec = self.platform.spec();
+        spec.check_golden_overlap(spec.program_location, design.bitstream().len())?;
+        // Then check to see if we even need to program by comparing the hashes
+        // Prefer the SHA-256 if the board has one, older metadata only has the md5
+        let meta = self.metadata()?;
+        let programmed = match (meta.get("sha256"), meta.get("md5")) {
+            (Some(hash), _) => hash == &design.sha256_string(),
+            (None, Some(hash)) => hash == &design.md5_string(),
+            (None, None) => false,
+        };
+        if programmed && !force {
+            return Ok(());
+        }
+        // Else we're programming! Mark that we've started (dropping the old hashes) so an
+        // interrupted write can be detected later, see `verify_programmed`
+        self.mark_programming(design)?;
+        if !self.write_image(
+            self.platform.flash_location(),
+            spec.program_location.into(),
+            design,
+            0,
+            cancel,
+        )? {
+            return Err(super::Error::Cancelled);
         }
-        bar.finish();
         // Then readback to verify
         // TODO
 
-        // Set the metadata (to also indicate that we successfully programmed)
-        self.update_metadata(design)?;
-
-        // And reboot from the program location
-        // We expect an error because the whole design will freeze up
-
-        // Mystery bitshift
-        tapcp::progdev(
-            match self.platform {
-                Platform::SNAP => self.platform.program_location() >> 8,
-                Platform::SNAP2 => self.platform.program_location(),
-            },
-            &self.socket,
-        )
-        .map_err(Error::from)?;
-        Ok(())
+        self.finish_programming(design)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "tapcp::deprogram", skip_all, err)
+    )]
     fn deprogram(&mut self) -> TransportResult<()> {
-        Ok(tapcp::progdev(0, &self.socket).map_err(Error::from)?)
+        self.listdev_cache = None;
+        Ok(tapcp::progdev(0, &self.socket, self.reboot_wait).map_err(Error::from)?)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "tapcp::read", level = "trace", skip(self), err)
+    )]
     fn read_n_bytes(&mut self, device: &str, offset: usize, n: usize) -> TransportResult<Vec<u8>> {
         // TAPCP works on a block of size 4 bytes, so we need to do some chunking and slicing
         // The goal here is to be efficient, we don't want to query bytes we don't need.
@@ -207,12 +1177,27 @@ impl Transport for Tapcp {
         // i.e. If the device contains [1,2,3,4,5,6,7,8] and we want to read offset=2, N=3
         // Which is the last 2 bytes of the first word and the first byte of the second word.
         // In that case, we need to read both words.
+        check_access(&self.access, device, false)?;
+        self.check_device(device)?;
+        self.check_bounds(device, offset, n)?;
+        // A count of zero words would read the whole device
+        if n == 0 {
+            return Ok(vec![]);
+        }
         // First, grab enough multiple of 4 bytes
         let first_word = offset / 4;
-        let last_word = (offset + n) / 4;
+        let last_word = (offset + n + 3) / 4;
         let word_n = last_word - first_word;
-        let bytes = tapcp::read_device(device, first_word, word_n, &self.socket, self.retries)
-            .map_err(Error::from)?;
+        let bytes = self.recovering(|t| {
+            Ok(tapcp::read_device_chunked(
+                device,
+                first_word,
+                word_n,
+                t.read_chunk_words,
+                &t.socket,
+                t.retry,
+            )?)
+        })?;
         // Now we slice out the the relevant chunk
         let start_idx = offset % 4;
         Ok(bytes[start_idx..start_idx + n].to_vec())
@@ -221,44 +1206,1838 @@ impl Transport for Tapcp {
 
 // Tapcp-specific methods
 impl Tapcp {
+    /// Record that `design` finished programming and reboot into it
+    fn finish_programming<D>(&mut self, design: &D) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        // Set the metadata (to also indicate that we successfully programmed)
+        self.update_metadata(design)?;
+
+        // And reboot from the program location
+        // We expect an error because the whole design will freeze up
+
+        // Mystery bitshift
+        let spec = self.platform.spec();
+        self.listdev_cache = None;
+        tapcp::progdev(
+            spec.progdev_address(spec.program_location),
+            &self.socket,
+            self.reboot_wait,
+        )
+        .map_err(Error::from)?;
+        Ok(())
+    }
+
+    /// Resume programming `design` after an interrupted (or cancelled) [`Transport::program`].
+    /// The sectors the metadata records as written are kept, the ones after them are read back
+    /// and compared by checksum, and writing continues from the first one that doesn't match, so a
+    /// failure late in a long upload doesn't start over. Returns the number of sectors that were
+    /// already written.
+    /// # Errors
+    /// Returns errors on transport failures or if the design would overlap the golden image
+    pub fn resume_program<D>(&mut self, design: &D) -> TransportResult<usize>
+    where
+        D: FpgaDesign,
+    {
+        let spec = self.platform.spec();
+        spec.check_golden_overlap(spec.program_location, design.bitstream().len())?;
+        // Keep the marker up while we're writing, it's already there (with the progress) if this
+        // design was the one that got interrupted
+        let written = match self.verify_programmed()? {
+            ProgramState::Interrupted { md5, sectors } if md5 == design.md5_string() => sectors,
+            _ => {
+                self.mark_programming(design)?;
+                0
+            }
+        };
+        let first = self.with_timeout(self.flash_timeout, |t| {
+            t.first_mismatched_sector(spec.program_location.into(), design.bitstream(), written)
+        })?;
+        self.write_image(
+            self.platform.flash_location(),
+            spec.program_location.into(),
+            design,
+            first,
+            &CancelToken::new(),
+        )?;
+        self.finish_programming(design)?;
+        Ok(first)
+    }
+
+    /// The index of the first sector of `bitstream` from `from` on that doesn't match what's in
+    /// flash at `location`, or the number of sectors if they all do
+    fn first_mismatched_sector(
+        &mut self,
+        location: FlashAddr,
+        bitstream: &[u8],
+        from: usize,
+    ) -> Result<usize, Error> {
+        let sector_size = tapcp::FLASH_SECTOR_SIZE as usize;
+        for (idx, chunk) in bitstream.chunks(sector_size).enumerate().skip(from) {
+            // Sectors are addressed the same way `write_sectors` writes them
+            let written = tapcp::read_flash_with_progress(
+                location.offset(sector_size * idx)?.word()?,
+                (chunk.len() + 3) / 4,
+                &self.socket,
+                self.retry,
+                self.transfer_options,
+                |_, _| {},
+            )?;
+            if written.get(..chunk.len()).map(sha256) != Some(sha256(chunk)) {
+                return Ok(idx);
+            }
+        }
+        Ok(bitstream.chunks(sector_size).len().max(from))
+    }
+
+    /// Read `n` words of flash at the word offset `offset` with the flash timeout, calling
+    /// `progress` with the number of bytes read so far and the total after every block. Reads
+    /// are split into requests like register reads, see [`Tapcp::set_read_chunk_words`].
+    /// # Errors
+    /// Returns an error on bad transport or if the flash read came back short
+    pub fn read_flash<P>(
+        &mut self,
+        offset: usize,
+        n: usize,
+        mut progress: P,
+    ) -> Result<Vec<u8>, Error>
+    where
+        P: FnMut(usize, usize),
+    {
+        self.with_timeout(self.flash_timeout, |t| {
+            let mut read = 0;
+            Ok(tapcp::read_chunked(
+                offset,
+                n,
+                t.read_chunk_words,
+                |chunk_offset, chunk_n| {
+                    let bytes = tapcp::read_flash_with_progress(
+                        chunk_offset,
+                        chunk_n,
+                        &t.socket,
+                        t.retry,
+                        t.transfer_options,
+                        |chunk_read, _| progress(read + chunk_read, 4 * n),
+                    )?;
+                    read += bytes.len();
+                    Ok(bytes)
+                },
+            )?)
+        })
+    }
+
+    /// Write a bitstream to flash starting at the flash address `location`, skipping the sectors
+    /// before `first_sector`. Returns false if `cancel` was cancelled before every sector was
+    /// written.
+    fn write_bitstream(
+        &mut self,
+        location: FlashAddr,
+        bitstream: &[u8],
+        first_sector: usize,
+        cancel: &CancelToken,
+    ) -> Result<bool, Error> {
+        let sectors = first_sector..sector_count(bitstream);
+        // Flash writes are much slower than register accesses, so use the flash timeout and a
+        // few more retries
+        self.with_timeout(self.flash_timeout, |t| {
+            t.write_sectors(location, bitstream, sectors, cancel)
+        })
+    }
+
+    /// Write the bitstream of `design` to the flash address `location` like
+    /// [`Tapcp::write_bitstream`], recording the number of sectors written every
+    /// [`PROGRESS_SECTORS`] in the metadata at `meta_location` (which has to be marked as
+    /// programming `design`) for [`Tapcp::resume_program`]
+    fn write_image<D>(
+        &mut self,
+        meta_location: u32,
+        location: FlashAddr,
+        design: &D,
+        first_sector: usize,
+        cancel: &CancelToken,
+    ) -> Result<bool, Error>
+    where
+        D: FpgaDesign,
+    {
+        let bitstream = design.bitstream();
+        let sectors = sector_count(bitstream);
+        let mut written = first_sector;
+        while written < sectors {
+            let end = (written + PROGRESS_SECTORS).min(sectors);
+            if !self.with_timeout(self.flash_timeout, |t| {
+                t.write_sectors(location, bitstream, written..end, cancel)
+            })? {
+                return Ok(false);
+            }
+            written = end;
+            if written < sectors {
+                self.write_metadata(
+                    meta_location,
+                    [
+                        ("sector_size", tapcp::FLASH_SECTOR_SIZE.to_string()),
+                        (PROGRAMMING_KEY, design.md5_string()),
+                        (PROGRESS_KEY, written.to_string()),
+                    ],
+                )?;
+            }
+        }
+        Ok(true)
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    fn write_sectors(
+        &mut self,
+        location: FlashAddr,
+        bitstream: &[u8],
+        sectors: Range<usize>,
+        cancel: &CancelToken,
+    ) -> Result<bool, Error> {
+        // We have to write in whole sectors of FLASH_SECTOR_SIZE, so each transfer is a run of
+        // them (the last possibly short)
+        let sector = tapcp::FLASH_SECTOR_SIZE as usize;
+        let FlashWriteConfig {
+            sectors_per_write,
+            sector_delay,
+            pipeline,
+        } = self.flash_write;
+        let last = (sectors.end * sector).min(bitstream.len());
+        let writes: Vec<_> = (sectors.start * sector..last)
+            .step_by(sector * sectors_per_write)
+            .map(|start| {
+                let end = (start + sector * sectors_per_write).min(last);
+                (start, &bitstream[start..end])
+            })
+            .collect();
+        #[cfg(feature = "progress")]
+        let bar = ProgressBar::new((bitstream.len() as f64 / sector as f64).ceil() as u64);
+        #[cfg(feature = "progress")]
+        bar.set_message("Writting bitstream");
+        #[cfg(feature = "progress")]
+        bar.set_position(sectors.start as u64);
+        let retry = RetryPolicy {
+            attempts: FLASH_RETRIES,
+            ..self.retry
+        };
+        // Every socket takes the next write that no other one has started
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let work = |socket: &UdpSocket| -> Result<bool, Error> {
+            loop {
+                if cancel.is_cancelled() {
+                    return Ok(false);
+                }
+                if failed.load(Ordering::Relaxed) {
+                    return Ok(true);
+                }
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let Some(&(start, data)) = writes.get(idx) else {
+                    return Ok(true);
+                };
+                let written = location
+                    .offset(start)
+                    .and_then(FlashAddr::word)
+                    .and_then(|offset| tapcp::write_flash(offset, data, socket, retry));
+                if let Err(e) = written {
+                    failed.store(true, Ordering::Relaxed);
+                    return Err(e.into());
+                }
+                #[cfg(feature = "progress")]
+                bar.inc(((data.len() + sector - 1) / sector) as u64);
+                if idx + 1 < writes.len() && !sector_delay.is_zero() {
+                    std::thread::sleep(sector_delay);
+                }
+            }
+        };
+        let extra = (1..pipeline.min(writes.len()))
+            .map(|_| {
+                let local = SocketAddr::new(self.socket.local_addr()?.ip(), 0);
+                open_socket(local, self.remote, self.flash_timeout)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let results: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = extra
+                .iter()
+                .map(|socket| s.spawn(|| work(socket)))
+                .collect();
+            std::iter::once(work(&self.socket))
+                .chain(
+                    handles
+                        .into_iter()
+                        .map(|h| h.join().expect("Flash writer panicked")),
+                )
+                .collect()
+        });
+        let mut done = true;
+        for result in results {
+            done &= result?;
+        }
+        #[cfg(feature = "progress")]
+        bar.finish();
+        Ok(done)
+    }
+
+    /// Overwrite the golden (fallback) image with `design`. This is the image the board falls
+    /// back to if the user image is bad, so only do this with a known-good design.
+    /// The board is not rebooted.
+    /// # Errors
+    /// Returns errors on transport failures or if the design doesn't fit in the golden region
+    pub fn program_golden<D>(&mut self, design: &D) -> Result<(), Error>
+    where
+        D: FpgaDesign,
+    {
+        let spec = self.platform.spec();
+        if design.bitstream().len() > spec.golden_size as usize {
+            return Err(Error::GoldenTooLarge {
+                len: design.bitstream().len(),
+                size: spec.golden_size,
+            });
+        }
+        self.write_bitstream(
+            spec.golden_location.into(),
+            design.bitstream(),
+            0,
+            &CancelToken::new(),
+        )?;
+        Ok(())
+    }
+
+    /// Reboot the FPGA into the golden image
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn boot_golden(&mut self) -> Result<(), Error> {
+        let spec = self.platform.spec();
+        self.listdev_cache = None;
+        Ok(tapcp::progdev(
+            spec.progdev_address(spec.golden_location),
+            &self.socket,
+            self.reboot_wait,
+        )?)
+    }
+
+    /// Gets the platform commands the board's TAPCP server supports, discovered with `/help`
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn platform_commands(&mut self) -> Result<Vec<PlatformCommand>, Error> {
+        Ok(tapcp::platform_commands(&self.socket, self.retry)?)
+    }
+
+    /// Check the board's TAPCP server supports `command`
+    /// # Errors
+    /// Returns errors on transport failures or [`Error::Unsupported`] if it doesn't
+    pub fn require_command(&mut self, command: PlatformCommand) -> Result<(), Error> {
+        if self.platform_commands()?.contains(&command) {
+            Ok(())
+        } else {
+            Err(Error::Unsupported(command))
+        }
+    }
+
+    /// Reboot the FPGA into the golden image like [`Tapcp::boot_golden`], checking first that the
+    /// server supports `/progdev`
+    /// # Errors
+    /// Returns errors on transport failures or [`Error::Unsupported`]
+    pub fn reboot_to_golden(&mut self) -> Result<(), Error> {
+        self.require_command(PlatformCommand::Progdev)?;
+        self.boot_golden()
+    }
+
+    /// Program `design` like [`Transport::program`] and check the board came back running it, see
+    /// [`Tapcp::verify_boot`]
+    /// # Errors
+    /// Returns errors on transport failures or [`Error::WrongImageBooted`] if the board booted
+    /// something else
+    pub fn program_verified<D>(
+        &mut self,
+        design: &D,
+        force: bool,
+        timeout: Duration,
+    ) -> TransportResult<ImageIdentity>
+    where
+        D: FpgaDesign,
+    {
+        self.program(design, force)?;
+        self.verify_boot(design, 0, timeout)
+    }
+
+    /// Check that the board is running `design` after being booted from design slot `slot` (0
+    /// for the usual user image), i.e. right after [`Transport::program`] or
+    /// [`Tapcp::boot_slot`]. The connection is re-established and the board polled for up to
+    /// `timeout` while it comes back up, then the md5 in the slot's metadata and the `listdev`
+    /// register map are compared to the design's. Returns what the board reported.
+    /// # Errors
+    /// Returns the last transport error if the board didn't answer within `timeout`, or
+    /// [`Error::WrongImageBooted`] with both identities if it booted something else
+    pub fn verify_boot<D>(
+        &mut self,
+        design: &D,
+        slot: u32,
+        timeout: Duration,
+    ) -> TransportResult<ImageIdentity>
+    where
+        D: FpgaDesign,
+    {
+        let location = self.platform.spec().slot_location(slot)?;
+        let expected = ImageIdentity::of(design);
+        let start = std::time::Instant::now();
+        self.reconnect()?;
+        let found = loop {
+            match self.booted_identity(location) {
+                Ok(found) => break found,
+                Err(e) if start.elapsed() >= timeout => return Err(e.into()),
+                // Errors here are expected while the board is still coming up
+                Err(_) => std::thread::sleep(Duration::from_millis(500)),
+            }
+        };
+        if found == expected {
+            Ok(found)
+        } else {
+            Err(Error::WrongImageBooted { expected, found }.into())
+        }
+    }
+
+    /// The identity of the running image, with the md5 from the metadata at the flash address
+    /// `location`
+    fn booted_identity(&mut self, location: u32) -> Result<ImageIdentity, Error> {
+        let registers = self.fetch_registers()?;
+        let md5 = self
+            .find_metadata(location)?
+            .and_then(|mut meta| meta.remove("md5"));
+        let fingerprint = register_fingerprint(&registers);
+        self.listdev_cache = Some(registers);
+        Ok(ImageIdentity {
+            md5,
+            registers: fingerprint,
+        })
+    }
+
+    /// Wait up to `timeout` for the user image to start running, rebooting into the golden image
+    /// if it never does so the board stays reachable
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn recover(&mut self, timeout: Duration) -> TransportResult<BootOutcome> {
+        let start = std::time::Instant::now();
+        loop {
+            // Errors here are expected while the board is still coming up
+            if let Ok(true) = self.is_running() {
+                return Ok(BootOutcome::User);
+            }
+            if start.elapsed() >= timeout {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        self.boot_golden()?;
+        Ok(BootOutcome::Golden)
+    }
+
+    /// The designs stored in each of the platform's slots, see [`PlatformSpec::slot_location`].
+    /// Slots that were never written are [`ProgramState::Unknown`].
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn list_stored_designs(&mut self) -> Result<Vec<StoredDesign>, Error> {
+        let spec = self.platform.spec();
+        (0..spec.slots)
+            .map(|slot| {
+                let location = spec.slot_program_location(slot)?;
+                Ok(StoredDesign {
+                    slot,
+                    location,
+                    state: self.slot_state(spec.slot_location(slot)?)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Write `design` to the design slot `slot` without booting it, so it can later be switched
+    /// to with [`Tapcp::boot_slot`]. Storing to slot 0 replaces the user image.
+    /// # Errors
+    /// Returns errors on transport failures or if the design doesn't fit in the slot
+    pub fn store_design<D>(&mut self, design: &D, slot: u32) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        let spec = self.platform.spec();
+        spec.check_slot(slot, design.bitstream().len())?;
+        let meta_location = spec.slot_location(slot)?;
+        self.write_metadata(
+            meta_location,
+            [
+                ("sector_size", tapcp::FLASH_SECTOR_SIZE.to_string()),
+                (PROGRAMMING_KEY, design.md5_string()),
+            ],
+        )?;
+        if !self.write_image(
+            meta_location,
+            spec.slot_program_location(slot)?.into(),
+            design,
+            0,
+            &CancelToken::new(),
+        )? {
+            return Err(super::Error::Cancelled);
+        }
+        Ok(self.write_metadata(
+            meta_location,
+            [
+                ("sector_size", tapcp::FLASH_SECTOR_SIZE.to_string()),
+                ("md5", design.md5_string()),
+                ("sha256", design.sha256_string()),
+            ],
+        )?)
+    }
+
+    /// Reboot the FPGA into the design stored in slot `slot`
+    /// # Errors
+    /// Returns errors on transport failures or if the slot doesn't hold a finished design
+    pub fn boot_slot(&mut self, slot: u32) -> Result<(), Error> {
+        let spec = self.platform.spec();
+        let location = spec.slot_program_location(slot)?;
+        if !matches!(
+            self.slot_state(spec.slot_location(slot)?)?,
+            ProgramState::Programmed { .. }
+        ) {
+            return Err(Error::EmptySlot(slot));
+        }
+        self.listdev_cache = None;
+        tapcp::progdev(
+            spec.progdev_address(location),
+            &self.socket,
+            self.reboot_wait,
+        )?;
+        Ok(())
+    }
+
+    /// Check whether the last programming of the board finished, i.e. right after connecting. If
+    /// it was interrupted, the user image is probably corrupt and should be fixed with
+    /// [`Tapcp::repair`] (or the board booted into the golden image).
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn verify_programmed(&mut self) -> Result<ProgramState, Error> {
+        self.slot_state(self.platform.flash_location())
+    }
+
+    /// The state recorded in the metadata dictionary at the flash address `location`
+    fn slot_state(&mut self, location: u32) -> Result<ProgramState, Error> {
+        Ok(self
+            .find_metadata(location)?
+            .map_or(ProgramState::Unknown, ProgramState::from_metadata))
+    }
+
+    /// Recover from interrupted programming by programming `design`, which replaces whatever design
+    /// was being written. Does nothing if the last programming finished.
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn repair<D>(&mut self, design: &D) -> TransportResult<RepairOutcome>
+    where
+        D: FpgaDesign,
+    {
+        match self.verify_programmed()? {
+            // Pick up where we left off if it was this design that got interrupted
+            ProgramState::Interrupted { md5, .. } if md5 == design.md5_string() => {
+                self.resume_program(design)?;
+                Ok(RepairOutcome::Reprogrammed)
+            }
+            ProgramState::Interrupted { .. } => {
+                self.program(design, true)?;
+                Ok(RepairOutcome::Reprogrammed)
+            }
+            ProgramState::Programmed { .. } | ProgramState::Unknown => Ok(RepairOutcome::Intact),
+        }
+    }
+
     /// Gets the temperature from the connected device in Celsius
     /// # Errors
     /// Returns errors on transport failures
     pub fn temperature(&mut self) -> Result<f32, Error> {
-        Ok(tapcp::temp(&self.socket, self.retries)?)
+        Ok(tapcp::temp(&self.socket, self.retry)?)
+    }
+
+    /// Gets the runtime diagnostics the board's TAPCP server exposes. The endpoints are discovered
+    /// with `/help`, and the temperature is left as `None` if it's missing or fails to read.
+    /// # Errors
+    /// Returns errors on transport failures listing the endpoints
+    pub fn server_stats(&mut self) -> Result<ServerStats, Error> {
+        let endpoints = self.list_commands()?;
+        Ok(ServerStats::collect(endpoints, |name| {
+            tapcp::read_file(name, &self.socket, self.retry).ok()
+        }))
+    }
+
+    /// Gets the names of the top level commands the board's TAPCP server lists in `/help` (see
+    /// [`tapcp::list_commands`]), not the files stored on it
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn list_commands(&mut self) -> Result<Vec<String>, Error> {
+        Ok(tapcp::list_commands(&self.socket, self.retry)?)
+    }
+
+    /// Read an auxiliary file stored on the board, i.e. a calibration table or site config
+    /// # Errors
+    /// Returns errors on transport failures or if the file doesn't exist
+    pub fn read_file(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        Ok(tapcp::read_file(name, &self.socket, self.retry)?)
+    }
+
+    /// Write an auxiliary file to the board
+    /// # Errors
+    /// Returns errors on transport failures
+    pub fn write_file(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        Ok(tapcp::write_file(name, data, &self.socket, self.retry)?)
     }
 
     /// Gets the metadata for the currently programed design
     /// # Errors
     /// Returns errors on transport failures
     pub fn metadata(&mut self) -> Result<HashMap<KString, String>, Error> {
-        Ok(tapcp::get_metadata(
-            &self.socket,
+        self.slot_metadata(self.platform.flash_location())
+    }
+
+    /// Gets the metadata dictionary at the flash address `location`
+    fn slot_metadata(&mut self, location: u32) -> Result<HashMap<KString, String>, Error> {
+        Ok(tapcp::get_metadata(&self.socket, location, self.retry)?)
+    }
+
+    /// Gets the metadata dictionary at the flash address `location`, or `None` if there isn't one
+    fn find_metadata(&mut self, location: u32) -> Result<Option<HashMap<KString, String>>, Error> {
+        Ok(self.read_metadata(location)?.map(|(meta, _)| meta))
+    }
+
+    /// Gets the metadata dictionary at the flash address `location` and the number of bytes of
+    /// flash it covers, or `None` if there isn't one
+    fn read_metadata(&mut self, location: u32) -> Result<Option<(tapcp::Metadata, usize)>, Error> {
+        Ok(tapcp::read_metadata(&self.socket, location, self.retry)?)
+    }
+
+    /// Gets the board inventory, or `None` if one was never written
+    /// # Errors
+    /// Returns errors on transport failures or a malformed inventory
+    pub fn board_inventory(&mut self) -> Result<Option<BoardInventory>, Error> {
+        self.find_metadata(self.platform.flash_location())?
+            .map_or(Ok(None), |meta| BoardInventory::from_metadata(&meta))
+    }
+
+    /// Replace the board inventory, keeping the programming metadata as is
+    /// # Errors
+    /// Returns errors on transport failures or if the inventory can't be stored
+    pub fn set_board_inventory(&mut self, inventory: &BoardInventory) -> Result<(), Error> {
+        let entries = inventory.to_metadata()?;
+        let (mut meta, extent) = self
+            .read_metadata(self.platform.flash_location())?
+            .unwrap_or_default();
+        meta.retain(|k, _| !k.starts_with(INVENTORY_PREFIX));
+        meta.extend(entries);
+        self.store_metadata(self.platform.flash_location(), &meta, extent)
+    }
+
+    /// Write `meta` as the whole metadata dictionary at the flash address `location` over the
+    /// `extent` bytes of the previous one, checking it fits in front of the slot's bitstream
+    fn store_metadata(
+        &mut self,
+        location: u32,
+        meta: &HashMap<KString, String>,
+        extent: usize,
+    ) -> Result<(), Error> {
+        let spec = self.platform.spec();
+        let region = spec.program_location.saturating_sub(spec.flash_location) as usize;
+        let len = tapcp::encode_metadata(meta, 0)?.len();
+        if len > region {
+            return Err(Error::MetadataTooLarge { len, region });
+        }
+        self.with_timeout(self.flash_timeout, |t| {
+            Ok(tapcp::replace_metadata(
+                meta, extent, &t.socket, location, t.retry,
+            )?)
+        })
+    }
+
+    /// Replace the programming metadata at the flash address `location` with `entries`, carrying
+    /// over the board inventory
+    fn write_metadata<const N: usize>(
+        &mut self,
+        location: u32,
+        entries: [(&str, String); N],
+    ) -> Result<(), Error> {
+        let (mut meta, extent) = self.read_metadata(location)?.unwrap_or_default();
+        meta.retain(|k, _| k.starts_with(INVENTORY_PREFIX));
+        meta.extend(entries.into_iter().map(|(k, v)| (KString::from_ref(k), v)));
+        self.store_metadata(location, &meta, extent)
+    }
+
+    /// Replace the metadata with a marker that `design` is being programmed
+    fn mark_programming<D>(&mut self, design: &D) -> Result<(), Error>
+    where
+        D: FpgaDesign,
+    {
+        self.write_metadata(
             self.platform.flash_location(),
-            self.retries,
-        )?)
+            [
+                ("sector_size", tapcp::FLASH_SECTOR_SIZE.to_string()),
+                (PROGRAMMING_KEY, design.md5_string()),
+            ],
+        )
     }
 
     /// Update the metadata entry given a design
-    /// Currently not completley compatible with python as we only store the md5
+    /// Currently not completley compatible with python as we only store the hashes
     /// # Panics
     /// Panics if the filename of fpg file is not a valid rust string
     fn update_metadata<D>(&mut self, design: &D) -> Result<(), Error>
     where
         D: FpgaDesign,
     {
-        let meta = HashMap::from([
-            ("sector_size", tapcp::FLASH_SECTOR_SIZE.to_string()),
-            ("md5", design.md5_string()),
-        ])
-        .into_iter()
-        .map(|(k, v)| (k.into(), v))
-        .collect();
-        Ok(tapcp::set_metadata(
-            &meta,
-            &self.socket,
+        self.write_metadata(
             self.platform.flash_location(),
-            self.retries,
-        )?)
+            [
+                ("sector_size", tapcp::FLASH_SECTOR_SIZE.to_string()),
+                ("md5", design.md5_string()),
+                ("sha256", design.sha256_string()),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{
+        emulator::{
+            Board,
+            Emulator,
+            OptionSupport,
+            Request,
+        },
+        sim::SimulatedFpga,
+    };
+    use casper_utils::design_sources::{
+        raw::RawDesign,
+        Register as DesignRegister,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_deadline() {
+        // A socket that never answers
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut tapcp = Tapcp::connect(silent.local_addr().unwrap(), Platform::SNAP).unwrap();
+        let start = std::time::Instant::now();
+        let res = tapcp.with_deadline(Duration::from_millis(100), |t| {
+            t.read_n_bytes("sys_clkcounter", 0, 4)
+        });
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // The configured timeout comes back afterwards
+        assert_eq!(
+            tapcp.socket.read_timeout().unwrap(),
+            Some(Duration::from_secs_f32(DEFAULT_TIMEOUT))
+        );
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let protocol = || tftp_client::Error::Protocol {
+            code: tftp_client::parser::ErrorCode::Unspec,
+            msg: String::new(),
+        };
+        let policy = RetryPolicy {
+            initial_backoff: Some(Duration::from_millis(1)),
+            jitter: 0.5,
+            ..RetryPolicy::with_attempts(3)
+        };
+        // Protocol errors are retried until we run out of attempts
+        let mut calls = 0;
+        let res: Result<(), _> = policy.run("test", &socket, |_| {
+            calls += 1;
+            Err(protocol())
+        });
+        assert!(matches!(
+            res,
+            Err(tapcp::Error::Timeout { attempts: 3, .. })
+        ));
+        assert_eq!(calls, 3);
+        // Other errors aren't unless the predicate says so
+        let mut calls = 0;
+        let res: Result<(), _> = policy.run("test", &socket, |_| {
+            calls += 1;
+            Err(tftp_client::Error::BadFilename)
+        });
+        assert!(matches!(res, Err(tapcp::Error::Tftp(_))));
+        assert_eq!(calls, 1);
+        let policy = RetryPolicy {
+            retry_on: |_| true,
+            ..policy
+        };
+        let mut calls = 0;
+        let res = policy.run("test", &socket, |_| {
+            calls += 1;
+            if calls < 2 {
+                Err(tftp_client::Error::BadFilename)
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(res.unwrap(), 2);
+        // Running out of time stops early
+        let policy = RetryPolicy {
+            initial_backoff: Some(Duration::from_millis(50)),
+            max_elapsed: Some(Duration::from_millis(10)),
+            ..policy
+        };
+        let mut calls = 0;
+        let res: Result<(), _> = policy.run("test", &socket, |_| {
+            calls += 1;
+            Err(protocol())
+        });
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+        // The backoff grows and saturates
+        assert_eq!(
+            policy.next_backoff(Duration::from_secs(2)),
+            Duration::from_secs(3)
+        );
+        assert_eq!(policy.next_backoff(policy.max_backoff), policy.max_backoff);
+    }
+
+    #[test]
+    fn test_reconnect_policy() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut tapcp = Tapcp::connect(silent.local_addr().unwrap(), Platform::SNAP).unwrap();
+        tapcp.set_retry_policy(RetryPolicy::with_attempts(1));
+        tapcp.set_timeout(Duration::from_millis(5)).unwrap();
+        tapcp.set_reconnect_policy(Some(ReconnectPolicy {
+            after_timeouts: 2,
+            expected_md5: None,
+        }));
+        assert!(tapcp.read_n_bytes("sys_clkcounter", 0, 4).is_err());
+        assert_eq!(tapcp.timeouts, 1);
+        // The second timeout reconnects, but the board still isn't there
+        assert!(tapcp.read_n_bytes("sys_clkcounter", 0, 4).is_err());
+        assert_eq!(tapcp.timeouts, 2);
+        assert!(tapcp.take_reconnect_events().is_empty());
+        assert_eq!(
+            tapcp.socket.peer_addr().unwrap(),
+            silent.local_addr().unwrap()
+        );
+        // Other errors don't count towards reconnecting
+        tapcp.set_register_map(Some(RegisterMap::new()));
+        assert!(tapcp.read_n_bytes("sys_clkcounter", 0, 4).is_err());
+        assert_eq!(tapcp.timeouts, 2);
+    }
+
+    #[test]
+    fn test_listdev_cache() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut tapcp = Tapcp::connect(silent.local_addr().unwrap(), Platform::SNAP).unwrap();
+        let devices =
+            RegisterMap::from([("sys_clkcounter".into(), Register { addr: 0, length: 4 })]);
+        tapcp.listdev_cache = Some(devices.clone());
+        // Served from the cache without asking the (silent) board
+        assert_eq!(tapcp.listdev().unwrap(), devices);
+        tapcp.set_validate_devices(true);
+        assert!(matches!(
+            tapcp.read_n_bytes("nope", 0, 4),
+            Err(crate::transport::Error::DeviceNotFound(_))
+        ));
+        // Reconnecting drops it
+        tapcp.reconnect().unwrap();
+        assert!(tapcp.listdev_cache.is_none());
+    }
+
+    #[test]
+    fn test_partial_word_writes() {
+        let mut board = Board::new();
+        board.fpga = Some(SimulatedFpga::new(&RawDesign {
+            registers: HashMap::from([("dev".into(), DesignRegister { addr: 0, size: 12 })]),
+            ..sectored_design(5, 0)
+        }));
+        let board = Emulator::start(board);
+        let mut tapcp = connect(&board);
+        let memory = |board: &Emulator| {
+            board
+                .board()
+                .fpga()
+                .memory()
+                .read_n_bytes("dev", 0, 12)
+                .unwrap()
+        };
+        tapcp.write_bytes("dev", 0, &[0xAA; 12]).unwrap();
+        // Writes that don't cover whole words keep the bytes around them
+        tapcp.write_bytes("dev", 0, &[1, 2]).unwrap();
+        tapcp.write_bytes("dev", 7, &[3, 4]).unwrap();
+        tapcp.write_bytes("dev", 11, &[5]).unwrap();
+        tapcp.write_bytes("dev", 10, &[]).unwrap();
+        assert_eq!(
+            memory(&board),
+            vec![1, 2, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 3, 4, 0xAA, 0xAA, 5]
+        );
+    }
+
+    #[cfg(feature = "chaos")]
+    #[test]
+    fn test_transfers_under_loss() {
+        use crate::transport::chaos::{
+            ChaosConfig,
+            LossyLink,
+        };
+        let sector = tapcp::FLASH_SECTOR_SIZE as usize;
+        let mut board = Board::new();
+        board.fpga = Some(SimulatedFpga::new(&RawDesign {
+            registers: HashMap::from([(
+                "dev".into(),
+                DesignRegister {
+                    addr: 0,
+                    size: 4096,
+                },
+            )]),
+            ..sectored_design(5, 0)
+        }));
+        let board = Emulator::start(board);
+
+        // Every datagram each way may be lost, repeated, corrupted, or overtaken by the next
+        let link = LossyLink::start(
+            board.addr(),
+            ChaosConfig {
+                seed: 3,
+                drop_request: 0.05,
+                drop_response: 0.05,
+                duplicate: 0.05,
+                bit_flip: 0.02,
+                reorder: 0.05,
+                ..ChaosConfig::default()
+            },
+        )
+        .unwrap();
+        let mut tapcp = Tapcp::connect(link.addr(), Platform::SNAP).unwrap();
+        tapcp.set_timeout(Duration::from_millis(20)).unwrap();
+        tapcp.set_flash_timeout(Duration::from_millis(20));
+        tapcp.set_retry_policy(RetryPolicy {
+            initial_backoff: Some(Duration::from_millis(20)),
+            max_backoff: Duration::from_millis(100),
+            retry_on: |_| true,
+            ..RetryPolicy::with_attempts(30)
+        });
+
+        // Multi-block transfers of device memory still arrive intact
+        let data: Vec<u8> = (0..4096u32).map(|i| (i * 7).to_le_bytes()[0]).collect();
+        for _ in 0..5 {
+            tapcp.write_bytes("dev", 0, &data).unwrap();
+            assert_eq!(tapcp.read_n_bytes("dev", 0, 4096).unwrap(), data);
+        }
+        assert_eq!(
+            board
+                .board()
+                .fpga()
+                .memory()
+                .read_n_bytes("dev", 0, 4096)
+                .unwrap(),
+            data
+        );
+
+        // As do flash writes and reads
+        let image: Vec<u8> = (0..sector * 2).map(|i| (i / 5).to_le_bytes()[0]).collect();
+        assert!(tapcp
+            .write_bitstream(FlashAddr::new(0x0010_0000), &image, 0, &CancelToken::new())
+            .unwrap());
+        assert_eq!(
+            board.board().flash[0x0010_0000..0x0010_0000 + image.len()],
+            image
+        );
+        assert_eq!(
+            tapcp
+                .read_flash(0x0010_0000 / 4, image.len() / 4, |_, _| ())
+                .unwrap(),
+            image
+        );
+
+        // And the link really did misbehave
+        let stats = link.stats();
+        assert!(stats.dropped_requests > 0);
+        assert!(stats.dropped_responses > 0);
+        assert!(stats.duplicates > 0);
+        assert!(stats.bit_flips > 0);
+        assert!(stats.reordered > 0);
+    }
+
+    /// A board serving `files` (and reading `flash` at the start of its flash)
+    fn board_with(files: &[(&str, Vec<u8>)], flash: &[u8]) -> Board {
+        let mut board = Board::new();
+        board.files = files
+            .iter()
+            .map(|(name, file)| ((*name).to_string(), file.clone()))
+            .collect();
+        board.flash[..flash.len()].copy_from_slice(flash);
+        board
+    }
+
+    /// A transport to `board`, which reboots instantly
+    fn connect(board: &Emulator) -> Tapcp {
+        let mut tapcp = Tapcp::connect(board.addr(), Platform::SNAP).unwrap();
+        tapcp.set_reboot_wait(Duration::ZERO);
+        tapcp
+    }
+
+    #[test]
+    fn test_transfer_options() {
+        use tapcp::negotiate::{
+            self,
+            Negotiated,
+        };
+        let file: Vec<u8> = (0..1300u32).map(|i| i.to_le_bytes()[0]).collect();
+        // The file is 0x145 words from word 0x10 of the flash
+        let flash = [vec![0; 0x40], file.clone()].concat();
+        let options = TransferOptions::with_timeout(2);
+        for (support, negotiated) in [
+            (
+                OptionSupport::Accept,
+                Negotiated {
+                    tsize: Some(1300),
+                    timeout: Some(Duration::from_secs(2)),
+                    blksize: None,
+                },
+            ),
+            (OptionSupport::Ignore, Negotiated::default()),
+            (OptionSupport::Refuse, Negotiated::default()),
+        ] {
+            let mut board = board_with(&[], &flash);
+            board.options = support;
+            let board = Emulator::start(board);
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.connect(board.addr()).unwrap();
+            let mut progress = vec![];
+            let (data, got) = negotiate::download(
+                "/flash.10.145",
+                &socket,
+                Duration::from_millis(500),
+                Duration::from_secs(1),
+                3,
+                options,
+                |read, total| progress.push((read, total)),
+            )
+            .unwrap();
+            assert_eq!(data, file);
+            assert_eq!(got, negotiated);
+            let total = negotiated.tsize;
+            assert_eq!(progress, vec![(512, total), (1024, total), (1300, total)]);
+            let requests = &board.board().requests;
+            assert!(requests[0]
+                .packet
+                .starts_with(b"\0\x01/flash.10.145\0octet\0tsize\x000\0timeout\x002\0"));
+            if support == OptionSupport::Refuse {
+                assert_eq!(requests[1].packet, b"\0\x01/flash.10.145\0octet\0");
+            }
+        }
+
+        // Flash reads through the transport always know the total
+        let board = Emulator::start(board_with(&[], &flash));
+        let mut tapcp = connect(&board);
+        tapcp.set_transfer_options(options);
+        let mut progress = vec![];
+        let data = tapcp
+            .read_flash(0x10, 325, |read, total| progress.push((read, total)))
+            .unwrap();
+        assert_eq!(data, file);
+        assert_eq!(progress.last(), Some(&(1300, 1300)));
+        assert_eq!(board.board().requests[0].filename(), "/flash.10.145");
+        assert_eq!(
+            tapcp.socket.read_timeout().unwrap(),
+            Some(Duration::from_secs_f32(DEFAULT_TIMEOUT))
+        );
+    }
+
+    #[test]
+    fn test_datagram_sizing_and_stats() {
+        use tapcp::negotiate::{
+            self,
+            MAX_BLOCK_SIZE,
+        };
+        let download = |board: &Emulator, options| {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.connect(board.addr()).unwrap();
+            let res = negotiate::download(
+                "/dev/big",
+                &socket,
+                Duration::from_millis(50),
+                Duration::from_millis(100),
+                2,
+                options,
+                |_, _| {},
+            );
+            (res, tapcp::stats::get(&socket))
+        };
+        let big = [("/dev/big", vec![7; 1124])];
+
+        // Bigger blocks are negotiated, but never more than fits in a datagram
+        let mut board = board_with(&big, &[]);
+        board.options = OptionSupport::Accept;
+        board.max_block_size = 1024;
+        let board = Emulator::start(board);
+        let options = TransferOptions {
+            blksize: Some(9000),
+            ..TransferOptions::default()
+        };
+        let (res, stats) = download(&board, options);
+        let (bytes, negotiated) = res.unwrap();
+        assert_eq!(bytes, vec![7; 1124]);
+        assert_eq!(negotiated.blksize, Some(1024));
+        let request = board.board().requests[0].packet.clone();
+        assert!(request.ends_with(format!("octet\0blksize\0{MAX_BLOCK_SIZE}\0").as_bytes()));
+        // The request and three ACKs for the OACK and two blocks
+        assert_eq!(
+            stats,
+            SocketStats {
+                datagrams_sent: 4,
+                datagrams_received: 3,
+                bytes_sent: (request.len() + 3 * 4) as u64,
+                bytes_received: 15 + 1028 + 104,
+                timeouts: 0,
+                oversized: 0,
+            }
+        );
+
+        // Blocks bigger than the transfer's fail it rather than being cut short
+        let mut board = board_with(&big, &[]);
+        board.block_size = Some(1000);
+        let board = Emulator::start(board);
+        let (res, stats) = download(&board, TransferOptions::default());
+        match res {
+            Err(tftp_client::Error::SocketIo(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+            }
+            r => panic!("Expected an oversized datagram, got {r:?}"),
+        }
+        assert_eq!(stats.oversized, 1);
+
+        // Every timeout is counted on the transport's socket
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut tapcp = Tapcp::connect(silent.local_addr().unwrap(), Platform::SNAP).unwrap();
+        tapcp.set_retry_policy(RetryPolicy::with_attempts(2));
+        tapcp.set_timeout(Duration::from_millis(5)).unwrap();
+        assert!(tapcp.read_n_bytes("sys_clkcounter", 0, 4).is_err());
+        // The request and its one retransmit
+        let stats = tapcp.socket_stats();
+        assert_eq!(stats.timeouts, 2);
+        assert_eq!(stats.datagrams_sent, 2);
+        assert_eq!(stats.datagrams_received, 0);
+        tapcp.reset_socket_stats();
+        assert_eq!(tapcp.socket_stats(), SocketStats::default());
+    }
+
+    #[test]
+    fn test_chunked_reads() {
+        // Chunks are reassembled in order and a short one fails the whole read
+        let mut requests = vec![];
+        let bytes = tapcp::read_chunked(3, 5, 2, |offset, n| {
+            requests.push((offset, n));
+            Ok(vec![u8::try_from(offset).unwrap(); 4 * n])
+        })
+        .unwrap();
+        assert_eq!(requests, vec![(3, 2), (5, 2), (7, 1)]);
+        assert_eq!(bytes.len(), 20);
+        assert_eq!(bytes[8], 5);
+        assert!(matches!(
+            tapcp::read_chunked(0, 4, 2, |_, n| Ok(vec![0; 4 * n - 1])),
+            Err(tapcp::Error::ShortRead {
+                expected: 16,
+                got: 7
+            })
+        ));
+
+        // Reads between words take every word they touch, split into chunks
+        let mut fpga = SimulatedFpga::new(&RawDesign {
+            registers: HashMap::from([("dev".into(), DesignRegister { addr: 0, size: 12 })]),
+            devices: HashMap::new(),
+            bitstream: vec![],
+            md5: [0; 16],
+            sha256: [0; 32],
+            filename: "design.bin".into(),
+        });
+        fpga.memory()
+            .write_bytes("dev", 0, &(0..12).collect::<Vec<u8>>())
+            .unwrap();
+        let mut board = Board::new();
+        board.fpga = Some(fpga);
+        let board = Emulator::start(board);
+        let mut tapcp = connect(&board);
+        tapcp.set_read_chunk_words(2);
+        assert_eq!(
+            tapcp.read_n_bytes("dev", 2, 9).unwrap(),
+            (2..11).collect::<Vec<u8>>()
+        );
+        assert_eq!(tapcp.read_n_bytes("dev", 2, 4).unwrap(), vec![2, 3, 4, 5]);
+        assert!(tapcp.read_n_bytes("dev", 0, 0).unwrap().is_empty());
+        let requests: Vec<_> = board
+            .board()
+            .requests
+            .iter()
+            .map(Request::filename)
+            .collect();
+        assert_eq!(
+            requests,
+            vec!["/dev/dev.0.2", "/dev/dev.2.1", "/dev/dev.0.2"]
+        );
+    }
+
+    #[test]
+    fn test_flash_write_config() {
+        let sector = tapcp::FLASH_SECTOR_SIZE as usize;
+        let bitstream: Vec<u8> = (0..sector * 9 / 2 + 100)
+            .map(|i| (i / 7).to_le_bytes()[0])
+            .collect();
+        let image = 0x0010_0000;
+        // The files written and the number of source ports they came from
+        let written = |board: &Emulator| {
+            let board = board.board();
+            let clients: HashSet<_> = board.requests.iter().map(|r| r.client).collect();
+            (board.writes(), clients.len())
+        };
+
+        // One sector per transfer by default
+        let board = Emulator::start(Board::new());
+        let mut tapcp = connect(&board);
+        assert_eq!(*tapcp.flash_write_config(), FlashWriteConfig::default());
+        assert!(tapcp
+            .write_bitstream(
+                FlashAddr::new(0x0010_0000),
+                &bitstream,
+                0,
+                &CancelToken::new()
+            )
+            .unwrap());
+        let (files, clients) = written(&board);
+        assert_eq!(clients, 1);
+        assert_eq!(
+            files,
+            vec![
+                "/flash.40000",
+                "/flash.44000",
+                "/flash.48000",
+                "/flash.4c000",
+                "/flash.50000"
+            ]
+        );
+        assert_eq!(
+            board.board().flash[image..image + bitstream.len()],
+            bitstream
+        );
+
+        // Runs of sectors, two transfers at a time, resuming from the second sector
+        let board = Emulator::start(Board::new());
+        let mut tapcp = connect(&board);
+        tapcp.set_flash_write_config(FlashWriteConfig {
+            sectors_per_write: 2,
+            sector_delay: Duration::from_millis(1),
+            pipeline: 2,
+        });
+        assert!(tapcp
+            .write_bitstream(
+                FlashAddr::new(0x0010_0000),
+                &bitstream,
+                1,
+                &CancelToken::new()
+            )
+            .unwrap());
+        let (mut files, clients) = written(&board);
+        files.sort();
+        assert_eq!(clients, 2);
+        assert_eq!(files, vec!["/flash.44000", "/flash.4c000"]);
+        let flash = &board.board().flash;
+        assert!(flash[image..image + sector].iter().all(|&b| b == 0xFF));
+        assert_eq!(
+            flash[image + sector..image + bitstream.len()],
+            bitstream[sector..]
+        );
+
+        // Nothing is written once cancelled
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(!tapcp
+            .write_bitstream(FlashAddr::new(0), &bitstream, 0, &cancel)
+            .unwrap());
+
+        // Zero sectors or transfers would never write anything
+        tapcp.set_flash_write_config(FlashWriteConfig {
+            sectors_per_write: 0,
+            sector_delay: Duration::ZERO,
+            pipeline: 0,
+        });
+        assert_eq!(*tapcp.flash_write_config(), FlashWriteConfig::default());
+    }
+
+    #[test]
+    fn test_connect_config() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let remote = silent.local_addr().unwrap();
+        let config = ConnectConfig {
+            local: "127.0.0.1:0".parse().unwrap(),
+            remote_port: remote.port(),
+            pin_source_port: true,
+        };
+        let mut tapcp = Tapcp::connect_with(remote.ip(), Platform::SNAP, config).unwrap();
+        let local = tapcp.local_addr().unwrap();
+        assert_eq!(tapcp.socket.peer_addr().unwrap(), remote);
+        // A pinned reconnect comes back on the same source port
+        tapcp.reconnect().unwrap();
+        assert_eq!(tapcp.local_addr().unwrap(), local);
+        assert_eq!(tapcp.socket.peer_addr().unwrap(), remote);
+        assert_eq!(
+            tapcp.socket.read_timeout().unwrap(),
+            Some(Duration::from_secs_f32(DEFAULT_TIMEOUT))
+        );
+    }
+
+    #[test]
+    fn test_platform_specs() {
+        let snap = Platform::SNAP.spec();
+        assert_eq!(snap.program_location, 0x0081_0000);
+        assert_eq!(snap.progdev_address(snap.program_location), 0x8100);
+        let snap2 = Platform::SNAP2.spec();
+        assert_eq!(snap2.progdev_address(snap2.program_location), 0x00C1_0000);
+        let custom = PlatformSpec {
+            golden_location: 0x0010_0000,
+            ..PlatformSpec::with_flash_location(0x0200_0000, 0)
+        };
+        assert_eq!(Platform::Custom(custom).flash_location(), 0x0200_0000);
+    }
+
+    #[test]
+    fn test_flash_addressing() {
+        // (metadata word, bitstream word) for each platform
+        for (platform, words) in [
+            (Platform::SNAP, (0x0020_0000, 0x0020_4000)),
+            (Platform::SNAP2, (0x0030_0000, 0x0030_4000)),
+        ] {
+            let spec = platform.spec();
+            let meta = FlashAddr::from(spec.flash_location);
+            let image = FlashAddr::from(spec.program_location);
+            assert_eq!((meta.word().unwrap(), image.word().unwrap()), words);
+            assert_eq!(FlashAddr::from_word(words.1).unwrap(), image);
+            // The metadata fills its sector right up to the bitstream
+            assert_eq!(meta.sector_room(), tapcp::FLASH_SECTOR_SIZE as usize);
+            assert_eq!(meta.offset(meta.sector_room()).unwrap(), image);
+            assert!(image.offset(u32::MAX as usize).is_err());
+            assert_eq!(image.offset(4).unwrap().sector_room(), 0xFFFC);
+            assert!(matches!(
+                image.offset(2).unwrap().word(),
+                Err(tapcp::Error::MisalignedFlash(_))
+            ));
+        }
+        // Nothing past the end of the 32-bit address space
+        let end = FlashAddr::new(u32::MAX - 3);
+        assert_eq!(end.word().unwrap(), 0x3FFF_FFFF);
+        assert_eq!(end.sector_room(), 4);
+        assert!(matches!(
+            end.offset(4),
+            Err(tapcp::Error::FlashOverflow { offset: 4, .. })
+        ));
+        assert!(FlashAddr::from_word(0x4000_0000).is_err());
+        assert_eq!(end.to_string(), "0xfffffffc");
+    }
+
+    #[test]
+    fn test_file_io() {
+        let board = Emulator::start(board_with(&[("/cal", vec![1, 2, 3])], &[]));
+        let mut tapcp = connect(&board);
+        // `/help` lists commands, not the files on the board
+        assert_eq!(
+            tapcp.list_commands().unwrap(),
+            ["/dev", "/flash", "/help", "/listdev", "/progdev", "/temp"]
+        );
+        // With or without the leading slash
+        assert_eq!(tapcp.read_file("/cal").unwrap(), vec![1, 2, 3]);
+        assert_eq!(tapcp.read_file("cal").unwrap(), vec![1, 2, 3]);
+        // Bigger than a single TFTP block
+        let table: Vec<u8> = (0..1500u32).map(|i| i.to_le_bytes()[0]).collect();
+        tapcp.write_file("cal", &table).unwrap();
+        assert_eq!(board.board().files["/cal"], table);
+        assert_eq!(tapcp.read_file("cal").unwrap(), table);
+        assert_eq!(board.board().writes(), vec!["/cal"]);
+
+        // Missing files are the server's errors
+        tapcp.set_retry_policy(RetryPolicy::with_attempts(1));
+        for res in [
+            tapcp.read_file("missing").map(|_| ()),
+            tapcp.write_file("missing", &[0; 4]),
+        ] {
+            let Err(Error::Lower(e)) = res else {
+                panic!("Expected a server error, got {res:?}");
+            };
+            assert!(matches!(
+                e.tftp(),
+                Some(tftp_client::Error::Protocol {
+                    code: tftp_client::parser::ErrorCode::NoFile,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_platform_commands() {
+        // A server without `/progdev` is never sent it
+        let board = Emulator::start(board_with(
+            &[("/help", b"/help /listdev /temp\n".to_vec())],
+            &[],
+        ));
+        let mut tapcp = connect(&board);
+        assert!(tapcp.platform_commands().unwrap().is_empty());
+        assert!(matches!(
+            tapcp.reboot_to_golden(),
+            Err(Error::Unsupported(PlatformCommand::Progdev))
+        ));
+        assert!(board.board().progdevs.is_empty());
+
+        let board = Emulator::start(Board::new());
+        let mut tapcp = connect(&board);
+        assert_eq!(
+            tapcp.platform_commands().unwrap(),
+            vec![PlatformCommand::Progdev]
+        );
+        tapcp.reboot_to_golden().unwrap();
+        let spec = Platform::SNAP.spec();
+        assert_eq!(
+            board.board().progdevs,
+            vec![spec.progdev_address(spec.golden_location)]
+        );
+
+        assert_eq!(
+            PlatformCommand::from_path("/progdev"),
+            Some(PlatformCommand::Progdev)
+        );
+        assert_eq!(PlatformCommand::from_path("/listdev"), None);
+        assert_eq!(PlatformCommand::Progdev.to_string(), "/progdev");
+    }
+
+    #[test]
+    fn test_server_stats() {
+        let files = HashMap::from([("/temp", 45.5f32.to_be_bytes().to_vec())]);
+        let endpoints = ["/temp", "/listdev"].map(String::from).to_vec();
+        let stats = ServerStats::collect(endpoints.clone(), |name| files.get(name).cloned());
+        assert_eq!(
+            stats,
+            ServerStats {
+                temperature: Some(45.5),
+                endpoints,
+            }
+        );
+
+        // Endpoints that aren't advertised aren't read
+        let stats = ServerStats::collect(vec!["/listdev".into()], |name| {
+            panic!("Read {name}");
+        });
+        assert_eq!(stats.temperature, None);
+
+        // From the board itself
+        let board = Emulator::start(Board::new());
+        let stats = connect(&board).server_stats().unwrap();
+        assert_eq!(stats.temperature, Some(42.5));
+        assert_eq!(stats.endpoints.len(), 6);
+    }
+
+    #[test]
+    fn test_board_inventory() {
+        let inventory = BoardInventory {
+            serial: Some("SNAP-0042".into()),
+            antenna: None,
+            cable_lengths: BTreeMap::from([("rf.a".into(), 12.5), ("pps".into(), 3.0)]),
+            extra: BTreeMap::from([("site".into(), "OVRO".into())]),
+        };
+        let mut meta = inventory.to_metadata().unwrap();
+        assert_eq!(meta["inventory.version"], "1");
+        assert_eq!(meta["inventory.cable.rf.a"], "12.5");
+        // Programming metadata is ignored
+        meta.insert("md5".into(), "abc".into());
+        assert_eq!(
+            BoardInventory::from_metadata(&meta).unwrap(),
+            Some(inventory.clone())
+        );
+        assert_eq!(
+            BoardInventory::from_metadata(&HashMap::from([("md5".into(), "abc".into())])).unwrap(),
+            None
+        );
+        meta.insert("inventory.version".into(), "2".into());
+        assert!(matches!(
+            BoardInventory::from_metadata(&meta),
+            Err(Error::InventoryVersion(2))
+        ));
+
+        let mut bad = inventory.clone();
+        bad.serial = Some("a?b".into());
+        assert!(matches!(bad.to_metadata(), Err(Error::BadInventory(_))));
+        let mut bad = inventory;
+        bad.extra.insert("cable.x".into(), "1".into());
+        assert!(matches!(bad.to_metadata(), Err(Error::BadInventory(_))));
+    }
+
+    #[test]
+    fn test_metadata_boundaries() {
+        // A dictionary of one entry, `total` bytes long with its `?end`
+        let dict = |total: usize| {
+            HashMap::from([(KString::from_ref("k"), "v".repeat(total - "?k\t?end".len()))])
+        };
+        // What reading the flash back finds
+        let read = |flash: &[u8]| {
+            let end = flash.windows(4).position(|w| w == b"?end").unwrap();
+            tapcp::decode_metadata(&flash[..end]).unwrap()
+        };
+        let chunk = tapcp::METADATA_CHUNK_SIZE;
+        for total in [8, chunk - 1, chunk, chunk + 1, 2 * chunk, 2 * chunk + 3] {
+            let meta = dict(total);
+            let bytes = tapcp::encode_metadata(&meta, 0).unwrap();
+            assert_eq!(bytes.len(), (total + chunk - 1) / chunk * chunk);
+            assert_eq!(read(&bytes), meta);
+
+            // Shrinking overwrites everything the longer dictionary used
+            let mut flash = tapcp::encode_metadata(&dict(3 * chunk + 1), 0).unwrap();
+            let shrunk = tapcp::encode_metadata(&meta, 3 * chunk + 1).unwrap();
+            assert_eq!(shrunk.len(), flash.len());
+            flash[..shrunk.len()].copy_from_slice(&shrunk);
+            assert_eq!(read(&flash), meta);
+            assert_eq!(flash.windows(4).filter(|w| w == b"?end").count(), 1);
+        }
+        let max = tapcp::MAX_METADATA_CHUNKS * chunk;
+        assert_eq!(tapcp::encode_metadata(&dict(max), 0).unwrap().len(), max);
+        assert!(matches!(
+            tapcp::encode_metadata(&dict(max + 1), 0),
+            Err(tapcp::Error::MetadataTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_image_identity() {
+        let design = RawDesign {
+            registers: [("sys_clkcounter", 0), ("tx_en", 4)]
+                .into_iter()
+                .map(|(name, addr)| (name.into(), DesignRegister { addr, size: 4 }))
+                .collect(),
+            devices: HashMap::new(),
+            bitstream: vec![],
+            md5: [0xAB; 16],
+            sha256: [0; 32],
+            filename: "design.bin".into(),
+        };
+        let listdev = |regs: &[(&str, usize)]| -> RegisterMap {
+            regs.iter()
+                .map(|(name, addr)| {
+                    (
+                        KString::from_ref(name),
+                        Register {
+                            addr: *addr,
+                            length: 4,
+                        },
+                    )
+                })
+                .collect()
+        };
+        let booted = ImageIdentity {
+            md5: Some(design.md5_string()),
+            registers: register_fingerprint(&listdev(&[("tx_en", 4), ("sys_clkcounter", 0)])),
+        };
+        assert_eq!(ImageIdentity::of(&design), booted);
+        let golden = ImageIdentity {
+            md5: None,
+            registers: register_fingerprint(&listdev(&[("sys_clkcounter", 0)])),
+        };
+        assert_ne!(golden, booted);
+        let err = Error::WrongImageBooted {
+            expected: booted,
+            found: golden,
+        };
+        assert!(err.to_string().contains("running md5 (none)"));
+
+        // A board that never comes back gives up after the timeout
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut tapcp = Tapcp::connect(silent.local_addr().unwrap(), Platform::SNAP).unwrap();
+        tapcp.set_retry_policy(RetryPolicy::with_attempts(1));
+        tapcp.set_timeout(Duration::from_millis(20)).unwrap();
+        assert!(tapcp
+            .verify_boot(&design, 0, Duration::from_millis(10))
+            .is_err());
+    }
+
+    #[test]
+    fn test_design_slots() {
+        let snap = Platform::SNAP.spec();
+        // The stock layout only has the user image
+        assert_eq!(snap.slot_location(0).unwrap(), snap.flash_location);
+        assert_eq!(
+            snap.slot_program_location(0).unwrap(),
+            snap.program_location
+        );
+        assert!(matches!(
+            snap.slot_location(1),
+            Err(Error::BadSlot { slot: 1, slots: 1 })
+        ));
+        let multi = PlatformSpec {
+            slot_size: 0x0080_0000,
+            slots: 3,
+            ..PlatformSpec::with_flash_location(0x0080_0000, 8)
+        };
+        assert_eq!(multi.slot_location(2).unwrap(), 0x0180_0000);
+        assert_eq!(multi.slot_program_location(1).unwrap(), 0x0101_0000);
+        assert_eq!(
+            multi.progdev_address(multi.slot_program_location(1).unwrap()),
+            0x0001_0100
+        );
+        // A slot holds everything up to the next one, less its metadata sector
+        assert!(multi.check_slot(1, 0x007F_0000).is_ok());
+        assert!(matches!(
+            multi.check_slot(1, 0x007F_0001),
+            Err(Error::SlotTooSmall {
+                slot: 1,
+                capacity: 0x007F_0000,
+                ..
+            })
+        ));
+        assert!(multi.check_slot(3, 16).is_err());
+    }
+
+    #[test]
+    fn test_golden_overlap() {
+        let snap = Platform::SNAP.spec();
+        // The golden image spans everything before the metadata
+        assert!(snap.check_golden_overlap(0, 16).is_err());
+        assert!(snap.check_golden_overlap(0x007F_FFF0, 0x20).is_err());
+        assert!(snap.check_golden_overlap(0x0080_0000, 0x20).is_ok());
+        assert!(snap
+            .check_golden_overlap(snap.program_location, 0x0040_0000)
+            .is_ok());
+        assert!(snap.check_golden_overlap(0, 0).is_ok());
+    }
+
+    /// A design `sectors` flash sectors and a bit long, told apart from others by `id`
+    fn sectored_design(id: u8, sectors: usize) -> RawDesign {
+        let len = sectors * tapcp::FLASH_SECTOR_SIZE as usize + 100;
+        RawDesign {
+            registers: HashMap::from([(
+                "sys_clkcounter".into(),
+                DesignRegister { addr: 0, size: 4 },
+            )]),
+            devices: HashMap::new(),
+            bitstream: (0..len).map(|i| (i / 3).to_le_bytes()[0] ^ id).collect(),
+            md5: [id; 16],
+            sha256: [id; 32],
+            filename: "design.bin".into(),
+        }
+    }
+
+    #[test]
+    fn test_program_state() {
+        let board = Emulator::start(Board::with_design(sectored_design(1, 2), |_| ()));
+        let mut tapcp = connect(&board);
+        let design = sectored_design(1, 2);
+        // A blank dictionary records nothing
+        assert_eq!(tapcp.verify_programmed().unwrap(), ProgramState::Unknown);
+
+        // Starting to program drops the old hashes but keeps the inventory
+        let inventory = BoardInventory {
+            serial: Some("SNAP-0042".into()),
+            ..BoardInventory::default()
+        };
+        tapcp.set_board_inventory(&inventory).unwrap();
+        tapcp.mark_programming(&design).unwrap();
+        assert_eq!(
+            tapcp.verify_programmed().unwrap(),
+            ProgramState::Interrupted {
+                md5: design.md5_string(),
+                sectors: 0,
+            }
+        );
+        assert_eq!(tapcp.board_inventory().unwrap(), Some(inventory.clone()));
+
+        // Finishing replaces the marker with the hashes
+        tapcp.program(&design, false).unwrap();
+        assert_eq!(
+            tapcp.verify_programmed().unwrap(),
+            ProgramState::Programmed {
+                md5: Some(design.md5_string()),
+                sha256: Some(design.sha256_string()),
+            }
+        );
+        assert_eq!(tapcp.board_inventory().unwrap(), Some(inventory));
+        assert!(board.board().fpga.is_some());
+
+        // An erased sector has no dictionary at all
+        board.board().flash[Platform::SNAP.flash_location() as usize..]
+            [..tapcp::FLASH_SECTOR_SIZE as usize]
+            .fill(0xFF);
+        assert_eq!(tapcp.verify_programmed().unwrap(), ProgramState::Unknown);
+        assert_eq!(tapcp.board_inventory().unwrap(), None);
+    }
+
+    #[test]
+    fn test_metadata_read_once() {
+        let board = Emulator::start(Board::with_design(sectored_design(1, 2), |_| ()));
+        let mut tapcp = connect(&board);
+        let design = sectored_design(1, 2);
+        tapcp.program(&design, false).unwrap();
+
+        // Replacing the dictionary reads the old one once, to carry over the inventory and to
+        // know how much of it to overwrite
+        board.board().requests.clear();
+        tapcp.mark_programming(&design).unwrap();
+        let dict = format!("/flash.{:x}.", Platform::SNAP.flash_location() / 4);
+        let requests = board.board().requests.clone();
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|r| !r.is_write() && r.filename().starts_with(&dict))
+                .count(),
+            1
+        );
+        assert_eq!(
+            tapcp.verify_programmed().unwrap(),
+            ProgramState::Interrupted {
+                md5: design.md5_string(),
+                sectors: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_program_golden_overlap() {
+        let board = Emulator::start(Board::with_design(sectored_design(1, 2), |_| ()));
+        let design = sectored_design(1, 2);
+        connect(&board).program(&design, false).unwrap();
+
+        // A layout that puts the user image inside the golden region is refused before the
+        // metadata of the design already on the board is touched
+        let spec = PlatformSpec {
+            golden_size: 0x0100_0000,
+            ..Platform::SNAP.spec()
+        };
+        let mut tapcp = Tapcp::connect(board.addr(), Platform::Custom(spec)).unwrap();
+        tapcp.set_reboot_wait(Duration::ZERO);
+        assert!(matches!(
+            tapcp.program(&sectored_design(2, 2), false),
+            Err(crate::transport::Error::Tapcp(Error::GoldenOverlap { .. }))
+        ));
+        assert_eq!(
+            tapcp.verify_programmed().unwrap(),
+            ProgramState::Programmed {
+                md5: Some(design.md5_string()),
+                sha256: Some(design.sha256_string()),
+            }
+        );
+        assert_eq!(board.board().progdevs.len(), 1);
+    }
+
+    #[test]
+    fn test_repair() {
+        let board = Emulator::start(Board::with_design(sectored_design(1, 2), |_| ()));
+        let mut tapcp = connect(&board);
+        let design = sectored_design(1, 2);
+
+        // A finished (or never started) programming is left alone
+        assert_eq!(tapcp.repair(&design).unwrap(), RepairOutcome::Intact);
+        tapcp.program(&design, false).unwrap();
+        assert_eq!(tapcp.repair(&design).unwrap(), RepairOutcome::Intact);
+        assert_eq!(board.board().progdevs.len(), 1);
+
+        // Programming another design that was cut off is replaced with this one
+        let other = sectored_design(2, 2);
+        tapcp.mark_programming(&other).unwrap();
+        let image = Platform::SNAP.spec().program_location as usize;
+        board.board().flash[image..image + other.bitstream.len()].copy_from_slice(&other.bitstream);
+        assert_eq!(tapcp.repair(&design).unwrap(), RepairOutcome::Reprogrammed);
+        assert_eq!(
+            board.board().flash[image..image + design.bitstream.len()],
+            design.bitstream
+        );
+        assert!(matches!(
+            tapcp.verify_programmed().unwrap(),
+            ProgramState::Programmed { .. }
+        ));
+        assert!(board.board().fpga.is_some());
+
+        // As is this design cut off after its first sector
+        tapcp.mark_programming(&design).unwrap();
+        board.board().flash[image + tapcp::FLASH_SECTOR_SIZE as usize..][..4]
+            .copy_from_slice(&[0; 4]);
+        assert_eq!(tapcp.repair(&design).unwrap(), RepairOutcome::Reprogrammed);
+        assert_eq!(
+            board.board().flash[image..image + design.bitstream.len()],
+            design.bitstream
+        );
+        assert_eq!(board.board().progdevs.len(), 3);
+    }
+
+    #[test]
+    fn test_resume_program() {
+        let design = sectored_design(1, PROGRESS_SECTORS + 4);
+        let board = Emulator::start(Board::with_design(
+            sectored_design(1, PROGRESS_SECTORS + 4),
+            |_| (),
+        ));
+        let mut tapcp = connect(&board);
+        tapcp.set_retry_policy(RetryPolicy {
+            retry_on: |_| false,
+            ..RetryPolicy::with_attempts(1)
+        });
+        let image = FlashAddr::from(Platform::SNAP.spec().program_location);
+        let word = |sector: usize| {
+            image
+                .offset(sector * tapcp::FLASH_SECTOR_SIZE as usize)
+                .unwrap()
+                .word()
+                .unwrap()
+        };
+        // The flash word offsets of the image `requests` touched
+        let image_words = |requests: &[Request], write: bool| -> Vec<usize> {
+            requests
+                .iter()
+                .filter(|r| r.is_write() == write)
+                .filter_map(|r| {
+                    let name = r.filename();
+                    let offset = name.strip_prefix("/flash.")?.split('.').next()?;
+                    usize::from_str_radix(offset, 16).ok()
+                })
+                .filter(|&w| w >= word(0))
+                .collect()
+        };
+
+        // The board stops taking writes partway through the second run of sectors
+        board
+            .board()
+            .refused
+            .insert(format!("/flash.{:x}", word(PROGRESS_SECTORS + 2)));
+        assert!(tapcp.program(&design, false).is_err());
+        assert_eq!(
+            tapcp.verify_programmed().unwrap(),
+            ProgramState::Interrupted {
+                md5: design.md5_string(),
+                sectors: PROGRESS_SECTORS,
+            }
+        );
+
+        // Resuming keeps the recorded sectors without reading them back, and only checks the
+        // ones written after the last record
+        board.board().refused.clear();
+        board.board().requests.clear();
+        assert_eq!(tapcp.resume_program(&design).unwrap(), PROGRESS_SECTORS + 2);
+        let requests = board.board().requests.clone();
+        assert_eq!(
+            image_words(&requests, false),
+            (PROGRESS_SECTORS..PROGRESS_SECTORS + 3)
+                .map(word)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            image_words(&requests, true),
+            (PROGRESS_SECTORS + 2..PROGRESS_SECTORS + 5)
+                .map(word)
+                .collect::<Vec<_>>()
+        );
+        let start = image.bytes() as usize;
+        assert_eq!(
+            board.board().flash[start..start + design.bitstream.len()],
+            design.bitstream
+        );
+        assert!(matches!(
+            tapcp.verify_programmed().unwrap(),
+            ProgramState::Programmed { .. }
+        ));
+        assert!(board.board().fpga.is_some());
     }
 }
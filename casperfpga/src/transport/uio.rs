@@ -0,0 +1,349 @@
+//! A local transport for system-on-chip platforms (i.e. Zynq) using the Linux userspace
+//! I/O (UIO) framework
+//!
+//! Rather than mapping one giant window of `/dev/mem`, the CASPER device tree exposes a UIO node
+//! per block. Blocks are discovered through sysfs (`/sys/class/uio/uioN/name`) and each one is
+//! mapped on its own, so a device name maps directly to its own window.
+//!
+//! Registers are accessed a 32-bit word at a time and each word is presented in big-endian byte
+//! order, matching the other transports.
+
+use super::{
+    check_bounds,
+    Transport,
+    TransportResult,
+};
+use crate::core::{
+    Register,
+    RegisterMap,
+};
+use casper_utils::design_sources::{
+    mangle_name,
+    FpgaDesign,
+};
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    os::fd::AsRawFd,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Internal system IO error")]
+    Io(#[from] std::io::Error),
+    #[error("No UIO device named `{0}`")]
+    NotFound(String),
+    #[error("Couldn't parse the sysfs attribute `{0}`")]
+    BadSysfs(PathBuf),
+    #[error("This transport was opened read-only")]
+    ReadOnly,
+    #[error("Programming isn't supported over UIO, use the FPGA manager instead")]
+    Unsupported,
+}
+
+/// The default location of the UIO class in sysfs
+pub const SYSFS_UIO: &str = "/sys/class/uio";
+
+/// A UIO node as described by sysfs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UioDevice {
+    /// The (mangled) name of the block this node exposes
+    pub name: String,
+    /// The path to the device node, i.e. `/dev/uio3`
+    pub path: PathBuf,
+    /// The size of the first memory map of the node in bytes
+    pub size: usize,
+}
+
+/// Find every UIO node listed under `sysfs` (normally [`SYSFS_UIO`]), with device nodes assumed
+/// to live in `dev` (normally `/dev`)
+/// # Errors
+/// Returns an error if sysfs couldn't be read or had unexpected contents
+pub fn discover(sysfs: &Path, dev: &Path) -> Result<Vec<UioDevice>, Error> {
+    let mut devices = vec![];
+    for entry in std::fs::read_dir(sysfs)? {
+        let entry = entry?;
+        let node = entry.file_name();
+        let Some(node) = node.to_str() else {
+            continue;
+        };
+        if !node.starts_with("uio") {
+            continue;
+        }
+        let name = std::fs::read_to_string(entry.path().join("name"))?;
+        let size_path = entry.path().join("maps/map0/size");
+        let size = std::fs::read_to_string(&size_path)?;
+        let size = usize::from_str_radix(size.trim().trim_start_matches("0x"), 16)
+            .map_err(|_| Error::BadSysfs(size_path))?;
+        devices.push(UioDevice {
+            name: mangle_name(name.trim()),
+            path: dev.join(node),
+            size,
+        });
+    }
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(devices)
+}
+
+/// A single memory-mapped UIO window
+#[derive(Debug)]
+struct Mapping {
+    ptr: *mut u32,
+    len: usize,
+}
+
+// The mapping is uniquely owned by the transport, so it's safe to move between threads
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn new(path: &Path, len: usize, writable: bool) -> Result<Self, Error> {
+        let file = OpenOptions::new().read(true).write(writable).open(path)?;
+        let prot = if writable {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_READ
+        };
+        // SAFETY: We map a fresh region (no fixed address) of a file we just opened, the result is
+        // checked before use. The mapping outlives the file descriptor by design.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                prot,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    fn read_word(&self, word: usize) -> u32 {
+        assert!((word + 1) * 4 <= self.len);
+        // SAFETY: Bounds checked above and the mapping is page (and therefore word) aligned
+        unsafe { self.ptr.add(word).read_volatile() }
+    }
+
+    fn write_word(&mut self, word: usize, value: u32) {
+        assert!((word + 1) * 4 <= self.len);
+        // SAFETY: Bounds checked above and the mapping is page (and therefore word) aligned
+        unsafe { self.ptr.add(word).write_volatile(value) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` came from a successful mmap that nothing else references
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+/// A transport over individually-mapped UIO devices
+#[derive(Debug)]
+pub struct Uio {
+    maps: HashMap<String, Mapping>,
+    registers: RegisterMap,
+    writable: bool,
+}
+
+impl Uio {
+    /// Map every UIO device on the system for reading and writing
+    /// # Errors
+    /// Returns an error if the devices couldn't be discovered or mapped
+    pub fn new() -> Result<Self, Error> {
+        Self::from_devices(&discover(Path::new(SYSFS_UIO), Path::new("/dev"))?, true)
+    }
+
+    /// Map only the UIO devices named in `names`, optionally read-only - monitoring tools should
+    /// prefer this to minimize what is exposed
+    /// # Errors
+    /// Returns an error if any device isn't found or couldn't be mapped
+    pub fn with_devices(names: &[&str], writable: bool) -> Result<Self, Error> {
+        let all = discover(Path::new(SYSFS_UIO), Path::new("/dev"))?;
+        let selected = names
+            .iter()
+            .map(|name| {
+                let name = mangle_name(name);
+                all.iter()
+                    .find(|d| d.name == name)
+                    .cloned()
+                    .ok_or(Error::NotFound(name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_devices(&selected, writable)
+    }
+
+    /// Map the given UIO devices. Writes return [`Error::ReadOnly`] unless `writable` is set.
+    /// # Errors
+    /// Returns an error if any of the devices couldn't be mapped
+    pub fn from_devices(devices: &[UioDevice], writable: bool) -> Result<Self, Error> {
+        let mut maps = HashMap::new();
+        let mut registers = RegisterMap::new();
+        for device in devices {
+            maps.insert(
+                device.name.clone(),
+                Mapping::new(&device.path, device.size, writable)?,
+            );
+            // Every device lives at the start of its own window
+            registers.insert(
+                device.name.clone().into(),
+                Register {
+                    addr: 0,
+                    length: device.size,
+                },
+            );
+        }
+        Ok(Self {
+            maps,
+            registers,
+            writable,
+        })
+    }
+
+    fn mapping(&mut self, device: &str) -> TransportResult<&mut Mapping> {
+        self.maps
+            .get_mut(device)
+            .ok_or_else(|| super::Error::DeviceNotFound(device.to_string()))
+    }
+}
+
+impl Transport for Uio {
+    fn is_running(&mut self) -> TransportResult<bool> {
+        // Blocks only show up in the device tree once the overlay with the design is applied
+        Ok(!self.maps.is_empty())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "uio::read", level = "trace", skip(self), err)
+    )]
+    fn read_n_bytes(&mut self, device: &str, offset: usize, n: usize) -> TransportResult<Vec<u8>> {
+        check_bounds(&self.registers, device, offset, n)?;
+        let map = self.mapping(device)?;
+        let mut bytes = Vec::with_capacity(n);
+        for word in offset / 4..(offset + n + 3) / 4 {
+            bytes.extend_from_slice(&map.read_word(word).to_be_bytes());
+        }
+        let start = offset % 4;
+        Ok(bytes[start..start + n].to_vec())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "uio::write",
+            level = "trace",
+            skip(self, data),
+            fields(n = data.len()),
+            err,
+        )
+    )]
+    fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+        if !self.writable {
+            return Err(Error::ReadOnly.into());
+        }
+        check_bounds(&self.registers, device, offset, data.len())?;
+        let map = self.mapping(device)?;
+        let mut pos = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let word = pos / 4;
+            let start = pos % 4;
+            let len = (4 - start).min(data.len());
+            // Partial words are read-modify-write
+            let mut bytes = if len == 4 {
+                [0u8; 4]
+            } else {
+                map.read_word(word).to_be_bytes()
+            };
+            bytes[start..start + len].copy_from_slice(&data[..len]);
+            map.write_word(word, u32::from_be_bytes(bytes));
+            data = &data[len..];
+            pos += len;
+        }
+        Ok(())
+    }
+
+    fn listdev(&mut self) -> TransportResult<RegisterMap> {
+        Ok(self.registers.clone())
+    }
+
+    fn program<D>(&mut self, _design: &D, _force: bool) -> TransportResult<()>
+    where
+        D: FpgaDesign,
+    {
+        Err(Error::Unsupported.into())
+    }
+
+    fn deprogram(&mut self) -> TransportResult<()> {
+        Err(Error::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a fake sysfs tree and "device nodes" (plain files, which mmap just as well)
+    fn fake_uio(tag: &str, devices: &[(&str, usize)]) -> (PathBuf, PathBuf) {
+        let root =
+            std::env::temp_dir().join(format!("casperfpga_uio_{tag}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let sysfs = root.join("sys");
+        let dev = root.join("dev");
+        std::fs::create_dir_all(&dev).unwrap();
+        for (idx, (name, size)) in devices.iter().enumerate() {
+            let node = sysfs.join(format!("uio{idx}"));
+            std::fs::create_dir_all(node.join("maps/map0")).unwrap();
+            std::fs::write(node.join("name"), format!("{name}\n")).unwrap();
+            std::fs::write(node.join("maps/map0/size"), format!("{size:#x}\n")).unwrap();
+            let file = std::fs::File::create(dev.join(format!("uio{idx}"))).unwrap();
+            file.set_len(*size as u64).unwrap();
+        }
+        (sysfs, dev)
+    }
+
+    #[test]
+    fn test_uio_read_write() {
+        let (sysfs, dev) = fake_uio("rw", &[("sys", 0x1000), ("adc/snap_bram", 0x1000)]);
+        let devices = discover(&sysfs, &dev).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "adc_snap_bram");
+        let mut uio = Uio::from_devices(&devices, true).unwrap();
+        uio.write("sys", 4, &0xDEAD_BEEFu32).unwrap();
+        assert_eq!(uio.read::<u32, 4>("sys", 4).unwrap(), 0xDEAD_BEEF);
+        // Unaligned accesses only touch the requested bytes
+        uio.write_bytes("sys", 5, &[0x12, 0x34]).unwrap();
+        assert_eq!(uio.read::<u32, 4>("sys", 4).unwrap(), 0xDE12_34EF);
+        assert_eq!(uio.read_n_bytes("sys", 6, 3).unwrap(), vec![0x34, 0xEF, 0]);
+        assert!(uio.read_n_bytes("sys", 0xFFE, 4).is_err());
+        assert!(uio.read_n_bytes("not_a_device", 0, 4).is_err());
+        let _ = std::fs::remove_dir_all(sysfs.parent().unwrap());
+    }
+
+    #[test]
+    fn test_uio_read_only() {
+        let (sysfs, dev) = fake_uio("ro", &[("sys", 0x1000)]);
+        let devices = discover(&sysfs, &dev).unwrap();
+        let mut uio = Uio::from_devices(&devices, false).unwrap();
+        assert_eq!(uio.read::<u32, 4>("sys", 0).unwrap(), 0);
+        assert!(matches!(
+            uio.write("sys", 0, &1u32),
+            Err(super::super::Error::Uio(Error::ReadOnly))
+        ));
+        let _ = std::fs::remove_dir_all(sysfs.parent().unwrap());
+    }
+}
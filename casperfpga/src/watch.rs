@@ -0,0 +1,218 @@
+//! Change detection on registers, for "tell me when this changes" debugging
+//!
+//! A [`Watch`] polls a set of 32-bit registers at a fixed rate and reports every change as a
+//! [`ChangeEvent`], either through a callback with [`Watch::run`] or as an iterator with
+//! [`Watch::events`]. Each register can have a mask so that only the bits of interest are compared
+//! (i.e. to ignore a free-running counter packed in with some status flags).
+//!
+//! The first poll only records the starting values, so events are only produced for changes after
+//! the watch started.
+use crate::transport::{
+    Transport,
+    TransportResult,
+};
+use std::{
+    collections::VecDeque,
+    ops::ControlFlow,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+/// A 32-bit register word being watched
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Watched {
+    device: String,
+    offset: usize,
+    mask: u32,
+    last: Option<u32>,
+}
+
+/// A detected change in a watched register
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// The register that changed
+    pub device: String,
+    /// The byte offset of the word in the register
+    pub offset: usize,
+    /// The previous (masked) value
+    pub old: u32,
+    /// The new (masked) value
+    pub new: u32,
+    /// When the change was seen
+    pub time: SystemTime,
+}
+
+/// A set of registers polled for changes
+#[derive(Debug, Clone)]
+pub struct Watch {
+    registers: Vec<Watched>,
+    interval: Duration,
+}
+
+impl Watch {
+    /// Create an empty watch that polls every `interval`
+    #[must_use]
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            registers: vec![],
+            interval,
+        }
+    }
+
+    /// Watch every bit of the first word of `device`
+    #[must_use]
+    pub fn register(self, device: &str) -> Self {
+        self.masked(device, 0, u32::MAX)
+    }
+
+    /// Watch only the bits set in `mask` of the word at byte `offset` of `device`
+    #[must_use]
+    pub fn masked(mut self, device: &str, offset: usize, mask: u32) -> Self {
+        self.registers.push(Watched {
+            device: device.to_string(),
+            offset,
+            mask,
+            last: None,
+        });
+        self
+    }
+
+    /// Read every watched register once, returning the changes since the last poll
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn poll<T>(&mut self, transport: &mut T) -> TransportResult<Vec<ChangeEvent>>
+    where
+        T: Transport,
+    {
+        let mut events = vec![];
+        for reg in &mut self.registers {
+            let value = transport.read::<u32, 4>(&reg.device, reg.offset)? & reg.mask;
+            match reg.last {
+                Some(old) if old != value => events.push(ChangeEvent {
+                    device: reg.device.clone(),
+                    offset: reg.offset,
+                    old,
+                    new: value,
+                    time: SystemTime::now(),
+                }),
+                _ => {}
+            }
+            reg.last = Some(value);
+        }
+        Ok(events)
+    }
+
+    /// Poll until `callback` returns [`ControlFlow::Break`], calling it with every change
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn run<T, F>(&mut self, transport: &mut T, mut callback: F) -> TransportResult<()>
+    where
+        T: Transport,
+        F: FnMut(&ChangeEvent) -> ControlFlow<()>,
+    {
+        loop {
+            let start = Instant::now();
+            for event in self.poll(transport)? {
+                if callback(&event).is_break() {
+                    return Ok(());
+                }
+            }
+            std::thread::sleep(self.interval.saturating_sub(start.elapsed()));
+        }
+    }
+
+    /// An endless iterator of changes, polling as needed. Transport errors are yielded as they
+    /// happen and polling continues afterwards.
+    pub fn events<'a, T>(&'a mut self, transport: &'a mut T) -> Events<'a, T>
+    where
+        T: Transport,
+    {
+        Events {
+            watch: self,
+            transport,
+            pending: VecDeque::new(),
+            last_poll: None,
+        }
+    }
+}
+
+/// An iterator over the changes seen by a [`Watch`], see [`Watch::events`]
+#[derive(Debug)]
+pub struct Events<'a, T> {
+    watch: &'a mut Watch,
+    transport: &'a mut T,
+    pending: VecDeque<ChangeEvent>,
+    last_poll: Option<Instant>,
+}
+
+impl<T> Iterator for Events<'_, T>
+where
+    T: Transport,
+{
+    type Item = TransportResult<ChangeEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            if let Some(last) = self.last_poll {
+                std::thread::sleep(self.watch.interval.saturating_sub(last.elapsed()));
+            }
+            self.last_poll = Some(Instant::now());
+            match self.watch.poll(self.transport) {
+                Ok(events) => self.pending.extend(events),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_watch() {
+        let mut transport = Mock::new(HashMap::from([
+            ("status".into(), Register { addr: 0, length: 4 }),
+            ("ctrl".into(), Register { addr: 4, length: 4 }),
+        ]));
+        let mut watch = Watch::new(Duration::from_millis(1))
+            .masked("status", 0, 0xFF)
+            .register("ctrl");
+        // The first poll is the baseline
+        assert!(watch.poll(&mut transport).unwrap().is_empty());
+        // Changes outside of the mask are ignored
+        transport.write("status", 0, &0x100u32).unwrap();
+        assert!(watch.poll(&mut transport).unwrap().is_empty());
+        transport.write("status", 0, &0x142u32).unwrap();
+        transport.write("ctrl", 0, &7u32).unwrap();
+        let events = watch.poll(&mut transport).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].old, events[0].new), (0, 0x42));
+        assert_eq!(events[1].device, "ctrl");
+
+        transport.write("ctrl", 0, &8u32).unwrap();
+        let event = watch.events(&mut transport).next().unwrap().unwrap();
+        assert_eq!((event.old, event.new), (7, 8));
+
+        transport.write("ctrl", 0, &9u32).unwrap();
+        let mut seen = vec![];
+        watch
+            .run(&mut transport, |event| {
+                seen.push(event.new);
+                ControlFlow::Break(())
+            })
+            .unwrap();
+        assert_eq!(seen, vec![9]);
+    }
+}
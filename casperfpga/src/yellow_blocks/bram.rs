@@ -1,6 +1,21 @@
-use crate::transport::Transport;
+use crate::{
+    transport::{
+        Transport,
+        WORD_BYTES,
+    },
+    yellow_blocks::{
+        device,
+        metadata_entry,
+        FromFpg,
+        Metadata,
+        TransportHandle,
+        YellowBlock,
+    },
+};
+use casper_utils::design_sources::Devices;
 use fixed::traits::Fixed;
 use std::{
+    any::Any,
     marker::PhantomData,
     sync::{
         Arc,
@@ -10,6 +25,9 @@ use std::{
 };
 use thiserror::Error;
 
+/// The default number of words fetched at once by [`Words`]
+pub const DEFAULT_READ_WINDOW: usize = 256;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
@@ -26,7 +44,7 @@ pub enum Error {
 #[derive(Debug)]
 pub struct Bram<T, F> {
     /// Upwards pointer to the parent class' transport
-    transport: Weak<Mutex<T>>,
+    transport: TransportHandle<T>,
     /// The name of the register
     name: String,
     /// Marker for the integer type of the data type
@@ -44,7 +62,7 @@ where
     pub fn new(transport: &Arc<Mutex<T>>, reg_name: &str, size: usize) -> Self {
         let transport = Arc::downgrade(transport);
         Self {
-            transport,
+            transport: TransportHandle::new(transport, reg_name),
             name: reg_name.to_string(),
             phantom: PhantomData,
             size,
@@ -60,7 +78,7 @@ where
         addr_width: &str,
     ) -> Result<Self, Error> {
         Ok(Self {
-            transport,
+            transport: TransportHandle::new(transport, reg_name),
             name: reg_name.to_string(),
             phantom: PhantomData,
             size: 1
@@ -79,61 +97,406 @@ where
     /// Read one fixed point word at `addr` from the BRAM
     /// # Errors
     /// Returns an error on transport errors
-    #[allow(clippy::missing_panics_doc)]
     pub fn read_addr(&self, addr: usize) -> Result<F, Error> {
         if addr >= self.size {
             return Err(Error::OutOfBounds);
         }
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(F::from_be_bytes(transport.read(&self.name, addr)?))
+        self.transport
+            .with_transport(|transport| Ok(F::from_be_bytes(transport.read(&self.name, addr)?)))
+    }
+
+    /// Read the `n` words starting at word `start` from the BRAM in a single transaction
+    /// # Errors
+    /// Returns an error on transport errors or if the range runs past the end of the BRAM
+    #[allow(clippy::missing_panics_doc)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "bram::read", skip_all, fields(device = %self.name), err)
+    )]
+    pub fn read_range(&self, start: usize, n: usize) -> Result<Vec<F>, Error> {
+        if start.checked_add(n).map_or(true, |end| end > self.size) {
+            return Err(Error::OutOfBounds);
+        }
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        self.transport.with_transport(|transport| {
+            let v = transport.read_n_bytes(&self.name, start * N, n * N)?;
+            // Transform the vec of bytes to the vec of fixed point words
+            Ok(v.chunks(N)
+                .map(|c| F::from_be_bytes(c.try_into().unwrap()))
+                .collect())
+        })
     }
 
     /// Reads the entire BRAM
     /// # Errors
     /// Returns an error on transport errors
-    #[allow(clippy::missing_panics_doc)]
     pub fn read(&self) -> Result<Vec<F>, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Read all the data
-        let total_bytes = self.size * N;
-        let v = transport.read_n_bytes(&self.name, 0, total_bytes)?;
-        // Transform the vec of bytes to the vec of fixed point words
-        Ok(v.chunks(N)
-            .map(|c| F::from_be_bytes(c.try_into().unwrap()))
-            .collect())
+        self.read_range(0, self.size)
+    }
+
+    /// Iterate over every word of the BRAM, see [`Bram::words_range`]
+    #[must_use]
+    pub fn words(&self) -> Words<'_, T, F> {
+        self.words_range(0, self.size)
+    }
+
+    /// Iterate over every word of the BRAM, the same as [`Bram::words`]
+    #[must_use]
+    pub fn iter(&self) -> Words<'_, T, F> {
+        self.words()
+    }
+
+    /// Iterate over the `n` words starting at word `start`. Words are fetched
+    /// [`DEFAULT_READ_WINDOW`] at a time (or as set with [`Words::window`]) with
+    /// [`Bram::read_range`] and served from a buffer, so walking the BRAM takes one transaction
+    /// per window instead of one per word. The iterator stops after the first error.
+    #[must_use]
+    pub fn words_range(&self, start: usize, n: usize) -> Words<'_, T, F> {
+        Words {
+            bram: self,
+            next: start,
+            end: start.saturating_add(n),
+            window: DEFAULT_READ_WINDOW,
+            buffer: vec![].into_iter(),
+        }
     }
 
     /// Write the entire BRAM
     /// # Errors
     /// Returns an error on transport errors or if the data is not the correct size
-    #[allow(clippy::missing_panics_doc)]
     pub fn write(&self, data: &[F]) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Transform the vec of fixed point words to the vec of bytes
-        let total_bytes = self.size * N;
-        let v = data
-            .iter()
-            .flat_map(|f| f.to_be_bytes().to_vec())
-            .collect::<Vec<_>>();
-        if v.len() != total_bytes {
-            return Err(Error::BadSize);
-        }
-        // Write all the data
-        transport.write_bytes(&self.name, 0, &v)?;
-        Ok(())
+        self.transport.with_transport(|transport| {
+            // Transform the vec of fixed point words to the vec of bytes
+            let total_bytes = self.size * N;
+            let v = data
+                .iter()
+                .flat_map(|f| f.to_be_bytes().to_vec())
+                .collect::<Vec<_>>();
+            if v.len() != total_bytes {
+                return Err(Error::BadSize);
+            }
+            // Write all the data
+            transport.write_bytes(&self.name, 0, &v)?;
+            Ok(())
+        })
+    }
+
+    /// Write `data` to the words starting at word `start` in a single transaction, leaving the rest
+    /// of the BRAM as is. Words narrower than a bus word that don't fill their first or last bus
+    /// word have the bytes around them read back first and written along with them.
+    /// # Errors
+    /// Returns an error on transport errors or if the data runs past the end of the BRAM
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "bram::write", skip_all, fields(device = %self.name), err)
+    )]
+    pub fn write_range(&self, start: usize, data: &[F]) -> Result<(), Error> {
+        if start
+            .checked_add(data.len())
+            .map_or(true, |end| end > self.size)
+        {
+            return Err(Error::OutOfBounds);
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        let offset = start * N;
+        let end = offset + data.len() * N;
+        // Transports move whole bus words, so widen the write out to them
+        let first = offset - offset % WORD_BYTES;
+        let last = (end + WORD_BYTES - 1) / WORD_BYTES * WORD_BYTES;
+        self.transport.with_transport(|transport| {
+            let mut v = Vec::with_capacity(last - first);
+            if first < offset {
+                v.extend(transport.read_n_bytes(&self.name, first, WORD_BYTES)?);
+                v.truncate(offset - first);
+            }
+            v.extend(data.iter().flat_map(|f| f.to_be_bytes()));
+            if end < last {
+                let tail = transport.read_n_bytes(&self.name, last - WORD_BYTES, WORD_BYTES)?;
+                v.extend_from_slice(&tail[WORD_BYTES - (last - end)..]);
+            }
+            transport.write_bytes(&self.name, first, &v)?;
+            Ok(())
+        })
     }
 
     /// Write a fixed point word at `addr` to the BRAM
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn write_addr(&self, addr: usize, val: F) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Perform the write
-        Ok(transport.write(&self.name, addr, &(val.to_be_bytes()))?)
+        self.transport.with_transport(|transport| {
+            // Perform the write
+            Ok(transport.write(&self.name, addr, &(val.to_be_bytes()))?)
+        })
+    }
+}
+
+impl<T, F> FromFpg<T> for Bram<T, F>
+where
+    T: Transport,
+    F: Fixed,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Ok(Self::from_fpg(
+            transport,
+            name,
+            metadata_entry(metadata, name, "addr_width")?,
+        )?)
+    }
+}
+
+impl<T, F> YellowBlock<T> for Bram<T, F>
+where
+    T: Transport + 'static,
+    F: Fixed + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_metadata(transport, name, &device(devices, name)?.metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:bram"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "xps:bram `{}` ({} words of {} bits, binary point {})",
+            self.name,
+            self.size,
+            F::INT_NBITS + F::FRAC_NBITS,
+            F::FRAC_NBITS
+        )
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<'a, T, F, const N: usize> IntoIterator for &'a Bram<T, F>
+where
+    T: Transport,
+    F: Fixed<Bytes = [u8; N]>,
+{
+    type IntoIter = Words<'a, T, F>;
+    type Item = Result<F, Error>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.words()
+    }
+}
+
+/// A buffered iterator over the words of a [`Bram`], see [`Bram::words_range`]
+#[derive(Debug)]
+pub struct Words<'a, T, F> {
+    bram: &'a Bram<T, F>,
+    /// The address of the first word not yet fetched
+    next: usize,
+    end: usize,
+    window: usize,
+    buffer: std::vec::IntoIter<F>,
+}
+
+impl<T, F> Words<'_, T, F> {
+    /// Set the number of words fetched per transaction (at least one)
+    #[must_use]
+    pub fn window(mut self, words: usize) -> Self {
+        self.window = words.max(1);
+        self
+    }
+}
+
+impl<T, F, const N: usize> Iterator for Words<'_, T, F>
+where
+    T: Transport,
+    F: Fixed<Bytes = [u8; N]>,
+{
+    type Item = Result<F, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(word) = self.buffer.next() {
+            return Some(Ok(word));
+        }
+        if self.next >= self.end {
+            return None;
+        }
+        let n = self.window.min(self.end - self.next);
+        match self.bram.read_range(self.next, n) {
+            Ok(words) => {
+                self.next += n;
+                self.buffer = words.into_iter();
+                self.buffer.next().map(Ok)
+            }
+            Err(e) => {
+                self.next = self.end;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{
+            Register,
+            RegisterMap,
+        },
+        transport::{
+            check_word_aligned,
+            mock::Mock,
+            TransportResult,
+        },
+    };
+    use casper_utils::design_sources::FpgaDesign;
+    use fixed::types::{
+        I16F16,
+        I8F0,
+        U8F8,
+    };
+    use std::collections::HashMap;
+
+    /// A mock that counts read transactions and refuses writes that aren't whole words
+    #[derive(Debug)]
+    struct Counting {
+        inner: Mock,
+        reads: usize,
+    }
+
+    impl Transport for Counting {
+        fn is_running(&mut self) -> TransportResult<bool> {
+            self.inner.is_running()
+        }
+
+        fn read_n_bytes(
+            &mut self,
+            device: &str,
+            offset: usize,
+            n: usize,
+        ) -> TransportResult<Vec<u8>> {
+            self.reads += 1;
+            self.inner.read_n_bytes(device, offset, n)
+        }
+
+        fn write_bytes(&mut self, device: &str, offset: usize, data: &[u8]) -> TransportResult<()> {
+            check_word_aligned(device, offset, data.len())?;
+            self.inner.write_bytes(device, offset, data)
+        }
+
+        fn listdev(&mut self) -> TransportResult<RegisterMap> {
+            self.inner.listdev()
+        }
+
+        fn program<D>(&mut self, design: &D, force: bool) -> TransportResult<()>
+        where
+            D: FpgaDesign,
+        {
+            self.inner.program(design, force)
+        }
+
+        fn deprogram(&mut self) -> TransportResult<()> {
+            self.inner.deprogram()
+        }
+    }
+
+    #[test]
+    fn test_read_coalescing() {
+        let transport = Arc::new(Mutex::new(Counting {
+            inner: Mock::new(HashMap::from([(
+                "bram".into(),
+                Register {
+                    addr: 0,
+                    length: 40,
+                },
+            )])),
+            reads: 0,
+        }));
+        let bram: Bram<_, I16F16> = Bram::new(&transport, "bram", 10);
+        let data: Vec<_> = (0..10).map(I16F16::from_num).collect();
+        bram.write(&data).unwrap();
+        // The single word accessors take byte addresses
+        bram.write_addr(3 * 4, I16F16::from_num(-3)).unwrap();
+        assert_eq!(bram.read_addr(4).unwrap(), I16F16::from_num(1));
+        transport.lock().unwrap().reads = 0;
+
+        assert_eq!(bram.read_range(2, 3).unwrap()[2], I16F16::from_num(4));
+        assert!(bram.read_range(8, 3).is_err());
+        let words: Vec<_> = bram
+            .words_range(1, 9)
+            .window(4)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(words.len(), 9);
+        assert_eq!(words[0], I16F16::from_num(1));
+        assert_eq!(words[2], I16F16::from_num(-3));
+        // One read for the range plus three windows of 4, 4, and 1 words
+        assert_eq!(transport.lock().unwrap().reads, 4);
+        // Errors end the iteration
+        let mut iter = bram.words_range(8, 3);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+
+        // Partial updates leave the rest of the table alone
+        let patch: Vec<_> = (0..3).map(|x| I16F16::from_num(x) / 2).collect();
+        bram.write_range(6, &patch).unwrap();
+        assert!(matches!(
+            bram.write_range(8, &patch),
+            Err(Error::OutOfBounds)
+        ));
+        let all: Vec<_> = bram.iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(all[5], I16F16::from_num(5));
+        assert_eq!(&all[6..9], patch.as_slice());
+        assert_eq!(all[9], I16F16::from_num(9));
+    }
+
+    #[test]
+    fn test_narrow_write_range() {
+        let transport = Arc::new(Mutex::new(Counting {
+            inner: Mock::new(HashMap::from([(
+                "bram".into(),
+                Register {
+                    addr: 0,
+                    length: 16,
+                },
+            )])),
+            reads: 0,
+        }));
+        let bytes: Bram<_, I8F0> = Bram::new(&transport, "bram", 16);
+        let halves: Bram<_, U8F8> = Bram::new(&transport, "bram", 8);
+        let data: Vec<_> = (0..16).map(I8F0::from_num).collect();
+        bytes.write(&data).unwrap();
+
+        // Words that don't fill the bus words they're in keep their neighbours
+        let patch: Vec<_> = (0..6).map(|x| I8F0::from_num(-x)).collect();
+        bytes.write_range(3, &patch).unwrap();
+        bytes.write_range(13, &patch[..1]).unwrap();
+        halves.write_range(7, &[U8F8::from_bits(0xABCD)]).unwrap();
+        let expected: Vec<_> = [0, 1, 2, 0, -1, -2, -3, -4, -5, 9, 10, 11, 12, 0, -85, -51]
+            .into_iter()
+            .map(I8F0::from_num)
+            .collect();
+        assert_eq!(bytes.read().unwrap(), expected);
+
+        // And ones that do are written without reading anything
+        transport.lock().unwrap().reads = 0;
+        halves.write_range(2, &[U8F8::ZERO; 2]).unwrap();
+        assert_eq!(transport.lock().unwrap().reads, 0);
+        assert_eq!(bytes.read_range(4, 4).unwrap(), vec![I8F0::ZERO; 4]);
     }
 }
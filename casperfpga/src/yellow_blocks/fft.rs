@@ -0,0 +1,253 @@
+//! # FFT/PFB Control
+//!
+//! Most CASPER spectrometers wrap their FFT (or PFB) in the same handful of software registers - a
+//! shift schedule register where bit `i` enables the divide-by-two in stage `i`, an overflow
+//! counter (e.g. `fft_overflow_cnt`), and optionally a per-stage overflow latch with a register to
+//! clear it.
+//!
+//! Like the vacc, these are user-built registers rather than a yellow block with fpg metadata, so
+//! the register names are supplied explicitly.
+
+use crate::{
+    transport::Transport,
+    yellow_blocks::TransportHandle,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("The FFT has {0} stages, but shift schedules can only cover 32")]
+    TooManyStages(u32),
+    #[error("Shift schedule has {got} stages, expected {expected}")]
+    BadSchedule { got: usize, expected: u32 },
+    #[error("This FFT has no overflow latch register")]
+    NoLatch,
+}
+
+/// An FFT shift schedule, where stage 0 is the first butterfly stage (the LSB of the register)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShiftSchedule(pub Vec<bool>);
+
+impl ShiftSchedule {
+    /// Shift on every one of the `stages` stages
+    #[must_use]
+    pub fn full(stages: u32) -> Self {
+        Self(vec![true; stages as usize])
+    }
+
+    fn to_bits(&self) -> u32 {
+        self.0
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, &s)| acc | (u32::from(s) << i))
+    }
+
+    fn from_bits(bits: u32, stages: u32) -> Self {
+        Self((0..stages).map(|i| (bits >> i) & 1 == 1).collect())
+    }
+}
+
+/// The overflow state of the FFT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverflowStatus {
+    /// The running overflow count
+    pub count: u32,
+    /// Per-stage latched overflows, if the design has a latch register
+    pub stages: Option<Vec<bool>>,
+}
+
+impl OverflowStatus {
+    /// Whether any overflow has been seen at all
+    #[must_use]
+    pub fn overflowed(&self) -> bool {
+        self.count > 0 || self.stages.as_ref().is_some_and(|s| s.iter().any(|&o| o))
+    }
+}
+
+/// Control of the shift schedule and overflow monitoring of an FFT or PFB
+#[derive(Debug)]
+pub struct FftControl<T> {
+    /// Upwards pointer to the parent class' transport
+    transport: TransportHandle<T>,
+    /// The name of the shift schedule register
+    shift: String,
+    /// The name of the overflow counter register
+    overflow_cnt: String,
+    /// The names of the overflow latch and latch clear registers
+    latch: Option<(String, String)>,
+    /// Number of butterfly stages
+    stages: u32,
+}
+
+impl<T> FftControl<T>
+where
+    T: Transport,
+{
+    /// Construct an FFT controller from its register names.
+    /// `latch` is the (latch register, clear register) pair for designs with per-stage latches.
+    /// # Errors
+    /// Returns an error if `stages` is more than the 32 a shift register can hold
+    pub fn new(
+        transport: &Arc<Mutex<T>>,
+        shift: &str,
+        overflow_cnt: &str,
+        latch: Option<(&str, &str)>,
+        stages: u32,
+    ) -> Result<Self, Error> {
+        if stages > 32 {
+            return Err(Error::TooManyStages(stages));
+        }
+        let transport = Arc::downgrade(transport);
+        Ok(Self {
+            transport: TransportHandle::new(transport, shift),
+            shift: shift.to_string(),
+            overflow_cnt: overflow_cnt.to_string(),
+            latch: latch.map(|(l, c)| (l.to_string(), c.to_string())),
+            stages,
+        })
+    }
+
+    /// Set the shift schedule
+    /// # Errors
+    /// Returns an error on bad transport or if the schedule doesn't match the number of stages
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "fft::set_shift_schedule",
+            skip_all,
+            fields(device = %self.shift),
+            err,
+        )
+    )]
+    pub fn set_shift_schedule(&self, schedule: &ShiftSchedule) -> Result<(), Error> {
+        if schedule.0.len() != self.stages as usize {
+            return Err(Error::BadSchedule {
+                got: schedule.0.len(),
+                expected: self.stages,
+            });
+        }
+        self.transport
+            .with_transport(|transport| Ok(transport.write(&self.shift, 0, &schedule.to_bits())?))
+    }
+
+    /// Get the shift schedule
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn get_shift_schedule(&self) -> Result<ShiftSchedule, Error> {
+        self.transport.with_transport(|transport| {
+            let bits: u32 = transport.read(&self.shift, 0)?;
+            Ok(ShiftSchedule::from_bits(bits, self.stages))
+        })
+    }
+
+    /// Get the running overflow count
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn overflow_count(&self) -> Result<u32, Error> {
+        self.transport
+            .with_transport(|transport| Ok(transport.read(&self.overflow_cnt, 0)?))
+    }
+
+    /// Get the overflow count and, if available, the per-stage latched overflows
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn overflow_status(&self) -> Result<OverflowStatus, Error> {
+        self.transport.with_transport(|transport| {
+            let count: u32 = transport.read(&self.overflow_cnt, 0)?;
+            let stages = match &self.latch {
+                Some((latch, _)) => {
+                    let bits: u32 = transport.read(latch, 0)?;
+                    Some(ShiftSchedule::from_bits(bits, self.stages).0)
+                }
+                None => None,
+            };
+            Ok(OverflowStatus { count, stages })
+        })
+    }
+
+    /// Clear the overflow latches by pulsing the clear register
+    /// # Errors
+    /// Returns an error on bad transport or if the design has no latch
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "fft::clear_overflow",
+            skip_all,
+            fields(device = %self.overflow_cnt),
+            err,
+        )
+    )]
+    pub fn clear_overflow(&self) -> Result<(), Error> {
+        let (_, clear) = self.latch.as_ref().ok_or(Error::NoLatch)?;
+        self.transport.with_transport(|transport| {
+            transport.write(clear, 0, &0u32)?;
+            transport.write(clear, 0, &1u32)?;
+            transport.write(clear, 0, &0u32)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_fft_control() {
+        let transport = Mock::new(HashMap::from([
+            ("fft_shift".into(), Register { addr: 0, length: 4 }),
+            ("fft_overflow_cnt".into(), Register { addr: 4, length: 4 }),
+            ("fft_of_latch".into(), Register { addr: 8, length: 4 }),
+            (
+                "fft_of_clr".into(),
+                Register {
+                    addr: 12,
+                    length: 4,
+                },
+            ),
+        ]));
+        let transport = Arc::new(Mutex::new(transport));
+        let fft = FftControl::new(
+            &transport,
+            "fft_shift",
+            "fft_overflow_cnt",
+            Some(("fft_of_latch", "fft_of_clr")),
+            4,
+        )
+        .unwrap();
+        let schedule = ShiftSchedule(vec![true, false, true, true]);
+        fft.set_shift_schedule(&schedule).unwrap();
+        assert_eq!(
+            transport
+                .lock()
+                .unwrap()
+                .read::<u32, 4>("fft_shift", 0)
+                .unwrap(),
+            0b1101
+        );
+        assert_eq!(fft.get_shift_schedule().unwrap(), schedule);
+        assert!(fft.set_shift_schedule(&ShiftSchedule::full(5)).is_err());
+
+        transport
+            .lock()
+            .unwrap()
+            .write("fft_of_latch", 0, &0b0010u32)
+            .unwrap();
+        let status = fft.overflow_status().unwrap();
+        assert_eq!(status.count, 0);
+        assert_eq!(status.stages, Some(vec![false, true, false, false]));
+        assert!(status.overflowed());
+        fft.clear_overflow().unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! # iADC
+//!
+//! The iADC is the dual channel, 8 bit, 1 GSPS (Atmel/e2v AT84AD001) ZDOK card from the iBOB and
+//! ROACH days, whose channels can also be interleaved to sample one input at 2 GSPS. Designs with
+//! one or two of them share a single `iadc_controller` register:
+//!
+//! - Word 0 holds the reset lines, bit `adc_brd` resets the card on that ZDOK port
+//! - Word `1 + adc_brd` is the three wire interface of that card, written as the 16 bit register
+//!   value, the 3 bit register address, and a one to start the transfer
+//!
+//! The three wire interface is write-only, so nothing here reads back from the ADC.
+//!
+//! The gain of each channel is adjustable by about +/- 1.5 dB around a code of [`UNITY_GAIN`].
+
+use crate::{
+    transport::Transport,
+    yellow_blocks::{
+        device,
+        metadata_entry,
+        FromFpg,
+        Metadata,
+        TransportHandle,
+        YellowBlock,
+    },
+};
+use casper_utils::design_sources::Devices;
+use std::{
+    any::Any,
+    sync::{
+        Arc,
+        Mutex,
+        Weak,
+    },
+};
+use thiserror::Error;
+
+/// The register shared by the iADCs of a design
+const CONTROLLER: &str = "iadc_controller";
+/// The largest register address of the three wire interface
+pub const MAX_ADDR: u8 = 0b111;
+/// The gain code of 0 dB, which is also the code after a reset
+pub const UNITY_GAIN: u8 = 0x80;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("Bad ZDOK port `{0}` from the fpg file, expected 0 or 1")]
+    BadBoard(String),
+    #[error("The register address {0:#x} is larger than the maximum of {MAX_ADDR:#x}")]
+    BadAddress(u8),
+}
+
+/// One of the two inputs of the ADC
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    I,
+    Q,
+}
+
+impl Channel {
+    /// The address of the gain adjust register of the channel
+    fn gain_addr(self) -> u8 {
+        match self {
+            Self::I => 0b001,
+            Self::Q => 0b011,
+        }
+    }
+}
+
+/// The iADC yellow block
+#[derive(Debug)]
+pub struct IAdc<T> {
+    /// Upwards pointer to the parent class' transport
+    transport: TransportHandle<T>,
+    /// The name of the block in the design
+    name: String,
+    /// The ZDOK port of the card
+    adc_brd: u8,
+}
+
+impl<T> IAdc<T>
+where
+    T: Transport,
+{
+    /// Construct the iADC on the ZDOK port `adc_brd`
+    /// # Errors
+    /// Returns an error if `adc_brd` isn't 0 or 1
+    pub fn new(transport: &Arc<Mutex<T>>, reg_name: &str, adc_brd: u8) -> Result<Self, Error> {
+        Self::from_fpg(Arc::downgrade(transport), reg_name, &adc_brd.to_string())
+    }
+
+    /// Builds a [`IAdc`] from FPG description strings
+    /// # Errors
+    /// Returns an error on bad string arguments
+    pub fn from_fpg(
+        transport: Weak<Mutex<T>>,
+        reg_name: &str,
+        adc_brd: &str,
+    ) -> Result<Self, Error> {
+        let adc_brd = match adc_brd {
+            "0" => 0,
+            "1" => 1,
+            _ => return Err(Error::BadBoard(adc_brd.to_string())),
+        };
+        Ok(Self {
+            transport: TransportHandle::new(transport, reg_name),
+            name: reg_name.to_string(),
+            adc_brd,
+        })
+    }
+
+    /// The ZDOK port of the card
+    #[must_use]
+    pub fn adc_brd(&self) -> u8 {
+        self.adc_brd
+    }
+
+    /// Write `value` to the ADC register `addr` over the three wire interface
+    /// # Errors
+    /// Returns an error on bad transport or an address past [`MAX_ADDR`]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "iadc::spi_write", skip_all, fields(device = %self.name), err)
+    )]
+    pub fn spi_write(&self, addr: u8, value: u16) -> Result<(), Error> {
+        if addr > MAX_ADDR {
+            return Err(Error::BadAddress(addr));
+        }
+        let [hi, lo] = value.to_be_bytes();
+        self.transport.with_transport(|transport| {
+            Ok(transport.write_bytes(
+                CONTROLLER,
+                4 * (1 + self.adc_brd as usize),
+                &[hi, lo, addr, 0x01],
+            )?)
+        })
+    }
+
+    /// Pulse the reset line of the card, which returns every ADC register to its default
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "iadc::reset", skip_all, fields(device = %self.name), err)
+    )]
+    pub fn reset(&self) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            transport.write(CONTROLLER, 0, &(1u32 << self.adc_brd))?;
+            Ok(transport.write(CONTROLLER, 0, &0u32)?)
+        })
+    }
+
+    /// Set the analog gain of `channel` to `code`, from about -1.5 dB at zero through 0 dB at
+    /// [`UNITY_GAIN`] to about +1.5 dB at `0xFF`
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn set_gain(&self, channel: Channel, code: u8) -> Result<(), Error> {
+        self.spi_write(channel.gain_addr(), code.into())
+    }
+}
+
+impl<T> FromFpg<T> for IAdc<T>
+where
+    T: Transport,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Ok(Self::from_fpg(
+            transport,
+            name,
+            metadata_entry(metadata, name, "adc_brd")?,
+        )?)
+    }
+}
+
+impl<T> YellowBlock<T> for IAdc<T>
+where
+    T: Transport + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_metadata(transport, name, &device(devices, name)?.metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:iadc"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        format!("xps:iadc `{}` on ZDOK {}", self.name, self.adc_brd)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_iadc() {
+        let transport = Mock::new(HashMap::from([(
+            CONTROLLER.into(),
+            Register {
+                addr: 0,
+                length: 12,
+            },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        assert!(matches!(
+            IAdc::from_fpg(Arc::downgrade(&transport), "iadc", "zdok0"),
+            Err(Error::BadBoard(_))
+        ));
+        let adc = IAdc::new(&transport, "iadc0", 0).unwrap();
+        adc.set_gain(Channel::Q, 0x90).unwrap();
+        assert_eq!(
+            transport
+                .lock()
+                .unwrap()
+                .read_n_bytes(CONTROLLER, 4, 4)
+                .unwrap(),
+            vec![0x00, 0x90, 0b011, 0x01]
+        );
+        assert!(matches!(
+            adc.spi_write(0b1000, 0),
+            Err(Error::BadAddress(0b1000))
+        ));
+        adc.reset().unwrap();
+        assert_eq!(
+            transport
+                .lock()
+                .unwrap()
+                .read::<u32, 4>(CONTROLLER, 0)
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! # KAT ADC
+//!
+//! The KAT ADC is the dual channel, 8 bit, 1.5 GSPS (National ADC083000) ZDOK card from the ROACH
+//! days. Designs with one or two of them share a single `kat_adc_controller` register:
+//!
+//! - Word 0 holds the reset lines, bit `adc_brd` resets the card on that ZDOK port
+//! - Word `1 + adc_brd` is the serial interface of that card, written as the 16 bit register value,
+//!   the register address, and a one to start the transfer
+//!
+//! The serial interface is write-only, so nothing here reads back from the ADC.
+//!
+//! The gain of the ADC is its full scale range, which is set per channel from 560 mVpp (a code of
+//! zero) to 840 mVpp (a code of [`MAX_FULL_SCALE`]).
+
+use crate::{
+    transport::Transport,
+    yellow_blocks::{
+        device,
+        metadata_entry,
+        FromFpg,
+        Metadata,
+        TransportHandle,
+        YellowBlock,
+    },
+};
+use casper_utils::design_sources::Devices;
+use std::{
+    any::Any,
+    sync::{
+        Arc,
+        Mutex,
+        Weak,
+    },
+};
+use thiserror::Error;
+
+/// The register shared by the KAT ADCs of a design
+const CONTROLLER: &str = "kat_adc_controller";
+/// The largest full scale code, 840 mVpp
+pub const MAX_FULL_SCALE: u16 = 511;
+/// The full scale code after a reset, 700 mVpp
+pub const DEFAULT_FULL_SCALE: u16 = 256;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("Bad ZDOK port `{0}` from the fpg file, expected 0 or 1")]
+    BadBoard(String),
+    #[error("The full scale code {0} is larger than the maximum of {MAX_FULL_SCALE}")]
+    BadFullScale(u16),
+}
+
+/// One of the two inputs of the ADC
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    I,
+    Q,
+}
+
+impl Channel {
+    /// The address of the full scale adjust register of the channel
+    fn full_scale_addr(self) -> u8 {
+        match self {
+            Self::I => 0x3,
+            Self::Q => 0xB,
+        }
+    }
+}
+
+/// The KAT ADC yellow block
+#[derive(Debug)]
+pub struct KatAdc<T> {
+    /// Upwards pointer to the parent class' transport
+    transport: TransportHandle<T>,
+    /// The name of the block in the design
+    name: String,
+    /// The ZDOK port of the card
+    adc_brd: u8,
+}
+
+impl<T> KatAdc<T>
+where
+    T: Transport,
+{
+    /// Construct the KAT ADC on the ZDOK port `adc_brd`
+    /// # Errors
+    /// Returns an error if `adc_brd` isn't 0 or 1
+    pub fn new(transport: &Arc<Mutex<T>>, reg_name: &str, adc_brd: u8) -> Result<Self, Error> {
+        Self::from_fpg(Arc::downgrade(transport), reg_name, &adc_brd.to_string())
+    }
+
+    /// Builds a [`KatAdc`] from FPG description strings
+    /// # Errors
+    /// Returns an error on bad string arguments
+    pub fn from_fpg(
+        transport: Weak<Mutex<T>>,
+        reg_name: &str,
+        adc_brd: &str,
+    ) -> Result<Self, Error> {
+        let adc_brd = match adc_brd {
+            "0" => 0,
+            "1" => 1,
+            _ => return Err(Error::BadBoard(adc_brd.to_string())),
+        };
+        Ok(Self {
+            transport: TransportHandle::new(transport, reg_name),
+            name: reg_name.to_string(),
+            adc_brd,
+        })
+    }
+
+    /// The ZDOK port of the card
+    #[must_use]
+    pub fn adc_brd(&self) -> u8 {
+        self.adc_brd
+    }
+
+    /// Write `value` to the ADC register `addr` over the serial interface
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "katadc::spi_write", skip_all, fields(device = %self.name), err)
+    )]
+    pub fn spi_write(&self, addr: u8, value: u16) -> Result<(), Error> {
+        let [hi, lo] = value.to_be_bytes();
+        self.transport.with_transport(|transport| {
+            Ok(transport.write_bytes(
+                CONTROLLER,
+                4 * (1 + self.adc_brd as usize),
+                &[hi, lo, addr, 0x01],
+            )?)
+        })
+    }
+
+    /// Pulse the reset line of the card, which returns every ADC register to its default
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "katadc::reset", skip_all, fields(device = %self.name), err)
+    )]
+    pub fn reset(&self) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            transport.write(CONTROLLER, 0, &(1u32 << self.adc_brd))?;
+            Ok(transport.write(CONTROLLER, 0, &0u32)?)
+        })
+    }
+
+    /// Set the full scale range (the gain) of `channel` to `code`, from 560 mVpp at zero to 840
+    /// mVpp at [`MAX_FULL_SCALE`]
+    /// # Errors
+    /// Returns an error on bad transport or a code past [`MAX_FULL_SCALE`]
+    pub fn set_full_scale(&self, channel: Channel, code: u16) -> Result<(), Error> {
+        if code > MAX_FULL_SCALE {
+            return Err(Error::BadFullScale(code));
+        }
+        // The code sits in the top nine bits, the rest must be ones
+        self.spi_write(channel.full_scale_addr(), code << 7 | 0x7F)
+    }
+}
+
+impl<T> FromFpg<T> for KatAdc<T>
+where
+    T: Transport,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Ok(Self::from_fpg(
+            transport,
+            name,
+            metadata_entry(metadata, name, "adc_brd")?,
+        )?)
+    }
+}
+
+impl<T> YellowBlock<T> for KatAdc<T>
+where
+    T: Transport + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_metadata(transport, name, &device(devices, name)?.metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:katadc"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        format!("xps:katadc `{}` on ZDOK {}", self.name, self.adc_brd)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_katadc() {
+        let transport = Mock::new(HashMap::from([(
+            CONTROLLER.into(),
+            Register {
+                addr: 0,
+                length: 12,
+            },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        assert!(matches!(
+            KatAdc::new(&transport, "katadc2", 2),
+            Err(Error::BadBoard(_))
+        ));
+        let adc = KatAdc::new(&transport, "katadc1", 1).unwrap();
+        adc.set_full_scale(Channel::Q, DEFAULT_FULL_SCALE).unwrap();
+        assert_eq!(
+            transport
+                .lock()
+                .unwrap()
+                .read_n_bytes(CONTROLLER, 8, 4)
+                .unwrap(),
+            vec![0x80, 0x7F, 0x0B, 0x01]
+        );
+        assert!(matches!(
+            adc.set_full_scale(Channel::I, 512),
+            Err(Error::BadFullScale(512))
+        ));
+        adc.reset().unwrap();
+        assert_eq!(
+            transport
+                .lock()
+                .unwrap()
+                .read::<u32, 4>(CONTROLLER, 0)
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -1,46 +1,498 @@
-//! Logic and implementations for CASPER "Yellow Block" devices.
-//! These are at the heart of a casperfpga design and will be the structs you primarily interact
-//! with.
-//!
-//! From a design perspective, all of the yellow block structs contain a `transport` field which is
-//! of type `Weak<Mutex<T: Transport>>`, this allows the yellow block to interact with the
-//! transport, but not own the transport. This is important as one will almost certainly have
-//! many yellow blocks that will all needs to interface to the hardware. Although nothing enforces
-//! the convention, it is best practice to put the owned `Arc<Mutex<T:Transport>>` in some top-level
-//! struct and then have the yellow blocks as members of that struct.
-//!
-//! To this end, all yellow block structs follow the constructor convention of `new(transport:
-//! &Arc<Mutex<T:Transport>>, reg_name: &str, ..<metadata>)`, where the constructor implicitly calls
-//! `Arc::downgrade`.
-//!
-//! Additionally, from an error handling perspective, every yellow block will have its own error
-//! type, usually including a thin wrapper around the transport error.
-
-use thiserror::Error;
-
-pub mod bram;
-pub mod snapadc;
-pub mod snapshot;
-pub mod swreg;
-pub mod ten_gbe;
-
-/// Certain Yellow Block struct types will implement this trait to allow for auto offsets in
-/// transport read methods
-pub trait Address {
-    fn addr() -> u16;
-}
-
-#[derive(Error, Debug)]
-/// Top level error for all yellow blocks (rarely used)
-pub enum Error {
-    #[error(transparent)]
-    Bram(#[from] bram::Error),
-    #[error(transparent)]
-    SnapAdc(#[from] snapadc::Error),
-    #[error(transparent)]
-    Snapshot(#[from] snapshot::Error),
-    #[error(transparent)]
-    Swreg(#[from] swreg::Error),
-    #[error(transparent)]
-    TenGbE(#[from] ten_gbe::Error),
-}
+//! Logic and implementations for CASPER "Yellow Block" devices.
+//! These are at the heart of a casperfpga design and will be the structs you primarily interact
+//! with.
+//!
+//! From a design perspective, all of the yellow block structs contain a `transport` field which
+//! wraps a `Weak<Mutex<T: Transport>>`, this allows the yellow block to interact with the
+//! transport, but not own the transport. This is important as one will almost certainly have
+//! many yellow blocks that will all needs to interface to the hardware. Although nothing enforces
+//! the convention, it is best practice to put the owned `Arc<Mutex<T:Transport>>` in some top-level
+//! struct and then have the yellow blocks as members of that struct.
+//!
+//! To this end, all yellow block structs follow the constructor convention of `new(transport:
+//! &Arc<Mutex<T:Transport>>, reg_name: &str, ..<metadata>)`, where the constructor implicitly calls
+//! `Arc::downgrade`.
+//!
+//! Additionally, from an error handling perspective, every yellow block will have its own error
+//! type, usually including a thin wrapper around the transport error. Using a block after the
+//! transport was dropped (or after another user of it panicked while holding the lock) is one of
+//! those transport errors rather than a panic. Debug builds also track which blocks hold handles
+//! on each transport, so that error lists the blocks left behind (see [`outstanding_blocks`]).
+//!
+//! Every block also implements [`YellowBlock`], so designs can be worked with at runtime without
+//! knowing the block types up front (see [`registry::Registry`]), and [`FromFpg`], which is how the
+//! structs generated from fpg files build their blocks.
+
+use casper_utils::design_sources::{
+    Device,
+    Devices,
+};
+use kstring::KString;
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+        Weak,
+    },
+};
+#[cfg(debug_assertions)]
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        MutexGuard,
+        PoisonError,
+    },
+};
+use thiserror::Error;
+
+pub mod bram;
+pub mod fft;
+pub mod iadc;
+pub mod katadc;
+pub mod packetizer;
+pub mod registry;
+pub mod snapadc;
+pub mod snapshot;
+pub mod swreg;
+pub mod ten_gbe;
+pub mod vacc;
+pub mod xsg;
+
+/// Certain Yellow Block struct types will implement this trait to allow for auto offsets in
+/// transport read methods
+pub trait Address {
+    fn addr() -> u16;
+}
+
+/// The blocks holding handles on each transport by handle id, keyed by the address of the
+/// transport's allocation (which the handles' weak pointers keep from being reused). Only tracked
+/// in debug builds.
+#[cfg(debug_assertions)]
+static HANDLES: Mutex<BTreeMap<usize, BTreeMap<u64, String>>> = Mutex::new(BTreeMap::new());
+#[cfg(debug_assertions)]
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(debug_assertions)]
+fn handles() -> MutexGuard<'static, BTreeMap<usize, BTreeMap<u64, String>>> {
+    // The map is only ever inserted into and removed from, so it's fine after a panic
+    HANDLES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn transport_key<T>(transport: &Weak<Mutex<T>>) -> usize {
+    transport.as_ptr().cast::<()>() as usize
+}
+
+/// The names of the blocks that still have handles on `transport`, sorted. Blocks outliving
+/// their transport is a bug (they all fail with a transport error), so this is meant for tracking
+/// down who kept them around. Handles are only tracked in debug builds, this is always empty in
+/// release builds.
+#[must_use]
+pub fn outstanding_blocks<T>(transport: &Arc<Mutex<T>>) -> Vec<String> {
+    blocks_on(transport_key(&Arc::downgrade(transport)))
+}
+
+#[cfg(debug_assertions)]
+fn blocks_on(key: usize) -> Vec<String> {
+    let mut blocks: Vec<_> = handles()
+        .get(&key)
+        .map(|blocks| blocks.values().cloned().collect())
+        .unwrap_or_default();
+    blocks.sort();
+    blocks
+}
+
+#[cfg(not(debug_assertions))]
+fn blocks_on(_key: usize) -> Vec<String> {
+    vec![]
+}
+
+/// A yellow block's handle on the transport it shares with the rest of the design
+pub(crate) struct TransportHandle<T> {
+    transport: Weak<Mutex<T>>,
+    /// The id of this handle in [`HANDLES`]
+    #[cfg(debug_assertions)]
+    id: u64,
+}
+
+impl<T> TransportHandle<T> {
+    /// The handle of the block `name` on `transport`
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(crate) fn new(transport: Weak<Mutex<T>>, name: &str) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            let id = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+            handles()
+                .entry(transport_key(&transport))
+                .or_default()
+                .insert(id, name.to_string());
+            Self { transport, id }
+        }
+        #[cfg(not(debug_assertions))]
+        Self { transport }
+    }
+
+    /// Run `f` with exclusive access to the transport
+    /// # Errors
+    /// Returns the error of `f`, or a transport error if the transport was dropped or its lock
+    /// was poisoned
+    pub(crate) fn with_transport<R, E, F>(&self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut T) -> Result<R, E>,
+        E: From<crate::transport::Error>,
+    {
+        let tarc = self.transport.upgrade().ok_or_else(|| {
+            let blocks = blocks_on(transport_key(&self.transport));
+            if blocks.is_empty() {
+                crate::transport::Error::TransportDropped
+            } else {
+                crate::transport::Error::TransportDroppedEarly { blocks }
+            }
+        })?;
+        let mut transport = tarc
+            .lock()
+            .map_err(|_| crate::transport::Error::TransportPoisoned)?;
+        f(&mut transport)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<T> Drop for TransportHandle<T> {
+    fn drop(&mut self) {
+        let mut handles = handles();
+        let key = transport_key(&self.transport);
+        if let Some(blocks) = handles.get_mut(&key) {
+            blocks.remove(&self.id);
+            if blocks.is_empty() {
+                handles.remove(&key);
+            }
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for TransportHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TransportHandle")
+            .field(&self.transport)
+            .finish()
+    }
+}
+
+/// The interface common to every yellow block
+pub trait YellowBlock<T>: Any {
+    /// Build the block `name` from the design's devices, the runtime equivalent of its `from_fpg`
+    /// constructor. This gets every device as some blocks also need entries of others (i.e. the
+    /// SNAP ADC needs the clock source from the platform block, see [`xsg::Xsg::from_devices`]).
+    /// # Errors
+    /// Returns an error if the device or any of the metadata it needs is missing or malformed
+    fn from_device(transport: Weak<Mutex<T>>, name: &str, devices: &Devices) -> Result<Self, Error>
+    where
+        Self: Sized;
+
+    /// The fpg kind of the block, i.e. `xps:sw_reg`
+    fn kind(&self) -> &'static str;
+
+    /// The name of the block in the design
+    fn name(&self) -> &str;
+
+    /// A one line summary of the block and its configuration
+    fn describe(&self) -> String;
+
+    /// The block as [`Any`], for downcasting
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// The metadata of a device from an fpg file, by key
+pub type Metadata = HashMap<KString, String>;
+
+/// Construction of a block from its entry in an fpg file, which is how the structs generated from
+/// fpg files build every block
+pub trait FromFpg<T>: Sized {
+    /// Build the block `name` from the `metadata` of its device. Blocks that need entries of other
+    /// devices expect them merged into `metadata` (i.e. the SNAP ADC takes `clk_src` from the
+    /// platform block).
+    /// # Errors
+    /// Returns an error if any of the metadata the block needs is missing or malformed
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, Error>;
+}
+
+impl<T: 'static> dyn YellowBlock<T> {
+    /// Get the block as its concrete type `B`, if it is one
+    #[must_use]
+    pub fn downcast_ref<B: YellowBlock<T>>(&self) -> Option<&B> {
+        self.as_any().downcast_ref()
+    }
+}
+
+/// The device `name`
+pub(crate) fn device<'a>(devices: &'a Devices, name: &str) -> Result<&'a Device, Error> {
+    devices
+        .get(name)
+        .ok_or_else(|| Error::MissingDevice(name.to_string()))
+}
+
+/// The metadata entry `key` of the device `name`
+pub(crate) fn device_meta<'a>(
+    devices: &'a Devices,
+    name: &str,
+    key: &str,
+) -> Result<&'a str, Error> {
+    metadata_entry(&device(devices, name)?.metadata, name, key)
+}
+
+/// The entry `key` of the `metadata` of the device `name`
+pub(crate) fn metadata_entry<'a>(
+    metadata: &'a Metadata,
+    name: &str,
+    key: &str,
+) -> Result<&'a str, Error> {
+    metadata
+        .get(key)
+        .map(String::as_str)
+        .ok_or_else(|| Error::MissingMetadata {
+            device: name.to_string(),
+            key: key.to_string(),
+        })
+}
+
+#[derive(Error, Debug)]
+/// Top level error for all yellow blocks (rarely used)
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error(transparent)]
+    Bram(#[from] bram::Error),
+    #[error(transparent)]
+    Fft(#[from] fft::Error),
+    #[error(transparent)]
+    IAdc(#[from] iadc::Error),
+    #[error(transparent)]
+    KatAdc(#[from] katadc::Error),
+    #[error(transparent)]
+    Packetizer(#[from] packetizer::Error),
+    #[error(transparent)]
+    SnapAdc(#[from] snapadc::Error),
+    #[error(transparent)]
+    Snapshot(#[from] snapshot::Error),
+    #[error(transparent)]
+    Swreg(#[from] swreg::Error),
+    #[error(transparent)]
+    TenGbE(#[from] ten_gbe::Error),
+    #[error(transparent)]
+    Vacc(#[from] vacc::Error),
+    #[error(transparent)]
+    Xsg(#[from] xsg::Error),
+    #[error("The design has no device named `{0}`")]
+    MissingDevice(String),
+    #[error("Device `{device}` is missing the `{key}` metadata entry")]
+    MissingMetadata { device: String, key: String },
+    #[error("Device `{device}` has an unsupported `{key}` of `{value}`")]
+    Unsupported {
+        device: String,
+        key: String,
+        value: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::{
+            mock::Mock,
+            Deserialize,
+            Serialize,
+            Transport,
+        },
+    };
+    use casperfpga_derive::{
+        address,
+        CasperSerde,
+    };
+    use packed_struct::prelude::*;
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
+
+    const BASE: u16 = 0x100;
+
+    #[address(BASE + 0x4, size = 4)]
+    #[derive(PackedStruct)]
+    #[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+    struct OffsetRegister {
+        #[packed_field(bits = "0")]
+        flag: bool,
+    }
+
+    #[test]
+    fn test_address_expression() {
+        assert_eq!(OffsetRegister::addr(), 0x104);
+    }
+
+    #[address(0x8, size = 16)]
+    #[derive(PackedStruct, CasperSerde, Debug, PartialEq)]
+    struct WideRegister {
+        #[packed_field(endian = "msb")]
+        high: u64,
+        #[packed_field(endian = "msb")]
+        low: u64,
+    }
+
+    /// A hand-written address that doesn't go through the derive's checks
+    #[derive(PackedStruct, CasperSerde, Debug)]
+    #[casper_serde(size = 8, layout_tests)]
+    struct MisalignedRegister {
+        #[packed_field(endian = "msb")]
+        value: u64,
+    }
+
+    impl Address for MisalignedRegister {
+        fn addr() -> u16 {
+            0x2
+        }
+    }
+
+    #[test]
+    fn test_multi_word_register() {
+        let mut transport = Mock::new(HashMap::from([(
+            "wide".into(),
+            Register {
+                addr: 0,
+                length: 0x18,
+            },
+        )]));
+        let reg = WideRegister {
+            high: 0x0102_0304_0506_0708,
+            low: 0x090A_0B0C_0D0E_0F10,
+        };
+        transport.write_addr("wide", &reg).unwrap();
+        // Laid out in order from the register's address
+        assert_eq!(transport.read::<u32, 4>("wide", 0xC).unwrap(), 0x0506_0708);
+        assert_eq!(transport.read::<u32, 4>("wide", 0x14).unwrap(), 0x0D0E_0F10);
+        let read: WideRegister = transport.read_addr("wide").unwrap();
+        assert_eq!(read, reg);
+        assert!(matches!(
+            transport.read_addr::<MisalignedRegister, 8>("wide"),
+            Err(crate::transport::Error::Misaligned { offset: 2, .. })
+        ));
+
+        // Verified writes go a word at a time too
+        let reg = WideRegister {
+            high: 0x1112_1314_1516_1718,
+            low: 0x191A_1B1C_1D1E_1F20,
+        };
+        transport.verified_write_addr("wide", &reg, 0).unwrap();
+        assert_eq!(transport.read::<u32, 4>("wide", 0xC).unwrap(), 0x1516_1718);
+        assert!(matches!(
+            transport.verified_write_addr("wide", &MisalignedRegister { value: 0 }, 0),
+            Err(crate::transport::Error::Misaligned { offset: 2, .. })
+        ));
+        assert_eq!(transport.read::<u32, 4>("wide", 0x0).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_from_fpg() {
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::new())));
+        let metadata = |entries: &[(&str, &str)]| -> Metadata {
+            entries
+                .iter()
+                .map(|&(k, v)| (KString::from_ref(k), v.to_string()))
+                .collect()
+        };
+        let reg =
+            <swreg::ReadOnly<swreg::BooleanSoftwareRegister<Mock>> as FromFpg<_>>::from_metadata(
+                Arc::downgrade(&transport),
+                "flag",
+                &metadata(&[("io_dir", "To\\_Processor"), ("arith_types", "2")]),
+            )
+            .unwrap();
+        assert_eq!(
+            reg.describe(),
+            "xps:sw_reg `flag` (ToProcessor, boolean) (read-only)"
+        );
+        let adc = snapadc::SnapAdc::from_metadata(
+            Arc::downgrade(&transport),
+            "adc",
+            &metadata(&[
+                ("adc_resolution", "8"),
+                ("sample_rate", "250"),
+                ("snap_inputs", "12"),
+                ("clk_src", "sys_clk"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(adc.source, snapadc::clockswitch::Source::Internal);
+        assert!(matches!(
+            bram::Bram::<Mock, fixed::FixedU32<fixed::types::extra::U0>>::from_metadata(
+                Arc::downgrade(&transport),
+                "ram",
+                &Metadata::new(),
+            ),
+            Err(Error::MissingMetadata { key, .. }) if key == "addr_width"
+        ));
+    }
+
+    #[test]
+    fn test_transport_handle() {
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::from([(
+            "flag".into(),
+            Register { addr: 0, length: 4 },
+        )]))));
+        let reg = swreg::BooleanSoftwareRegister::new(
+            &transport,
+            "flag",
+            swreg::Direction::FromProcessor,
+        );
+        reg.write(true).unwrap();
+        assert!(reg.read().unwrap());
+
+        // Panicking while holding the lock poisons it
+        let poison = transport.clone();
+        std::thread::spawn(move || {
+            let _guard = poison.lock().unwrap();
+            panic!("Poisoning the transport");
+        })
+        .join()
+        .unwrap_err();
+        assert!(matches!(
+            reg.read(),
+            Err(swreg::Error::Transport(
+                crate::transport::Error::TransportPoisoned
+            ))
+        ));
+
+        // The block outlives the transport, which debug builds report with the blocks left behind
+        let expected: &[&str] = if cfg!(debug_assertions) {
+            &["flag"]
+        } else {
+            &[]
+        };
+        assert_eq!(outstanding_blocks(&transport), expected);
+        drop(transport);
+        #[cfg(debug_assertions)]
+        assert!(matches!(
+            reg.write(false),
+            Err(swreg::Error::Transport(
+                crate::transport::Error::TransportDroppedEarly { blocks }
+            )) if blocks == ["flag"]
+        ));
+        #[cfg(not(debug_assertions))]
+        assert!(matches!(
+            reg.write(false),
+            Err(swreg::Error::Transport(
+                crate::transport::Error::TransportDropped
+            ))
+        ));
+    }
+}
@@ -0,0 +1,418 @@
+//! # Packetizer
+//!
+//! Designs that stream data out over Ethernet put a packetizer in front of the core, controlled by
+//! a few software registers - an enable (e.g. `tx_en`), the destination IP and port (`dest_ip` and
+//! `dest_port`), and optionally a count of packets sent along with a register to reset it. Fast
+//! links wrap a 32-bit count in minutes, so the count can also be 64 bits split across two
+//! registers.
+//!
+//! Like the vacc, these are user-built registers rather than a yellow block with fpg metadata, so
+//! the register names are supplied explicitly with [`Registers`], which defaults to the usual
+//! names.
+
+use crate::{
+    core::{
+        poll,
+        read_u64_counter,
+    },
+    transport::Transport,
+    yellow_blocks::TransportHandle,
+};
+use std::{
+    net::{
+        Ipv4Addr,
+        SocketAddrV4,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("This packetizer has no packet count register")]
+    NoCounter,
+    #[error("This packetizer has no packet count reset register")]
+    NoCounterReset,
+    #[error("The destination port register holds {0}, which isn't a valid port")]
+    BadPort(u32),
+    #[error("Timed out waiting for the packetizer to drain, the packet count was still at {0}")]
+    NotDrained(u64),
+}
+
+/// The names of the packetizer's registers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registers {
+    /// The enable register, where bit 0 turns on transmission
+    pub enable: String,
+    /// The destination IP register
+    pub dest_ip: String,
+    /// The destination port register
+    pub dest_port: String,
+    /// The count of packets sent, if the design has one
+    pub packet_count: Option<String>,
+    /// The high word of the packet count, if it's 64 bits with `packet_count` as the low word
+    pub packet_count_msb: Option<String>,
+    /// The register that resets the packet count on a rising edge, if the design has one
+    pub count_reset: Option<String>,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self {
+            enable: "tx_en".to_string(),
+            dest_ip: "dest_ip".to_string(),
+            dest_port: "dest_port".to_string(),
+            packet_count: None,
+            packet_count_msb: None,
+            count_reset: None,
+        }
+    }
+}
+
+/// Control of a streaming packetizer
+#[derive(Debug)]
+pub struct Packetizer<T> {
+    /// Upwards pointer to the parent class' transport
+    transport: TransportHandle<T>,
+    /// The names of the registers
+    regs: Registers,
+}
+
+impl<T> Packetizer<T>
+where
+    T: Transport,
+{
+    /// Construct a packetizer from its register names
+    pub fn new(transport: &Arc<Mutex<T>>, regs: Registers) -> Self {
+        let transport = Arc::downgrade(transport);
+        Self {
+            transport: TransportHandle::new(transport, &regs.enable),
+            regs,
+        }
+    }
+
+    /// The names of the registers
+    #[must_use]
+    pub fn registers(&self) -> &Registers {
+        &self.regs
+    }
+
+    /// Start transmitting packets
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "packetizer::enable",
+            skip_all,
+            fields(device = %self.regs.enable),
+            err,
+        )
+    )]
+    pub fn enable(&self) -> Result<(), Error> {
+        self.transport
+            .with_transport(|transport| Ok(transport.write(&self.regs.enable, 0, &1u32)?))
+    }
+
+    /// Stop transmitting packets, leaving any already in flight to go out. See
+    /// [`Packetizer::drain_and_stop`] to wait for them.
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "packetizer::disable",
+            skip_all,
+            fields(device = %self.regs.enable),
+            err,
+        )
+    )]
+    pub fn disable(&self) -> Result<(), Error> {
+        self.transport
+            .with_transport(|transport| Ok(transport.write(&self.regs.enable, 0, &0u32)?))
+    }
+
+    /// Whether the packetizer is transmitting
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn is_enabled(&self) -> Result<bool, Error> {
+        self.transport.with_transport(|transport| {
+            let en: u32 = transport.read(&self.regs.enable, 0)?;
+            Ok(en & 1 == 1)
+        })
+    }
+
+    /// Set where packets are sent
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "packetizer::set_destination",
+            skip_all,
+            fields(device = %self.regs.dest_ip, dest = %dest),
+            err,
+        )
+    )]
+    pub fn set_destination(&self, dest: SocketAddrV4) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            transport.write(&self.regs.dest_ip, 0, &u32::from(*dest.ip()))?;
+            transport.write(&self.regs.dest_port, 0, &u32::from(dest.port()))?;
+            Ok(())
+        })
+    }
+
+    /// Get where packets are sent
+    /// # Errors
+    /// Returns an error on bad transport or if the port register holds more than 16 bits
+    pub fn destination(&self) -> Result<SocketAddrV4, Error> {
+        self.transport.with_transport(|transport| {
+            let ip: u32 = transport.read(&self.regs.dest_ip, 0)?;
+            let port: u32 = transport.read(&self.regs.dest_port, 0)?;
+            let port = u16::try_from(port).map_err(|_| Error::BadPort(port))?;
+            Ok(SocketAddrV4::new(Ipv4Addr::from(ip), port))
+        })
+    }
+
+    /// Get the number of packets sent
+    /// # Errors
+    /// Returns an error on bad transport or if the design has no packet count register
+    pub fn packet_count(&self) -> Result<u64, Error> {
+        let count = self.regs.packet_count.as_ref().ok_or(Error::NoCounter)?;
+        self.transport.with_transport(|transport| {
+            Ok(match &self.regs.packet_count_msb {
+                Some(msb) => read_u64_counter(transport, msb, count)?,
+                None => u64::from(transport.read::<u32, 4>(count, 0)?),
+            })
+        })
+    }
+
+    /// Reset the packet count by pulsing the reset register
+    /// # Errors
+    /// Returns an error on bad transport or if the design has no packet count reset register
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "packetizer::reset_count",
+            skip_all,
+            fields(device = %self.regs.enable),
+            err,
+        )
+    )]
+    pub fn reset_count(&self) -> Result<(), Error> {
+        let reset = self
+            .regs
+            .count_reset
+            .as_ref()
+            .ok_or(Error::NoCounterReset)?;
+        self.transport.with_transport(|transport| {
+            transport.write(reset, 0, &0u32)?;
+            transport.write(reset, 0, &1u32)?;
+            transport.write(reset, 0, &0u32)?;
+            Ok(())
+        })
+    }
+
+    /// Stop transmitting and, if the design counts packets, block until the count holds still for
+    /// a whole `poll_interval`, returning the final count
+    /// # Errors
+    /// Returns an error on bad transport or if the count was still moving after `timeout`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "packetizer::drain_and_stop",
+            skip_all,
+            fields(device = %self.regs.enable),
+            err,
+        )
+    )]
+    pub fn drain_and_stop(
+        &self,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<Option<u64>, Error> {
+        self.disable()?;
+        if self.regs.packet_count.is_none() {
+            return Ok(None);
+        }
+        let mut last = None;
+        // Each poll takes the transport on its own so others can use it while we wait
+        let drained = poll(
+            || self.packet_count(),
+            |cnt| last.replace(*cnt) == Some(*cnt),
+            timeout,
+            poll_interval,
+        )?
+        .map_err(|t| Error::NotDrained(t.last))?;
+        Ok(Some(drained))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::{
+            mock::Mock,
+            sim::SimulatedFpga,
+        },
+    };
+    use casper_utils::design_sources::fpg::read_fpg_file;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_packetizer() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let transport = Arc::new(Mutex::new(SimulatedFpga::new(&design)));
+        let tx = Packetizer::new(&transport, Registers::default());
+        let dest = SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 1), 60000);
+        tx.set_destination(dest).unwrap();
+        tx.enable().unwrap();
+        {
+            let mut transport = transport.lock().unwrap();
+            let memory = transport.memory();
+            assert_eq!(
+                memory.read_n_bytes("dest_ip", 0, 4).unwrap(),
+                vec![192, 168, 0, 1]
+            );
+            assert_eq!(
+                memory.read_n_bytes("dest_port", 0, 4).unwrap(),
+                vec![0, 0, 0xEA, 0x60]
+            );
+            assert_eq!(
+                memory.read_n_bytes("tx_en", 0, 4).unwrap(),
+                vec![0, 0, 0, 1]
+            );
+        }
+        assert_eq!(tx.destination().unwrap(), dest);
+        assert!(tx.is_enabled().unwrap());
+        assert!(matches!(tx.packet_count(), Err(Error::NoCounter)));
+        assert!(matches!(tx.reset_count(), Err(Error::NoCounterReset)));
+        // Without a counter there's nothing to wait for
+        assert_eq!(
+            tx.drain_and_stop(Duration::ZERO, Duration::ZERO).unwrap(),
+            None
+        );
+        assert!(!tx.is_enabled().unwrap());
+
+        transport
+            .lock()
+            .unwrap()
+            .memory()
+            .write("dest_port", 0, &0x10000u32)
+            .unwrap();
+        assert!(matches!(tx.destination(), Err(Error::BadPort(0x10000))));
+    }
+
+    #[test]
+    fn test_drain_and_stop() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        // Stand in for a packet counter, which keeps going for a few packets after the packetizer
+        // is disabled
+        let mut in_flight = 3;
+        sim.on_read("gbe0_rxctr", move |mem, _, _| {
+            let en: u32 = mem.read("tx_en", 0)?;
+            if en == 1 || in_flight > 0 {
+                in_flight -= u32::from(en == 0);
+                let cnt: u32 = mem.read("gbe0_rxctr", 0)?;
+                mem.write("gbe0_rxctr", 0, &(cnt + 1))?;
+            }
+            Ok(())
+        });
+        let transport = Arc::new(Mutex::new(sim));
+        let tx = Packetizer::new(
+            &transport,
+            Registers {
+                packet_count: Some("gbe0_rxctr".to_string()),
+                ..Registers::default()
+            },
+        );
+        tx.enable().unwrap();
+        assert_eq!(tx.packet_count().unwrap(), 1);
+        assert_eq!(tx.packet_count().unwrap(), 2);
+        let drained = tx
+            .drain_and_stop(Duration::from_secs(1), Duration::from_millis(1))
+            .unwrap();
+        // The three in flight, then the count holding still
+        assert_eq!(drained, Some(5));
+        assert_eq!(tx.packet_count().unwrap(), 5);
+    }
+
+    #[test]
+    fn test_drain_timeout() {
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::from([
+            ("tx_en".into(), Register { addr: 0, length: 4 }),
+            ("tx_cnt".into(), Register { addr: 4, length: 4 }),
+            ("tx_cnt_rst".into(), Register { addr: 8, length: 4 }),
+        ]))));
+        let tx = Packetizer::new(
+            &transport,
+            Registers {
+                packet_count: Some("tx_cnt".to_string()),
+                count_reset: Some("tx_cnt_rst".to_string()),
+                ..Registers::default()
+            },
+        );
+        tx.reset_count().unwrap();
+        // A single read can't show the count holding still
+        assert!(matches!(
+            tx.drain_and_stop(Duration::ZERO, Duration::ZERO),
+            Err(Error::NotDrained(0))
+        ));
+        assert_eq!(
+            tx.drain_and_stop(Duration::from_secs(1), Duration::from_millis(1))
+                .unwrap(),
+            Some(0)
+        );
+        // The transport is free between polls, so a late packet gets counted
+        let late = {
+            let transport = transport.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(5));
+                transport.lock().unwrap().write("tx_cnt", 0, &7u32).unwrap();
+            })
+        };
+        assert_eq!(
+            tx.drain_and_stop(Duration::from_secs(1), Duration::from_millis(20))
+                .unwrap(),
+            Some(7)
+        );
+        late.join().unwrap();
+    }
+
+    #[test]
+    fn test_wide_packet_count() {
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::from([
+            ("tx_en".into(), Register { addr: 0, length: 4 }),
+            ("tx_cnt_lsb".into(), Register { addr: 4, length: 4 }),
+            ("tx_cnt_msb".into(), Register { addr: 8, length: 4 }),
+        ]))));
+        let tx = Packetizer::new(
+            &transport,
+            Registers {
+                packet_count: Some("tx_cnt_lsb".to_string()),
+                packet_count_msb: Some("tx_cnt_msb".to_string()),
+                ..Registers::default()
+            },
+        );
+        {
+            let mut transport = transport.lock().unwrap();
+            transport.write("tx_cnt_msb", 0, &3u32).unwrap();
+            transport.write("tx_cnt_lsb", 0, &5u32).unwrap();
+        }
+        assert_eq!(tx.packet_count().unwrap(), 0x3_0000_0005);
+        assert_eq!(
+            tx.drain_and_stop(Duration::from_secs(1), Duration::from_millis(1))
+                .unwrap(),
+            Some(0x3_0000_0005)
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! A runtime mapping from fpg device kinds to yellow block constructors
+//!
+//! The derive macros build a typed struct for a design at compile time. When the design is only
+//! known at runtime (i.e. a generic bringup tool), a [`Registry`] builds every block it knows how
+//! to from the design's device list as boxed [`YellowBlock`]s, which can be downcast to their
+//! concrete types with [`downcast_ref`](trait.YellowBlock.html#method.downcast_ref).
+//!
+//! The binary point of fixed point blocks is part of their type, so the built-in constructors
+//! build fixed point software registers and BRAMs as raw integer views (a binary point of zero,
+//! i.e. `FixedU32<U0>`) of the same width and signedness. Register a custom constructor for a kind
+//! to get something else.
+use super::{
+    bram::Bram,
+    device,
+    device_meta,
+    iadc::IAdc,
+    katadc::KatAdc,
+    snapadc::SnapAdc,
+    snapshot::Snapshot,
+    swreg::{
+        ArraySoftwareRegister,
+        BooleanSoftwareRegister,
+        FixedSoftwareRegister,
+    },
+    ten_gbe::TenGbE,
+    xsg::Xsg,
+    Error,
+    YellowBlock,
+};
+use crate::transport::Transport;
+use casper_utils::design_sources::{
+    DeviceKind,
+    Devices,
+    FpgaDesign,
+};
+use fixed::{
+    types::extra::U0,
+    FixedI128,
+    FixedI16,
+    FixedI32,
+    FixedI64,
+    FixedI8,
+    FixedU128,
+    FixedU16,
+    FixedU32,
+    FixedU64,
+    FixedU8,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        Weak,
+    },
+};
+
+/// A boxed yellow block
+pub type Block<T> = Box<dyn YellowBlock<T>>;
+
+/// Builds the block `name` from the design's devices
+pub type Constructor<T> = fn(Weak<Mutex<T>>, &str, &Devices) -> Result<Block<T>, Error>;
+
+fn unsupported(device: &str, key: &str, value: &str) -> Error {
+    Error::Unsupported {
+        device: device.to_string(),
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn sw_reg<T>(transport: Weak<Mutex<T>>, name: &str, devices: &Devices) -> Result<Block<T>, Error>
+where
+    T: Transport + 'static,
+{
+    let meta = |key| device_meta(devices, name, key);
+    let arith_types = meta("arith_types")?;
+    // Registers wider than a word are arrays of them, booleans included
+    let size = device(devices, name)?.register.map_or(4, |reg| reg.size);
+    if size > 4 {
+        let (io_dir, bitwidths, size) = (meta("io_dir")?, meta("bitwidths")?, size.to_string());
+        return Ok(match arith_types {
+            "0" | "2" => Box::new(ArraySoftwareRegister::<T, FixedU32<U0>>::from_fpg(
+                transport, name, io_dir, bitwidths, "0", &size,
+            )?),
+            "1" => Box::new(ArraySoftwareRegister::<T, FixedI32<U0>>::from_fpg(
+                transport, name, io_dir, bitwidths, "0", &size,
+            )?),
+            other => return Err(unsupported(name, "arith_types", other)),
+        });
+    }
+    if arith_types == "2" {
+        return Ok(Box::new(BooleanSoftwareRegister::from_device(
+            transport, name, devices,
+        )?));
+    }
+    // The fixed point registers are built as raw integers, see the module docs
+    let (io_dir, bitwidths) = (meta("io_dir")?, meta("bitwidths")?);
+    Ok(match arith_types {
+        "0" => Box::new(FixedSoftwareRegister::<T, FixedU32<U0>>::from_fpg(
+            transport, name, io_dir, bitwidths, "0",
+        )?),
+        "1" => Box::new(FixedSoftwareRegister::<T, FixedI32<U0>>::from_fpg(
+            transport, name, io_dir, bitwidths, "0",
+        )?),
+        other => return Err(unsupported(name, "arith_types", other)),
+    })
+}
+
+fn bram<T>(transport: Weak<Mutex<T>>, name: &str, devices: &Devices) -> Result<Block<T>, Error>
+where
+    T: Transport + 'static,
+{
+    let meta = |key| device_meta(devices, name, key);
+    macro_rules! raw {
+        ($ty:ty) => {
+            Box::new(Bram::<T, $ty>::from_device(transport, name, devices)?)
+        };
+    }
+    Ok(match (meta("arith_type")?, meta("data_width")?) {
+        ("Unsigned", "8") => raw!(FixedU8<U0>),
+        ("Unsigned", "16") => raw!(FixedU16<U0>),
+        ("Unsigned", "32") => raw!(FixedU32<U0>),
+        ("Unsigned", "64") => raw!(FixedU64<U0>),
+        ("Unsigned", "128") => raw!(FixedU128<U0>),
+        ("Signed", "8") => raw!(FixedI8<U0>),
+        ("Signed", "16") => raw!(FixedI16<U0>),
+        ("Signed", "32") => raw!(FixedI32<U0>),
+        ("Signed", "64") => raw!(FixedI64<U0>),
+        ("Signed", "128") => raw!(FixedI128<U0>),
+        ("Unsigned" | "Signed", other) => return Err(unsupported(name, "data_width", other)),
+        (other, _) => return Err(unsupported(name, "arith_type", other)),
+    })
+}
+
+fn snapshot<T>(transport: Weak<Mutex<T>>, name: &str, devices: &Devices) -> Result<Block<T>, Error>
+where
+    T: Transport + 'static,
+{
+    macro_rules! typed {
+        ($ty:ty) => {
+            Box::new(Snapshot::<T, $ty>::from_device(transport, name, devices)?)
+        };
+    }
+    Ok(match device_meta(devices, name, "data_width")? {
+        "8" => typed!(u8),
+        "16" => typed!(u16),
+        "32" => typed!(u32),
+        "64" => typed!(u64),
+        "128" => typed!(u128),
+        other => return Err(unsupported(name, "data_width", other)),
+    })
+}
+
+fn boxed<T, B>(transport: Weak<Mutex<T>>, name: &str, devices: &Devices) -> Result<Block<T>, Error>
+where
+    B: YellowBlock<T>,
+{
+    Ok(Box::new(B::from_device(transport, name, devices)?))
+}
+
+/// A mapping from fpg device kinds to the constructors of their yellow blocks
+#[derive(Debug, Clone)]
+pub struct Registry<T> {
+    constructors: HashMap<DeviceKind, Constructor<T>>,
+}
+
+impl<T> Default for Registry<T>
+where
+    T: Transport + 'static,
+{
+    /// A registry of every yellow block in this crate with a fpg kind
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(DeviceKind::SwReg, sw_reg);
+        registry.register(DeviceKind::Bram, bram);
+        registry.register(DeviceKind::Snapshot, snapshot);
+        registry.register(DeviceKind::TenGbe, boxed::<T, TenGbE<T>>);
+        registry.register(DeviceKind::SnapAdc, boxed::<T, SnapAdc<T>>);
+        registry.register(DeviceKind::KatAdc, boxed::<T, KatAdc<T>>);
+        registry.register(DeviceKind::IAdc, boxed::<T, IAdc<T>>);
+        registry.register(DeviceKind::Xsg, boxed::<T, Xsg>);
+        registry
+    }
+}
+
+impl<T> Registry<T>
+where
+    T: Transport + 'static,
+{
+    /// An empty registry
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            constructors: HashMap::new(),
+        }
+    }
+
+    /// Use `constructor` for devices of `kind` (a [`DeviceKind`] or its name, i.e. `xps:sw_reg`),
+    /// replacing any previous constructor
+    pub fn register<K>(&mut self, kind: K, constructor: Constructor<T>)
+    where
+        K: Into<DeviceKind>,
+    {
+        self.constructors.insert(kind.into(), constructor);
+    }
+
+    /// The kinds with registered constructors
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.constructors.keys().map(DeviceKind::as_str)
+    }
+
+    /// Build the block `name` from `devices`, or `None` if there's no constructor for its kind
+    /// # Errors
+    /// Returns an error if the device doesn't exist or its metadata is missing or malformed
+    pub fn build(
+        &self,
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Option<Block<T>>, Error> {
+        let device = devices
+            .get(name)
+            .ok_or_else(|| Error::MissingDevice(name.to_string()))?;
+        self.constructors
+            .get(&device.kind)
+            .map(|constructor| constructor(transport, name, devices))
+            .transpose()
+    }
+
+    /// Build every block of `design` with a registered kind, by name. Devices of other kinds are
+    /// skipped.
+    /// # Errors
+    /// Returns an error if any device's metadata is missing or malformed
+    pub fn build_all<D>(
+        &self,
+        transport: &Arc<Mutex<T>>,
+        design: &D,
+    ) -> Result<BTreeMap<String, Block<T>>, Error>
+    where
+        D: FpgaDesign,
+    {
+        let devices = design.devices();
+        let mut blocks = BTreeMap::new();
+        for name in devices.keys() {
+            if let Some(block) = self.build(Arc::downgrade(transport), name, devices)? {
+                blocks.insert(name.to_string(), block);
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::sim::SimulatedFpga;
+    use casper_utils::design_sources::fpg::read_fpg_file;
+
+    #[test]
+    fn test_build_all() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let transport = Arc::new(Mutex::new(SimulatedFpga::new(&design)));
+        let registry = Registry::default();
+        let blocks = registry.build_all(&transport, &design).unwrap();
+
+        let gbe = &blocks["gbe0"];
+        assert_eq!(gbe.kind(), "xps:ten_gbe");
+        assert_eq!(gbe.name(), "gbe0");
+        assert!(gbe.downcast_ref::<TenGbE<_>>().is_some());
+        assert!(gbe
+            .downcast_ref::<BooleanSoftwareRegister<SimulatedFpga>>()
+            .is_none());
+        assert!(blocks["snap_adc"].downcast_ref::<SnapAdc<_>>().is_some());
+        assert!(blocks["adc_snap"].describe().starts_with("casper:snapshot"));
+        assert!(blocks["SNAP"].downcast_ref::<Xsg>().is_some());
+        // Blocks without an implementation are skipped
+        assert!(!blocks.contains_key("pfb_fir_real"));
+
+        // Custom constructors replace the built-in ones
+        let mut registry = Registry::new();
+        registry.register("xps:ten_gbe", |_, name, _| {
+            Err(Error::MissingDevice(name.to_string()))
+        });
+        assert_eq!(registry.kinds().collect::<Vec<_>>(), vec!["xps:ten_gbe"]);
+        assert!(registry.build_all(&transport, &design).is_err());
+        assert!(matches!(
+            registry.build(Arc::downgrade(&transport), "nope", design.devices()),
+            Err(Error::MissingDevice(_))
+        ));
+    }
+}
@@ -1,4 +1,7 @@
-use crate::transport::Transport;
+use crate::{
+    transport::Transport,
+    yellow_blocks::TransportHandle,
+};
 use std::sync::{
     Mutex,
     Weak,
@@ -23,7 +26,7 @@ pub enum Source {
 #[derive(Debug)]
 pub struct ClockSwitch<T> {
     /// Upwards pointer to the parent class' transport
-    transport: Weak<Mutex<T>>,
+    transport: TransportHandle<T>,
 }
 
 impl<T> ClockSwitch<T>
@@ -34,34 +37,32 @@ where
 
     #[must_use]
     pub fn new(transport: Weak<Mutex<T>>) -> Self {
-        Self { transport }
+        Self {
+            transport: TransportHandle::new(transport, Self::NAME),
+        }
     }
 
     /// Sets the source of the clock switch
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_source(&self, source: Source) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        match source {
+        self.transport.with_transport(|transport| match source {
             Source::Internal => Ok(transport.write(Self::NAME, 0, &1u32)?),
             Source::External => Ok(transport.write(Self::NAME, 0, &0u32)?),
-        }
+        })
     }
 
     /// Gets the source of the clock switch
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn get_source(&self) -> Result<Source, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let raw: u32 = transport.read(Self::NAME, 0)?;
-        Ok(match raw {
-            1 => Source::Internal,
-            0 => Source::External,
-            _ => unreachable!(),
+        self.transport.with_transport(|transport| {
+            let raw: u32 = transport.read(Self::NAME, 0)?;
+            Ok(match raw {
+                1 => Source::Internal,
+                0 => Source::External,
+                _ => unreachable!(),
+            })
         })
     }
 }
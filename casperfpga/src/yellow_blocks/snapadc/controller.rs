@@ -14,7 +14,10 @@ use crate::{
         Serialize,
         Transport,
     },
-    yellow_blocks::Address,
+    yellow_blocks::{
+        Address,
+        TransportHandle,
+    },
 };
 use casperfpga_derive::{
     address,
@@ -33,15 +36,97 @@ pub enum Error {
     Transport(#[from] crate::transport::Error),
     #[error("ADC16 controller doesn't support demux modes")]
     NoDemux,
+    #[error("Fine gain {0} is outside of the 7-bit signed range -64..=63")]
+    BadFineGain(i8),
+    #[error("Delay tap {0} is outside of the 5-bit range 0..=31")]
+    BadDelayTap(u8),
+    #[error("There is no lane {lane} on chip {chip}, chips are 0..=7 and lanes 0..=7")]
+    BadLane { chip: u8, lane: u8 },
+}
+
+/// The number of ADC chips a controller can address
+pub const MAX_CHIPS: usize = 8;
+/// The number of LVDS data lanes of each ADC chip
+pub const LANES: usize = 8;
+/// The largest IDELAY tap
+pub const MAX_DELAY_TAP: u8 = 31;
+
+/// The IDELAY taps applied to every lane of every chip, `None` for the lanes that were never set
+///
+/// Lanes 0..=3 are the "a" lanes of channels 1 through 4 (strobed by [`AdcDelayAStrobe`]) and
+/// lanes 4..=7 are the "b" lanes (strobed by [`AdcDelayBStrobe`]). The [`std::fmt::Display`]
+/// impl renders a table of the chips with any taps set, for logging.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct DelayTaps(pub [[Option<u8>; LANES]; MAX_CHIPS]);
+
+impl DelayTaps {
+    /// The taps of `lane` on `chip`, `None` if they were never set or don't exist
+    #[must_use]
+    pub fn get(&self, chip: u8, lane: u8) -> Option<u8> {
+        *self.0.get(chip as usize)?.get(lane as usize)?
+    }
+}
+
+/// The name of a lane, i.e. `2a`
+fn lane_name(lane: usize) -> String {
+    let half = if lane < LANES / 2 { 'a' } else { 'b' };
+    format!("{}{half}", lane % (LANES / 2) + 1)
+}
+
+impl std::fmt::Display for DelayTaps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chip")?;
+        for lane in 0..LANES {
+            write!(f, " {:>3}", lane_name(lane))?;
+        }
+        for (chip, taps) in self.0.iter().enumerate() {
+            if taps.iter().all(Option::is_none) {
+                continue;
+            }
+            write!(f, "\n{chip:>4}")?;
+            for tap in taps {
+                match tap {
+                    Some(tap) => write!(f, " {tap:>3}")?,
+                    None => write!(f, " {:>3}", "-")?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The analog tuning of the ADC chips, which trades power consumption against noise and start-up
+/// time. Applied with [`Adc16::set_tuning`] and again by every [`Adc16::init`]; the default is the
+/// power-on state of the chips.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AdcTuning {
+    /// Current of the clock circuitry, see [`Adc16::set_jitter`]
+    pub jitter: Jitter,
+    /// Reduction of the ADC core current, see [`Adc16::set_current`]
+    pub current: AdcCurrentControl,
+    /// Drive of the buffer on the VCM pin, see [`Adc16::set_current`]
+    pub vcm_drive: VcmBufferDrive,
+    /// Start-up delay after power up, see [`Adc16::set_startup_timing`]
+    pub startup: StartupTiming,
 }
 
 /// Controller for the ADC chips themselves
 #[derive(Debug)]
 pub struct Adc16<T> {
     /// Upwards pointer to the parent class' transport
-    transport: Weak<Mutex<T>>,
+    transport: TransportHandle<T>,
     /// Holds the current chip select state,
     cs: ChipSelect,
+    /// The last fine gains written, as the 3-wire registers can't be read back
+    fine_gains: [i8; 8],
+    /// The last gain control written, so one setting can be changed without clobbering the other
+    gain_ctl: GainCtl,
+    /// The last input inversion written, `None` if it was never set
+    invert: Option<ChannelInvert>,
+    /// The last delay taps written, as the IDELAYs can't be read back
+    delay_taps: DelayTaps,
+    /// The tuning applied at every init
+    tuning: AdcTuning,
 }
 
 impl<T> Adc16<T>
@@ -53,48 +138,50 @@ where
     #[must_use]
     pub fn new(transport: Weak<Mutex<T>>) -> Self {
         Self {
-            transport,
+            transport: TransportHandle::new(transport, Self::NAME),
             cs: ChipSelect::default(),
+            fine_gains: [0; 8],
+            gain_ctl: GainCtl::default(),
+            invert: None,
+            delay_taps: DelayTaps::default(),
+            tuning: AdcTuning::default(),
         }
     }
 
     /// Gets the number of ADC chips this controller supports
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn supported_chips(&self) -> Result<u8, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let word: Adc3Wire = transport.read_addr(Self::NAME)?;
-        Ok(word.supported_chips.into())
+        self.transport.with_transport(|transport| {
+            let word: Adc3Wire = transport.read_addr(Self::NAME)?;
+            Ok(word.supported_chips.into())
+        })
     }
 
     /// Gets the controller revision
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn revision(&self) -> Result<u8, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let word: Adc3Wire = transport.read_addr(Self::NAME)?;
-        Ok(word.revision.into())
+        self.transport.with_transport(|transport| {
+            let word: Adc3Wire = transport.read_addr(Self::NAME)?;
+            Ok(word.revision.into())
+        })
     }
 
     /// Checks to see if the ADCs are locked
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn locked(&self) -> Result<bool, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let word: Adc3Wire = transport.read_addr(Self::NAME)?;
-        let ll: u8 = word.line_lock.into();
-        let num_adcs: u8 = word.supported_chips.into();
-        Ok(match ll {
-            0 | 2 => false,
-            1 => num_adcs <= 4,
-            3 => true,
-            _ => unreachable!(),
+        self.transport.with_transport(|transport| {
+            let word: Adc3Wire = transport.read_addr(Self::NAME)?;
+            let ll: u8 = word.line_lock.into();
+            let num_adcs: u8 = word.supported_chips.into();
+            Ok(match ll {
+                0 | 2 => false,
+                1 => num_adcs <= 4,
+                3 => true,
+                _ => unreachable!(),
+            })
         })
     }
 
@@ -103,17 +190,17 @@ where
         self.cs = *cs;
     }
 
-    /// Cursed bit-banging to send a bit to the current chip select taking a mutable transport ref
+    /// Cursed bit-banging to send a bit to the chip select `cs` taking a mutable transport ref
     /// # Errors
     /// Returns an error on bad transport
-    fn send_3wire_bit(&self, transport: &mut T, bit: bool) -> Result<(), Error> {
+    fn send_3wire_bit(transport: &mut T, cs: ChipSelect, bit: bool) -> Result<(), Error> {
         // Clock low, data and chip select set accordingly
         transport.write_addr(
             Self::NAME,
             &Adc3Wire {
                 sclk: false,
                 sdata: bit,
-                chip_select: self.cs,
+                chip_select: cs,
                 ..Default::default()
             },
         )?;
@@ -123,23 +210,23 @@ where
             &Adc3Wire {
                 sclk: true,
                 sdata: bit,
-                chip_select: self.cs,
+                chip_select: cs,
                 ..Default::default()
             },
         )?;
         Ok(())
     }
 
-    fn send_reg_raw(&self, transport: &mut T, addr: u8, val: u16) -> Result<(), Error> {
+    fn send_reg_raw(transport: &mut T, cs: ChipSelect, addr: u8, val: u16) -> Result<(), Error> {
         // Idle
         transport.write_addr(Self::NAME, &Adc3Wire::idle())?;
         // Write the address
         for i in (0..=7).rev() {
-            self.send_3wire_bit(transport, ((addr >> i) & 1) == 1)?;
+            Self::send_3wire_bit(transport, cs, ((addr >> i) & 1) == 1)?;
         }
         // And the data
         for i in (0..=15).rev() {
-            self.send_3wire_bit(transport, ((val >> i) & 1) == 1)?;
+            Self::send_3wire_bit(transport, cs, ((val >> i) & 1) == 1)?;
         }
         // Idle
         transport.write_addr(Self::NAME, &Adc3Wire::idle())?;
@@ -148,6 +235,14 @@ where
 
     /// Cursed bit-banging to send an ADC register over the 3 wire to the current chip select
     fn send_reg<R>(&self, transport: &mut T, reg: &R) -> Result<(), Error>
+    where
+        R: Address + PackedStruct,
+    {
+        Self::send_reg_to(transport, self.cs, reg)
+    }
+
+    /// Cursed bit-banging to send an ADC register over the 3 wire to the chip select `cs`
+    fn send_reg_to<R>(transport: &mut T, cs: ChipSelect, reg: &R) -> Result<(), Error>
     where
         R: Address + PackedStruct,
     {
@@ -157,8 +252,9 @@ where
         reg.pack_to_slice(&mut packed)
             .map_err(crate::transport::Error::Packing)?;
         let value = u16::from_be_bytes(packed);
-        self.send_reg_raw(
+        Self::send_reg_raw(
             transport,
+            cs,
             addr.try_into().expect("Address didn't fit in a u8"),
             value,
         )
@@ -169,36 +265,33 @@ where
     /// channel configurations
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn supports_demux(&self) -> Result<bool, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Check to see if we support demux by testing the demux write enable bit
-        // If we /can't/ set it, we /do/ support demux
-        transport.write_addr(
-            Self::NAME,
-            &AdcControl {
-                demux_write_enable: true,
-                ..Default::default()
-            },
-        )?;
-        let demux_test: AdcControl = transport.read_addr(Self::NAME)?;
-        // If we were able to set the bit, we do not support demux modes
-        Ok(!demux_test.demux_write_enable)
+        self.transport.with_transport(|transport| {
+            // Check to see if we support demux by testing the demux write enable bit
+            // If we /can't/ set it, we /do/ support demux
+            transport.write_addr(
+                Self::NAME,
+                &AdcControl {
+                    demux_write_enable: true,
+                    ..Default::default()
+                },
+            )?;
+            let demux_test: AdcControl = transport.read_addr(Self::NAME)?;
+            // If we were able to set the bit, we do not support demux modes
+            Ok(!demux_test.demux_write_enable)
+        })
     }
 
     /// Gets the current demux mode if the gateware supports it, otherwise returns None
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn get_demux(&self) -> Result<Option<DemuxMode>, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(if self.supports_demux()? {
+        if !self.supports_demux()? {
+            return Ok(None);
+        }
+        self.transport.with_transport(|transport| {
             let demux_test: AdcControl = transport.read_addr(Self::NAME)?;
-            Some(demux_test.demux_mode)
-        } else {
-            None
+            Ok(Some(demux_test.demux_mode))
         })
     }
 
@@ -216,17 +309,20 @@ where
     /// at initialization time is consistent with the demux mode set using this
     /// method.  Mismatches will result in improper interpretation of the data. method.
     /// Mismatches will result in improper interpretation of the data.
-    #[allow(clippy::missing_panics_doc)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "adc16::set_demux", skip_all, fields(device = Self::NAME), err)
+    )]
     pub fn set_demux(&self, mode: DemuxMode) -> Result<(), Error> {
         if self.supports_demux()? {
-            let tarc = self.transport.upgrade().unwrap();
-            let mut transport = (*tarc).lock().unwrap();
-            // Grab the current state of the control register
-            let mut ctl: AdcControl = transport.read_addr(Self::NAME)?;
-            ctl.demux_mode = mode;
-            // Write the update
-            transport.write_addr(Self::NAME, &ctl)?;
-            Ok(())
+            self.transport.with_transport(|transport| {
+                // Grab the current state of the control register
+                let mut ctl: AdcControl = transport.read_addr(Self::NAME)?;
+                ctl.demux_mode = mode;
+                // Write the update
+                transport.write_addr(Self::NAME, &ctl)?;
+                Ok(())
+            })
         } else {
             Err(Error::NoDemux)
         }
@@ -235,172 +331,208 @@ where
     /// Resets all the chips selected by the current chip select
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn reset(&self) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        self.send_reg(&mut transport, &Reset { reset: true })
+        self.transport
+            .with_transport(|transport| self.send_reg(transport, &Reset { reset: true }))
     }
 
     /// Power down the ADCs by setting the pd bit
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn power_down(&self) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Powerdown
-        self.send_reg(
-            &mut transport,
-            &SleepPd {
-                pd: true,
-                ..Default::default()
-            },
-        )
+        self.transport.with_transport(|transport| {
+            // Powerdown
+            self.send_reg(
+                transport,
+                &SleepPd {
+                    pd: true,
+                    ..Default::default()
+                },
+            )
+        })
     }
 
     /// Power up the ADCs by setting the pd bit
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn power_up(&self) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Powerdown
-        self.send_reg(
-            &mut transport,
-            &SleepPd {
-                pd: false,
-                ..Default::default()
-            },
-        )
+        self.transport.with_transport(|transport| {
+            // Powerdown
+            self.send_reg(
+                transport,
+                &SleepPd {
+                    pd: false,
+                    ..Default::default()
+                },
+            )
+        })
     }
 
     /// Power cycles all the ADCs selected by the current chip select
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
-    pub fn power_cycle(&mut self) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Powerdown
-        self.send_reg(
-            &mut transport,
-            &SleepPd {
-                pd: true,
-                ..Default::default()
-            },
-        )?;
-        // Store old chip select
-        let old_cs = self.cs;
-        // Power up one chip at a time
-        for i in 0..=7 {
-            self.cs = ChipSelect::by_number(i);
-            // Powerup
-            self.send_reg(&mut transport, &SleepPd::default())?;
-        }
-        // Restore old cs
-        self.cs = old_cs;
-        Ok(())
+    pub fn power_cycle(&self) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            // Powerdown
+            self.send_reg(
+                transport,
+                &SleepPd {
+                    pd: true,
+                    ..Default::default()
+                },
+            )?;
+            // Power up one chip at a time
+            for i in 0..=7 {
+                Self::send_reg_to(transport, ChipSelect::by_number(i), &SleepPd::default())?;
+            }
+            Ok(())
+        })
     }
 
     /// Selects a test pattern or sampled data for all the adc currently selected
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn enable_pattern(&self, pat: TestPattern) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        self.send_reg(&mut transport, &PatternCtl::default())?;
-        self.send_reg(&mut transport, &DeskewSyncPattern::default())?;
-        match pat {
-            TestPattern::Ramp => self.send_reg(
-                &mut transport,
-                &PatternCtl {
-                    pattern: Pattern::Ramp,
-                },
-            ),
-            TestPattern::Deskew => self.send_reg(
-                &mut transport,
-                &DeskewSyncPattern {
-                    pat_deskew_sync: DeskewSyncMode::Deskew,
-                },
-            ),
-            TestPattern::Sync => self.send_reg(
-                &mut transport,
-                &DeskewSyncPattern {
-                    pat_deskew_sync: DeskewSyncMode::Sync,
-                },
-            ),
-            TestPattern::Custom1 | TestPattern::Custom2 => self.send_reg(
-                &mut transport,
-                &PatternCtl {
-                    pattern: Pattern::SingleCustom,
-                },
-            ),
-            TestPattern::Dual => self.send_reg(
-                &mut transport,
-                &PatternCtl {
-                    pattern: Pattern::DualCustom,
-                },
-            ),
-            TestPattern::None => Ok(()),
-        }
+        self.transport.with_transport(|transport| {
+            self.send_reg(transport, &PatternCtl::default())?;
+            self.send_reg(transport, &DeskewSyncPattern::default())?;
+            match pat {
+                TestPattern::Ramp => self.send_reg(
+                    transport,
+                    &PatternCtl {
+                        pattern: Pattern::Ramp,
+                    },
+                ),
+                TestPattern::Deskew => self.send_reg(
+                    transport,
+                    &DeskewSyncPattern {
+                        pat_deskew_sync: DeskewSyncMode::Deskew,
+                    },
+                ),
+                TestPattern::Sync => self.send_reg(
+                    transport,
+                    &DeskewSyncPattern {
+                        pat_deskew_sync: DeskewSyncMode::Sync,
+                    },
+                ),
+                TestPattern::Custom1 | TestPattern::Custom2 => self.send_reg(
+                    transport,
+                    &PatternCtl {
+                        pattern: Pattern::SingleCustom,
+                    },
+                ),
+                TestPattern::Dual => self.send_reg(
+                    transport,
+                    &PatternCtl {
+                        pattern: Pattern::DualCustom,
+                    },
+                ),
+                TestPattern::None => Ok(()),
+            }
+        })
     }
 
     /// Set the "Custom 1 " pattern
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn custom_1(&self, bits: [bool; 8]) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        self.send_reg(&mut transport, &CustomPattern1 { bits_custom1: bits })
+        self.transport.with_transport(|transport| {
+            self.send_reg(transport, &CustomPattern1 { bits_custom1: bits })
+        })
     }
 
     /// Set the "Custom 2 " pattern
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn custom_2(&self, bits: [bool; 8]) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        self.send_reg(&mut transport, &CustomPattern2 { bits_custom2: bits })
+        self.transport.with_transport(|transport| {
+            self.send_reg(transport, &CustomPattern2 { bits_custom2: bits })
+        })
     }
 
     /// Perform a bitslip operation on specified chips
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn bitslip(&self, bitslips: Bitslip) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let slip = AdcControl {
-            bitslip: bitslips,
-            ..Default::default()
-        };
-        transport.write_addr(Self::NAME, &AdcControl::default())?;
-        transport.write_addr(Self::NAME, &slip)?;
-        transport.write_addr(Self::NAME, &AdcControl::default())?;
-        Ok(())
+        self.transport.with_transport(|transport| {
+            let slip = AdcControl {
+                bitslip: bitslips,
+                ..Default::default()
+            };
+            transport.write_addr(Self::NAME, &AdcControl::default())?;
+            transport.write_addr(Self::NAME, &slip)?;
+            transport.write_addr(Self::NAME, &AdcControl::default())?;
+            Ok(())
+        })
     }
 
     /// Request a snapshot - reads from the corresponding BRAM happen elsewhere
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn snap_req(&self) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Request the snapshot
-        let snap_req = AdcControl {
-            snap_request: true,
-            ..Default::default()
-        };
-        transport.write_addr(Self::NAME, &AdcControl::default())?;
-        transport.write_addr(Self::NAME, &snap_req)?;
-        transport.write_addr(Self::NAME, &AdcControl::default())?;
-        Ok(())
+        self.transport.with_transport(|transport| {
+            // Request the snapshot
+            let snap_req = AdcControl {
+                snap_request: true,
+                ..Default::default()
+            };
+            transport.write_addr(Self::NAME, &AdcControl::default())?;
+            transport.write_addr(Self::NAME, &snap_req)?;
+            transport.write_addr(Self::NAME, &AdcControl::default())?;
+            Ok(())
+        })
+    }
+
+    /// Manually set the IDELAY taps of a single lane of one chip, see [`DelayTaps`] for the lane
+    /// numbering
+    /// # Errors
+    /// Returns an error on bad transport or if the chip, lane, or taps are out of range
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "adc16::set_delay_taps",
+            skip_all,
+            fields(device = Self::NAME),
+            err,
+        )
+    )]
+    pub fn set_delay_taps(&mut self, chip: u8, lane: u8, taps: u8) -> Result<(), Error> {
+        if chip as usize >= MAX_CHIPS || lane as usize >= LANES {
+            return Err(Error::BadLane { chip, lane });
+        }
+        if taps > MAX_DELAY_TAP {
+            return Err(Error::BadDelayTap(taps));
+        }
+        self.transport.with_transport(|transport| {
+            // Each chip has a nibble of strobes in each register, one bit per channel
+            let strobe = |lanes: u8| (u32::from(lanes) << (4 * chip)).to_be_bytes();
+            let (a, b) = if (lane as usize) < LANES / 2 {
+                (strobe(1 << lane), strobe(0))
+            } else {
+                (strobe(0), strobe(1 << (lane as usize - LANES / 2)))
+            };
+            let a = AdcDelayAStrobe::unpack(&a).map_err(crate::transport::Error::Packing)?;
+            let b = AdcDelayBStrobe::unpack(&b).map_err(crate::transport::Error::Packing)?;
+            // Set the taps, strobe them into the selected lane, then clear everything
+            let ctl = AdcControl {
+                delay_taps: std::array::from_fn(|i| (taps >> (4 - i)) & 1 == 1),
+                ..Default::default()
+            };
+            transport.write_addr(Self::NAME, &ctl)?;
+            transport.write_addr(Self::NAME, &a)?;
+            transport.write_addr(Self::NAME, &b)?;
+            transport.write_addr(Self::NAME, &AdcControl::default())?;
+            transport.write_addr(Self::NAME, &AdcDelayAStrobe::default())?;
+            transport.write_addr(Self::NAME, &AdcDelayBStrobe::default())?;
+            self.delay_taps.0[chip as usize][lane as usize] = Some(taps);
+            Ok(())
+        })
+    }
+
+    /// The last delay taps written with [`Adc16::set_delay_taps`]
+    #[must_use]
+    pub fn get_delay_taps(&self) -> DelayTaps {
+        self.delay_taps
     }
 
     /// Set the operating mode along with the clock frequency in megahertz
@@ -408,154 +540,333 @@ where
     /// to change, but would need manual intervention at init time.
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_operating_mode(&self, mode: AdcMode, freq: f64) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-
-        // Determine if we need to set the low frequency bit
-        let low_clk = LvdsOutputControl {
-            low_clk_freq: ((mode == AdcMode::Single) && (freq < 240.))
-                || ((mode == AdcMode::Dual) && (freq < 120.))
-                || ((mode == AdcMode::Quad) && (freq < 60.)),
-            lvds_shift: LvdsShift::Disabled,
-        };
+        self.transport.with_transport(|transport| {
+            // Determine if we need to set the low frequency bit
+            let low_clk = LvdsOutputControl {
+                low_clk_freq: ((mode == AdcMode::Single) && (freq < 240.))
+                    || ((mode == AdcMode::Dual) && (freq < 120.))
+                    || ((mode == AdcMode::Quad) && (freq < 60.)),
+                lvds_shift: LvdsShift::Disabled,
+            };
 
-        // Match mode with the corresponding register
-        let chan_cfg = ChanNumClkDiv {
-            channel_num: match mode {
-                AdcMode::Single => ChannelNum::Single,
-                AdcMode::Dual => ChannelNum::Dual,
-                AdcMode::Quad => ChannelNum::Quad,
-            },
-            clk_divide: ClockDivide::_1,
-        };
+            // Match mode with the corresponding register
+            let chan_cfg = ChanNumClkDiv {
+                channel_num: match mode {
+                    AdcMode::Single => ChannelNum::Single,
+                    AdcMode::Dual => ChannelNum::Dual,
+                    AdcMode::Quad => ChannelNum::Quad,
+                },
+                clk_divide: ClockDivide::_1,
+            };
 
-        // Send all the bits
-        self.send_reg(&mut transport, &chan_cfg)?;
-        self.send_reg(&mut transport, &low_clk)?;
+            // Send all the bits
+            self.send_reg(transport, &chan_cfg)?;
+            self.send_reg(transport, &low_clk)?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     /// Startup the ADCs into a clean slate
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "adc16::init", skip_all, fields(device = Self::NAME), err)
+    )]
     pub fn init(&mut self, mode: AdcMode, freq: f64) -> Result<(), Error> {
         self.reset()?;
         self.power_down()?;
         self.set_operating_mode(mode, freq)?;
+        // The reset put the chips back to their power-on tuning
+        self.apply_tuning(self.tuning)?;
         self.power_up()?;
         Ok(())
     }
 
+    /// Set the current of the clock circuitry of the selected ADCs. More current means less
+    /// aperture jitter, which matters for high input frequencies (the SNR of a full-scale sine
+    /// falls off with the product of its frequency and the jitter), at up to 8 mA per chip.
+    /// [`Jitter::_0`] stops the clock entirely.
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn set_jitter(&self, jitter: Jitter) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            self.send_reg(
+                transport,
+                &JitterCtl {
+                    jitter_ctrl: jitter,
+                },
+            )
+        })
+    }
+
+    /// Scale down the core current of the selected ADCs and set the drive of the VCM buffer.
+    /// Less current saves power, but degrades the performance at the higher sample rates, so only
+    /// reduce it when running well below the maximum rate. The VCM drive only matters if the
+    /// common mode of the analog front end is taken from the VCM pin.
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn set_current(
+        &self,
+        current: AdcCurrentControl,
+        vcm_drive: VcmBufferDrive,
+    ) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            self.send_reg(
+                transport,
+                &AdcCurrentVcmDrive {
+                    adc_curr: current,
+                    ext_vcm_bc: vcm_drive,
+                },
+            )
+        })
+    }
+
+    /// Set how long the selected ADCs wait for their clock to settle when powering up. Which
+    /// setting is right depends on the channel count and the clock frequency (see the start-up
+    /// table of the HMCAD1511 datasheet), too short a delay can leave the chips in a bad state.
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn set_startup_timing(&self, timing: StartupTiming) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            self.send_reg(
+                transport,
+                &StartupControl {
+                    startup_ctrl: timing,
+                },
+            )
+        })
+    }
+
+    /// Write every setting of `tuning` to the selected ADCs
+    fn apply_tuning(&self, tuning: AdcTuning) -> Result<(), Error> {
+        self.set_jitter(tuning.jitter)?;
+        self.set_current(tuning.current, tuning.vcm_drive)?;
+        self.set_startup_timing(tuning.startup)
+    }
+
+    /// Apply `tuning` to the selected ADCs now and at every following [`Adc16::init`]
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn set_tuning(&mut self, tuning: AdcTuning) -> Result<(), Error> {
+        self.apply_tuning(tuning)?;
+        self.tuning = tuning;
+        Ok(())
+    }
+
+    /// The tuning applied at init, the last one given to [`Adc16::set_tuning`]
+    #[must_use]
+    pub fn tuning(&self) -> AdcTuning {
+        self.tuning
+    }
+
     /// Set the crossbars in the chip selected adc
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "adc16::input_select",
+            skip_all,
+            fields(device = Self::NAME),
+            err,
+        )
+    )]
     pub fn input_select(&self, inputs: ChannelInput) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Make the selections
-        let mut selections = [InputSelect::default(); 4];
-        match inputs {
-            ChannelInput::Single(a) => {
-                selections[0] = a;
-                selections[1] = a;
-                selections[2] = a;
-                selections[3] = a;
-            }
-            ChannelInput::Dual(a, b) => {
-                selections[0] = a;
-                selections[1] = a;
-                selections[2] = b;
-                selections[3] = b;
-            }
-            ChannelInput::Quad(a, b, c, d) => {
-                selections[0] = a;
-                selections[1] = b;
-                selections[2] = c;
-                selections[3] = d;
+        self.transport.with_transport(|transport| {
+            // Make the selections
+            let mut selections = [InputSelect::default(); 4];
+            match inputs {
+                ChannelInput::Single(a) => {
+                    selections[0] = a;
+                    selections[1] = a;
+                    selections[2] = a;
+                    selections[3] = a;
+                }
+                ChannelInput::Dual(a, b) => {
+                    selections[0] = a;
+                    selections[1] = a;
+                    selections[2] = b;
+                    selections[3] = b;
+                }
+                ChannelInput::Quad(a, b, c, d) => {
+                    selections[0] = a;
+                    selections[1] = b;
+                    selections[2] = c;
+                    selections[3] = d;
+                }
             }
+            // Write the inputs
+            self.send_reg(
+                transport,
+                &InputSelect12 {
+                    inp_sel_adc1: selections[0],
+                    inp_sel_adc2: selections[1],
+                },
+            )?;
+            self.send_reg(
+                transport,
+                &InputSelect34 {
+                    inp_sel_adc3: selections[2],
+                    inp_sel_adc4: selections[3],
+                },
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Set the fine gain of each of the eight branches of the selected ADCs, in steps of roughly
+    /// 0.0017 dB (see the HMCAD1511 datasheet), and enable fine gain adjustment
+    /// # Errors
+    /// Returns an error on bad transport or if a gain is outside of the 7-bit signed range
+    pub fn set_fine_gains(&mut self, gains: [i8; 8]) -> Result<(), Error> {
+        if let Some(&bad) = gains.iter().find(|g| !(-64..=63).contains(*g)) {
+            return Err(Error::BadFineGain(bad));
         }
-        // Write the inputs
-        self.send_reg(
-            &mut transport,
-            &InputSelect12 {
-                inp_sel_adc1: selections[0],
-                inp_sel_adc2: selections[1],
-            },
-        )?;
-        self.send_reg(
-            &mut transport,
-            &InputSelect34 {
-                inp_sel_adc3: selections[2],
-                inp_sel_adc4: selections[3],
-            },
-        )?;
+        let gain_ctl = GainCtl {
+            fine_gain_en: true,
+            ..self.gain_ctl
+        };
+        self.transport.with_transport(|transport| {
+            self.send_reg(
+                transport,
+                &FineGain12 {
+                    fgain_branch1: gains[0].into(),
+                    fgain_branch2: gains[1].into(),
+                },
+            )?;
+            self.send_reg(
+                transport,
+                &FineGain34 {
+                    fgain_branch3: gains[2].into(),
+                    fgain_branch4: gains[3].into(),
+                },
+            )?;
+            self.send_reg(
+                transport,
+                &FineGain56 {
+                    fgain_branch5: gains[4].into(),
+                    fgain_branch6: gains[5].into(),
+                },
+            )?;
+            self.send_reg(
+                transport,
+                &FineGain78 {
+                    fgain_branch7: gains[6].into(),
+                    fgain_branch8: gains[7].into(),
+                },
+            )?;
+            self.send_reg(transport, &gain_ctl)
+        })?;
+        self.fine_gains = gains;
+        self.gain_ctl = gain_ctl;
+        Ok(())
+    }
+
+    /// The last fine gains written with [`Adc16::set_fine_gains`] (zero at power-on)
+    #[must_use]
+    pub fn fine_gains(&self) -> [i8; 8] {
+        self.fine_gains
+    }
+
+    /// Select whether the coarse gains of the selected ADCs are in the "gain factor" steps of
+    /// [`CoarseGain`] rather than the dB steps, leaving fine gain adjustment as it was
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn set_coarse_gain_factor(&mut self, factor: bool) -> Result<(), Error> {
+        let gain_ctl = GainCtl {
+            coarse_gain_cfg: factor,
+            ..self.gain_ctl
+        };
+        self.transport
+            .with_transport(|transport| self.send_reg(transport, &gain_ctl))?;
+        self.gain_ctl = gain_ctl;
+        Ok(())
+    }
+
+    /// Swap the positive and negative analog inputs of the selected ADCs, per channel of the mode
+    /// the ADCs are operating in
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn set_input_invert(&mut self, invert: ChannelInvert) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            let mut ctl = InvertCtl::default();
+            match invert {
+                ChannelInvert::Single(a) => ctl.intert1 = a,
+                ChannelInvert::Dual(a, b) => ctl.invert2 = [a, b],
+                ChannelInvert::Quad(a, b, c, d) => ctl.invert4 = [a, b, c, d],
+            }
+            self.send_reg(transport, &ctl)
+        })?;
+        self.invert = Some(invert);
         Ok(())
     }
 
+    /// The last input inversion written with [`Adc16::set_input_invert`], `None` if it was never
+    /// set (the inputs aren't inverted at power-on)
+    #[must_use]
+    pub fn input_invert(&self) -> Option<ChannelInvert> {
+        self.invert
+    }
+
     /// Disable LVDS terminations
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn disable_termination(&self) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        self.send_reg(
-            &mut transport,
-            &LvdsTerminations {
-                en_lvds_term: false,
-                ..Default::default()
-            },
-        )
+        self.transport.with_transport(|transport| {
+            self.send_reg(
+                transport,
+                &LvdsTerminations {
+                    en_lvds_term: false,
+                    ..Default::default()
+                },
+            )
+        })
     }
 
     /// Set the three LVDS terminations
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_terminations(
         &self,
         lclk: LvdsTermination,
         frame: LvdsTermination,
         data: LvdsTermination,
     ) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        self.send_reg(
-            &mut transport,
-            &LvdsTerminations {
-                en_lvds_term: true,
-                term_lclk: lclk,
-                term_frame: frame,
-                term_dat: data,
-            },
-        )
+        self.transport.with_transport(|transport| {
+            self.send_reg(
+                transport,
+                &LvdsTerminations {
+                    en_lvds_term: true,
+                    term_lclk: lclk,
+                    term_frame: frame,
+                    term_dat: data,
+                },
+            )
+        })
     }
 
     /// Set the LVDS drive strengths
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_drive_strength(
         &self,
         lclk: LvdsDriveStrength,
         frame: LvdsDriveStrength,
         data: LvdsDriveStrength,
     ) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        self.send_reg(
-            &mut transport,
-            &LvdsDrives {
-                ilvds_lclk: lclk,
-                ilvds_frame: frame,
-                ilvds_dat: data,
-            },
-        )
+        self.transport.with_transport(|transport| {
+            self.send_reg(
+                transport,
+                &LvdsDrives {
+                    ilvds_lclk: lclk,
+                    ilvds_frame: frame,
+                    ilvds_dat: data,
+                },
+            )
+        })
     }
 }
 
@@ -570,6 +881,17 @@ pub enum ChannelInput {
     Quad(InputSelect, InputSelect, InputSelect, InputSelect),
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Analog input inversions for given input modes, in the same order as [`ChannelInput`]
+pub enum ChannelInvert {
+    /// All interleaved - one input for all cores
+    Single(bool),
+    /// ADCs 1 and 2 then 3 and 4 interleaved
+    Dual(bool, bool),
+    /// ADCs 1 through 4 independently
+    Quad(bool, bool, bool, bool),
+}
+
 #[derive(Debug, Copy, Clone, Default)]
 /// Test patterns to enable
 pub enum TestPattern {
@@ -659,6 +981,7 @@ impl ChipSelect {
 #[derive(Debug, PackedStruct, CasperSerde, Default)]
 #[address(0x0)]
 #[packed_struct(bit_numbering = "msb0", size_bytes = "4")]
+#[casper_serde(golden(value = "Adc3Wire::idle()", bytes = "[0, 0, 0x02, 0]"))]
 pub struct Adc3Wire {
     #[packed_field(bits = "6..=7")]
     line_lock: Integer<u8, packed_bits::Bits<2>>,
@@ -717,6 +1040,20 @@ pub struct Bitslip {
 #[derive(Debug, PackedStruct, CasperSerde, Default)]
 #[address(0x4)]
 #[packed_struct(bit_numbering = "msb0", size_bytes = "4")]
+#[casper_serde(
+    golden(
+        value = "AdcControl { reset: true, ..Default::default() }",
+        bytes = "[0, 0x10, 0, 0]"
+    ),
+    golden(
+        value = "AdcControl {
+            demux_write_enable: true,
+            demux_mode: DemuxMode::DualChannel,
+            ..Default::default()
+        }",
+        bytes = "[0x05, 0, 0, 0]"
+    )
+)]
 pub struct AdcControl {
     #[packed_field(bits = "5")]
     demux_write_enable: bool,
@@ -732,9 +1069,10 @@ pub struct AdcControl {
     delay_taps: [bool; 5],
 }
 
-#[derive(Debug, PackedStruct, CasperSerde)]
+#[derive(Debug, PackedStruct, CasperSerde, Default)]
 #[address(0x8)]
 #[packed_struct(bit_numbering = "msb0", size_bytes = "4")]
+#[casper_serde(layout_tests)]
 pub struct AdcDelayAStrobe {
     #[packed_field(bits = "0..=3")]
     h: [bool; 4],
@@ -754,9 +1092,10 @@ pub struct AdcDelayAStrobe {
     a: [bool; 4],
 }
 
-#[derive(Debug, PackedStruct, CasperSerde)]
+#[derive(Debug, PackedStruct, CasperSerde, Default)]
 #[address(0xC)]
 #[packed_struct(bit_numbering = "msb0", size_bytes = "4")]
+#[casper_serde(layout_tests)]
 pub struct AdcDelayBStrobe {
     #[packed_field(bits = "0..=3")]
     h: [bool; 4],
@@ -775,3 +1114,191 @@ pub struct AdcDelayBStrobe {
     #[packed_field(bits = "28..=31")]
     a: [bool; 4],
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::{
+            mock::Mock,
+            sim::SimulatedFpga,
+        },
+    };
+    use casper_utils::design_sources::fpg::read_fpg_file;
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
+
+    #[test]
+    fn test_fine_gain_invert() {
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::from([(
+            "adc16_controller".into(),
+            Register {
+                addr: 0,
+                length: 0x100,
+            },
+        )]))));
+        let mut adc = Adc16::new(Arc::downgrade(&transport));
+        assert!(matches!(
+            adc.set_fine_gains([0, 0, 64, 0, 0, 0, 0, 0]),
+            Err(Error::BadFineGain(64))
+        ));
+        assert_eq!(adc.fine_gains(), [0; 8]);
+        let gains = [-64, -1, 0, 1, 2, 3, 4, 63];
+        adc.set_fine_gains(gains).unwrap();
+        assert_eq!(adc.fine_gains(), gains);
+        assert_eq!(adc.input_invert(), None);
+        adc.set_input_invert(ChannelInvert::Dual(true, false))
+            .unwrap();
+        assert_eq!(adc.input_invert(), Some(ChannelInvert::Dual(true, false)));
+    }
+
+    /// The (address, value) of every register sent over the 3-wire interface in `words`, the
+    /// writes to the control register
+    fn decode_3wire(words: &[u32]) -> Vec<(u8, u16)> {
+        let mut regs = vec![];
+        let mut bits = 0u32;
+        let mut n = 0;
+        for &word in words {
+            // Bits are clocked in on the rising edge, with a chip selected
+            let [_, _, sclk_sdata, cs] = word.to_be_bytes();
+            if cs == 0 || sclk_sdata & 0b10 == 0 {
+                continue;
+            }
+            bits = bits << 1 | u32::from(sclk_sdata & 1);
+            n += 1;
+            if n == 24 {
+                let [_, addr, hi, lo] = bits.to_be_bytes();
+                regs.push((addr, u16::from_be_bytes([hi, lo])));
+                bits = 0;
+                n = 0;
+            }
+        }
+        regs
+    }
+
+    #[test]
+    fn test_tuning() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        let words = Arc::new(Mutex::new(vec![]));
+        let record = words.clone();
+        sim.on_write("adc16_controller", move |_, offset, data| {
+            if offset == 0 {
+                record
+                    .lock()
+                    .unwrap()
+                    .push(u32::from_be_bytes(data.try_into().unwrap()));
+            }
+            Ok(())
+        });
+        let transport = Arc::new(Mutex::new(sim));
+        let mut adc = Adc16::new(Arc::downgrade(&transport));
+        adc.chip_select(&ChipSelect::select_all());
+        let tuning = AdcTuning {
+            jitter: Jitter::_8,
+            current: AdcCurrentControl::_20,
+            vcm_drive: VcmBufferDrive::Pm400,
+            startup: StartupTiming::_101,
+        };
+        adc.set_tuning(tuning).unwrap();
+        let expected = [(0x30, 0xFF), (0x50, 0b100_0110), (0x56, 0b101)];
+        assert_eq!(decode_3wire(&words.lock().unwrap()), expected);
+        assert!(matches!(adc.tuning().jitter, Jitter::_8));
+
+        // Init resets the chips, so it puts the tuning back while they're powered down
+        words.lock().unwrap().clear();
+        adc.init(AdcMode::Single, 500.).unwrap();
+        let sent = decode_3wire(&words.lock().unwrap());
+        let power_up = sent.iter().rposition(|&(addr, _)| addr == 0x0F).unwrap();
+        assert_eq!(sent[power_up - 3..power_up], expected);
+        assert_eq!(sent[0], (0x00, 1));
+    }
+
+    #[test]
+    fn test_gain_ctl() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        let words = Arc::new(Mutex::new(vec![]));
+        let record = words.clone();
+        sim.on_write("adc16_controller", move |_, offset, data| {
+            if offset == 0 {
+                record
+                    .lock()
+                    .unwrap()
+                    .push(u32::from_be_bytes(data.try_into().unwrap()));
+            }
+            Ok(())
+        });
+        let transport = Arc::new(Mutex::new(sim));
+        let mut adc = Adc16::new(Arc::downgrade(&transport));
+        adc.chip_select(&ChipSelect::select_all());
+        adc.set_coarse_gain_factor(true).unwrap();
+        adc.set_fine_gains([0; 8]).unwrap();
+        // Enabling the fine gains keeps the coarse gain configuration
+        let sent = decode_3wire(&words.lock().unwrap());
+        assert_eq!(sent.first(), Some(&(0x33, 0b01)));
+        assert_eq!(sent.last(), Some(&(0x33, 0b11)));
+        words.lock().unwrap().clear();
+        adc.set_coarse_gain_factor(false).unwrap();
+        assert_eq!(decode_3wire(&words.lock().unwrap()), [(0x33, 0b10)]);
+    }
+
+    #[test]
+    fn test_delay_taps() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        // Record every write to the control and strobe registers
+        let writes = Arc::new(Mutex::new(vec![]));
+        let record = writes.clone();
+        sim.on_write("adc16_controller", move |_, offset, data| {
+            let word = u32::from_be_bytes(data.try_into().unwrap());
+            record.lock().unwrap().push((offset, word));
+            Ok(())
+        });
+        let transport = Arc::new(Mutex::new(sim));
+        let mut adc = Adc16::new(Arc::downgrade(&transport));
+        assert!(matches!(
+            adc.set_delay_taps(0, 0, 32),
+            Err(Error::BadDelayTap(32))
+        ));
+        assert!(matches!(
+            adc.set_delay_taps(8, 0, 1),
+            Err(Error::BadLane { chip: 8, lane: 0 })
+        ));
+        assert!(writes.lock().unwrap().is_empty());
+
+        // Lane 1b of chip 2
+        adc.set_delay_taps(2, 4, 21).unwrap();
+        assert_eq!(
+            *writes.lock().unwrap(),
+            vec![
+                (0x4, 21),
+                (0x8, 0),
+                (0xC, 1 << 8),
+                (0x4, 0),
+                (0x8, 0),
+                (0xC, 0)
+            ]
+        );
+        writes.lock().unwrap().clear();
+        // Lane 4a of chip 0
+        adc.set_delay_taps(0, 3, 7).unwrap();
+        assert_eq!(
+            writes.lock().unwrap()[..3],
+            [(0x4, 7), (0x8, 0b1000), (0xC, 0)]
+        );
+
+        let taps = adc.get_delay_taps();
+        assert_eq!(taps.get(2, 4), Some(21));
+        assert_eq!(taps.get(0, 3), Some(7));
+        assert_eq!(taps.get(1, 0), None);
+        assert_eq!(taps.get(9, 0), None);
+        assert_eq!(
+            taps.to_string(),
+            "chip  1a  2a  3a  4a  1b  2b  3b  4b\n   0   -   -   -   7   -   -   -   -\n   2   -   -   -   -  21   -   -   -"
+        );
+    }
+}
@@ -47,6 +47,7 @@ pub enum PdPinCfg {
 #[derive(Debug, PackedStruct, Default, Copy, Clone)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "2")]
 #[address(0x12)]
+#[allow(clippy::struct_field_names)]
 pub struct LvdsDrives {
     #[packed_field(bits = "0..=2", ty = "enum")]
     /// LVDS current drive for LCLK
@@ -161,6 +162,7 @@ pub struct CustomPattern2 {
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "2")]
 #[address(0x2A)]
 /// Programmable coarse gain in quad channel setup
+#[allow(clippy::struct_field_names)]
 pub struct QuadCoarseGains {
     #[packed_field(bits = "0..=3", ty = "enum")]
     pub(crate) cgain4_ch1: CoarseGain,
@@ -0,0 +1,203 @@
+//! Measuring and correcting the mismatch between the interleaved cores of an HMCAD1511
+//!
+//! Every 32-bit word of a snapshot holds one byte from each of the four cores of a chip (see
+//! [`super::demux_snapshot`]). When cores are interleaved into one input, differences in their DC
+//! offset and gain show up as spurs at multiples of the per-core sample rate. With a sine (or
+//! noise) on the inputs, the mean of each core's samples is its offset and their RMS about that
+//! mean is its gain.
+//!
+//! Gains are corrected with the fine gain of the two ADC branches that feed each core, which only
+//! reaches about ±0.1 dB, so coarse mismatch has to be fixed by matching the inputs. The ADC has no
+//! offset adjustment, but designs may subtract one in the FPGA from a register of per-core
+//! coefficients, see [`offset_coefficients`]. Timing (phase) mismatch between cores can't be
+//! corrected from here and isn't measured.
+
+use super::{
+    controller::Error as ControllerError,
+    Error,
+};
+
+/// The number of interleaved cores of each chip
+pub const CORES: usize = 4;
+/// The step of the fine gain registers in dB
+pub const FINE_GAIN_STEP_DB: f64 = 0.0017;
+/// The number of fractional bits of the FPGA offset coefficients, so a coefficient of 256 is one
+/// ADC code
+pub const OFFSET_FRAC_BITS: u32 = 8;
+
+/// The offset and gain of one core
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CoreStats {
+    /// The mean of the samples in ADC codes
+    pub offset: f64,
+    /// The RMS of the samples about their mean in ADC codes
+    pub rms: f64,
+    /// The number of samples measured
+    pub samples: usize,
+}
+
+/// The statistics of every core of a chip
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MismatchReport {
+    pub cores: [CoreStats; CORES],
+}
+
+impl MismatchReport {
+    /// Measure the cores from one or more raw snapshots of the same chip, pooling the samples of
+    /// every snapshot
+    pub fn measure<'a, I>(snapshots: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut sums = [0f64; CORES];
+        let mut squares = [0f64; CORES];
+        let mut counts = [0usize; CORES];
+        for raw in snapshots {
+            for word in raw.chunks_exact(CORES) {
+                for (core, &byte) in word.iter().enumerate() {
+                    let code = f64::from(i8::from_be_bytes([byte]));
+                    sums[core] += code;
+                    squares[core] += code * code;
+                    counts[core] += 1;
+                }
+            }
+        }
+        let mut report = Self::default();
+        for (core, stats) in report.cores.iter_mut().enumerate() {
+            if counts[core] == 0 {
+                continue;
+            }
+            #[allow(clippy::cast_precision_loss)]
+            let n = counts[core] as f64;
+            let mean = sums[core] / n;
+            *stats = CoreStats {
+                offset: mean,
+                rms: (squares[core] / n - mean * mean).max(0.).sqrt(),
+                samples: counts[core],
+            };
+        }
+        report
+    }
+
+    /// The difference between the largest and smallest offset in ADC codes
+    #[must_use]
+    pub fn offset_spread(&self) -> f64 {
+        spread(self.cores.iter().map(|c| c.offset))
+    }
+
+    /// The ratio of the largest to the smallest RMS in dB, infinite if a core saw no signal
+    #[must_use]
+    pub fn gain_spread_db(&self) -> f64 {
+        let (min, max) = min_max(self.cores.iter().map(|c| c.rms));
+        20. * (max / min).log10()
+    }
+}
+
+fn min_max<I: Iterator<Item = f64>>(values: I) -> (f64, f64) {
+    values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+        (lo.min(v), hi.max(v))
+    })
+}
+
+fn spread<I: Iterator<Item = f64>>(values: I) -> f64 {
+    let (min, max) = min_max(values);
+    max - min
+}
+
+/// The fine gains (in the order of [`super::controller::Adc16::set_fine_gains`]) that bring every
+/// core to the mean RMS of `report`, starting from the gains `current` that were in place when it
+/// was measured. Both branches of a core get the same adjustment.
+/// # Errors
+/// Returns [`Error::NoSignal`] if a core saw no signal, or a [`ControllerError::BadFineGain`] if
+/// the correction is past the range of the fine gain
+pub fn fine_gain_correction(report: &MismatchReport, current: [i8; 8]) -> Result<[i8; 8], Error> {
+    if let Some(core) = report.cores.iter().position(|c| c.rms <= 0.) {
+        return Err(Error::NoSignal { core });
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let target = report.cores.iter().map(|c| c.rms).sum::<f64>() / CORES as f64;
+    let mut gains = current;
+    for (core, stats) in report.cores.iter().enumerate() {
+        let steps = (20. * (target / stats.rms).log10() / FINE_GAIN_STEP_DB).round();
+        for gain in &mut gains[2 * core..2 * core + 2] {
+            #[allow(clippy::cast_possible_truncation)]
+            let corrected = (f64::from(*gain) + steps).clamp(-128., 127.) as i8;
+            if !(-64..=63).contains(&corrected) {
+                return Err(ControllerError::BadFineGain(corrected).into());
+            }
+            *gain = corrected;
+        }
+    }
+    Ok(gains)
+}
+
+/// The FPGA-side coefficients that cancel the offset of each core, as signed fixed point with
+/// [`OFFSET_FRAC_BITS`] fractional bits, for designs that add a per-core coefficient to the samples
+#[must_use]
+pub fn offset_coefficients(report: &MismatchReport) -> [i32; CORES] {
+    let scale = f64::from(1u32 << OFFSET_FRAC_BITS);
+    #[allow(clippy::cast_possible_truncation)]
+    report
+        .cores
+        .map(|c| (-c.offset * scale).round().clamp(-1e9, 1e9) as i32)
+}
+
+/// The result of [`super::SnapAdc::correct_mismatch`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MismatchCorrection {
+    /// The cores before correcting
+    pub before: MismatchReport,
+    /// The cores after correcting
+    pub after: MismatchReport,
+    /// The fine gains written
+    pub fine_gains: [i8; 8],
+    /// The offset coefficients written, if the design has a register for them
+    pub offsets: Option<[i32; CORES]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mismatch() {
+        // Core 0 has an offset of one code, core 3 a gain slightly higher than the others
+        let square = |amp: i8, offset: i8| [offset + amp, offset - amp];
+        let raw: Vec<u8> = (0..256)
+            .flat_map(|i| {
+                let cores = [
+                    square(100, 1),
+                    square(100, 0),
+                    square(100, 0),
+                    square(101, 0),
+                ];
+                cores.map(|c| c[i % 2].to_be_bytes()[0])
+            })
+            .collect();
+        let report = MismatchReport::measure([raw.as_slice()]);
+        assert_eq!(report.cores[0].samples, 256);
+        assert!((report.cores[0].offset - 1.).abs() < 1e-9);
+        assert!((report.cores[3].rms - 101.).abs() < 1e-9);
+        assert!((report.offset_spread() - 1.).abs() < 1e-9);
+        assert!((report.gain_spread_db() - 20. * (101f64 / 100.).log10()).abs() < 1e-9);
+        assert_eq!(offset_coefficients(&report), [-256, 0, 0, 0]);
+
+        // Core 3 is turned down, the rest up, to the mean of 100.25
+        let gains = fine_gain_correction(&report, [0; 8]).unwrap();
+        assert_eq!(gains, [13, 13, 13, 13, 13, 13, -38, -38]);
+        // Corrections add to the gains already in place
+        let gains = fine_gain_correction(&report, [0, 1, 0, 0, 0, 0, -20, -20]).unwrap();
+        assert_eq!(gains, [13, 14, 13, 13, 13, 13, -58, -58]);
+        assert!(matches!(
+            fine_gain_correction(&report, [0, 0, 0, 0, 0, 0, -30, -30]),
+            Err(Error::Controller(ControllerError::BadFineGain(-68)))
+        ));
+
+        let mut quiet = report;
+        quiet.cores[2].rms = 0.;
+        assert!(matches!(
+            fine_gain_correction(&quiet, [0; 8]),
+            Err(Error::NoSignal { core: 2 })
+        ));
+    }
+}
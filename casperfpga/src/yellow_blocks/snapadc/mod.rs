@@ -4,6 +4,7 @@ pub mod clockswitch;
 pub mod controller;
 pub mod hmcad1511;
 pub mod lmx;
+pub mod mismatch;
 
 use self::{
     clockswitch::{
@@ -14,17 +15,44 @@ use self::{
         Adc16,
         ChannelInput,
         ChipSelect,
+        DemuxMode,
     },
     hmcad1511::{
         LvdsDriveStrength,
         LvdsTermination,
     },
     lmx::Synth,
+    mismatch::{
+        MismatchCorrection,
+        MismatchReport,
+    },
 };
-use crate::transport::Transport;
-use std::sync::{
-    Mutex,
-    Weak,
+use crate::{
+    core::{
+        estimate_fpga_clock,
+        RegisterNamespace,
+    },
+    transport::{
+        CancelToken,
+        Transport,
+    },
+    yellow_blocks::{
+        device,
+        metadata_entry,
+        xsg::Xsg,
+        FromFpg,
+        Metadata,
+        TransportHandle,
+        YellowBlock,
+    },
+};
+use casper_utils::design_sources::Devices;
+use std::{
+    any::Any,
+    sync::{
+        Mutex,
+        Weak,
+    },
 };
 use thiserror::Error;
 
@@ -42,8 +70,22 @@ pub enum Error {
     BadAdcResolution,
     #[error("Bad sample rate from the fpg file")]
     BadSampleRate,
+    #[error("A sample rate of {rate} MHz is above the {max} MHz limit of {mode:?} mode")]
+    SampleRateTooHigh { mode: AdcMode, rate: f64, max: f64 },
+    #[error("The FPGA demux is set to {demux:?}, which doesn't match the {mode:?} ADC mode")]
+    DemuxMismatch { mode: AdcMode, demux: DemuxMode },
+    #[error("Core {core} saw no signal, so its gain can't be measured")]
+    NoSignal { core: usize },
+    #[error("The ADC controller isn't locked to the ADC clock, is the external clock connected?")]
+    NotLocked,
+    #[error("The external clock gives a sample rate of {measured:.1} MHz, not the {expected} MHz of the design")]
+    ClockMismatch { expected: f64, measured: f64 },
 }
 
+/// The default fraction the sample rate inferred from the FPGA clock can be off by in
+/// [`SnapAdc::check_clock`]
+pub const DEFAULT_CLOCK_TOLERANCE: f64 = 0.01;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 /// Valid modes for each HMCAD1511 ADC
 pub enum AdcMode {
@@ -55,12 +97,64 @@ pub enum AdcMode {
     Quad,
 }
 
+impl AdcMode {
+    /// The number of channels each chip digitizes in this mode
+    #[must_use]
+    pub fn channels(self) -> usize {
+        match self {
+            AdcMode::Single => 1,
+            AdcMode::Dual => 2,
+            AdcMode::Quad => 4,
+        }
+    }
+
+    /// The FPGA clock in MHz when the ADCs are sampling each channel at `rate` MHz and sourcing
+    /// it. The ADC16 interface runs at the rate of each ADC core and takes one byte from each of
+    /// the four cores of a chip per cycle.
+    #[must_use]
+    pub fn fpga_clock(self, rate: f64) -> f64 {
+        match self {
+            AdcMode::Single => rate / 4.,
+            AdcMode::Dual => rate / 2.,
+            AdcMode::Quad => rate,
+        }
+    }
+
+    /// The maximum per-channel sample rate of this mode in MHz
+    #[must_use]
+    pub fn max_sample_rate(self) -> f64 {
+        match self {
+            AdcMode::Single => 1000.,
+            AdcMode::Dual => 500.,
+            AdcMode::Quad => 250.,
+        }
+    }
+
+    /// Check that every channel can be sampled at `rate` MHz in this mode
+    /// # Errors
+    /// Returns an error if the rate isn't positive or is above the limit of this mode
+    pub fn validate_sample_rate(self, rate: f64) -> Result<(), Error> {
+        if rate.is_nan() || rate <= 0. {
+            return Err(Error::BadSampleRate);
+        }
+        let max = self.max_sample_rate();
+        if rate > max {
+            return Err(Error::SampleRateTooHigh {
+                mode: self,
+                rate,
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
 /// The HMCAD1511 ADCs on the SNAP platform
 #[derive(Debug)]
 pub struct SnapAdc<T> {
     /// Upwards pointer to the parent class' transport
-    transport: Weak<Mutex<T>>,
-    /// Sample rate in MHz
+    transport: TransportHandle<T>,
+    /// Sample rate of each channel in MHz
     pub sample_rate: f64,
     /// Channel mode for each chip
     pub mode: AdcMode,
@@ -72,21 +166,24 @@ pub struct SnapAdc<T> {
     pub synth: Synth<T>,
     /// ADC Controller
     pub controller: Adc16<T>,
+    /// The fraction the sample rate inferred from the FPGA clock can be off by before
+    /// initializing with an external clock fails, see [`SnapAdc::check_clock`]
+    pub clock_tolerance: f64,
     /// Register name
-    _name: String,
+    name: String,
+    /// The last fine gains written to each chip by [`SnapAdc::correct_mismatch`]
+    fine_gains: [[i8; 8]; 3],
 }
 
 impl<T> SnapAdc<T>
 where
     T: Transport,
 {
-    const RAM0_NAME: &'static str = "adc16_wb_ram0";
-    const RAM1_NAME: &'static str = "adc16_wb_ram1";
-    const RAM2_NAME: &'static str = "adc16_wb_ram2";
+    const NAMESPACE: &'static str = "adc16";
 
     /// Builds a [`SnapAdc`] from FPG description strings
     /// # Errors
-    /// Returns an error on bad string arguments
+    /// Returns an error on bad string arguments or a sample rate the mode can't support
     pub fn from_fpg(
         transport: Weak<Mutex<T>>,
         reg_name: &str,
@@ -111,43 +208,206 @@ where
             "sys_clk" => Source::Internal,
             _ => Source::External,
         };
+        let sample_rate = sample_rate.parse().map_err(|_| Error::BadSampleRate)?;
+        mode.validate_sample_rate(sample_rate)?;
         Ok(Self {
-            transport,
-            sample_rate: sample_rate.parse().map_err(|_| Error::BadSampleRate)?,
+            transport: TransportHandle::new(transport, reg_name),
+            sample_rate,
             mode,
             clksw,
             synth,
             controller,
-            _name: reg_name.to_string(),
+            clock_tolerance: DEFAULT_CLOCK_TOLERANCE,
+            name: reg_name.to_string(),
             source,
+            fine_gains: [[0; 8]; 3],
         })
     }
 
+    /// The sample rate of each channel in MHz
+    #[must_use]
+    pub fn channel_sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// The Nyquist frequency of each channel in MHz
+    #[must_use]
+    pub fn nyquist(&self) -> f64 {
+        self.sample_rate / 2.
+    }
+
+    /// The number of channels across all three chips
+    #[must_use]
+    pub fn num_channels(&self) -> usize {
+        3 * self.mode.channels()
+    }
+
     /// Request a snapshot of `chip`
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn snapshot(&self, chip: SnapAdcChip) -> Result<[u8; 1024], Error> {
         // Request the snapshot
         self.controller.snap_req()?;
         // Then read the BRAM
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(transport.read_bytes(
-            match chip {
-                SnapAdcChip::A => Self::RAM0_NAME,
-                SnapAdcChip::B => Self::RAM1_NAME,
-                SnapAdcChip::C => Self::RAM2_NAME,
-            },
-            0,
-        )?)
+        self.transport.with_transport(|transport| {
+            let ram = match chip {
+                SnapAdcChip::A => "wb_ram0",
+                SnapAdcChip::B => "wb_ram1",
+                SnapAdcChip::C => "wb_ram2",
+            };
+            Ok(transport.read_bytes(&RegisterNamespace::new(Self::NAMESPACE).reg(ram), 0)?)
+        })
     }
 
-    /// Initializes the ADCs - follow this up by setting the controller crossbar and calibrating
+    /// Request a snapshot of `chip` and split it into the sample codes of each input of the chip,
+    /// see [`demux_snapshot`]
+    /// # Errors
+    /// Returns an error on bad transport or if the FPGA demux doesn't match the mode
+    pub fn snapshot_channels(&self, chip: SnapAdcChip) -> Result<Vec<Vec<i8>>, Error> {
+        let raw = self.snapshot(chip)?;
+        demux_snapshot(&raw, self.mode, self.controller.get_demux()?)
+    }
+
+    /// Measure the offset and gain of each core of `chip`, pooled over `snapshots` snapshots
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
+    pub fn measure_mismatch(
+        &self,
+        chip: SnapAdcChip,
+        snapshots: usize,
+    ) -> Result<MismatchReport, Error> {
+        let raws = (0..snapshots.max(1))
+            .map(|_| self.snapshot(chip))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MismatchReport::measure(
+            raws.iter().map(<[u8; 1024]>::as_slice),
+        ))
+    }
+
+    /// Measure the core mismatch of `chip` (see [`mismatch`]) and correct it, matching the gains
+    /// of the cores with their fine gains and, if the design has an `offsets` register of signed
+    /// 32-bit words (four per chip, see [`mismatch::offset_coefficients`]), cancelling their
+    /// offsets in the FPGA. Every measurement is pooled over `snapshots` snapshots. Corrections
+    /// accumulate, so running this again refines the last one.
+    /// # Errors
+    /// Returns an error on bad transport, if a core saw no signal, or if the gain correction is out
+    /// of the range of the fine gains
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "snap_adc::correct_mismatch",
+            skip_all,
+            fields(device = %self.name),
+            err,
+        )
+    )]
+    pub fn correct_mismatch(
+        &mut self,
+        chip: SnapAdcChip,
+        snapshots: usize,
+        offsets: Option<&str>,
+    ) -> Result<MismatchCorrection, Error> {
+        let before = self.measure_mismatch(chip, snapshots)?;
+        let idx = chip as usize;
+        let fine_gains = mismatch::fine_gain_correction(&before, self.fine_gains[idx])?;
+        self.controller.chip_select(&chip.chip_select());
+        let written = self.controller.set_fine_gains(fine_gains);
+        self.controller.chip_select(&ChipSelect::select_all());
+        written?;
+        self.fine_gains[idx] = fine_gains;
+        let offsets = offsets
+            .map(|register| self.correct_offsets(register, idx, &before))
+            .transpose()?;
+        let after = self.measure_mismatch(chip, snapshots)?;
+        Ok(MismatchCorrection {
+            before,
+            after,
+            fine_gains,
+            offsets,
+        })
+    }
+
+    /// Add the coefficients that cancel the offsets of `report` to the ones of chip `idx` in the
+    /// `register` of FPGA offset coefficients, returning the new coefficients
+    fn correct_offsets(
+        &self,
+        register: &str,
+        idx: usize,
+        report: &MismatchReport,
+    ) -> Result<[i32; mismatch::CORES], Error> {
+        self.transport.with_transport(|transport| {
+            // The offsets already in place were part of the measurement, so add to them
+            let old = transport.read_n_bytes(register, 16 * idx, 4 * mismatch::CORES)?;
+            let mut coefficients = mismatch::offset_coefficients(report);
+            for (coefficient, old) in coefficients.iter_mut().zip(old.chunks_exact(4)) {
+                let old = i32::from_be_bytes(old.try_into().expect("Chunks are four bytes"));
+                *coefficient = coefficient.saturating_add(old);
+            }
+            let bytes: Vec<u8> = coefficients.iter().flat_map(|c| c.to_be_bytes()).collect();
+            transport.write_bytes(register, 16 * idx, &bytes)?;
+            Ok(coefficients)
+        })
+    }
+
+    /// Check that the ADCs are sampling at the design's sample rate. The ADCs drive the FPGA clock,
+    /// so the sample rate is inferred from an estimate of it (see [`estimate_fpga_clock`], which
+    /// takes a couple of seconds) once the controller reports it's locked to the ADC clock.
+    /// Returns the inferred sample rate of each channel in MHz.
+    /// # Errors
+    /// Returns an error on bad transport, if the controller isn't locked, or if the inferred rate
+    /// is off from the design's by more than [`SnapAdc::clock_tolerance`]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "snap_adc::check_clock",
+            skip_all,
+            fields(device = %self.name),
+            err,
+        )
+    )]
+    pub fn check_clock(&self) -> Result<f64, Error> {
+        if !self.controller.locked()? {
+            return Err(Error::NotLocked);
+        }
+        let fpga_clock = self.transport.with_transport(estimate_fpga_clock)?;
+        let measured = self.sample_rate * fpga_clock / self.mode.fpga_clock(self.sample_rate);
+        if (measured - self.sample_rate).abs() > self.clock_tolerance * self.sample_rate {
+            return Err(Error::ClockMismatch {
+                expected: self.sample_rate,
+                measured,
+            });
+        }
+        Ok(measured)
+    }
+
+    /// Initializes the ADCs - follow this up by setting the controller crossbar and calibrating
+    /// # Errors
+    /// Returns an error on bad transport, if the sample rate is invalid for the mode, or if an
+    /// external clock doesn't match it (see [`SnapAdc::check_clock`])
     pub fn initialize(&mut self) -> Result<(), Error> {
+        self.initialize_cancellable(&CancelToken::new())
+    }
+
+    /// Initializes the ADCs like [`SnapAdc::initialize`], checking `cancel` between each step. A
+    /// cancelled initialization leaves the ADCs part way configured, so initialize again before
+    /// using them.
+    /// # Errors
+    /// Returns [`crate::transport::Error::Cancelled`] (wrapped in [`Error::Transport`]) if
+    /// cancelled, errors on bad transport, if the sample rate is invalid for the mode, or if an
+    /// external clock doesn't match it
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "snap_adc::initialize",
+            skip_all,
+            fields(device = %self.name),
+            err,
+        )
+    )]
+    pub fn initialize_cancellable(&mut self, cancel: &CancelToken) -> Result<(), Error> {
+        // The mode and rate are public, so check them again before touching the hardware
+        self.mode.validate_sample_rate(self.sample_rate)?;
+        cancel.check()?;
         // Start off with a reset
         self.controller.reset()?;
         // Chip select all the ADCs in the SNAP
@@ -158,8 +418,10 @@ where
         if self.source == Source::Internal {
             todo!()
         }
+        cancel.check()?;
         // Initialize the ADCs (this does a reset, power cycles, and sets the modes)
         self.controller.init(self.mode, self.sample_rate)?;
+        cancel.check()?;
         // Set the termination and drive strength on two out of the three ADCs as the clock is only
         // sourced from adc0
         self.controller.chip_select(&ChipSelect {
@@ -181,9 +443,15 @@ where
         )?;
         // And back to select all
         self.controller.chip_select(&ChipSelect::select_all());
+        // Nothing else checks what's plugged into the external clock input
+        if self.source == Source::External {
+            cancel.check()?;
+            self.check_clock()?;
+        }
 
         // Calibrate here maybe?
 
+        cancel.check()?;
         // Setup the FPGA-side demux
         self.controller.set_demux(match self.mode {
             AdcMode::Single => controller::DemuxMode::SingleChannel,
@@ -198,18 +466,63 @@ where
     /// Returns an error on bad transport
     /// # Panics
     /// Panics if the given input selection does not match the current mode
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "snap_adc::select_inputs",
+            skip_all,
+            fields(device = %self.name),
+            err,
+        )
+    )]
     pub fn select_inputs(&self, inputs: ChannelInput) -> Result<(), Error> {
         // Extract channel mode and assert
         match self.mode {
             AdcMode::Single => assert!(matches!(inputs, ChannelInput::Single(_))),
             AdcMode::Dual => assert!(matches!(inputs, ChannelInput::Dual(_, _))),
             AdcMode::Quad => assert!(matches!(inputs, ChannelInput::Quad(_, _, _, _))),
-        };
+        }
         // Then set
         Ok(self.controller.input_select(inputs)?)
     }
 }
 
+/// Split the raw snapshot RAM of one chip into the sample codes of each of its inputs, in the
+/// order of [`ChannelInput`].
+///
+/// With the FPGA demux set to match the ADC mode, every 32-bit word of the RAM holds one byte from
+/// each of the four ADC cores. Quad mode gives each input its own core, dual mode interleaves cores
+/// 1 and 2 for the first input and 3 and 4 for the second, and single mode interleaves all four,
+/// so consecutive bytes of an input's cores are consecutive samples. `demux` is the setting from
+/// [`Adc16::get_demux`], where `None` (gateware without demux support) is taken to match.
+/// # Errors
+/// Returns [`Error::DemuxMismatch`] if the demux doesn't match the mode, as the data can't be
+/// interpreted then
+pub fn demux_snapshot(
+    raw: &[u8],
+    mode: AdcMode,
+    demux: Option<DemuxMode>,
+) -> Result<Vec<Vec<i8>>, Error> {
+    let expected = match mode {
+        AdcMode::Single => DemuxMode::SingleChannel,
+        AdcMode::Dual => DemuxMode::DualChannel,
+        AdcMode::Quad => DemuxMode::QuadChannel,
+    };
+    if let Some(demux) = demux.filter(|&d| d != expected) {
+        return Err(Error::DemuxMismatch { mode, demux });
+    }
+    let channels = mode.channels();
+    // The number of consecutive cores (bytes of each word) that belong to the same input
+    let cores = 4 / channels;
+    let mut inputs = vec![Vec::with_capacity(raw.len() / channels); channels];
+    for word in raw.chunks_exact(4) {
+        for (input, samples) in word.chunks_exact(cores).zip(&mut inputs) {
+            samples.extend(input.iter().map(|&b| i8::from_be_bytes([b])));
+        }
+    }
+    Ok(inputs)
+}
+
 #[derive(Debug, Copy, Clone)]
 /// Enumerates the three ADC chips on the SNAP platform
 pub enum SnapAdcChip {
@@ -217,3 +530,262 @@ pub enum SnapAdcChip {
     B = 1,
     C = 2,
 }
+
+impl SnapAdcChip {
+    /// The chip select of just this chip
+    fn chip_select(self) -> ChipSelect {
+        ChipSelect {
+            a: matches!(self, Self::A),
+            b: matches!(self, Self::B),
+            c: matches!(self, Self::C),
+            ..Default::default()
+        }
+    }
+}
+
+impl<T> FromFpg<T> for SnapAdc<T>
+where
+    T: Transport,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        let meta = |key| metadata_entry(metadata, name, key);
+        Ok(Self::from_fpg(
+            transport,
+            name,
+            meta("adc_resolution")?,
+            meta("sample_rate")?,
+            meta("snap_inputs")?,
+            meta("clk_src")?,
+        )?)
+    }
+}
+
+impl<T> YellowBlock<T> for SnapAdc<T>
+where
+    T: Transport + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        // The clock source comes from the platform block
+        let mut metadata = device(devices, name)?.metadata.clone();
+        metadata.insert(
+            "clk_src".into(),
+            Xsg::from_devices(devices)?.clock_source().to_string(),
+        );
+        Self::from_metadata(transport, name, &metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:snap_adc"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "xps:snap_adc `{}` ({:?} at {} MHz, {:?} clock)",
+            self.name, self.mode, self.sample_rate, self.source
+        )
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::{
+            mock::Mock,
+            sim::SimulatedFpga,
+        },
+    };
+    use casper_utils::design_sources::fpg::read_fpg_file;
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
+
+    #[test]
+    fn test_sample_rate_limits() {
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::new())));
+        let build = |rate, inputs| {
+            SnapAdc::from_fpg(
+                Arc::downgrade(&transport),
+                "snap_adc",
+                "8",
+                rate,
+                inputs,
+                "adc",
+            )
+        };
+        let adc = build("500", "6").unwrap();
+        assert!((adc.channel_sample_rate() - 500.).abs() < f64::EPSILON);
+        assert!((adc.nyquist() - 250.).abs() < f64::EPSILON);
+        assert_eq!(adc.num_channels(), 6);
+        assert!(build("1000", "3").is_ok());
+        assert!(matches!(
+            build("500", "12"),
+            Err(Error::SampleRateTooHigh {
+                mode: AdcMode::Quad,
+                ..
+            })
+        ));
+        assert!(matches!(build("-1", "3"), Err(Error::BadSampleRate)));
+        assert!(matches!(build("NaN", "3"), Err(Error::BadSampleRate)));
+
+        // Changing the rate after construction is caught at init
+        let mut adc = build("250", "12").unwrap();
+        adc.sample_rate = 300.;
+        assert!(matches!(
+            adc.initialize(),
+            Err(Error::SampleRateTooHigh { .. })
+        ));
+    }
+
+    #[test]
+    fn test_demux_snapshot() {
+        // Bytes count up so we can tell where each sample came from
+        let raw: Vec<u8> = (0..16).collect();
+        let quad = demux_snapshot(&raw, AdcMode::Quad, Some(DemuxMode::QuadChannel)).unwrap();
+        assert_eq!(quad.len(), 4);
+        assert_eq!(quad[1], vec![1, 5, 9, 13]);
+        let dual = demux_snapshot(&raw, AdcMode::Dual, None).unwrap();
+        assert_eq!(dual[0], vec![0, 1, 4, 5, 8, 9, 12, 13]);
+        assert_eq!(dual[1], vec![2, 3, 6, 7, 10, 11, 14, 15]);
+        let single = demux_snapshot(&raw, AdcMode::Single, Some(DemuxMode::SingleChannel)).unwrap();
+        assert_eq!(single, vec![(0..16).collect::<Vec<i8>>()]);
+        // Codes are two's complement
+        let codes = demux_snapshot(&[0x80, 0xFF, 0x7F, 0], AdcMode::Quad, None).unwrap();
+        assert_eq!(codes, vec![vec![-128], vec![-1], vec![127], vec![0]]);
+        assert!(matches!(
+            demux_snapshot(&raw, AdcMode::Quad, Some(DemuxMode::SingleChannel)),
+            Err(Error::DemuxMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_correct_mismatch() {
+        let register = |addr, length| Register { addr, length };
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::from([
+            ("adc16_controller".into(), register(0, 0x100)),
+            ("adc16_wb_ram1".into(), register(0x100, 1024)),
+            ("adc_offsets".into(), register(0x500, 48)),
+        ]))));
+        // Core 1 of chip B reads a code high
+        let raw: Vec<u8> = (0..1024)
+            .map(|i: usize| {
+                let code: i8 = if i % 8 < 4 { 50 } else { -50 };
+                (code + i8::from(i % 4 == 1)).to_be_bytes()[0]
+            })
+            .collect();
+        transport
+            .lock()
+            .unwrap()
+            .write_bytes("adc16_wb_ram1", 0, &raw)
+            .unwrap();
+        transport
+            .lock()
+            .unwrap()
+            .write_bytes("adc_offsets", 20, &(-256i32).to_be_bytes())
+            .unwrap();
+        let mut adc = SnapAdc::from_fpg(
+            Arc::downgrade(&transport),
+            "snap_adc",
+            "8",
+            "500",
+            "6",
+            "adc",
+        )
+        .unwrap();
+        let fixed = adc
+            .correct_mismatch(SnapAdcChip::B, 2, Some("adc_offsets"))
+            .unwrap();
+        assert!((fixed.before.offset_spread() - 1.).abs() < 1e-9);
+        assert_eq!(fixed.before.cores[1].samples, 512);
+        assert_eq!(fixed.fine_gains, [0; 8]);
+        // The existing coefficient is kept and corrected further
+        assert_eq!(fixed.offsets, Some([0, -512, 0, 0]));
+        let offsets = transport
+            .lock()
+            .unwrap()
+            .read_n_bytes("adc_offsets", 16, 16)
+            .unwrap();
+        assert_eq!(&offsets[4..8], &(-512i32).to_be_bytes());
+        assert!(matches!(
+            adc.correct_mismatch(SnapAdcChip::A, 1, None),
+            Err(Error::Transport(_))
+        ));
+    }
+
+    #[test]
+    fn test_initialize_cancelled() {
+        let transport = Arc::new(Mutex::new(Mock::new(HashMap::new())));
+        let mut adc = SnapAdc::from_fpg(
+            Arc::downgrade(&transport),
+            "snap_adc",
+            "8",
+            "500",
+            "6",
+            "adc",
+        )
+        .unwrap();
+        let cancel = CancelToken::new();
+        let supervisor = cancel.clone();
+        supervisor.cancel();
+        assert!(cancel.is_cancelled());
+        // Nothing is touched once cancelled (the mock has no registers, so any access would fail)
+        assert!(matches!(
+            adc.initialize_cancellable(&cancel),
+            Err(Error::Transport(crate::transport::Error::Cancelled))
+        ));
+    }
+
+    #[test]
+    fn test_check_clock() {
+        assert!((AdcMode::Dual.fpga_clock(500.) - 250.).abs() < f64::EPSILON);
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        // The ADC16 controller only reports lock once something's plugged in
+        let adc = |clock_mhz, locked: bool| {
+            let mut sim = SimulatedFpga::new(&design).with_clock_rate(clock_mhz);
+            sim.on_read("adc16_controller", move |mem, _, _| {
+                let mut word: u32 = mem.read("adc16_controller", 0)?;
+                word = (word & !(0b11 << 24)) | (u32::from(locked) * (0b11 << 24)) | (0x30 << 16);
+                mem.write("adc16_controller", 0, &word)
+            });
+            let transport = Arc::new(Mutex::new(sim));
+            let adc = SnapAdc::from_device(Arc::downgrade(&transport), "snap_adc", &design.devices)
+                .unwrap();
+            (transport, adc)
+        };
+
+        let (_transport, mut unplugged) = adc(250., false);
+        assert_eq!(unplugged.source, Source::External);
+        assert!(matches!(unplugged.initialize(), Err(Error::NotLocked)));
+
+        // The design samples each of its two channels per chip at 500 MHz
+        let (_transport, good) = adc(250., true);
+        assert!((good.check_clock().unwrap() - 500.).abs() < 5.);
+
+        let (_transport, bad) = adc(200., true);
+        match bad.check_clock() {
+            Err(Error::ClockMismatch { expected, measured }) => {
+                assert!((expected - 500.).abs() < f64::EPSILON);
+                assert!((measured - 400.).abs() < 4.);
+            }
+            r => panic!("Expected a clock mismatch, got {r:?}"),
+        }
+    }
+}
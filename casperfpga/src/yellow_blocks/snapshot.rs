@@ -1,20 +1,37 @@
 //! TODO - support bitsnap, integrate with bram lib
 
-use crate::transport::{
-    Deserialize,
-    Serialize,
-    Transport,
+use crate::{
+    core::RegisterNamespace,
+    transport::{
+        Deserialize,
+        Serialize,
+        Transport,
+    },
+    yellow_blocks::{
+        device,
+        metadata_entry,
+        FromFpg,
+        Metadata,
+        TransportHandle,
+        YellowBlock,
+    },
 };
+use casper_utils::design_sources::Devices;
 use casperfpga_derive::CasperSerde;
 use num_traits::Unsigned;
 use packed_struct::prelude::*;
 use std::{
+    any::Any,
     marker::PhantomData,
     sync::{
         Arc,
         Mutex,
         Weak,
     },
+    time::{
+        Duration,
+        Instant,
+    },
 };
 use thiserror::Error;
 
@@ -24,17 +41,44 @@ pub enum Error {
     Transport(#[from] crate::transport::Error),
     #[error("Failed to parse number of samples from fpg file")]
     BadSampleN,
+    #[error("Failed to parse the offset option from fpg file")]
+    BadOffset,
     #[error("The snapshot block that we tried to set an offset on didn't support offsets")]
     NoOffsets,
+    #[error("The snapshot capture didn't finish within {0:?}")]
+    CaptureTimeout(Duration),
+}
+
+/// How often [`Snapshot::capture`] polls the status register
+const CAPTURE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What starts a capture once the snapshot block is armed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TriggerSource {
+    /// The trigger input of the block in the design
+    #[default]
+    External,
+    /// Trigger from software as soon as the block is armed
+    Software,
+}
+
+/// Which samples are written once a capture starts
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum WriteEnable {
+    /// Only samples with the write enable input of the block in the design set
+    #[default]
+    External,
+    /// Every sample, ignoring the write enable input
+    Always,
 }
 
 /// The snapshot yellow block to capture a chunk of samples
 #[derive(Debug)]
 pub struct Snapshot<T, F> {
     /// Upwards pointer to the parent class' transport
-    transport: Weak<Mutex<T>>,
-    /// The name of the register
-    name: String,
+    transport: TransportHandle<T>,
+    /// The namespace of the block's registers
+    ns: RegisterNamespace,
     /// Marker for the integer type of the data type
     phantom: PhantomData<F>,
     /// Flag for whether this snapshot block has separate "offset" control
@@ -45,6 +89,10 @@ pub struct Snapshot<T, F> {
 
 #[derive(Debug, PackedStruct, Default, Copy, Clone, CasperSerde)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+#[casper_serde(golden(
+    value = "Control { arm: true, trig_override: true, ..Default::default() }",
+    bytes = "[0, 0, 0, 0x03]"
+))]
 #[allow(clippy::struct_excessive_bools)]
 pub struct Control {
     #[packed_field(bits = "0")]
@@ -59,6 +107,7 @@ pub struct Control {
 
 #[derive(Debug, PackedStruct, Default, Copy, Clone, CasperSerde)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+#[casper_serde(golden(value = "Status { addr: 5, done: true }", bytes = "[0x80, 0, 0, 0x05]"))]
 pub struct Status {
     #[packed_field(bits = "0..31", endian = "msb")]
     addr: u32,
@@ -80,8 +129,8 @@ where
     ) -> Self {
         let transport = Arc::downgrade(transport);
         Self {
-            transport,
-            name: reg_name.to_string(),
+            transport: TransportHandle::new(transport, reg_name),
+            ns: RegisterNamespace::new(reg_name),
             phantom: PhantomData,
             has_offset,
             samples_n,
@@ -101,11 +150,11 @@ where
         let has_offset = match offset {
             "off" => false,
             "on" => true,
-            _ => unreachable!(),
+            _ => return Err(Error::BadOffset),
         };
         Ok(Self {
-            transport,
-            name: reg_name.to_string(),
+            transport: TransportHandle::new(transport, reg_name),
+            ns: RegisterNamespace::new(reg_name),
             phantom: PhantomData,
             has_offset,
             samples_n,
@@ -115,16 +164,79 @@ where
     /// Arm the snapshot block so that the next trigger starts capture
     /// # Errors
     /// Returns an error on transport errors
-    #[allow(clippy::missing_panics_doc)]
     pub fn arm(&self) -> Result<(), Error> {
-        let control_reg = format!("{}_ctrl", self.name);
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let mut ctrl = Control::default();
-        transport.write(&control_reg, 0, &ctrl)?;
-        ctrl.arm = true;
-        transport.write(&control_reg, 0, &ctrl)?;
-        Ok(())
+        self.arm_with(TriggerSource::External, WriteEnable::External)
+    }
+
+    /// Arm the snapshot block to capture on `trigger`, writing the samples selected by
+    /// `write_enable`. With a [`TriggerSource::Software`] trigger, capture starts right away.
+    /// # Errors
+    /// Returns an error on transport errors
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "snapshot::arm",
+            skip_all,
+            fields(device = %self.ns.prefix()),
+            err,
+        )
+    )]
+    pub fn arm_with(&self, trigger: TriggerSource, write_enable: WriteEnable) -> Result<(), Error> {
+        let control_reg = self.ns.reg("ctrl");
+        self.transport.with_transport(|transport| {
+            // The block arms on the rising edge, so write the overrides first
+            let mut ctrl = Control {
+                trig_override: trigger == TriggerSource::Software,
+                write_enable_override: write_enable == WriteEnable::Always,
+                ..Default::default()
+            };
+            transport.write(&control_reg, 0, &ctrl)?;
+            ctrl.arm = true;
+            transport.write(&control_reg, 0, &ctrl)?;
+            Ok(())
+        })
+    }
+
+    /// Whether the last armed capture has finished
+    /// # Errors
+    /// Returns an error on transport errors
+    pub fn done(&self) -> Result<bool, Error> {
+        self.transport.with_transport(|transport| {
+            let status: Status = transport.read(&self.ns.reg("status"), 0)?;
+            Ok(status.done)
+        })
+    }
+
+    /// Arm with [`Snapshot::arm_with`], wait up to `timeout` for the capture to finish, and read
+    /// the captured data
+    /// # Errors
+    /// Returns an error on transport errors or [`Error::CaptureTimeout`] if the capture never
+    /// finished (i.e. the external trigger never came)
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "snapshot::capture",
+            skip_all,
+            fields(device = %self.ns.prefix()),
+            err,
+        )
+    )]
+    pub fn capture(
+        &self,
+        trigger: TriggerSource,
+        write_enable: WriteEnable,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Error> {
+        self.arm_with(trigger, write_enable)?;
+        let start = Instant::now();
+        // The transport lock is released between polls
+        while !self.done()? {
+            if start.elapsed() >= timeout {
+                return Err(Error::CaptureTimeout(timeout));
+            }
+            std::thread::sleep(CAPTURE_POLL_INTERVAL);
+        }
+        self.read()
     }
 
     /// Read the data from the snapshot block.
@@ -134,45 +246,151 @@ where
     /// Returns an error on transport errors
     #[allow(clippy::missing_panics_doc)]
     pub fn read(&self) -> Result<Vec<u8>, Error> {
-        let status_reg = format!("{}_status", self.name);
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let _status: Status = transport.read(&status_reg, 0)?;
-        // FIXME
-        let bram_reg = format!("{}_bram", self.name);
-        let bytes =
-            transport.read_n_bytes(&bram_reg, 0, 2u32.pow(self.samples_n).try_into().unwrap())?;
-        // There's a way to reinterpret this inplace...somehow
-        Ok(bytes)
+        let status_reg = self.ns.reg("status");
+        self.transport.with_transport(|transport| {
+            let _status: Status = transport.read(&status_reg, 0)?;
+            // FIXME
+            let bram_reg = self.ns.reg("bram");
+            let bytes = transport.read_n_bytes(
+                &bram_reg,
+                0,
+                2u32.pow(self.samples_n).try_into().unwrap(),
+            )?;
+            // There's a way to reinterpret this inplace...somehow
+            Ok(bytes)
+        })
     }
 
     /// Force a trigger
     /// # Errors
     /// Returns an error on transport errors
-    #[allow(clippy::missing_panics_doc)]
     pub fn trigger(&self) -> Result<(), Error> {
-        let control_reg = format!("{}_ctrl", self.name);
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let mut ctrl: Control = transport.read(&control_reg, 0)?;
-        ctrl.trig_override = true;
-        transport.write(&control_reg, 0, &ctrl)?;
-        Ok(())
+        let control_reg = self.ns.reg("ctrl");
+        self.transport.with_transport(|transport| {
+            let mut ctrl: Control = transport.read(&control_reg, 0)?;
+            ctrl.trig_override = true;
+            transport.write(&control_reg, 0, &ctrl)?;
+            Ok(())
+        })
     }
 
     /// Set the capture trigger offset
     /// # Errors
     /// Returns an error on transport errors and when the snapshot block doesn't support offsets
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_offset(&self, offset: u32) -> Result<(), Error> {
         if self.has_offset {
-            let offset_reg = format!("{}_trig_offset", self.name);
-            let tarc = self.transport.upgrade().unwrap();
-            let mut transport = (*tarc).lock().unwrap();
-            transport.write(&offset_reg, 0, &offset)?;
+            let offset_reg = self.ns.reg("trig_offset");
+            self.transport
+                .with_transport(|transport| Ok(transport.write(&offset_reg, 0, &offset)?))
         } else {
-            return Err(Error::NoOffsets);
+            Err(Error::NoOffsets)
         }
-        Ok(())
+    }
+}
+
+impl<T, F> FromFpg<T> for Snapshot<T, F>
+where
+    T: Transport,
+    F: Unsigned,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        let meta = |key| metadata_entry(metadata, name, key);
+        Ok(Self::from_fpg(
+            transport,
+            name,
+            meta("nsamples")?,
+            meta("offset")?,
+        )?)
+    }
+}
+
+impl<T, F> YellowBlock<T> for Snapshot<T, F>
+where
+    T: Transport + 'static,
+    F: Unsigned + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_metadata(transport, name, &device(devices, name)?.metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "casper:snapshot"
+    }
+
+    fn name(&self) -> &str {
+        self.ns.prefix()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "casper:snapshot `{}` (2^{} samples of {} bits, offset {})",
+            self.ns,
+            self.samples_n,
+            std::mem::size_of::<F>() * 8,
+            if self.has_offset { "on" } else { "off" }
+        )
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::sim::SimulatedFpga;
+    use casper_utils::design_sources::fpg::read_fpg_file;
+
+    #[test]
+    fn test_capture() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        // A fake snapshot that finishes as soon as it's armed with a software trigger, with every
+        // sample written
+        sim.on_write("adc_snap_ctrl", |mem, _, data| {
+            let ctrl = data[3];
+            if ctrl & 0b111 == 0b111 {
+                mem.write_bytes("adc_snap_bram", 0, &[1, 2, 3, 4])?;
+                mem.write_bytes("adc_snap_status", 0, &[0x80, 0, 0x10, 0])?;
+            } else {
+                mem.write_bytes("adc_snap_status", 0, &[0; 4])?;
+            }
+            Ok(())
+        });
+        let transport = Arc::new(Mutex::new(sim));
+        let snap =
+            Snapshot::<_, u32>::from_fpg(Arc::downgrade(&transport), "adc_snap", "12", "off")
+                .unwrap();
+
+        // Waiting for an external trigger that never comes times out
+        assert!(matches!(
+            snap.capture(
+                TriggerSource::External,
+                WriteEnable::Always,
+                Duration::from_millis(5)
+            ),
+            Err(Error::CaptureTimeout(_))
+        ));
+        assert!(!snap.done().unwrap());
+
+        let data = snap
+            .capture(
+                TriggerSource::Software,
+                WriteEnable::Always,
+                Duration::from_secs(1),
+            )
+            .unwrap();
+        assert_eq!(data.len(), 4096);
+        assert_eq!(&data[..4], &[1, 2, 3, 4]);
+        assert!(snap.done().unwrap());
     }
 }
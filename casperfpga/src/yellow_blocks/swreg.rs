@@ -6,18 +6,38 @@
 //!
 //! There are two unique types for this register, signed fixed point ([`FixedSoftwareRegister`]) and
 //! boolean ([`BooleanSoftwareRegister`]). Both of these types will have read
-//! and write methods, bailing on write if [Direction] isn't [`Direction::FromProcessor`].
+//! and write methods, bailing on write if [Direction] isn't [`Direction::FromProcessor`]. The
+//! structs generated from fpg files wrap read-only registers in [`ReadOnly`], which has no write
+//! methods at all, so writing to one is a compile error instead.
+//!
+//! Some designs have registers wider than one word, where the fpg register is an array of 32-bit
+//! words of the same type. These are [`ArraySoftwareRegister`]s, read and written a word at a time
+//! by index (or all at once), with the index checked against the size of the register.
 //!
 //! Interactions with this block require the use of types from the [fixed](https://docs.rs/fixed/latest/fixed/) crate,
 //! and are currently a little clunky as that crate hasn't fully updated to use const-generics for
-//! the binary point. This will improve once those features arrive in rust stable.
+//! the binary point. This will improve once those features arrive in rust stable. When the exact
+//! LSBs don't matter, [`FixedSoftwareRegister::read_f64`] and [`FixedSoftwareRegister::write_f64`]
+//! convert to and from plain floats, range checking against the register's declared bitwidth.
 //!
 //! ## Toolflow Documentation
 //! <https://casper-toolflow.readthedocs.io/en/latest/src/blockdocs/Software_register.html>
 
-use crate::transport::Transport;
+use crate::{
+    transport::Transport,
+    yellow_blocks::{
+        device,
+        metadata_entry,
+        FromFpg,
+        Metadata,
+        TransportHandle,
+        YellowBlock,
+    },
+};
+use casper_utils::design_sources::Devices;
 use fixed::traits::Fixed;
 use std::{
+    any::Any,
     marker::PhantomData,
     sync::{
         Arc,
@@ -37,8 +57,32 @@ pub enum Error {
     BadDirection,
     #[error("Failed to parse the bitwidth field from the fpg file")]
     BadBitwidth,
+    #[error("The binary point from the fpg file ({0}) doesn't match the fixed point type")]
+    BadBinPt(String),
     #[error("The number we tried to write doesn't fit in the destination")]
     Overflow,
+    #[error("Can't write a NaN or infinite value to a fixed point register")]
+    NotFinite,
+    #[error("Index {index} is out of bounds for a register of {len} words")]
+    OutOfBounds { index: usize, len: usize },
+    #[error("Got {got} values for a register of {expected} words")]
+    BadLength { got: usize, expected: usize },
+    #[error("Bad register size `{0}`, expected a whole number of words")]
+    BadSize(String),
+}
+
+/// How to round a floating point number onto the LSBs of a fixed point register
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// Round to the nearest representable value, ties away from zero
+    #[default]
+    Nearest,
+    /// Round towards negative infinity
+    Floor,
+    /// Round towards positive infinity
+    Ceil,
+    /// Round towards zero
+    Truncate,
 }
 
 /// The IO direction of this register
@@ -54,11 +98,33 @@ pub enum Direction {
 #[derive(Debug)]
 pub struct FixedSoftwareRegister<T, F> {
     /// Upwards pointer to the parent class' transport
-    transport: Weak<Mutex<T>>,
+    transport: TransportHandle<T>,
     /// IO direction of this register
     direction: Direction,
     /// Number of bits
     width: usize,
+    /// Number of fractional bits, always that of `F`
+    bin_pt: u32,
+    /// The name of the register
+    name: String,
+    /// Marker for the fixed point type
+    phantom: PhantomData<F>,
+}
+
+/// The unidirectional software register yellow block wider than one word, an array of 32-bit fixed
+/// point words
+#[derive(Debug)]
+pub struct ArraySoftwareRegister<T, F> {
+    /// Upwards pointer to the parent class' transport
+    transport: TransportHandle<T>,
+    /// IO direction of this register
+    direction: Direction,
+    /// Number of bits of each word
+    width: usize,
+    /// Number of fractional bits, always that of `F`
+    bin_pt: u32,
+    /// Number of words
+    len: usize,
     /// The name of the register
     name: String,
     /// Marker for the fixed point type
@@ -69,13 +135,65 @@ pub struct FixedSoftwareRegister<T, F> {
 #[derive(Debug)]
 pub struct BooleanSoftwareRegister<T> {
     /// Upwards pointer to the parent class' transport
-    transport: Weak<Mutex<T>>,
+    transport: TransportHandle<T>,
     /// IO direction of this register
     direction: Direction,
     /// The name of the register
     name: String,
 }
 
+fn parse_direction(io_dir: &str) -> Result<Direction, Error> {
+    match io_dir {
+        "To\\_Processor" => Ok(Direction::ToProcessor),
+        "From\\_Processor" => Ok(Direction::FromProcessor),
+        _ => Err(Error::BadDirection),
+    }
+}
+
+/// The range of the raw integer of a `width` bit register of `F`, in LSBs
+fn lsb_range<F: Fixed>(width: usize) -> (f64, f64) {
+    // The width is at most 32, so this can't truncate
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let width = width as i32;
+    if F::IS_SIGNED {
+        (-(2f64.powi(width - 1)), 2f64.powi(width - 1) - 1.0)
+    } else {
+        (0.0, 2f64.powi(width) - 1.0)
+    }
+}
+
+/// The size of one LSB of `F` as a float
+fn lsb<F: Fixed>() -> f64 {
+    // The binary point of a 32-bit type is at most 32
+    #[allow(clippy::cast_possible_wrap)]
+    2f64.powi(-(F::FRAC_NBITS as i32))
+}
+
+/// The smallest and largest values of `F` representable in a `width` bit register
+fn bounds<F: Fixed>(width: usize) -> (F, F) {
+    // Bounds in the declared width are exactly representable in both f64 and F
+    let (min, max) = lsb_range::<F>(width);
+    (F::from_num(min * lsb::<F>()), F::from_num(max * lsb::<F>()))
+}
+
+/// Parse the io direction, bitwidth, and binary point of a register of `F` from FPG description
+/// strings
+fn parse_fixed<F: Fixed>(
+    io_dir: &str,
+    bitwidths: &str,
+    bin_pts: &str,
+) -> Result<(Direction, usize), Error> {
+    let direction = parse_direction(io_dir)?;
+    let width = bitwidths.parse().map_err(|_| Error::BadBitwidth)?;
+    if width > 32 {
+        return Err(Error::BadBitwidth);
+    }
+    if bin_pts.parse::<u32>().ok() != Some(F::FRAC_NBITS) {
+        return Err(Error::BadBinPt(bin_pts.to_string()));
+    }
+    Ok((direction, width))
+}
+
 impl<T, F> FixedSoftwareRegister<T, F>
 where
     T: Transport,
@@ -90,9 +208,10 @@ where
     ) -> Self {
         let transport = Arc::downgrade(transport);
         Self {
-            transport,
+            transport: TransportHandle::new(transport, reg_name),
             direction,
-            width,
+            width: width.min(32),
+            bin_pt: F::FRAC_NBITS,
             name: reg_name.to_string(),
             phantom: PhantomData,
         }
@@ -100,37 +219,74 @@ where
 
     /// Builds a [`FixedSoftwareRegister`] from FPG description strings
     /// # Errors
-    /// Returns an error on bad string arguments
+    /// Returns an error on bad string arguments or if `bin_pts` isn't the binary point of `F`
     pub fn from_fpg(
         transport: Weak<Mutex<T>>,
         reg_name: &str,
         io_dir: &str,
         bitwidths: &str,
+        bin_pts: &str,
     ) -> Result<Self, Error> {
-        let direction = match io_dir {
-            "To\\_Processor" => Direction::ToProcessor,
-            "From\\_Processor" => Direction::FromProcessor,
-            _ => return Err(Error::BadDirection),
-        };
-        let width = bitwidths.parse().map_err(|_| Error::BadBitwidth)?;
+        let (direction, width) = parse_fixed::<F>(io_dir, bitwidths, bin_pts)?;
         Ok(Self {
-            transport,
+            transport: TransportHandle::new(transport, reg_name),
             direction,
             width,
+            bin_pt: F::FRAC_NBITS,
             name: reg_name.to_string(),
             phantom: PhantomData,
         })
     }
 
+    /// The declared bitwidth of the register
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The number of fractional bits of the register
+    #[must_use]
+    pub fn bin_pt(&self) -> u32 {
+        self.bin_pt
+    }
+
+    /// The range of the raw integer in the declared width, in LSBs
+    fn lsb_range(&self) -> (f64, f64) {
+        lsb_range::<F>(self.width)
+    }
+
+    /// The smallest value representable in the register's declared bitwidth
+    #[must_use]
+    pub fn min(&self) -> F {
+        bounds::<F>(self.width).0
+    }
+
+    /// The largest value representable in the register's declared bitwidth
+    #[must_use]
+    pub fn max(&self) -> F {
+        bounds::<F>(self.width).1
+    }
+
+    /// Round `val` to the nearest value representable in the register, saturating at
+    /// [`FixedSoftwareRegister::min`] and [`FixedSoftwareRegister::max`]. NaN becomes zero.
+    #[must_use]
+    pub fn quantize(&self, val: f64) -> F {
+        if val.is_nan() {
+            return F::ZERO;
+        }
+        let (min, max) = self.lsb_range();
+        let lsbs = (val / lsb::<F>()).round().clamp(min, max);
+        F::from_num(lsbs * lsb::<F>())
+    }
+
     /// Reads a fixed point number from the register
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn read(&self) -> Result<F, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Perform the read
-        Ok(F::from_be_bytes(transport.read(&self.name, 0)?))
+        self.transport.with_transport(|transport| {
+            // Perform the read
+            Ok(F::from_be_bytes(transport.read(&self.name, 0)?))
+        })
     }
 
     /// Write a fixed point number to the register
@@ -143,14 +299,211 @@ where
         if self.direction == Direction::ToProcessor {
             return Err(Error::ReadOnly);
         }
-        // Check width
-        if val > (2_usize.pow(self.width.try_into().unwrap()) - 1 / 2_usize.pow(F::FRAC_NBITS)) {
+        // Check width, the FPGA would silently drop the extra bits
+        if val < self.min() || val > self.max() {
+            return Err(Error::Overflow);
+        }
+        self.transport.with_transport(|transport| {
+            // Perform the write
+            Ok(transport.write(&self.name, 0, &(val.to_be_bytes()))?)
+        })
+    }
+
+    /// Reads the register as a floating point number
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn read_f64(&self) -> Result<f64, Error> {
+        Ok(self.read()?.to_num())
+    }
+
+    /// Write a floating point number to the register, rounding it onto the register's binary point
+    /// with `rounding` and checking that it fits in the register's declared bitwidth
+    /// # Errors
+    /// Returns an error on bad transport, non-finite values, or if the rounded value doesn't fit
+    pub fn write_f64(&self, val: f64, rounding: Rounding) -> Result<(), Error> {
+        if !val.is_finite() {
+            return Err(Error::NotFinite);
+        }
+        let scaled = val / lsb::<F>();
+        let lsbs = match rounding {
+            Rounding::Nearest => scaled.round(),
+            Rounding::Floor => scaled.floor(),
+            Rounding::Ceil => scaled.ceil(),
+            Rounding::Truncate => scaled.trunc(),
+        };
+        let (min, max) = self.lsb_range();
+        if lsbs < min || lsbs > max {
+            return Err(Error::Overflow);
+        }
+        // The rounded value is exactly representable, so this conversion is lossless
+        let fixed = F::checked_from_num(lsbs * lsb::<F>()).ok_or(Error::Overflow)?;
+        self.write(fixed)
+    }
+}
+
+impl<T, F> ArraySoftwareRegister<T, F>
+where
+    T: Transport,
+    F: Fixed<Bytes = [u8; 4]>,
+{
+    #[must_use]
+    pub fn new(
+        transport: &Arc<Mutex<T>>,
+        reg_name: &str,
+        direction: Direction,
+        width: usize,
+        len: usize,
+    ) -> Self {
+        let transport = Arc::downgrade(transport);
+        Self {
+            transport: TransportHandle::new(transport, reg_name),
+            direction,
+            width: width.min(32),
+            bin_pt: F::FRAC_NBITS,
+            len,
+            name: reg_name.to_string(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Builds a [`ArraySoftwareRegister`] from FPG description strings, where `size` is the size of
+    /// the register in bytes
+    /// # Errors
+    /// Returns an error on bad string arguments or if `bin_pts` isn't the binary point of `F`
+    pub fn from_fpg(
+        transport: Weak<Mutex<T>>,
+        reg_name: &str,
+        io_dir: &str,
+        bitwidths: &str,
+        bin_pts: &str,
+        size: &str,
+    ) -> Result<Self, Error> {
+        let (direction, width) = parse_fixed::<F>(io_dir, bitwidths, bin_pts)?;
+        let len = match size.parse::<usize>() {
+            Ok(bytes) if bytes > 0 && bytes % 4 == 0 => bytes / 4,
+            _ => return Err(Error::BadSize(size.to_string())),
+        };
+        Ok(Self {
+            transport: TransportHandle::new(transport, reg_name),
+            direction,
+            width,
+            bin_pt: F::FRAC_NBITS,
+            len,
+            name: reg_name.to_string(),
+            phantom: PhantomData,
+        })
+    }
+
+    /// The number of words in the register
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the register has no words, which registers from fpg files never are
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The declared bitwidth of each word
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The number of fractional bits of each word
+    #[must_use]
+    pub fn bin_pt(&self) -> u32 {
+        self.bin_pt
+    }
+
+    /// The smallest value representable in the declared bitwidth
+    #[must_use]
+    pub fn min(&self) -> F {
+        bounds::<F>(self.width).0
+    }
+
+    /// The largest value representable in the declared bitwidth
+    #[must_use]
+    pub fn max(&self) -> F {
+        bounds::<F>(self.width).1
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), Error> {
+        if index < self.len {
+            Ok(())
+        } else {
+            Err(Error::OutOfBounds {
+                index,
+                len: self.len,
+            })
+        }
+    }
+
+    fn check_write(&self, vals: &[F]) -> Result<(), Error> {
+        if self.direction == Direction::ToProcessor {
+            return Err(Error::ReadOnly);
+        }
+        // Check width, the FPGA would silently drop the extra bits
+        let (min, max) = bounds::<F>(self.width);
+        if vals.iter().any(|val| *val < min || *val > max) {
             return Err(Error::Overflow);
         }
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Perform the write
-        Ok(transport.write(&self.name, 0, &(val.to_be_bytes()))?)
+        Ok(())
+    }
+
+    /// Reads the fixed point number at word `index`
+    /// # Errors
+    /// Returns an error on bad transport or if `index` is out of bounds
+    pub fn read_index(&self, index: usize) -> Result<F, Error> {
+        self.check_index(index)?;
+        self.transport.with_transport(|transport| {
+            Ok(F::from_be_bytes(transport.read(&self.name, 4 * index)?))
+        })
+    }
+
+    /// Write a fixed point number to word `index`
+    /// # Errors
+    /// Returns an error on bad transport, if `index` is out of bounds, or if the value doesn't fit
+    /// in the declared bitwidth
+    pub fn write_index(&self, index: usize, val: F) -> Result<(), Error> {
+        self.check_index(index)?;
+        self.check_write(&[val])?;
+        self.transport.with_transport(|transport| {
+            Ok(transport.write(&self.name, 4 * index, &(val.to_be_bytes()))?)
+        })
+    }
+
+    /// Reads every word of the register
+    /// # Errors
+    /// Returns an error on bad transport
+    #[allow(clippy::missing_panics_doc)]
+    pub fn read(&self) -> Result<Vec<F>, Error> {
+        self.transport.with_transport(|transport| {
+            let bytes = transport.read_n_bytes(&self.name, 0, 4 * self.len)?;
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|word| F::from_be_bytes(word.try_into().unwrap()))
+                .collect())
+        })
+    }
+
+    /// Write every word of the register
+    /// # Errors
+    /// Returns an error on bad transport, if there isn't exactly one value per word, or if any
+    /// value doesn't fit in the declared bitwidth
+    pub fn write(&self, vals: &[F]) -> Result<(), Error> {
+        if vals.len() != self.len {
+            return Err(Error::BadLength {
+                got: vals.len(),
+                expected: self.len,
+            });
+        }
+        self.check_write(vals)?;
+        let bytes: Vec<_> = vals.iter().flat_map(|val| val.to_be_bytes()).collect();
+        self.transport
+            .with_transport(|transport| Ok(transport.write_bytes(&self.name, 0, &bytes)?))
     }
 }
 
@@ -162,7 +515,7 @@ where
     pub fn new(transport: &Arc<Mutex<T>>, reg_name: &str, direction: Direction) -> Self {
         let transport = Arc::downgrade(transport);
         Self {
-            transport,
+            transport: TransportHandle::new(transport, reg_name),
             direction,
             name: reg_name.to_string(),
         }
@@ -176,14 +529,9 @@ where
         reg_name: &str,
         io_dir: &str,
     ) -> Result<Self, Error> {
-        let direction = match io_dir {
-            "To\\_Processor" => Direction::ToProcessor,
-            "From\\_Processor" => Direction::FromProcessor,
-            _ => return Err(Error::BadDirection),
-        };
-
+        let direction = parse_direction(io_dir)?;
         Ok(Self {
-            transport,
+            transport: TransportHandle::new(transport, reg_name),
             direction,
             name: reg_name.to_string(),
         })
@@ -192,27 +540,386 @@ where
     /// Reads a boolean from the register
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn read(&self) -> Result<bool, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Perform the read
-        let raw: u32 = transport.read(&self.name, 0)?;
-        Ok(raw == 1)
+        self.transport.with_transport(|transport| {
+            // Perform the read
+            let raw: u32 = transport.read(&self.name, 0)?;
+            Ok(raw == 1)
+        })
     }
 
     /// Writes a boolean to the register
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn write(&self, val: bool) -> Result<(), Error> {
         if self.direction == Direction::ToProcessor {
             return Err(Error::ReadOnly);
         }
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // Perform the write
-        Ok(transport.write(&self.name, 0, &(u32::from(val)))?)
+        self.transport.with_transport(|transport| {
+            // Perform the write
+            Ok(transport.write(&self.name, 0, &(u32::from(val)))?)
+        })
+    }
+}
+
+/// A software register clients can only read, i.e. one that goes to the processor
+#[derive(Debug)]
+pub struct ReadOnly<R>(R);
+
+impl<R> ReadOnly<R> {
+    /// Wrap `register`, hiding its write methods
+    #[must_use]
+    pub fn new(register: R) -> Self {
+        Self(register)
+    }
+}
+
+impl<T, F> ReadOnly<FixedSoftwareRegister<T, F>>
+where
+    T: Transport,
+    F: Fixed<Bytes = [u8; 4]>,
+{
+    /// Builds a read-only [`FixedSoftwareRegister`] from FPG description strings
+    /// # Errors
+    /// Returns an error on bad string arguments or if `bin_pts` isn't the binary point of `F`
+    pub fn from_fpg(
+        transport: Weak<Mutex<T>>,
+        reg_name: &str,
+        io_dir: &str,
+        bitwidths: &str,
+        bin_pts: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self(FixedSoftwareRegister::from_fpg(
+            transport, reg_name, io_dir, bitwidths, bin_pts,
+        )?))
+    }
+
+    /// See [`FixedSoftwareRegister::width`]
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.0.width()
+    }
+
+    /// See [`FixedSoftwareRegister::bin_pt`]
+    #[must_use]
+    pub fn bin_pt(&self) -> u32 {
+        self.0.bin_pt()
+    }
+
+    /// See [`FixedSoftwareRegister::min`]
+    #[must_use]
+    pub fn min(&self) -> F {
+        self.0.min()
+    }
+
+    /// See [`FixedSoftwareRegister::max`]
+    #[must_use]
+    pub fn max(&self) -> F {
+        self.0.max()
+    }
+
+    /// Reads a fixed point number from the register
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn read(&self) -> Result<F, Error> {
+        self.0.read()
+    }
+
+    /// Reads the register as a floating point number
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn read_f64(&self) -> Result<f64, Error> {
+        self.0.read_f64()
+    }
+}
+
+impl<T, F> ReadOnly<ArraySoftwareRegister<T, F>>
+where
+    T: Transport,
+    F: Fixed<Bytes = [u8; 4]>,
+{
+    /// Builds a read-only [`ArraySoftwareRegister`] from FPG description strings
+    /// # Errors
+    /// Returns an error on bad string arguments or if `bin_pts` isn't the binary point of `F`
+    pub fn from_fpg(
+        transport: Weak<Mutex<T>>,
+        reg_name: &str,
+        io_dir: &str,
+        bitwidths: &str,
+        bin_pts: &str,
+        size: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self(ArraySoftwareRegister::from_fpg(
+            transport, reg_name, io_dir, bitwidths, bin_pts, size,
+        )?))
+    }
+
+    /// See [`ArraySoftwareRegister::len`]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// See [`ArraySoftwareRegister::is_empty`]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// See [`ArraySoftwareRegister::width`]
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.0.width()
+    }
+
+    /// See [`ArraySoftwareRegister::bin_pt`]
+    #[must_use]
+    pub fn bin_pt(&self) -> u32 {
+        self.0.bin_pt()
+    }
+
+    /// Reads the fixed point number at word `index`
+    /// # Errors
+    /// Returns an error on bad transport or if `index` is out of bounds
+    pub fn read_index(&self, index: usize) -> Result<F, Error> {
+        self.0.read_index(index)
+    }
+
+    /// Reads every word of the register
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn read(&self) -> Result<Vec<F>, Error> {
+        self.0.read()
+    }
+}
+
+impl<T> ReadOnly<BooleanSoftwareRegister<T>>
+where
+    T: Transport,
+{
+    /// Builds a read-only [`BooleanSoftwareRegister`] from FPG description strings
+    /// # Errors
+    /// Returns an error on bad string arguments
+    pub fn from_fpg(
+        transport: Weak<Mutex<T>>,
+        reg_name: &str,
+        io_dir: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self(BooleanSoftwareRegister::from_fpg(
+            transport, reg_name, io_dir,
+        )?))
+    }
+
+    /// Reads a boolean from the register
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn read(&self) -> Result<bool, Error> {
+        self.0.read()
+    }
+}
+
+impl<T, R> FromFpg<T> for ReadOnly<R>
+where
+    R: FromFpg<T>,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Ok(Self(R::from_metadata(transport, name, metadata)?))
+    }
+}
+
+impl<T, R> YellowBlock<T> for ReadOnly<R>
+where
+    T: 'static,
+    R: YellowBlock<T>,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Ok(Self(R::from_device(transport, name, devices)?))
+    }
+
+    fn kind(&self) -> &'static str {
+        self.0.kind()
+    }
+
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn describe(&self) -> String {
+        format!("{} (read-only)", self.0.describe())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<T, F> FromFpg<T> for FixedSoftwareRegister<T, F>
+where
+    T: Transport,
+    F: Fixed<Bytes = [u8; 4]>,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        let meta = |key| metadata_entry(metadata, name, key);
+        Ok(Self::from_fpg(
+            transport,
+            name,
+            meta("io_dir")?,
+            meta("bitwidths")?,
+            meta("bin_pts")?,
+        )?)
+    }
+}
+
+impl<T, F> YellowBlock<T> for FixedSoftwareRegister<T, F>
+where
+    T: Transport + 'static,
+    F: Fixed<Bytes = [u8; 4]> + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_metadata(transport, name, &device(devices, name)?.metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:sw_reg"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "xps:sw_reg `{}` ({:?}, {} bits, binary point {})",
+            self.name, self.direction, self.width, self.bin_pt
+        )
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The size of an array register isn't a metadata entry of its device but the size of its register,
+/// which is expected merged into the metadata as `size` (in bytes)
+impl<T, F> FromFpg<T> for ArraySoftwareRegister<T, F>
+where
+    T: Transport,
+    F: Fixed<Bytes = [u8; 4]>,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        let meta = |key| metadata_entry(metadata, name, key);
+        Ok(Self::from_fpg(
+            transport,
+            name,
+            meta("io_dir")?,
+            meta("bitwidths")?,
+            meta("bin_pts")?,
+            meta("size")?,
+        )?)
+    }
+}
+
+impl<T, F> YellowBlock<T> for ArraySoftwareRegister<T, F>
+where
+    T: Transport + 'static,
+    F: Fixed<Bytes = [u8; 4]> + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        let device = device(devices, name)?;
+        let mut metadata = device.metadata.clone();
+        if let Some(reg) = device.register {
+            metadata.insert("size".into(), reg.size.to_string());
+        }
+        Self::from_metadata(transport, name, &metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:sw_reg"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "xps:sw_reg `{}` ({:?}, {} words of {} bits, binary point {})",
+            self.name, self.direction, self.len, self.width, self.bin_pt
+        )
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<T> FromFpg<T> for BooleanSoftwareRegister<T>
+where
+    T: Transport,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Ok(Self::from_fpg(
+            transport,
+            name,
+            metadata_entry(metadata, name, "io_dir")?,
+        )?)
+    }
+}
+
+impl<T> YellowBlock<T> for BooleanSoftwareRegister<T>
+where
+    T: Transport + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_metadata(transport, name, &device(devices, name)?.metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:sw_reg"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        format!("xps:sw_reg `{}` ({:?}, boolean)", self.name, self.direction)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
@@ -266,6 +973,155 @@ mod tests {
         assert_eq!(test_num, my_reg.read().unwrap());
     }
 
+    #[test]
+    fn test_f64_readwrite() {
+        let transport = Mock::new(HashMap::from([(
+            "my_reg".into(),
+            Register { addr: 0, length: 4 },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        let my_reg = FixedSoftwareRegister::<_, U27F5>::new(
+            &transport,
+            "my_reg",
+            Direction::FromProcessor,
+            8,
+        );
+        my_reg.write_f64(2.7, Rounding::Nearest).unwrap();
+        assert!((my_reg.read_f64().unwrap() - 2.6875).abs() < f64::EPSILON);
+        my_reg.write_f64(2.7, Rounding::Ceil).unwrap();
+        assert!((my_reg.read_f64().unwrap() - 2.71875).abs() < f64::EPSILON);
+        // 8 bits with 5 fractional bits tops out just under 8
+        my_reg.write_f64(7.96875, Rounding::Floor).unwrap();
+        assert!(matches!(
+            my_reg.write_f64(8.0, Rounding::Floor),
+            Err(Error::Overflow)
+        ));
+        assert!(matches!(
+            my_reg.write_f64(-0.5, Rounding::Nearest),
+            Err(Error::Overflow)
+        ));
+        assert!(matches!(
+            my_reg.write_f64(f64::NAN, Rounding::Nearest),
+            Err(Error::NotFinite)
+        ));
+
+        let signed = FixedSoftwareRegister::<_, I25F7>::new(
+            &transport,
+            "my_reg",
+            Direction::FromProcessor,
+            8,
+        );
+        signed.write_f64(-1.0, Rounding::Nearest).unwrap();
+        assert!((signed.read_f64().unwrap() + 1.0).abs() < f64::EPSILON);
+        assert!(signed.write_f64(1.0, Rounding::Nearest).is_err());
+    }
+
+    #[test]
+    fn test_range_helpers() {
+        let transport = Mock::new(HashMap::from([(
+            "my_reg".into(),
+            Register { addr: 0, length: 4 },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        let my_reg = FixedSoftwareRegister::<_, I25F7>::from_fpg(
+            Arc::downgrade(&transport),
+            "my_reg",
+            "From\\_Processor",
+            "10",
+            "7",
+        )
+        .unwrap();
+        assert_eq!((my_reg.width(), my_reg.bin_pt()), (10, 7));
+        assert_eq!(my_reg.min(), I25F7::from_num(-4));
+        assert_eq!(my_reg.max(), I25F7::from_num(3.992_187_5));
+        assert_eq!(my_reg.quantize(100.0), my_reg.max());
+        assert_eq!(my_reg.quantize(-100.0), my_reg.min());
+        assert_eq!(my_reg.quantize(1.001), I25F7::from_num(1));
+        assert_eq!(my_reg.quantize(f64::NAN), I25F7::ZERO);
+        // Values outside of the declared width would be truncated, so they're rejected
+        assert!(matches!(
+            my_reg.write(I25F7::from_num(4)),
+            Err(Error::Overflow)
+        ));
+        my_reg.write(my_reg.quantize(4.0)).unwrap();
+        assert_eq!(my_reg.read().unwrap(), my_reg.max());
+
+        assert!(matches!(
+            FixedSoftwareRegister::<_, I25F7>::from_fpg(
+                Arc::downgrade(&transport),
+                "my_reg",
+                "From\\_Processor",
+                "10",
+                "5",
+            ),
+            Err(Error::BadBinPt(_))
+        ));
+    }
+
+    #[test]
+    fn test_array_readwrite() {
+        let transport = Mock::new(HashMap::from([(
+            "my_reg".into(),
+            Register {
+                addr: 0,
+                length: 12,
+            },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        let my_reg = ArraySoftwareRegister::<_, I25F7>::from_fpg(
+            Arc::downgrade(&transport),
+            "my_reg",
+            "From\\_Processor",
+            "10",
+            "7",
+            "12",
+        )
+        .unwrap();
+        assert_eq!(my_reg.len(), 3);
+        my_reg.write_index(1, I25F7::from_num(-2.5)).unwrap();
+        assert_eq!(
+            transport
+                .lock()
+                .unwrap()
+                .read::<i32, 4>("my_reg", 4)
+                .unwrap(),
+            -320
+        );
+        assert_eq!(my_reg.read_index(1).unwrap(), I25F7::from_num(-2.5));
+        assert!(matches!(
+            my_reg.read_index(3),
+            Err(Error::OutOfBounds { index: 3, len: 3 })
+        ));
+        assert!(matches!(
+            my_reg.write_index(0, I25F7::from_num(4)),
+            Err(Error::Overflow)
+        ));
+
+        let vals = [1.0, 0.5, -4.0].map(I25F7::from_num);
+        my_reg.write(&vals).unwrap();
+        assert_eq!(my_reg.read().unwrap(), vals);
+        assert!(matches!(
+            my_reg.write(&vals[..2]),
+            Err(Error::BadLength {
+                got: 2,
+                expected: 3
+            })
+        ));
+        for size in ["0", "6", "big"] {
+            assert!(matches!(
+                ArraySoftwareRegister::<_, I25F7>::from_fpg(
+                    Arc::downgrade(&transport),
+                    "my_reg",
+                    "From\\_Processor",
+                    "10",
+                    "7",
+                    size,
+                ),
+                Err(Error::BadSize(_))
+            ));
+        }
+    }
+
     #[test]
     fn test_bool_readwrite() {
         let transport = Mock::new(HashMap::from([(
@@ -1,13 +1,41 @@
 //! Routines for interacting with the CASPER 10GbE Core
 use crate::{
-    transport::{Deserialize, Serialize, Transport},
-    yellow_blocks::Address,
+    core::poll,
+    transport::{
+        Deserialize,
+        Serialize,
+        Transport,
+    },
+    yellow_blocks::{
+        device,
+        Address,
+        FromFpg,
+        Metadata,
+        TransportHandle,
+        YellowBlock,
+    },
+};
+use casper_utils::design_sources::Devices;
+use casperfpga_derive::{
+    address,
+    CasperSerde,
+};
+use packed_struct::{
+    prelude::*,
+    PackedStruct,
+    PackingResult,
 };
-use casperfpga_derive::{address, CasperSerde};
-use packed_struct::{prelude::*, PackedStruct, PackingResult};
 use std::{
+    any::Any,
+    fmt::Display,
     net::Ipv4Addr,
-    sync::{Arc, Mutex, Weak},
+    str::FromStr,
+    sync::{
+        Arc,
+        Mutex,
+        Weak,
+    },
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -30,6 +58,7 @@ pub enum EthernetType {
 #[derive(PackedStruct, CasperSerde, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
 #[address(0x0)]
+#[casper_serde(layout_tests)]
 pub struct CoreType {
     #[packed_field(bits = "24")]
     pub cpu_tx_enable: bool,
@@ -43,6 +72,7 @@ pub struct CoreType {
 
 #[derive(PackedStruct, CasperSerde, Debug)]
 #[address(0x4)]
+#[casper_serde(layout_tests)]
 pub struct BufferSizes {
     #[packed_field(endian = "msb")]
     pub tx_buf_max: u16,
@@ -52,6 +82,7 @@ pub struct BufferSizes {
 
 #[derive(PackedStruct, CasperSerde, Debug)]
 #[address(0x8)]
+#[casper_serde(layout_tests)]
 pub struct WordLengths {
     #[packed_field(endian = "msb")]
     pub tx_word_size: u16,
@@ -59,23 +90,117 @@ pub struct WordLengths {
     pub rx_word_size: u16,
 }
 
+/// The start of the buffer the CPU writes frames to transmit into
+pub const CPU_TX_BUFFER: usize = 0x4000;
+/// The start of the buffer frames for the CPU are received into
+pub const CPU_RX_BUFFER: usize = 0x8000;
+/// How often the link is polled while waiting for it
+const LINK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// The word size to assume if the core doesn't report one
+const DEFAULT_WORD_SIZE: usize = 8;
+
+/// An Ethernet MAC address, written and parsed as `aa:bb:cc:dd:ee:ff`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    #[must_use]
+    pub const fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Whether this is a multicast (or broadcast) address, marked by the LSB of the first octet
+    #[must_use]
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] & 1 == 1
+    }
+}
+
+impl Display for MacAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, octet) in self.0.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{octet:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Bad MAC address `{0}`, expected six hex octets like aa:bb:cc:dd:ee:ff")]
+pub struct ParseMacError(String);
+
+impl FromStr for MacAddr {
+    type Err = ParseMacError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseMacError(s.to_string());
+        let mut octets = [0; 6];
+        let mut parts = s.split([':', '-']);
+        for octet in &mut octets {
+            let part = parts.next().filter(|p| p.len() == 2).ok_or_else(err)?;
+            *octet = u8::from_str_radix(part, 16).map_err(|_| err())?;
+        }
+        if parts.next().is_some() {
+            return Err(err());
+        }
+        Ok(Self(octets))
+    }
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> Self {
+        Self(octets)
+    }
+}
+
+impl From<MacAddr> for [u8; 6] {
+    fn from(mac: MacAddr) -> Self {
+        mac.0
+    }
+}
+
+/// The MAC as the core stores it, in the low 48 bits of a big-endian word
+impl From<MacAddr> for u64 {
+    fn from(mac: MacAddr) -> Self {
+        let mut bytes = [0; 8];
+        bytes[2..].copy_from_slice(&mac.0);
+        u64::from_be_bytes(bytes)
+    }
+}
+
+/// The MAC from the low 48 bits of a register word, ignoring the rest
+impl From<u64> for MacAddr {
+    fn from(word: u64) -> Self {
+        Self(
+            word.to_be_bytes()[2..]
+                .try_into()
+                .expect("Sliced six bytes"),
+        )
+    }
+}
+
 // Implement the packing traits for network objects
 
 #[derive(CasperSerde, Debug)]
-#[address(0xC)]
-pub struct MacAddress([u8; 6]);
+#[address(0xC, size = 8)]
+#[casper_serde(golden(
+    value = "MacAddress(MacAddr([1, 2, 3, 4, 5, 6]))",
+    bytes = "[0, 0, 1, 2, 3, 4, 5, 6]"
+))]
+pub struct MacAddress(pub MacAddr);
 
 impl PackedStruct for MacAddress {
     type ByteArray = [u8; 8];
 
     fn pack(&self) -> PackingResult<Self::ByteArray> {
-        let mut dest = [0u8; 8];
-        dest[2..].copy_from_slice(&self.0);
-        Ok(dest)
+        Ok(u64::from(self.0).to_be_bytes())
     }
 
     fn unpack(src: &Self::ByteArray) -> packed_struct::PackingResult<Self> {
-        Ok(MacAddress(src[2..].try_into().unwrap()))
+        Ok(MacAddress(u64::from_be_bytes(*src).into()))
     }
 }
 
@@ -83,6 +208,7 @@ macro_rules! ip_register {
     ($name:ident, $addr:literal) => {
         #[derive(Debug, CasperSerde)]
         #[address($addr)]
+        #[casper_serde(layout_tests)]
         pub struct $name(pub Ipv4Addr);
 
         impl PackedStruct for $name {
@@ -107,6 +233,7 @@ ip_register!(MulticastMask, 0x24);
 
 #[derive(PackedStruct, CasperSerde, Debug)]
 #[address(0x28)]
+#[casper_serde(layout_tests)]
 pub struct BytesAvailable {
     #[packed_field(endian = "msb")]
     pub tx_size: u16,
@@ -117,6 +244,10 @@ pub struct BytesAvailable {
 #[derive(PackedStruct, CasperSerde, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
 #[address(0x2C)]
+#[casper_serde(golden(
+    value = "PromiscRstEn { soft_rst: false, promisc: true, enable: true }",
+    bytes = "[0, 0, 0, 0x05]"
+))]
 pub struct PromiscRstEn {
     #[packed_field(bits = "4")]
     pub soft_rst: bool,
@@ -128,6 +259,10 @@ pub struct PromiscRstEn {
 
 #[derive(PackedStruct, CasperSerde, Debug)]
 #[address(0x30)]
+#[casper_serde(golden(
+    value = "Port { port_mask: 0xFFFF, port: 60000 }",
+    bytes = "[0xFF, 0xFF, 0xEA, 0x60]"
+))]
 pub struct Port {
     #[packed_field(endian = "msb")]
     pub port_mask: u16,
@@ -137,22 +272,220 @@ pub struct Port {
 
 #[derive(PackedStruct, CasperSerde, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "8")]
-#[address(0x34)]
+#[address(0x34, size = 8)]
+#[casper_serde(golden(
+    value = "Status { phy_ready: false, link_up: true, rx_eq_status: 0xAB }",
+    bytes = "[0, 0, 0, 0, 0, 0, 0xAB, 0x01]"
+))]
 pub struct Status {
     // There's other (undocumented) stuff in here
+    /// The PHY has finished its reset sequence, cores without this report always read false
+    #[packed_field(bits = "1")]
+    pub phy_ready: bool,
     #[packed_field(bits = "0")]
     pub link_up: bool,
+    /// The receive equalizer status of the PHY transceivers, zero on cores that don't report it
+    #[packed_field(bits = "15..=8")]
+    pub rx_eq_status: u8,
+}
+
+/// A structured report of the state of the link and the core's MAC settings
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct LinkStatus {
+    pub link_up: bool,
+    pub phy_ready: bool,
+    pub rx_eq_status: u8,
+    /// Whether the core fabric is enabled
+    pub enabled: bool,
+    /// Whether the core accepts packets for any destination address
+    pub promiscuous: bool,
+    /// Whether the core is being held in software reset
+    pub in_reset: bool,
+}
+
+/// The outcome of sending an ARP request from the CPU interface
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ArpCheck {
+    /// The address we asked for
+    pub target: Ipv4Addr,
+    /// The MAC address from the reply, if one arrived in time
+    pub reply: Option<MacAddr>,
+    /// The number of unrelated frames received (and discarded) while waiting
+    pub discarded: usize,
+}
+
+/// The result of [`TenGbE::self_test`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SelfTest {
+    pub link: LinkStatus,
+    /// The ARP check, if one was requested
+    pub arp: Option<ArpCheck>,
+}
+
+impl SelfTest {
+    /// Whether the link is up and (if checked) the ARP request was answered
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.link.link_up && self.arp.map_or(true, |arp| arp.reply.is_some())
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
     Transport(#[from] crate::transport::Error),
+    #[error("Netmask {0} isn't a contiguous run of ones")]
+    BadNetmask(Ipv4Addr),
+    #[error("{ip} is the network or broadcast address of its subnet")]
+    ReservedIp { ip: Ipv4Addr },
+    #[error("Gateway {gateway} isn't in the same subnet as {ip}")]
+    GatewayOutsideSubnet { ip: Ipv4Addr, gateway: Ipv4Addr },
+    #[error("MAC address {0} is a multicast address")]
+    MulticastMac(MacAddr),
+    #[error("ARP entry for {arp} isn't in the same subnet as {ip}")]
+    ArpOutsideSubnet { ip: Ipv4Addr, arp: Ipv4Addr },
+    #[error("The link didn't come up within {timeout:?}")]
+    LinkTimeout { timeout: Duration, last: LinkStatus },
+    #[error("The TX buffer still held {tx_size} words after {timeout:?}")]
+    DrainTimeout { timeout: Duration, tx_size: u16 },
+    #[error("Bad buffer counters after a reset: {tx_size} words to send and {rx_size} received")]
+    BadCounters { tx_size: u16, rx_size: u16 },
+    #[error("The core was built without the CPU {0} interface")]
+    NoCpuInterface(&'static str),
+    #[error("Readback of `{field}` after configuring was {actual}, expected {expected}")]
+    Readback {
+        field: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// The full network configuration of a core
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkConfig {
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub mac: MacAddr,
+    pub port: u16,
+    /// Static ARP entries, which must all live in the core's subnet
+    pub arp: Vec<(Ipv4Addr, MacAddr)>,
+}
+
+impl NetworkConfig {
+    /// Checks the configuration is self-consistent
+    /// # Errors
+    /// Returns an error describing the first problem found
+    pub fn validate(&self) -> Result<(), Error> {
+        let mask = u32::from(self.netmask);
+        if mask.leading_ones() + mask.trailing_zeros() != 32 {
+            return Err(Error::BadNetmask(self.netmask));
+        }
+        let ip = u32::from(self.ip);
+        // /31 and /32 subnets have no network or broadcast address
+        if mask.trailing_zeros() > 1 && (ip & !mask == 0 || ip | mask == u32::MAX) {
+            return Err(Error::ReservedIp { ip: self.ip });
+        }
+        if u32::from(self.gateway) & mask != ip & mask {
+            return Err(Error::GatewayOutsideSubnet {
+                ip: self.ip,
+                gateway: self.gateway,
+            });
+        }
+        if self.mac.is_multicast() {
+            return Err(Error::MulticastMac(self.mac));
+        }
+        for (arp, _) in &self.arp {
+            if u32::from(*arp) & mask != ip & mask {
+                return Err(Error::ArpOutsideSubnet {
+                    ip: self.ip,
+                    arp: *arp,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A setting that was changed by [`TenGbE::configure`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    /// The name of the setting, i.e. `ip` or `arp[192.168.0.1]`
+    pub field: String,
+    /// The value before configuring
+    pub before: String,
+    /// The value read back after configuring
+    pub after: String,
+}
+
+/// The readable state of the core that [`TenGbE::configure`] touches
+fn snapshot<T: Transport>(
+    transport: &mut T,
+    name: &str,
+    arp: &[(Ipv4Addr, MacAddr)],
+) -> Result<Vec<(String, String)>, Error> {
+    let ip: IpAddress = transport.read_addr(name)?;
+    let netmask: Netmask = transport.read_addr(name)?;
+    let gateway: GatewayAddress = transport.read_addr(name)?;
+    let mac: MacAddress = transport.read_addr(name)?;
+    let port: Port = transport.read_addr(name)?;
+    let mut state = vec![
+        ("ip".to_string(), ip.0.to_string()),
+        ("netmask".to_string(), netmask.0.to_string()),
+        ("gateway".to_string(), gateway.0.to_string()),
+        ("mac".to_string(), mac.0.to_string()),
+        ("port".to_string(), port.port.to_string()),
+    ];
+    for (ip, _) in arp {
+        let entry: MacAddress = transport.read(name, arp_offset(*ip))?;
+        state.push((format!("arp[{ip}]"), entry.0.to_string()));
+    }
+    Ok(state)
+}
+
+/// ARP entries start at 0x1000 and are laid out like [`MacAddress`], indexed by the last octet
+fn arp_offset(ip: Ipv4Addr) -> usize {
+    0x1000 + 8 * ip.octets()[3] as usize
+}
+
+/// An ARP request (padded to the minimum frame size) from `mac`/`ip` asking for `target`
+fn arp_request(mac: MacAddr, ip: Ipv4Addr, target: Ipv4Addr) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(60);
+    frame.extend_from_slice(&[0xFF; 6]);
+    frame.extend_from_slice(&mac.0);
+    frame.extend_from_slice(&0x0806u16.to_be_bytes());
+    // Ethernet and IPv4 with their address lengths, then the "request" opcode
+    frame.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4, 0, 1]);
+    frame.extend_from_slice(&mac.0);
+    frame.extend_from_slice(&ip.octets());
+    frame.extend_from_slice(&[0; 6]);
+    frame.extend_from_slice(&target.octets());
+    frame.resize(60, 0);
+    frame
+}
+
+/// The sender MAC of `frame` if it's an ARP reply from `target` to `ip`
+fn arp_reply_from(frame: &[u8], target: Ipv4Addr, ip: Ipv4Addr) -> Option<MacAddr> {
+    let is_reply = frame.len() >= 42
+        && frame[12..14] == [0x08, 0x06]
+        && frame[20..22] == [0, 2]
+        && frame[28..32] == target.octets()
+        && frame[38..42] == ip.octets();
+    is_reply.then(|| MacAddr(frame[22..28].try_into().expect("Sliced six bytes")))
+}
+
+/// The word size reported by the core, or the default if it reports none
+fn word_size(reported: u16) -> usize {
+    match reported {
+        0 => DEFAULT_WORD_SIZE,
+        n => n as usize,
+    }
 }
 
 #[derive(Debug)]
 pub struct TenGbE<T> {
-    transport: Weak<Mutex<T>>,
+    transport: TransportHandle<T>,
     name: String,
 }
 
@@ -164,7 +497,7 @@ where
     pub fn new(transport: &Arc<Mutex<T>>, reg_name: &str) -> Self {
         let transport = Arc::downgrade(transport);
         Self {
-            transport,
+            transport: TransportHandle::new(transport, reg_name),
             name: reg_name.to_string(),
         }
     }
@@ -174,7 +507,7 @@ where
     /// Returns an error on bad string arguments
     pub fn from_fpg(transport: Weak<Mutex<T>>, reg_name: &str) -> Result<Self, Error> {
         Ok(Self {
-            transport,
+            transport: TransportHandle::new(transport, reg_name),
             name: reg_name.to_string(),
         })
     }
@@ -182,168 +515,497 @@ where
     /// Get the IP of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn get_ip(&self) -> Result<Ipv4Addr, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let ip: IpAddress = transport.read_addr(&self.name)?;
-        Ok(ip.0)
+        self.transport.with_transport(|transport| {
+            let ip: IpAddress = transport.read_addr(&self.name)?;
+            Ok(ip.0)
+        })
     }
 
     /// Set the IP of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_ip(&self, addr: Ipv4Addr) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(transport.write_addr(&self.name, &IpAddress(addr))?)
+        self.transport
+            .with_transport(|transport| Ok(transport.write_addr(&self.name, &IpAddress(addr))?))
     }
 
     /// Get the gateway IP of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn get_gateway(&self) -> Result<Ipv4Addr, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let ip: GatewayAddress = transport.read_addr(&self.name)?;
-        Ok(ip.0)
+        self.transport.with_transport(|transport| {
+            let ip: GatewayAddress = transport.read_addr(&self.name)?;
+            Ok(ip.0)
+        })
     }
 
     /// Set the gateway IP of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_gateway(&self, addr: Ipv4Addr) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(transport.write_addr(&self.name, &GatewayAddress(addr))?)
+        self.transport.with_transport(|transport| {
+            Ok(transport.write_addr(&self.name, &GatewayAddress(addr))?)
+        })
     }
 
     /// Get the port of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn get_port(&self) -> Result<u16, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let port: Port = transport.read_addr(&self.name)?;
-        Ok(port.port)
+        self.transport.with_transport(|transport| {
+            let port: Port = transport.read_addr(&self.name)?;
+            Ok(port.port)
+        })
     }
 
     /// Set the port of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_port(&self, port: u16) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(transport.write_addr(
-            &self.name,
-            &Port {
-                port_mask: 0xFF,
-                port,
-            },
-        )?)
+        self.transport.with_transport(|transport| {
+            Ok(transport.write_addr(
+                &self.name,
+                &Port {
+                    port_mask: 0xFF,
+                    port,
+                },
+            )?)
+        })
     }
 
     /// Get the subnet mask of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn get_netmask(&self) -> Result<Ipv4Addr, Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let ip: Netmask = transport.read_addr(&self.name)?;
-        Ok(ip.0)
+        self.transport.with_transport(|transport| {
+            let ip: Netmask = transport.read_addr(&self.name)?;
+            Ok(ip.0)
+        })
     }
 
     /// Set the subnet mask of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_netmask(&self, addr: Ipv4Addr) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(transport.write_addr(&self.name, &Netmask(addr))?)
+        self.transport
+            .with_transport(|transport| Ok(transport.write_addr(&self.name, &Netmask(addr))?))
     }
 
     /// Get the MAC address of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
-    pub fn get_mac(&self) -> Result<[u8; 6], Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let mac: MacAddress = transport.read_addr(&self.name)?;
-        Ok(mac.0)
+    pub fn get_mac(&self) -> Result<MacAddr, Error> {
+        self.transport.with_transport(|transport| {
+            let mac: MacAddress = transport.read_addr(&self.name)?;
+            Ok(mac.0)
+        })
     }
 
     /// Set the MAC address of the core
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
-    pub fn set_mac(&self, mac: &[u8; 6]) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(transport.write_addr(&self.name, &MacAddress(*mac))?)
+    pub fn set_mac(&self, mac: MacAddr) -> Result<(), Error> {
+        self.transport
+            .with_transport(|transport| Ok(transport.write_addr(&self.name, &MacAddress(mac))?))
     }
 
     /// Enable or disable the core fabric
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
     pub fn set_enable(&self, enabled: bool) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        Ok(transport.write_addr(
-            &self.name,
-            &PromiscRstEn {
-                soft_rst: false,
-                promisc: false,
-                enable: enabled,
-            },
-        )?)
+        self.transport.with_transport(|transport| {
+            let mut pre: PromiscRstEn = transport.read_addr(&self.name)?;
+            pre.soft_rst = false;
+            pre.enable = enabled;
+            Ok(transport.write_addr(&self.name, &pre)?)
+        })
     }
 
-    /// Toggle the software reset of the core
+    /// Enable or disable promiscuous mode, where the core accepts packets regardless of their
+    /// destination MAC address
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn set_promiscuous(&self, promiscuous: bool) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            let mut pre: PromiscRstEn = transport.read_addr(&self.name)?;
+            pre.promisc = promiscuous;
+            Ok(transport.write_addr(&self.name, &pre)?)
+        })
+    }
+
+    /// Get the state of the link along with the enable, promiscuous, and reset settings
     /// # Errors
     /// Returns an error on bad transport
+    pub fn link_status(&self) -> Result<LinkStatus, Error> {
+        self.transport.with_transport(|transport| {
+            let status: Status = transport.read_addr(&self.name)?;
+            let ctrl: PromiscRstEn = transport.read_addr(&self.name)?;
+            Ok(LinkStatus {
+                link_up: status.link_up,
+                phy_ready: status.phy_ready,
+                rx_eq_status: status.rx_eq_status,
+                enabled: ctrl.enable,
+                promiscuous: ctrl.promisc,
+                in_reset: ctrl.soft_rst,
+            })
+        })
+    }
+
+    /// Poll the link until it comes up, returning its status
+    /// # Errors
+    /// Returns an error on bad transport or [`Error::LinkTimeout`] if the link is still down after
+    /// `timeout`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "ten_gbe::wait_link_up",
+            skip_all,
+            fields(device = %self.name),
+            err,
+        )
+    )]
+    pub fn wait_link_up(&self, timeout: Duration) -> Result<LinkStatus, Error> {
+        // The transport is only held for each poll so others can use it while we wait
+        poll(
+            || self.link_status(),
+            |status| status.link_up,
+            timeout,
+            LINK_POLL_INTERVAL,
+        )?
+        .map_err(|t| Error::LinkTimeout {
+            timeout,
+            last: t.last,
+        })
+    }
+
+    /// Check the health of the core, reporting the link status and, if `arp_target` is given,
+    /// whether an ARP request for it sent from the CPU TX buffer was answered in the CPU RX buffer
+    /// within `timeout`. Frames that aren't the reply are discarded. Buffer sizes are in the word
+    /// sizes reported by the core.
+    /// # Errors
+    /// Returns an error on bad transport or if the core has no CPU interface to test with
     #[allow(clippy::missing_panics_doc)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "ten_gbe::self_test", skip_all, fields(device = %self.name), err)
+    )]
+    pub fn self_test(
+        &self,
+        arp_target: Option<Ipv4Addr>,
+        timeout: Duration,
+    ) -> Result<SelfTest, Error> {
+        let link = self.link_status()?;
+        let Some(target) = arp_target else {
+            return Ok(SelfTest { link, arp: None });
+        };
+        let (rx_word, ip) = self.transport.with_transport(|transport| {
+            let core: CoreType = transport.read_addr(&self.name)?;
+            if !core.cpu_tx_enable {
+                return Err(Error::NoCpuInterface("TX"));
+            }
+            if !core.cpu_rx_enable {
+                return Err(Error::NoCpuInterface("RX"));
+            }
+            let mac: MacAddress = transport.read_addr(&self.name)?;
+            let ip: IpAddress = transport.read_addr(&self.name)?;
+            let words: WordLengths = transport.read_addr(&self.name)?;
+            let (tx_word, rx_word) = (word_size(words.tx_word_size), word_size(words.rx_word_size));
+            let mut frame = arp_request(mac.0, ip.0, target);
+            let tx_words = (frame.len() + tx_word - 1) / tx_word;
+            frame.resize(tx_words * tx_word, 0);
+            transport.write_bytes(&self.name, CPU_TX_BUFFER, &frame)?;
+            // Writing the TX size (the upper half of the register) sends the frame. The sizes
+            // share a word, so the RX size goes back as it was.
+            let mut avail: BytesAvailable = transport.read_addr(&self.name)?;
+            avail.tx_size = u16::try_from(tx_words).expect("A single frame");
+            transport.write_addr(&self.name, &avail)?;
+            Ok((rx_word, ip.0))
+        })?;
+        let mut discarded = 0;
+        let read_reply = || {
+            let frame = self.transport.with_transport(|transport| {
+                let mut avail: BytesAvailable = transport.read_addr(&self.name)?;
+                if avail.rx_size == 0 {
+                    return Ok::<_, Error>(None);
+                }
+                let frame = transport.read_n_bytes(
+                    &self.name,
+                    CPU_RX_BUFFER,
+                    avail.rx_size as usize * rx_word,
+                )?;
+                // Clearing the RX size (the lower half) hands the buffer back to the core
+                avail.rx_size = 0;
+                transport.write_addr(&self.name, &avail)?;
+                Ok(Some(frame))
+            })?;
+            let reply = frame.and_then(|frame| {
+                let reply = arp_reply_from(&frame, target, ip);
+                discarded += usize::from(reply.is_none());
+                reply
+            });
+            Ok::<_, Error>(reply)
+        };
+        // A steady stream of unrelated frames mustn't keep us waiting past the timeout
+        let reply = poll(read_reply, Option::is_some, timeout, LINK_POLL_INTERVAL)?
+            .unwrap_or_else(|t| t.last);
+        Ok(SelfTest {
+            link,
+            arp: Some(ArpCheck {
+                target,
+                reply,
+                discarded,
+            }),
+        })
+    }
+
+    /// Toggle the software reset of the core
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "ten_gbe::toggle_reset",
+            skip_all,
+            fields(device = %self.name),
+            err,
+        )
+    )]
     pub fn toggle_reset(&self) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        let mut pre: PromiscRstEn = transport.read_addr(&self.name)?;
-        pre.soft_rst = false;
-        transport.write_addr(&self.name, &pre)?;
-        pre.soft_rst = true;
-        transport.write_addr(&self.name, &pre)?;
-        pre.soft_rst = false;
-        transport.write_addr(&self.name, &pre)?;
-        Ok(())
+        self.transport.with_transport(|transport| {
+            let mut pre: PromiscRstEn = transport.read_addr(&self.name)?;
+            pre.soft_rst = false;
+            transport.write_addr(&self.name, &pre)?;
+            pre.soft_rst = true;
+            transport.write_addr(&self.name, &pre)?;
+            pre.soft_rst = false;
+            transport.write_addr(&self.name, &pre)?;
+            Ok(())
+        })
+    }
+
+    /// Reset the core without corrupting packets in flight. The fabric is disabled, the TX buffer
+    /// is given up to `timeout` to drain, the core is reset and re-enabled, and then the link is
+    /// given up to `timeout` to come back. Returns the status of the link afterwards.
+    /// # Errors
+    /// Returns an error on bad transport, if the TX buffer doesn't drain
+    /// ([`Error::DrainTimeout`]), if the link doesn't come back ([`Error::LinkTimeout`]), or if the
+    /// buffer counters don't make sense after the reset ([`Error::BadCounters`])
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "ten_gbe::safe_reset",
+            skip_all,
+            fields(device = %self.name),
+            err,
+        )
+    )]
+    pub fn safe_reset(&self, timeout: Duration) -> Result<LinkStatus, Error> {
+        self.set_enable(false)?;
+        poll(
+            || {
+                self.transport.with_transport(|transport| {
+                    Ok::<BytesAvailable, Error>(transport.read_addr(&self.name)?)
+                })
+            },
+            |avail| avail.tx_size == 0,
+            timeout,
+            LINK_POLL_INTERVAL,
+        )?
+        .map_err(|t| Error::DrainTimeout {
+            timeout,
+            tx_size: t.last.tx_size,
+        })?;
+        self.toggle_reset()?;
+        self.set_enable(true)?;
+        let link = self.wait_link_up(timeout)?;
+        self.transport.with_transport(|transport| {
+            let avail: BytesAvailable = transport.read_addr(&self.name)?;
+            let sizes: BufferSizes = transport.read_addr(&self.name)?;
+            // Nothing was queued going into the reset, and cores without a CPU RX buffer report a
+            // size of zero
+            if avail.tx_size != 0 || (sizes.rx_buf_max != 0 && avail.rx_size > sizes.rx_buf_max) {
+                return Err(Error::BadCounters {
+                    tx_size: avail.tx_size,
+                    rx_size: avail.rx_size,
+                });
+            }
+            Ok(link)
+        })
     }
 
     /// Set a single entry in the ARP table
     /// # Errors
     /// Returns an error on bad transport
-    #[allow(clippy::missing_panics_doc)]
-    pub fn set_single_arp_entry(&self, ip: Ipv4Addr, mac: &[u8; 6]) -> Result<(), Error> {
-        let tarc = self.transport.upgrade().unwrap();
-        let mut transport = (*tarc).lock().unwrap();
-        // ARP entries start at 0x1000 and are laid out like MacAddress
-        // two bytes of zeros then mac
-        let offset = 0x1000 + 8 * (*ip.octets().last().unwrap()) as usize;
-        transport.write(&self.name, offset, &MacAddress(*mac))?;
-        Ok(())
+    pub fn set_single_arp_entry(&self, ip: Ipv4Addr, mac: MacAddr) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            transport.write(&self.name, arp_offset(ip), &MacAddress(mac))?;
+            Ok(())
+        })
+    }
+
+    /// Get the entry of the ARP table for `ip`, which is only indexed by the last octet
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn get_arp_entry(&self, ip: Ipv4Addr) -> Result<MacAddr, Error> {
+        self.transport.with_transport(|transport| {
+            let entry: MacAddress = transport.read(&self.name, arp_offset(ip))?;
+            Ok(entry.0)
+        })
+    }
+
+    /// Set every entry of `entries` in the ARP table
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "ten_gbe::set_arp_table",
+            skip_all,
+            fields(device = %self.name),
+            err,
+        )
+    )]
+    pub fn set_arp_table(&self, entries: &[(Ipv4Addr, MacAddr)]) -> Result<(), Error> {
+        self.transport.with_transport(|transport| {
+            for (ip, mac) in entries {
+                transport.write(&self.name, arp_offset(*ip), &MacAddress(*mac))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Apply a full network configuration, returning every setting whose value changed.
+    /// The core is disabled while the addresses are written, then reset and re-enabled, and
+    /// everything is read back to make sure the configuration took.
+    /// # Errors
+    /// Returns an error on an invalid configuration, bad transport, or if the readback doesn't
+    /// match the requested configuration
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "ten_gbe::configure", skip_all, fields(device = %self.name), err)
+    )]
+    pub fn configure(&self, config: &NetworkConfig) -> Result<Vec<ConfigChange>, Error> {
+        config.validate()?;
+        self.transport.with_transport(|transport| {
+            let before = snapshot(&mut *transport, &self.name, &config.arp)?;
+            // Disable the core while we change things underneath it
+            let mut pre: PromiscRstEn = transport.read_addr(&self.name)?;
+            pre.enable = false;
+            pre.soft_rst = false;
+            transport.write_addr(&self.name, &pre)?;
+            transport.write_addr(&self.name, &MacAddress(config.mac))?;
+            transport.write_addr(&self.name, &IpAddress(config.ip))?;
+            transport.write_addr(&self.name, &Netmask(config.netmask))?;
+            transport.write_addr(&self.name, &GatewayAddress(config.gateway))?;
+            transport.write_addr(
+                &self.name,
+                &Port {
+                    port_mask: 0xFF,
+                    port: config.port,
+                },
+            )?;
+            for (ip, mac) in &config.arp {
+                transport.write(&self.name, arp_offset(*ip), &MacAddress(*mac))?;
+            }
+            // Reset to latch the new settings, then bring the core back up
+            pre.soft_rst = true;
+            transport.write_addr(&self.name, &pre)?;
+            pre.soft_rst = false;
+            pre.enable = true;
+            transport.write_addr(&self.name, &pre)?;
+            // Make sure everything landed
+            let after = snapshot(&mut *transport, &self.name, &config.arp)?;
+            let expected = [
+                config.ip.to_string(),
+                config.netmask.to_string(),
+                config.gateway.to_string(),
+                config.mac.to_string(),
+                config.port.to_string(),
+            ]
+            .into_iter()
+            .chain(config.arp.iter().map(|(_, mac)| mac.to_string()));
+            for ((field, actual), expected) in after.iter().zip(expected) {
+                if *actual != expected {
+                    return Err(Error::Readback {
+                        field: field.clone(),
+                        expected,
+                        actual: actual.clone(),
+                    });
+                }
+            }
+            Ok(before
+                .into_iter()
+                .zip(after)
+                .filter(|((_, b), (_, a))| a != b)
+                .map(|((field, before), (_, after))| ConfigChange {
+                    field,
+                    before,
+                    after,
+                })
+                .collect())
+        })
+    }
+}
+
+impl<T> FromFpg<T> for TenGbE<T>
+where
+    T: Transport,
+{
+    fn from_metadata(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        // The core is configured at runtime, so there's nothing to take from the metadata
+        _metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Ok(Self::from_fpg(transport, name)?)
+    }
+}
+
+impl<T> YellowBlock<T> for TenGbE<T>
+where
+    T: Transport + 'static,
+{
+    fn from_device(
+        transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_metadata(transport, name, &device(devices, name)?.metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:ten_gbe"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        format!("xps:ten_gbe `{}`", self.name)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{core::Register, transport::mock::Mock};
-    use std::{collections::HashMap, sync::Arc};
+    use crate::{
+        core::Register,
+        transport::{
+            mock::Mock,
+            sim::SimulatedFpga,
+        },
+    };
+    use casper_utils::design_sources::fpg::read_fpg_file;
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
 
     #[test]
     fn test_set_single_arp_entry() {
@@ -358,7 +1020,7 @@ mod tests {
         let gbe0 = TenGbE::new(&transport, "gbe0");
         gbe0.set_single_arp_entry(
             "192.168.0.1".parse().unwrap(),
-            &[0xDE, 0xAD, 0xBE, 0xEF, 0xB0, 0xBA],
+            "de:ad:be:ef:b0:ba".parse().unwrap(),
         )
         .unwrap();
 
@@ -369,5 +1031,283 @@ mod tests {
             .unwrap();
 
         assert_eq!(vec![0, 0, 0xDE, 0xAD, 0xBE, 0xEF, 0xB0, 0xBA], bytes);
+        assert_eq!(
+            gbe0.get_arp_entry("10.0.0.1".parse().unwrap()).unwrap(),
+            MacAddr([0xDE, 0xAD, 0xBE, 0xEF, 0xB0, 0xBA])
+        );
+    }
+
+    #[test]
+    fn test_mac_addr() {
+        let mac: MacAddr = "02:2E:46:e0:64:a1".parse().unwrap();
+        assert_eq!(mac, MacAddr([0x02, 0x2E, 0x46, 0xE0, 0x64, 0xA1]));
+        assert_eq!(mac.to_string(), "02:2e:46:e0:64:a1");
+        assert_eq!("02-2e-46-e0-64-a1".parse(), Ok(mac));
+        for bad in [
+            "02:2e:46:e0:64",
+            "02:2e:46:e0:64:a1:00",
+            "02:2e:46:e0:64:a",
+            "zz:2e:46:e0:64:a1",
+        ] {
+            assert!(bad.parse::<MacAddr>().is_err(), "{bad}");
+        }
+        assert_eq!(u64::from(mac), 0x022E_46E0_64A1);
+        assert_eq!(MacAddr::from(0xFFFF_022E_46E0_64A1), mac);
+        assert!(!mac.is_multicast());
+        assert!(MacAddr([0xFF; 6]).is_multicast());
+    }
+
+    #[test]
+    fn test_link_status() {
+        let transport = Mock::new(HashMap::from([(
+            "gbe0".into(),
+            Register {
+                addr: 0,
+                length: 12411,
+            },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        let gbe0 = TenGbE::new(&transport, "gbe0");
+        gbe0.set_promiscuous(true).unwrap();
+        gbe0.set_enable(true).unwrap();
+        // Link up and PHY ready with some equalizer status
+        transport
+            .lock()
+            .unwrap()
+            .write_bytes("gbe0", 0x34, &[0, 0, 0, 0, 0, 0, 0x0F, 0b11])
+            .unwrap();
+        let status = gbe0.link_status().unwrap();
+        assert_eq!(
+            status,
+            LinkStatus {
+                link_up: true,
+                phy_ready: true,
+                rx_eq_status: 0x0F,
+                enabled: true,
+                promiscuous: true,
+                in_reset: false,
+            }
+        );
+        gbe0.set_promiscuous(false).unwrap();
+        let status = gbe0.link_status().unwrap();
+        assert!(!status.promiscuous && status.enabled);
+    }
+
+    #[test]
+    fn test_wait_link_and_self_test() {
+        let transport = Mock::new(HashMap::from([(
+            "gbe0".into(),
+            Register {
+                addr: 0,
+                length: CPU_RX_BUFFER + 0x800,
+            },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        let gbe0 = TenGbE::new(&transport, "gbe0");
+        assert!(matches!(
+            gbe0.wait_link_up(Duration::from_millis(20)),
+            Err(Error::LinkTimeout { last, .. }) if !last.link_up
+        ));
+        // A 10 GbE core without the CPU interfaces
+        transport
+            .lock()
+            .unwrap()
+            .write_bytes("gbe0", 0x0, &[0, 0, 0, 2])
+            .unwrap();
+        assert!(matches!(
+            gbe0.self_test(Some("10.0.0.1".parse().unwrap()), Duration::ZERO),
+            Err(Error::NoCpuInterface("TX"))
+        ));
+
+        let ip = "10.0.0.2".parse().unwrap();
+        let target = "10.0.0.1".parse().unwrap();
+        let peer = MacAddr([0x02, 0, 0, 0, 0, 1]);
+        gbe0.set_ip(ip).unwrap();
+        gbe0.set_mac(MacAddr([0x02, 0, 0, 0, 0, 2])).unwrap();
+        {
+            let mut t = transport.lock().unwrap();
+            // Link up, CPU interfaces enabled, and a reply waiting in the RX buffer
+            t.write_bytes("gbe0", 0x3B, &[1]).unwrap();
+            t.write_bytes("gbe0", 0x0, &[1, 1, 0, 2]).unwrap();
+            let mut reply = arp_request(peer, target, ip);
+            reply[21] = 2;
+            t.write_bytes("gbe0", CPU_RX_BUFFER, &reply).unwrap();
+            t.write_bytes("gbe0", 0x2A, &[0, 8]).unwrap();
+        }
+        assert!(gbe0.wait_link_up(Duration::ZERO).unwrap().link_up);
+        let result = gbe0
+            .self_test(Some(target), Duration::from_millis(20))
+            .unwrap();
+        assert!(result.passed());
+        assert_eq!(result.arp.unwrap().reply, Some(peer));
+        let mut t = transport.lock().unwrap();
+        // The request went out as 8 words and the RX buffer was released
+        let request = t.read_n_bytes("gbe0", CPU_TX_BUFFER, 42).unwrap();
+        assert_eq!(request[..6], [0xFF; 6]);
+        assert_eq!(request[38..42], [10, 0, 0, 1]);
+        let avail: BytesAvailable = t.read_addr("gbe0").unwrap();
+        assert_eq!((avail.tx_size, avail.rx_size), (8, 0));
+        drop(t);
+        // Nothing answers the second time
+        let result = gbe0
+            .self_test(Some(target), Duration::from_millis(20))
+            .unwrap();
+        assert!(!result.passed());
+        assert_eq!(result.arp.unwrap().reply, None);
+    }
+
+    #[test]
+    fn test_self_test_discards() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let mut sim = SimulatedFpga::new(&design);
+        let ip = "10.0.0.2".parse().unwrap();
+        let target = "10.0.0.1".parse().unwrap();
+        // The target answering someone else, which turns up every time the RX buffer is checked
+        let mut stray = arp_request(
+            MacAddr([0x02, 0, 0, 0, 0, 1]),
+            target,
+            "10.0.0.3".parse().unwrap(),
+        );
+        stray[21] = 2;
+        sim.on_read("gbe0", move |mem, offset, _| {
+            if offset == BytesAvailable::addr() as usize {
+                mem.write_bytes("gbe0", CPU_RX_BUFFER, &stray)?;
+                mem.write_bytes("gbe0", BytesAvailable::addr() as usize + 2, &[0, 8])?;
+            }
+            Ok(())
+        });
+        let transport = Arc::new(Mutex::new(sim));
+        let gbe0 = TenGbE::new(&transport, "gbe0");
+        gbe0.set_ip(ip).unwrap();
+        transport
+            .lock()
+            .unwrap()
+            .memory()
+            .write_bytes("gbe0", 0x0, &[1, 1, 0, 2])
+            .unwrap();
+        let arp = gbe0
+            .self_test(Some(target), Duration::from_millis(20))
+            .unwrap()
+            .arp
+            .unwrap();
+        assert_eq!(arp.reply, None);
+        assert!(arp.discarded > 0);
+    }
+
+    #[test]
+    fn test_safe_reset() {
+        let transport = Mock::new(HashMap::from([(
+            "gbe0".into(),
+            Register {
+                addr: 0,
+                length: 12411,
+            },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        let gbe0 = TenGbE::new(&transport, "gbe0");
+        gbe0.set_enable(true).unwrap();
+        // Frames stuck in the TX buffer
+        transport
+            .lock()
+            .unwrap()
+            .write_bytes("gbe0", 0x28, &[0, 3])
+            .unwrap();
+        assert!(matches!(
+            gbe0.safe_reset(Duration::from_millis(20)),
+            Err(Error::DrainTimeout { tx_size: 3, .. })
+        ));
+        // The fabric stays disabled rather than transmitting half-sent frames
+        assert!(!gbe0.link_status().unwrap().enabled);
+
+        {
+            let mut t = transport.lock().unwrap();
+            t.write_bytes("gbe0", 0x28, &[0, 0]).unwrap();
+            t.write_bytes("gbe0", 0x3B, &[1]).unwrap();
+        }
+        let link = gbe0.safe_reset(Duration::from_millis(20)).unwrap();
+        assert!(link.link_up && link.enabled && !link.in_reset);
+
+        // More received than the RX buffer holds
+        {
+            let mut t = transport.lock().unwrap();
+            t.write_bytes("gbe0", 0x4, &[0, 0, 0, 0x10]).unwrap();
+            t.write_bytes("gbe0", 0x2A, &[0, 0x20]).unwrap();
+        }
+        assert!(matches!(
+            gbe0.safe_reset(Duration::ZERO),
+            Err(Error::BadCounters {
+                tx_size: 0,
+                rx_size: 0x20
+            })
+        ));
+    }
+
+    #[test]
+    fn test_configure() {
+        let transport = Mock::new(HashMap::from([(
+            "gbe0".into(),
+            Register {
+                addr: 0,
+                length: 12411,
+            },
+        )]));
+        let transport = Arc::new(Mutex::new(transport));
+        let gbe0 = TenGbE::new(&transport, "gbe0");
+        let mut config = NetworkConfig {
+            ip: "192.168.0.20".parse().unwrap(),
+            netmask: "255.255.255.0".parse().unwrap(),
+            gateway: "192.168.0.1".parse().unwrap(),
+            mac: "02:00:00:00:00:14".parse().unwrap(),
+            port: 60000,
+            arp: vec![(
+                "192.168.0.1".parse().unwrap(),
+                "de:ad:be:ef:b0:ba".parse().unwrap(),
+            )],
+        };
+        let changes = gbe0.configure(&config).unwrap();
+        assert_eq!(changes.len(), 6);
+        assert_eq!(changes[0].field, "ip");
+        assert_eq!(changes[0].before, "0.0.0.0");
+        assert_eq!(changes[0].after, "192.168.0.20");
+        assert_eq!(gbe0.get_port().unwrap(), 60000);
+        let pre: PromiscRstEn = transport.lock().unwrap().read_addr("gbe0").unwrap();
+        assert!(pre.enable && !pre.soft_rst);
+
+        // Reapplying with one change only reports that change
+        config.port = 60001;
+        let changes = gbe0.configure(&config).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "port");
+
+        config.gateway = "10.0.0.1".parse().unwrap();
+        assert!(matches!(
+            gbe0.configure(&config),
+            Err(Error::GatewayOutsideSubnet { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = NetworkConfig {
+            ip: "10.0.0.255".parse().unwrap(),
+            netmask: "255.255.255.0".parse().unwrap(),
+            gateway: "10.0.0.1".parse().unwrap(),
+            mac: MacAddr([0x02, 0, 0, 0, 0, 1]),
+            port: 1,
+            arp: vec![],
+        };
+        assert!(matches!(config.validate(), Err(Error::ReservedIp { .. })));
+        let config = NetworkConfig {
+            netmask: "255.0.255.0".parse().unwrap(),
+            ..config
+        };
+        assert!(matches!(config.validate(), Err(Error::BadNetmask(_))));
+        let config = NetworkConfig {
+            ip: "10.0.0.2".parse().unwrap(),
+            netmask: "255.255.255.0".parse().unwrap(),
+            mac: MacAddr([0x01, 0, 0x5E, 0, 0, 1]),
+            ..config
+        };
+        assert!(matches!(config.validate(), Err(Error::MulticastMac(_))));
     }
 }
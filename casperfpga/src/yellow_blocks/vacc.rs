@@ -0,0 +1,283 @@
+//! # Vector Accumulator
+//!
+//! CASPER spectrometers accumulate spectra in a vector accumulator controlled by an `acc_len`
+//! software register (number of spectra per accumulation) and report progress through an
+//! `acc_cnt` software register that increments every time a new accumulation is dumped to the
+//! output BRAMs.
+//!
+//! The vacc isn't a single yellow block in the toolflow, so there is no `from_fpg` constructor -
+//! the register names are supplied explicitly.
+//!
+//! Spectra are often split across multiple BRAMs (i.e. even and odd channels), these are
+//! interleaved on readout such that channel `i` comes from BRAM `i % n` at word `i / n`.
+
+use crate::{
+    core::poll,
+    transport::Transport,
+    yellow_blocks::TransportHandle,
+};
+use fixed::traits::Fixed;
+use std::{
+    marker::PhantomData,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::Error),
+    #[error("Timed out waiting for a new accumulation, acc_cnt stayed at {0}")]
+    Timeout(u32),
+    #[error("The vector accumulator needs at least one output BRAM")]
+    NoBrams,
+}
+
+/// A single accumulated spectrum
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spectrum<F> {
+    /// The value of `acc_cnt` the spectrum was read after
+    pub acc_cnt: u32,
+    /// The accumulated values, one per channel
+    pub values: Vec<F>,
+    /// Whether each channel sits at the limits of the output type, implying the accumulator
+    /// saturated
+    pub saturated: Vec<bool>,
+}
+
+/// The vector accumulator control registers and output BRAMs
+#[derive(Debug)]
+pub struct Vacc<T, F> {
+    /// Upwards pointer to the parent class' transport
+    transport: TransportHandle<T>,
+    /// The name of the accumulation length register
+    acc_len: String,
+    /// The name of the accumulation count register
+    acc_cnt: String,
+    /// The names of the output BRAMs, in interleaving order
+    brams: Vec<String>,
+    /// Number of words in each output BRAM
+    words: usize,
+    /// Marker for the fixed point type of the output
+    phantom: PhantomData<F>,
+}
+
+impl<T, F, const N: usize> Vacc<T, F>
+where
+    T: Transport,
+    F: Fixed<Bytes = [u8; N]>,
+{
+    /// Construct a vector accumulator from its register names and the number of words in each
+    /// output BRAM
+    /// # Errors
+    /// Returns an error if `brams` is empty
+    pub fn new(
+        transport: &Arc<Mutex<T>>,
+        acc_len: &str,
+        acc_cnt: &str,
+        brams: &[&str],
+        words: usize,
+    ) -> Result<Self, Error> {
+        if brams.is_empty() {
+            return Err(Error::NoBrams);
+        }
+        let transport = Arc::downgrade(transport);
+        Ok(Self {
+            transport: TransportHandle::new(transport, acc_len),
+            acc_len: acc_len.to_string(),
+            acc_cnt: acc_cnt.to_string(),
+            brams: brams.iter().map(ToString::to_string).collect(),
+            words,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Number of channels in a full spectrum
+    #[must_use]
+    pub fn channels(&self) -> usize {
+        self.brams.len() * self.words
+    }
+
+    /// Set the number of spectra per accumulation
+    /// # Errors
+    /// Returns an error on bad transport
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "vacc::set_acc_len",
+            skip_all,
+            fields(device = %self.acc_len),
+            err,
+        )
+    )]
+    pub fn set_acc_len(&self, len: u32) -> Result<(), Error> {
+        self.transport
+            .with_transport(|transport| Ok(transport.write(&self.acc_len, 0, &len)?))
+    }
+
+    /// Get the number of spectra per accumulation
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn get_acc_len(&self) -> Result<u32, Error> {
+        self.transport
+            .with_transport(|transport| Ok(transport.read(&self.acc_len, 0)?))
+    }
+
+    /// Get the current accumulation count
+    /// # Errors
+    /// Returns an error on bad transport
+    pub fn acc_cnt(&self) -> Result<u32, Error> {
+        self.transport
+            .with_transport(|transport| Ok(transport.read(&self.acc_cnt, 0)?))
+    }
+
+    /// Block until `acc_cnt` changes from its current value, returning the new count
+    /// # Errors
+    /// Returns an error on bad transport or if no new accumulation arrived within `timeout`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "vacc::wait_for_acc",
+            skip_all,
+            fields(device = %self.acc_cnt),
+            err,
+        )
+    )]
+    pub fn wait_for_acc(&self, timeout: Duration, poll_interval: Duration) -> Result<u32, Error> {
+        let start = self.acc_cnt()?;
+        // Each poll takes the transport on its own so others can use it while we wait
+        poll(
+            || self.acc_cnt(),
+            |cnt| *cnt != start,
+            timeout,
+            poll_interval,
+        )?
+        .map_err(|t| Error::Timeout(t.last))
+    }
+
+    /// Read out the current spectrum, interleaving the output BRAMs
+    /// # Errors
+    /// Returns an error on bad transport
+    #[allow(clippy::missing_panics_doc)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "vacc::read_spectrum",
+            skip_all,
+            fields(device = %self.acc_cnt),
+            err,
+        )
+    )]
+    pub fn read_spectrum(&self) -> Result<Spectrum<F>, Error> {
+        self.transport.with_transport(|transport| {
+            let acc_cnt: u32 = transport.read(&self.acc_cnt, 0)?;
+            let outputs = self
+                .brams
+                .iter()
+                .map(|name| transport.read_n_bytes(name, 0, self.words * N))
+                .collect::<Result<Vec<_>, _>>()?;
+            let values: Vec<F> = (0..self.words)
+                .flat_map(|word| {
+                    outputs.iter().map(move |bytes| {
+                        F::from_be_bytes(bytes[word * N..(word + 1) * N].try_into().unwrap())
+                    })
+                })
+                .collect();
+            let saturated = values
+                .iter()
+                .map(|v| *v == F::MAX || (F::IS_SIGNED && *v == F::MIN))
+                .collect();
+            Ok(Spectrum {
+                acc_cnt,
+                values,
+                saturated,
+            })
+        })
+    }
+
+    /// Wait for the next accumulation and read it out
+    /// # Errors
+    /// Returns an error on bad transport or if no new accumulation arrived within `timeout`
+    pub fn next_spectrum(
+        &self,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<Spectrum<F>, Error> {
+        self.wait_for_acc(timeout, poll_interval)?;
+        self.read_spectrum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Register,
+        transport::mock::Mock,
+    };
+    use fixed::types::I32F0;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_read_spectrum() {
+        let transport = Mock::new(HashMap::from([
+            ("acc_len".into(), Register { addr: 0, length: 4 }),
+            ("acc_cnt".into(), Register { addr: 4, length: 4 }),
+            ("even".into(), Register { addr: 8, length: 8 }),
+            (
+                "odd".into(),
+                Register {
+                    addr: 16,
+                    length: 8,
+                },
+            ),
+        ]));
+        let transport = Arc::new(Mutex::new(transport));
+        {
+            let mut t = transport.lock().unwrap();
+            t.write("acc_cnt", 0, &3u32).unwrap();
+            t.write("even", 0, &1i32).unwrap();
+            t.write("even", 4, &i32::MAX).unwrap();
+            t.write("odd", 0, &2i32).unwrap();
+            t.write("odd", 4, &-4i32).unwrap();
+        }
+        let vacc =
+            Vacc::<_, I32F0>::new(&transport, "acc_len", "acc_cnt", &["even", "odd"], 2).unwrap();
+        vacc.set_acc_len(1024).unwrap();
+        assert_eq!(vacc.get_acc_len().unwrap(), 1024);
+        assert_eq!(vacc.channels(), 4);
+        let spec = vacc.read_spectrum().unwrap();
+        assert_eq!(spec.acc_cnt, 3);
+        assert_eq!(
+            spec.values,
+            [1, 2, i32::MAX, -4].map(I32F0::from_num).to_vec()
+        );
+        assert_eq!(spec.saturated, vec![false, false, true, false]);
+        assert!(matches!(
+            vacc.wait_for_acc(Duration::from_millis(5), Duration::from_millis(1)),
+            Err(Error::Timeout(3))
+        ));
+        // The transport is free between polls for whoever dumps the next accumulation
+        let dumper = {
+            let transport = transport.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(5));
+                transport
+                    .lock()
+                    .unwrap()
+                    .write("acc_cnt", 0, &4u32)
+                    .unwrap();
+            })
+        };
+        assert_eq!(
+            vacc.wait_for_acc(Duration::from_secs(1), Duration::from_millis(1))
+                .unwrap(),
+            4
+        );
+        dumper.join().unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+//! # XSG
+//!
+//! Every design has a single platform block (`xps:xsg`, named after the board, i.e. `SNAP`) that
+//! records what the design was built for: the board and FPGA part, where the FPGA clock comes
+//! from, and its rate. It has no registers, so [`Xsg`] is just those values, typed, for other
+//! blocks and bringup code that need the platform context of the design.
+
+use crate::{
+    transport::Transport,
+    yellow_blocks::{
+        device,
+        metadata_entry,
+        FromFpg,
+        Metadata,
+        YellowBlock,
+    },
+};
+use casper_utils::design_sources::{
+    Device,
+    DeviceKind,
+    Devices,
+};
+use std::{
+    any::Any,
+    fmt::Display,
+    sync::{
+        Mutex,
+        Weak,
+    },
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("The design has no platform (`xps:xsg`) block")]
+    NoPlatform,
+    #[error("Bad clock rate `{0}` from the fpg file, expected a number of MHz")]
+    BadClockRate(String),
+}
+
+/// The boards CASPER designs are built for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Board {
+    Snap,
+    Snap2,
+    Skarab,
+    Roach2,
+    RedPitaya,
+    /// Any other board, by the name the toolflow gave it
+    Other(String),
+}
+
+impl From<&str> for Board {
+    fn from(name: &str) -> Self {
+        match name {
+            "SNAP" => Self::Snap,
+            "SNAP2" => Self::Snap2,
+            "SKARAB" => Self::Skarab,
+            "ROACH2" => Self::Roach2,
+            "RED_PITAYA" => Self::RedPitaya,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+impl Display for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Snap => "SNAP",
+            Self::Snap2 => "SNAP2",
+            Self::Skarab => "SKARAB",
+            Self::Roach2 => "ROACH2",
+            Self::RedPitaya => "RED_PITAYA",
+            Self::Other(name) => name,
+        })
+    }
+}
+
+/// Where the FPGA clock of a design comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The board's own system clock (`sys_clk`)
+    System,
+    /// The sample clock of an ADC (`adc<n>_clk`)
+    Adc(u8),
+    /// Any other clock, by the name the toolflow gave it
+    Other(String),
+}
+
+impl ClockSource {
+    /// Whether the clock is the board's own, rather than coming in with the data from a
+    /// peripheral
+    #[must_use]
+    pub fn is_internal(&self) -> bool {
+        *self == Self::System
+    }
+}
+
+impl From<&str> for ClockSource {
+    fn from(name: &str) -> Self {
+        if name == "sys_clk" {
+            return Self::System;
+        }
+        name.strip_prefix("adc")
+            .and_then(|rest| rest.strip_suffix("_clk"))
+            .and_then(|n| n.parse().ok())
+            .map_or_else(|| Self::Other(name.to_string()), Self::Adc)
+    }
+}
+
+impl Display for ClockSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::System => f.write_str("sys_clk"),
+            Self::Adc(n) => write!(f, "adc{n}_clk"),
+            Self::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+/// The platform block of a design
+#[derive(Debug, Clone, PartialEq)]
+pub struct Xsg {
+    /// The name of the block in the design
+    name: String,
+    /// The board the design was built for
+    board: Board,
+    /// The FPGA part, if the toolflow recorded it
+    part: Option<String>,
+    /// Where the FPGA clock comes from
+    clock_source: ClockSource,
+    /// The FPGA clock rate in MHz
+    clock_rate: f64,
+}
+
+impl Xsg {
+    /// Builds a [`Xsg`] from FPG description strings, where `hw_sys` is the board and part
+    /// separated by a colon (i.e. `SNAP:xc7k160t`)
+    /// # Errors
+    /// Returns an error on bad string arguments
+    pub fn from_fpg(
+        name: &str,
+        hw_sys: &str,
+        clk_src: &str,
+        clk_rate: &str,
+    ) -> Result<Self, Error> {
+        let (board, part) = match hw_sys.split_once(':') {
+            Some((board, part)) => (board, Some(part.to_string())),
+            None => (hw_sys, None),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            board: board.into(),
+            part,
+            clock_source: clk_src.into(),
+            clock_rate: clk_rate
+                .parse()
+                .map_err(|_| Error::BadClockRate(clk_rate.to_string()))?,
+        })
+    }
+
+    /// Builds the platform block of a design from its devices, wherever it is
+    /// # Errors
+    /// Returns an error if the design has no platform block or its metadata is missing or
+    /// malformed
+    pub fn from_devices(devices: &Devices) -> Result<Self, crate::yellow_blocks::Error> {
+        let (name, device) = platform_device(devices)?;
+        Self::from_entries(name, &device.metadata)
+    }
+
+    /// The block `name` from the `metadata` of its device
+    fn from_entries(name: &str, metadata: &Metadata) -> Result<Self, crate::yellow_blocks::Error> {
+        let meta = |key| metadata_entry(metadata, name, key);
+        Ok(Self::from_fpg(
+            name,
+            meta("hw_sys")?,
+            meta("clk_src")?,
+            meta("clk_rate")?,
+        )?)
+    }
+
+    /// The board the design was built for
+    #[must_use]
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// The FPGA part the design was built for, if the toolflow recorded it
+    #[must_use]
+    pub fn part(&self) -> Option<&str> {
+        self.part.as_deref()
+    }
+
+    /// Where the FPGA clock comes from
+    #[must_use]
+    pub fn clock_source(&self) -> &ClockSource {
+        &self.clock_source
+    }
+
+    /// The FPGA clock rate in MHz
+    #[must_use]
+    pub fn clock_rate(&self) -> f64 {
+        self.clock_rate
+    }
+}
+
+/// The name and device of the platform block of a design. Designs only have one, but the first by
+/// name is taken if there are more.
+/// # Errors
+/// Returns an error if the design has no platform block
+pub fn platform_device(devices: &Devices) -> Result<(&str, &Device), crate::yellow_blocks::Error> {
+    devices
+        .iter()
+        .filter(|(_, dev)| dev.kind == DeviceKind::Xsg)
+        .min_by_key(|(name, _)| name.as_str())
+        .map(|(name, dev)| (name.as_str(), dev))
+        .ok_or_else(|| Error::NoPlatform.into())
+}
+
+impl<T> FromFpg<T> for Xsg {
+    fn from_metadata(
+        _transport: Weak<Mutex<T>>,
+        name: &str,
+        metadata: &Metadata,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_entries(name, metadata)
+    }
+}
+
+impl<T> YellowBlock<T> for Xsg
+where
+    T: Transport + 'static,
+{
+    fn from_device(
+        _transport: Weak<Mutex<T>>,
+        name: &str,
+        devices: &Devices,
+    ) -> Result<Self, crate::yellow_blocks::Error> {
+        Self::from_entries(name, &device(devices, name)?.metadata)
+    }
+
+    fn kind(&self) -> &'static str {
+        "xps:xsg"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn describe(&self) -> String {
+        let board = match &self.part {
+            Some(part) => format!("{} {part}", self.board),
+            None => self.board.to_string(),
+        };
+        format!(
+            "xps:xsg `{}` ({board} at {} MHz from {})",
+            self.name, self.clock_rate, self.clock_source
+        )
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::Mock;
+    use casper_utils::design_sources::{
+        fpg::read_fpg_file,
+        FpgaDesign,
+    };
+
+    #[test]
+    fn test_from_design() {
+        let design = read_fpg_file("examples/grex_gateware.fpg").unwrap();
+        let xsg = Xsg::from_devices(design.devices()).unwrap();
+        assert_eq!(xsg.board(), &Board::Snap);
+        assert_eq!(xsg.part(), Some("xc7k160t"));
+        assert_eq!(xsg.clock_source(), &ClockSource::Adc(0));
+        assert!(!xsg.clock_source().is_internal());
+        assert!((xsg.clock_rate() - 250.).abs() < f64::EPSILON);
+        assert_eq!(
+            YellowBlock::<Mock>::describe(&xsg),
+            "xps:xsg `SNAP` (SNAP xc7k160t at 250 MHz from adc0_clk)"
+        );
+        assert!(matches!(
+            Xsg::from_devices(&Devices::new()),
+            Err(crate::yellow_blocks::Error::Xsg(Error::NoPlatform))
+        ));
+    }
+
+    #[test]
+    fn test_parse() {
+        let xsg = Xsg::from_fpg("SKARAB", "SKARAB", "sys_clk", "195.3125").unwrap();
+        assert_eq!((xsg.board(), xsg.part()), (&Board::Skarab, None));
+        assert!(xsg.clock_source().is_internal());
+        for name in ["sys_clk", "adc1_clk", "arb_clk", "adcx_clk"] {
+            assert_eq!(ClockSource::from(name).to_string(), name);
+        }
+        assert_eq!(Board::from("VCU118"), Board::Other("VCU118".to_string()));
+        assert!(matches!(
+            Xsg::from_fpg("SNAP", "SNAP:xc7k160t", "sys_clk", "fast"),
+            Err(Error::BadClockRate(_))
+        ));
+    }
+}
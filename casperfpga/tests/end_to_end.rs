@@ -0,0 +1,392 @@
+//! End-to-end tests of the yellow blocks, driving a small synthetic design through the TAPCP
+//! transport against an emulated board
+#![cfg(feature = "emulator")]
+
+use casper_utils::design_sources::fpg::File;
+use casperfpga::{
+    prelude::*,
+    register::TypedRegister,
+    transport::{
+        emulator::{
+            Board as EmulatedBoard,
+            Emulator,
+        },
+        sim::SimulatedFpga,
+    },
+    yellow_blocks::{
+        snapshot::{
+            TriggerSource,
+            WriteEnable,
+        },
+        swreg,
+        ten_gbe::{
+            MacAddr,
+            NetworkConfig,
+            CPU_RX_BUFFER,
+            CPU_TX_BUFFER,
+        },
+        xsg::{
+            Board,
+            ClockSource,
+        },
+    },
+};
+use fixed::types::{
+    I24F8,
+    U16F0,
+    U32F0,
+};
+use std::{
+    net::Ipv4Addr,
+    time::Duration,
+};
+
+fpga_from_fpg!(SyntheticFpga, "tests/synthetic.fpg");
+fpga_from_fpg!(
+    OptionalFpga,
+    "tests/synthetic.fpg",
+    optional(gbe0, fft_shift)
+);
+
+fn design() -> File {
+    read_fpg_file(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/synthetic.fpg")).unwrap()
+}
+
+/// A transport to `board`, which reboots instantly
+fn connect(board: &Emulator) -> Tapcp {
+    let mut tapcp = Tapcp::connect(board.addr(), tapcp::Platform::SNAP).unwrap();
+    tapcp.set_reboot_wait(Duration::ZERO);
+    tapcp
+}
+
+/// An emulated board programmed with the synthetic design over TAPCP, and the FPGA struct talking
+/// to it
+fn programmed<F>(setup: F) -> (Emulator, SyntheticFpga<Tapcp>)
+where
+    F: Fn(&mut SimulatedFpga) + Send + 'static,
+{
+    let design = design();
+    let board = Emulator::start(EmulatedBoard::with_design(self::design(), setup));
+    let mut tapcp = connect(&board);
+    tapcp.program(&design, false).unwrap();
+    let fpga = SyntheticFpga::new(tapcp).unwrap();
+    (board, fpga)
+}
+
+#[test]
+fn test_design_metadata() {
+    assert!((SyntheticFpga::<Mock>::CLOCK_RATE_MHZ - 250.0).abs() < f64::EPSILON);
+    assert_eq!(SyntheticFpga::<Mock>::CLOCK_SOURCE, "adc0_clk");
+    assert_eq!(SyntheticFpga::<Mock>::PLATFORM, "SNAP:xc7k160t");
+    let meta = SyntheticFpga::<Mock>::design_metadata();
+    assert_eq!(meta["sample_period"], "1");
+    assert_eq!(meta.len(), 6);
+
+    // The same values, typed, for the blocks and bringup code that need them
+    let fpga = SyntheticFpga::new(SimulatedFpga::new(&design())).unwrap();
+    assert_eq!(fpga.platform.board(), &Board::Snap);
+    assert_eq!(fpga.platform.clock_source(), &ClockSource::Adc(0));
+    assert!(
+        (fpga.platform.clock_rate() - SyntheticFpga::<Mock>::CLOCK_RATE_MHZ).abs() < f64::EPSILON
+    );
+}
+
+#[test]
+fn test_program() {
+    let design = design();
+    let board = Emulator::start(EmulatedBoard::with_design(self::design(), |_| ()));
+    let mut tapcp = connect(&board);
+    assert!(!tapcp.is_running().unwrap());
+
+    tapcp.program(&design, false).unwrap();
+    let spec = tapcp::Platform::SNAP.spec();
+    {
+        let mut board = board.board();
+        let image = spec.program_location as usize;
+        assert_eq!(
+            &board.flash[image..image + design.bitstream().len()],
+            design.bitstream()
+        );
+        assert_eq!(
+            board.progdevs,
+            vec![spec.progdev_address(spec.program_location)]
+        );
+        assert_eq!(board.fpga().md5(), design.md5_string());
+    }
+    assert!(tapcp.is_running().unwrap());
+    assert_eq!(tapcp.metadata().unwrap()["sha256"], design.sha256_string());
+    let devices = tapcp.listdev().unwrap();
+    for (name, reg) in design.registers() {
+        assert_eq!(devices[name.as_str()].length, reg.size as usize, "{name}");
+    }
+
+    // Programming the same design again doesn't touch the board
+    tapcp.program(&design, false).unwrap();
+    assert_eq!(board.board().progdevs.len(), 1);
+
+    tapcp.deprogram().unwrap();
+    assert!(board.board().fpga.is_none());
+    assert!(!tapcp.is_running().unwrap());
+}
+
+#[test]
+fn test_software_registers() {
+    let (board, fpga) = programmed(|_| ());
+
+    fpga.fft_shift.write(U32F0::from_num(0b1010_1010)).unwrap();
+    fpga.arm.write(true).unwrap();
+    {
+        let mut board = board.board();
+        let memory = board.fpga().memory();
+        assert_eq!(
+            memory.read_n_bytes("fft_shift", 0, 4).unwrap(),
+            vec![0, 0, 0, 0b1010_1010]
+        );
+        assert_eq!(memory.read_n_bytes("arm", 0, 4).unwrap(), vec![0, 0, 0, 1]);
+    }
+    assert_eq!(fpga.fft_shift.read().unwrap(), 0b1010_1010);
+    assert!(fpga.arm.read().unwrap());
+
+    // Status registers report whatever the gateware put there
+    board
+        .board()
+        .fpga()
+        .memory()
+        .write_bytes("pps_cnt", 0, &1234u32.to_be_bytes())
+        .unwrap();
+    assert_eq!(fpga.pps_cnt.read().unwrap(), 1234);
+
+    // As do registers without a yellow block of their own
+    let scratch = fpga.register::<u32>("sys_scratchpad").unwrap();
+    scratch.write(&0xdead_beef).unwrap();
+    assert_eq!(
+        board
+            .board()
+            .fpga()
+            .memory()
+            .read_n_bytes("sys_scratchpad", 0, 4)
+            .unwrap(),
+        0xdead_beefu32.to_be_bytes()
+    );
+
+    // Values narrower than a word (or between words) only change their own bytes
+    fpga.fft_shift
+        .write(U32F0::from_num(0xAAAA_AAAAu32))
+        .unwrap();
+    let upper = fpga.register::<u16>("fft_shift").unwrap();
+    let second = TypedRegister::<_, u8>::new(&fpga.transport, "fft_shift", 1).unwrap();
+    upper.write(&5).unwrap();
+    second.write(&0x12).unwrap();
+    assert_eq!(fpga.fft_shift.read().unwrap(), 0x0012_AAAA);
+    assert_eq!(upper.read().unwrap(), 0x0012);
+    upper.pulse(&0xFFFF).unwrap();
+    assert_eq!(fpga.fft_shift.read().unwrap(), 0x0012_AAAA);
+    let straddling = TypedRegister::<_, u32>::new(&fpga.transport, "beam_weights", 6).unwrap();
+    straddling.write(&0x0102_0304).unwrap();
+    assert_eq!(
+        board
+            .board()
+            .fpga()
+            .memory()
+            .read_n_bytes("beam_weights", 4, 8)
+            .unwrap(),
+        vec![0, 0, 1, 2, 3, 4, 0, 0]
+    );
+
+    // Registers wider than a word are arrays of them
+    assert_eq!(fpga.beam_weights.len(), 4);
+    fpga.beam_weights
+        .write_index(2, I24F8::from_num(-1.5))
+        .unwrap();
+    assert_eq!(
+        board
+            .board()
+            .fpga()
+            .memory()
+            .read_n_bytes("beam_weights", 8, 4)
+            .unwrap(),
+        (-384i32).to_be_bytes()
+    );
+    assert_eq!(fpga.beam_weights.read_index(2).unwrap(), -1.5);
+    assert!(matches!(
+        fpga.beam_weights.read_index(4),
+        Err(swreg::Error::OutOfBounds { index: 4, len: 4 })
+    ));
+    let weights = [0.5, -0.25, 1.0, 127.0].map(I24F8::from_num);
+    fpga.beam_weights.write(&weights).unwrap();
+    assert_eq!(fpga.beam_weights.read().unwrap(), weights);
+}
+
+#[test]
+fn test_bram() {
+    let (board, fpga) = programmed(|_| ());
+
+    // Bigger than a single TFTP block each way
+    let gains: Vec<_> = (0..2048u16).map(|i| U16F0::from_num(i * 3)).collect();
+    fpga.requant_gains.write(&gains).unwrap();
+    let memory = board
+        .board()
+        .fpga()
+        .memory()
+        .read_n_bytes("requant_gains", 0, 4096)
+        .unwrap();
+    assert_eq!(&memory[..6], &[0, 0, 0, 3, 0, 6]);
+    assert_eq!(&memory[4094..], &(2047u16 * 3).to_be_bytes());
+    assert_eq!(fpga.requant_gains.read().unwrap(), gains);
+
+    fpga.requant_gains
+        .write_range(100, &[U16F0::from_num(7), U16F0::from_num(8)])
+        .unwrap();
+    assert_eq!(
+        board
+            .board()
+            .fpga()
+            .memory()
+            .read_n_bytes("requant_gains", 200, 4)
+            .unwrap(),
+        vec![0, 7, 0, 8]
+    );
+    assert_eq!(
+        fpga.requant_gains.read_range(99, 3).unwrap(),
+        vec![U16F0::from_num(297), U16F0::from_num(7), U16F0::from_num(8)]
+    );
+}
+
+#[test]
+fn test_snapshot() {
+    // The gateware captures a ramp as soon as the block is armed
+    let (board, fpga) = programmed(|fpga| {
+        fpga.on_write("adc_snap_ctrl", |memory, _, data| {
+            if data[3] & 1 == 1 {
+                let ramp: Vec<_> = (0..0x4000u32).map(|i| i.to_le_bytes()[0]).collect();
+                memory.write_bytes("adc_snap_bram", 0, &ramp)?;
+                memory.write_bytes("adc_snap_status", 0, &0x8000_1000u32.to_be_bytes())?;
+            }
+            Ok(())
+        });
+    });
+
+    assert!(!fpga.adc_snap.done().unwrap());
+    let data = fpga
+        .adc_snap
+        .capture(
+            TriggerSource::Software,
+            WriteEnable::Always,
+            Duration::from_secs(1),
+        )
+        .unwrap();
+    assert!(data
+        .iter()
+        .enumerate()
+        .all(|(i, &b)| b == i.to_le_bytes()[0]));
+    // Armed with both overrides on the rising edge
+    assert_eq!(
+        board
+            .board()
+            .fpga()
+            .memory()
+            .read_n_bytes("adc_snap_ctrl", 0, 4)
+            .unwrap(),
+        vec![0, 0, 0, 0b111]
+    );
+}
+
+#[test]
+fn test_ten_gbe() {
+    let (board, fpga) = programmed(|_| ());
+
+    let config = NetworkConfig {
+        ip: Ipv4Addr::new(192, 168, 0, 20),
+        netmask: Ipv4Addr::new(255, 255, 255, 0),
+        gateway: Ipv4Addr::new(192, 168, 0, 1),
+        mac: MacAddr([0x02, 0, 0, 0, 0, 0x20]),
+        port: 60000,
+        arp: vec![(
+            Ipv4Addr::new(192, 168, 0, 1),
+            MacAddr([0x98, 0xb7, 0x85, 0xa7, 0xec, 0x78]),
+        )],
+    };
+    let changes = fpga.gbe0.configure(&config).unwrap();
+    assert!(changes.iter().any(|c| c.field == "ip"));
+
+    let mut board = board.board();
+    let memory = board.fpga().memory();
+    let mut read = |offset, n| memory.read_n_bytes("gbe0", offset, n).unwrap();
+    assert_eq!(read(0xC, 8), vec![0, 0, 0x02, 0, 0, 0, 0, 0x20]);
+    assert_eq!(read(0x14, 4), vec![192, 168, 0, 20]);
+    assert_eq!(read(0x18, 4), vec![192, 168, 0, 1]);
+    assert_eq!(read(0x1C, 4), vec![255, 255, 255, 0]);
+    assert_eq!(read(0x30, 4), vec![0, 0xFF, 0xEA, 0x60]);
+    // Enabled and out of reset
+    assert_eq!(read(0x2C, 4)[3] & 0b1_0001, 0b1);
+    assert_eq!(
+        read(0x1000 + 8, 8),
+        vec![0, 0, 0x98, 0xb7, 0x85, 0xa7, 0xec, 0x78]
+    );
+}
+
+#[test]
+fn test_optional_devices() {
+    // Older gateware without the 10 GbE core, but otherwise the same design
+    let mut old = design();
+    old.registers.remove("gbe0");
+    let board = Emulator::start(EmulatedBoard::with_design(old, |_| ()));
+    let mut tapcp = connect(&board);
+    tapcp.program(&design(), false).unwrap();
+    assert!(!tapcp.listdev().unwrap().contains_key("gbe0"));
+
+    let fpga = OptionalFpga::new(tapcp).unwrap();
+    assert!(fpga.gbe0.is_none());
+    // The devices the board does list are there, optional or not
+    let fft_shift = fpga.fft_shift.as_ref().unwrap();
+    fft_shift.write(U32F0::from_num(0b1010)).unwrap();
+    assert_eq!(fft_shift.read().unwrap(), 0b1010);
+    fpga.arm.write(true).unwrap();
+    assert!(fpga.arm.read().unwrap());
+}
+
+#[test]
+fn test_ten_gbe_self_test() {
+    let peer = [0x02, 0, 0, 0, 0, 1];
+    let (board, fpga) = programmed(move |fpga| {
+        // Link up with both CPU interfaces
+        let memory = fpga.memory();
+        memory.write_bytes("gbe0", 0x0, &[1, 1, 0, 2]).unwrap();
+        memory.write_bytes("gbe0", 0x38, &[0, 0, 0, 1]).unwrap();
+        // The peer answers every frame sent from the TX buffer, as ARP requests are all we send
+        fpga.on_write("gbe0", move |memory, offset, data| {
+            if offset == 0x28 && data[..2] != [0, 0] {
+                let mut frame = memory.read_n_bytes("gbe0", CPU_TX_BUFFER, 64)?;
+                let (sender_ip, target_ip) = (frame[28..32].to_vec(), frame[38..42].to_vec());
+                frame[20..22].copy_from_slice(&[0, 2]);
+                frame[22..28].copy_from_slice(&peer);
+                frame[28..32].copy_from_slice(&target_ip);
+                frame.copy_within(6..12, 32);
+                frame[38..42].copy_from_slice(&sender_ip);
+                memory.write_bytes("gbe0", CPU_RX_BUFFER, &frame)?;
+                // The core clears the TX size once the frame is out
+                memory.write_bytes("gbe0", 0x28, &[0, 0, 0, 8])?;
+            }
+            Ok(())
+        });
+    });
+    fpga.gbe0.set_ip(Ipv4Addr::new(10, 0, 0, 2)).unwrap();
+    fpga.gbe0.set_mac(MacAddr([0x02, 0, 0, 0, 0, 2])).unwrap();
+
+    let result = fpga
+        .gbe0
+        .self_test(Some(Ipv4Addr::new(10, 0, 0, 1)), Duration::from_secs(1))
+        .unwrap();
+    assert!(result.passed());
+    assert_eq!(result.arp.unwrap().reply, Some(MacAddr(peer)));
+    // Both sizes are back to zero, so the request was sent once and the RX buffer handed back
+    assert_eq!(
+        board
+            .board()
+            .fpga()
+            .memory()
+            .read_n_bytes("gbe0", 0x28, 4)
+            .unwrap(),
+        vec![0, 0, 0, 0]
+    );
+}
@@ -1,8 +1,15 @@
 //! Methods/Macros for translating fpg files into Rust datatypes
 
-use casper_utils::design_sources::Device;
+use casper_utils::design_sources::{
+    Access,
+    Device,
+    DeviceKind,
+};
 use kstring::KString;
-use quote::quote;
+use quote::{
+    format_ident,
+    quote,
+};
 use std::collections::HashMap;
 use syn::{
     parse::{
@@ -17,6 +24,47 @@ use syn::{
 pub(crate) struct FpgFpga {
     pub name: Ident,
     pub filename: LitStr,
+    /// Order the generated fields by yellow block kind (then name) instead of just by name
+    pub group_by_kind: bool,
+    /// The devices that get `Option` fields
+    pub optional: Optional,
+}
+
+/// Which devices may be missing from the board, and so get `Option` fields filled in from the
+/// devices the board lists when the struct is built
+#[derive(Default)]
+pub(crate) enum Optional {
+    #[default]
+    None,
+    All,
+    Devices(Vec<Ident>),
+}
+
+impl Optional {
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        match self {
+            Self::None => false,
+            Self::All => true,
+            Self::Devices(devices) => devices.iter().any(|d| d == name),
+        }
+    }
+
+    /// Check that every listed device is in the design, pointing the error at the first that isn't
+    pub(crate) fn check(&self, devices: &HashMap<KString, Device>) -> syn::Result<()> {
+        let Self::Devices(optional) = self else {
+            return Ok(());
+        };
+        match optional
+            .iter()
+            .find(|d| !devices.contains_key(d.to_string().as_str()))
+        {
+            Some(unknown) => Err(syn::Error::new(
+                unknown.span(),
+                format!("The design has no device named `{unknown}`"),
+            )),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Parse for FpgFpga {
@@ -24,207 +72,592 @@ impl Parse for FpgFpga {
         let name = input.parse()?;
         input.parse::<Token![,]>()?;
         let filename = input.parse()?;
-        Ok(FpgFpga { name, filename })
+        let mut group_by_kind = false;
+        let mut optional = Optional::None;
+        while input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let option: Ident = input.parse()?;
+            if option == "group_by_kind" {
+                group_by_kind = true;
+            } else if option == "optional" {
+                optional = if input.peek(syn::token::Paren) {
+                    let devices;
+                    syn::parenthesized!(devices in input);
+                    Optional::Devices(
+                        devices
+                            .parse_terminated(Ident::parse, Token![,])?
+                            .into_iter()
+                            .collect(),
+                    )
+                } else {
+                    Optional::All
+                };
+            } else {
+                return Err(syn::Error::new(
+                    option.span(),
+                    "Unknown option, expected `group_by_kind` or `optional`",
+                ));
+            }
+        }
+        Ok(FpgFpga {
+            name,
+            filename,
+            group_by_kind,
+            optional,
+        })
     }
 }
 
-fn swreg_fixed_type(dev: &Device) -> proc_macro2::TokenStream {
-    let bin_pts: u32 = dev
-        .metadata
-        .get("bin_pts")
-        .expect("Malformed FPG metadata")
-        .parse()
-        .expect("Binary point wasn't a number");
-    let frac_ident = syn::parse_str::<Ident>(&format!("U{bin_pts}")).unwrap();
-    match dev.metadata.get("arith_types").unwrap().as_str() {
-        "0" => quote! {fixed::FixedU32::<fixed::types::extra::#frac_ident>},
-        "1" => quote! {fixed::FixedI32::<fixed::types::extra::#frac_ident>},
-        _ => unreachable!(),
+pub(crate) struct FpgPersonalities {
+    pub name: Ident,
+    pub designs: Vec<(Ident, LitStr)>,
+}
+
+impl Parse for FpgPersonalities {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        let mut designs = vec![];
+        while input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let design = input.parse()?;
+            input.parse::<Token![=>]>()?;
+            designs.push((design, input.parse()?));
+        }
+        if designs.is_empty() {
+            return Err(input.error("Expected at least one `Name => \"design.fpg\"` personality"));
+        }
+        Ok(FpgPersonalities { name, designs })
+    }
+}
+
+/// The devices in a deterministic order, as the map's iteration order changes between compiles
+pub(crate) fn ordered_devices(
+    devices: &HashMap<KString, Device>,
+    group_by_kind: bool,
+) -> Vec<(&KString, &Device)> {
+    let mut ordered: Vec<_> = devices.iter().collect();
+    if group_by_kind {
+        ordered.sort_by_key(|(name, dev)| (dev.kind.as_str(), name.as_str()));
+    } else {
+        ordered.sort_by_key(|(name, _)| name.as_str());
+    }
+    ordered
+}
+
+/// A problem with a single device's entry in the fpg file, reported as a compile error
+#[derive(Debug)]
+pub(crate) struct DeviceError {
+    pub device: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Malformed FPG entry for `{}` - {}",
+            self.device, self.message
+        )
     }
 }
 
-fn disambiguate_sw_reg(dev: &Device) -> proc_macro2::TokenStream {
+fn device_error(name: &str, message: String) -> DeviceError {
+    DeviceError {
+        device: name.to_string(),
+        message,
+    }
+}
+
+fn meta<'a>(name: &str, dev: &'a Device, key: &str) -> Result<&'a str, DeviceError> {
+    dev.metadata
+        .get(key)
+        .map(String::as_str)
+        .ok_or_else(|| device_error(name, format!("missing the `{key}` metadata entry")))
+}
+
+fn meta_number(name: &str, dev: &Device, key: &str) -> Result<u32, DeviceError> {
+    let value = meta(name, dev, key)?;
+    value.parse().map_err(|_| {
+        device_error(
+            name,
+            format!("the `{key}` metadata entry `{value}` isn't a number"),
+        )
+    })
+}
+
+fn unexpected(name: &str, key: &str, value: &str) -> DeviceError {
+    device_error(
+        name,
+        format!("unexpected value `{value}` for the `{key}` metadata entry"),
+    )
+}
+
+fn field_ident(name: &str) -> Result<Ident, DeviceError> {
+    syn::parse_str::<Ident>(name)
+        .map_err(|_| device_error(name, "the name is not a valid rust identifier".to_string()))
+}
+
+/// The identifier of the generated field of a device, which is its name except for the platform
+/// block picked by [`xsg_device`] (named `platform`), as it's named after the board
+fn device_ident(name: &str, platform: Option<&str>) -> Result<Ident, DeviceError> {
+    if platform == Some(name) {
+        Ok(format_ident!("platform"))
+    } else {
+        field_ident(name)
+    }
+}
+
+/// The platform (`xps:xsg`) block of the design, the first by name if there are several
+fn xsg_device<'a, I>(devices: I) -> Option<(&'a KString, &'a Device)>
+where
+    I: IntoIterator<Item = (&'a KString, &'a Device)>,
+{
+    devices
+        .into_iter()
+        .filter(|(_, dev)| dev.kind == DeviceKind::Xsg)
+        .min_by_key(|(name, _)| name.as_str())
+}
+
+fn swreg_fixed_type(name: &str, dev: &Device) -> Result<proc_macro2::TokenStream, DeviceError> {
+    let bin_pts = meta_number(name, dev, "bin_pts")?;
+    let frac_ident = format_ident!("U{bin_pts}");
+    match meta(name, dev, "arith_types")? {
+        "0" => Ok(quote! {fixed::FixedU32::<fixed::types::extra::#frac_ident>}),
+        "1" => Ok(quote! {fixed::FixedI32::<fixed::types::extra::#frac_ident>}),
+        other => Err(unexpected(name, "arith_types", other)),
+    }
+}
+
+/// Whether the software register is an array of words, which is only known from its size
+fn is_array_sw_reg(dev: &Device) -> bool {
+    dev.kind == DeviceKind::SwReg && dev.register.is_some_and(|reg| reg.size > 4)
+}
+
+fn disambiguate_sw_reg(name: &str, dev: &Device) -> Result<proc_macro2::TokenStream, DeviceError> {
     // Unfortunatley, software registers are not uniquely determined by their fpg type, we need
     // additional metadata to know what rust types they become
-    match dev.metadata.get("arith_types").unwrap().as_str() {
-        "0" | "1" => {
-            let fixed_ty = swreg_fixed_type(dev);
+    let ty = match (meta(name, dev, "arith_types")?, is_array_sw_reg(dev)) {
+        ("0" | "1", false) => {
+            let fixed_ty = swreg_fixed_type(name, dev)?;
             quote!(casperfpga::yellow_blocks::swreg::FixedSoftwareRegister::<T, #fixed_ty>)
         }
-        "2" => quote!(casperfpga::yellow_blocks::swreg::BooleanSoftwareRegister::<T>),
-        _ => unreachable!(),
+        ("0" | "1", true) => {
+            let fixed_ty = swreg_fixed_type(name, dev)?;
+            quote!(casperfpga::yellow_blocks::swreg::ArraySoftwareRegister::<T, #fixed_ty>)
+        }
+        ("2", false) => quote!(casperfpga::yellow_blocks::swreg::BooleanSoftwareRegister::<T>),
+        // Arrays of booleans are read as the raw words
+        ("2", true) => quote!(
+            casperfpga::yellow_blocks::swreg::ArraySoftwareRegister::<
+                T,
+                fixed::FixedU32<fixed::types::extra::U0>,
+            >
+        ),
+        (other, _) => return Err(unexpected(name, "arith_types", other)),
+    };
+    // Read-only registers don't get write methods at all
+    if dev.access() == Access::Read {
+        Ok(quote!(casperfpga::yellow_blocks::swreg::ReadOnly::<#ty>))
+    } else {
+        Ok(ty)
     }
 }
 
-fn disambiguate_snapshot(dev: &Device) -> proc_macro2::TokenStream {
-    let width: u32 = dev
-        .metadata
-        .get("data_width")
-        .expect("Malformed FPG metadata")
-        .parse()
-        .expect("Snapshot datawidth wasn't a number?");
-    let ty = match width {
+fn disambiguate_snapshot(
+    name: &str,
+    dev: &Device,
+) -> Result<proc_macro2::TokenStream, DeviceError> {
+    let ty = match meta_number(name, dev, "data_width")? {
         8 => quote!(u8),
         16 => quote!(u16),
         32 => quote!(u32),
         64 => quote!(u64),
         128 => quote!(u128),
-        _ => panic!("Invalid data_width"),
+        other => return Err(unexpected(name, "data_width", &other.to_string())),
     };
-    quote!(casperfpga::yellow_blocks::snapshot::Snapshot::<T, #ty>)
+    Ok(quote!(casperfpga::yellow_blocks::snapshot::Snapshot::<T, #ty>))
 }
 
 // This is obnoxiously slightly different from swreg
-fn disambiguate_bram(dev: &Device) -> proc_macro2::TokenStream {
-    let bin_pts: u32 = dev
-        .metadata
-        .get("data_bin_pt")
-        .expect("Malformed FPG metadata")
-        .parse()
-        .expect("Binary point wasn't a number");
-    let arith_type_str = match dev.metadata.get("arith_type").unwrap().as_str() {
+fn disambiguate_bram(name: &str, dev: &Device) -> Result<proc_macro2::TokenStream, DeviceError> {
+    let bin_pts = meta_number(name, dev, "data_bin_pt")?;
+    let arith_type_str = match meta(name, dev, "arith_type")? {
         "Unsigned" => "U",
         "Signed" => "I",
-        _ => unreachable!(),
+        other => return Err(unexpected(name, "arith_type", other)),
     };
-    let width: u32 = dev
-        .metadata
-        .get("data_width")
-        .expect("Malformed FPG metadata")
-        .parse()
-        .expect("Bram datawidth wasn't a number?");
-    let width_str = match width {
-        8 | 16 | 32 | 64 | 128 => width.to_string(),
-        _ => panic!("Invalid data_width"),
+    let width = match meta_number(name, dev, "data_width")? {
+        width @ (8 | 16 | 32 | 64 | 128) => width,
+        other => return Err(unexpected(name, "data_width", &other.to_string())),
     };
-    let fixed_ident =
-        syn::parse_str::<Ident>(&format!("Fixed{arith_type_str}{width_str}")).unwrap();
-    let frac_ident = syn::parse_str::<Ident>(&format!("U{bin_pts}")).unwrap();
+    let fixed_ident = format_ident!("Fixed{arith_type_str}{width}");
+    let frac_ident = format_ident!("U{bin_pts}");
     let fixed_type = quote! {fixed::#fixed_ident::<fixed::types::extra::#frac_ident>};
-    quote!(casperfpga::yellow_blocks::bram::Bram::<T, #fixed_type>)
+    Ok(quote!(casperfpga::yellow_blocks::bram::Bram::<T, #fixed_type>))
 }
 
-fn kind_to_type(dev: &Device) -> Option<proc_macro2::TokenStream> {
-    match dev.kind.as_str() {
-        "xps:sw_reg" => Some(disambiguate_sw_reg(dev)),
-        "xps:ten_gbe" => Some(quote!(casperfpga::yellow_blocks::ten_gbe::TenGbE::<T>)),
-        "xps:snap_adc" => Some(quote!(casperfpga::yellow_blocks::snapadc::SnapAdc::<T>)),
-        "casper:snapshot" => Some(disambiguate_snapshot(dev)),
-        "xps:bram" => Some(disambiguate_bram(dev)),
+fn kind_to_type(name: &str, dev: &Device) -> Result<Option<proc_macro2::TokenStream>, DeviceError> {
+    Ok(match dev.kind {
+        DeviceKind::SwReg => Some(disambiguate_sw_reg(name, dev)?),
+        DeviceKind::TenGbe => Some(quote!(casperfpga::yellow_blocks::ten_gbe::TenGbE::<T>)),
+        DeviceKind::SnapAdc => Some(quote!(casperfpga::yellow_blocks::snapadc::SnapAdc::<T>)),
+        DeviceKind::KatAdc => Some(quote!(casperfpga::yellow_blocks::katadc::KatAdc::<T>)),
+        DeviceKind::IAdc => Some(quote!(casperfpga::yellow_blocks::iadc::IAdc::<T>)),
+        DeviceKind::Snapshot => Some(disambiguate_snapshot(name, dev)?),
+        DeviceKind::Bram => Some(disambiguate_bram(name, dev)?),
+        DeviceKind::Xsg => Some(quote!(casperfpga::yellow_blocks::xsg::Xsg)),
         // Ignore the types that don't have mappings to yellow block implementations
         _ => None,
-    }
+    })
 }
 
 fn dev_to_constructor(
     name: &str,
     devices: &HashMap<KString, Device>,
-) -> Option<proc_macro2::TokenStream> {
-    // So, some devices will require entries from *other* devices, like SNAP ADCs needing to know
-    // the clock source, so we'll pass in a single key to the device map and the map itself, so we
-    // can look up other entires
-
-    let dev = devices.get(name).unwrap();
-
-    if let Some(ty) = kind_to_type(dev) {
-        let ident = syn::parse_str::<Ident>(name).ok()?;
-        // Build the constructor for the given device using its `from_fpg` method.
-        // Follows the informal contract that it begins with the weak transport pointer
-        // and the name of the device.
-        macro_rules! from_fpg {
-            () => {
-                Some(quote! {let #ident = #ty::from_fpg(tweak.clone(), #name)?;})
-            };
-            ($($key:ident),+) => {{
-                $(let $key = dev.metadata.get(stringify!($key)).expect("Malformed FPG metadata");)+
-                Some(quote! {let #ident = #ty::from_fpg(tweak.clone(), #name, $(#$key,)+)?;})
-            }};
+    optional: bool,
+) -> Result<Option<proc_macro2::TokenStream>, DeviceError> {
+    let dev = &devices[name];
+    let Some(ty) = kind_to_type(name, dev)? else {
+        return Ok(None);
+    };
+    let platform = xsg_device(devices).map(|(platform, _)| platform.as_str());
+    let ident = device_ident(name, platform)?;
+    // Every block is built through `FromFpg` from its metadata, sorted so the generated code is the
+    // same from build to build
+    let mut metadata: Vec<(&str, &str)> = dev
+        .metadata
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    // Some devices need entries from *other* devices, which are merged into their metadata
+    if dev.kind == DeviceKind::SnapAdc {
+        let (platform, xsg) = xsg_device(devices).ok_or_else(|| {
+            device_error(
+                name,
+                "SNAP ADC entries must accompany a platform (`xps:xsg`) entry".to_string(),
+            )
+        })?;
+        metadata.push(("clk_src", meta(platform, xsg, "clk_src")?));
+    }
+    // And array software registers need the size of their register
+    let size = dev.register.map(|reg| reg.size.to_string());
+    if let (true, Some(size)) = (is_array_sw_reg(dev), &size) {
+        metadata.push(("size", size));
+    }
+    metadata.sort_unstable();
+    let (keys, values): (Vec<_>, Vec<_>) = metadata.into_iter().unzip();
+    let construct = quote! {
+        <#ty as casperfpga::yellow_blocks::FromFpg<T>>::from_metadata(
+            tweak.clone(),
+            #name,
+            &std::collections::HashMap::from([#((#keys.into(), #values.to_string())),*]),
+        )?
+    };
+    // Only devices with their own register can be looked for on the board
+    Ok(Some(match (optional, dev.register.is_some()) {
+        (false, _) => quote! { let #ident = #construct; },
+        (true, false) => quote! { let #ident = Some(#construct); },
+        (true, true) => quote! {
+            let #ident = if live.contains_key(#name) { Some(#construct) } else { None };
+        },
+    }))
+}
+
+/// The design-level constants and the `design_metadata` method of the generated struct, from the
+/// design's `xps:xsg` platform block (named after the board, i.e. `SNAP`). Constants are only
+/// generated for the entries the fpg file has.
+pub(crate) fn generate_design_metadata(
+    devices: &HashMap<KString, Device>,
+) -> Result<proc_macro2::TokenStream, DeviceError> {
+    let Some((name, xsg)) = xsg_device(devices) else {
+        return Ok(quote! {
+            /// The design-level metadata of the fpg file, which this design doesn't have
+            #[must_use]
+            pub fn design_metadata() -> std::collections::HashMap<&'static str, &'static str> {
+                std::collections::HashMap::new()
+            }
+        });
+    };
+    let clock_rate = match xsg.metadata.get("clk_rate") {
+        Some(value) => {
+            let rate: f64 = value
+                .parse()
+                .map_err(|_| unexpected(name, "clk_rate", value))?;
+            let rate = proc_macro2::Literal::f64_unsuffixed(rate);
+            let doc = format!(" The clock rate of the design in MHz, from `{name}`");
+            Some(quote! {
+                #[doc = #doc]
+                pub const CLOCK_RATE_MHZ: f64 = #rate;
+            })
         }
-        // These need to match the key order from the device's `from_fpg` method
-        match dev.kind.as_str() {
-            "xps:sw_reg" => match dev.metadata.get("arith_types").unwrap().as_str() {
-                "0" | "1" => from_fpg!(io_dir, bitwidths),
-                "2" => from_fpg!(io_dir),
-                _ => unreachable!(),
-            },
-            "xps:ten_gbe" => from_fpg!(),
-            "casper:snapshot" => from_fpg!(nsamples, offset),
-            "xps:snap_adc" => {
-                let snap = devices
-                    .get("SNAP")
-                    .expect("SNAP ADC entries must accompany a SNAP entry");
-                // Do we need the FPGA clock rate too?
-                let src = snap
-                    .metadata
-                    .get("clk_src")
-                    .expect("SNAP must have clk_src entry");
-
-                // get the rest of the entires manually  - maybe clean this up by updating the macro
-                let adc_resolution = dev
-                    .metadata
-                    .get("adc_resolution")
-                    .expect("Malformed FPG metadata");
-
-                let sample_rate = dev
-                    .metadata
-                    .get("sample_rate")
-                    .expect("Malformed FPG metadata");
-
-                let snap_inputs = dev
-                    .metadata
-                    .get("snap_inputs")
-                    .expect("Malformed FPG metadata");
-
-                Some(quote! {
-                    let #ident = #ty::from_fpg(tweak.clone(), #name, #adc_resolution, #sample_rate, #snap_inputs, #src)?;
-                })
+        None => None,
+    };
+    let string_const = |key: &str, ident: &str, what: &str| {
+        xsg.metadata.get(key).map(|value| {
+            let ident = format_ident!("{ident}");
+            let doc = format!(" {what}, from `{name}`");
+            quote! {
+                #[doc = #doc]
+                pub const #ident: &str = #value;
             }
-            "xps:bram" => from_fpg!(addr_width),
-            // Ignore the types that don't have mappings to yellow block implementations
-            _ => None,
+        })
+    };
+    let clock_source = string_const("clk_src", "CLOCK_SOURCE", "The clock source of the design");
+    let platform = string_const(
+        "hw_sys",
+        "PLATFORM",
+        "The hardware platform (board and FPGA part) of the design",
+    );
+    let mut entries: Vec<_> = xsg
+        .metadata
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    entries.sort_unstable();
+    let (keys, values): (Vec<_>, Vec<_>) = entries.into_iter().unzip();
+    let doc = format!(" The design-level metadata of the fpg file, the entries of `{name}`");
+    Ok(quote! {
+        #clock_rate
+        #clock_source
+        #platform
+
+        #[doc = #doc]
+        #[must_use]
+        pub fn design_metadata() -> std::collections::HashMap<&'static str, &'static str> {
+            std::collections::HashMap::from([#((#keys, #values)),*])
         }
-    } else {
-        None
+    })
+}
+
+/// The metadata entries worth showing in the docs of the generated fields, in display order
+const DOC_METADATA: [&str; 16] = [
+    "io_dir",
+    "arith_types",
+    "bitwidths",
+    "bin_pts",
+    "arith_type",
+    "data_width",
+    "data_bin_pt",
+    "addr_width",
+    "nsamples",
+    "sample_rate",
+    "snap_inputs",
+    "adc_resolution",
+    "adc_brd",
+    "hw_sys",
+    "clk_src",
+    "clk_rate",
+];
+
+/// The lines of the doc comment of a generated field, describing the block from its fpg entry
+fn field_doc(name: &str, dev: &Device, optional: bool) -> Vec<String> {
+    let mut lines = vec![format!(" The `{name}` block (`{}`)", dev.kind)];
+    if optional {
+        lines.push(String::new());
+        lines.push(
+            " `None` if the board didn't list this device when the FPGA was built".to_string(),
+        );
+    }
+    let mut details = vec![];
+    if let Some(reg) = dev.register {
+        details.push(format!(
+            " - Address: `{:#010x}` ({} bytes)",
+            reg.addr, reg.size
+        ));
     }
+    for key in DOC_METADATA {
+        if let Some(value) = dev.metadata.get(key) {
+            details.push(format!(" - `{key}`: `{value}`"));
+        }
+    }
+    if !details.is_empty() {
+        lines.push(String::new());
+        lines.extend(details);
+    }
+    lines
 }
 
 pub(crate) fn generate_struct_fields(
-    devices: &HashMap<KString, Device>,
-) -> Vec<proc_macro2::TokenStream> {
-    devices
-        .iter()
-        .filter_map(|(name, dev)| {
-            // Construct the token stream
-            kind_to_type(dev).map(|ty| {
-                let ident = syn::parse_str::<Ident>(name.as_str()).unwrap_or_else(|_| {
-                    panic!("FPGA register name `{name}` is not a valid rust identifier")
-                });
-                quote! {
-                    pub #ident: #ty
-                }
-            })
-        })
-        .collect()
+    devices: &[(&KString, &Device)],
+    optional: &Optional,
+) -> Result<Vec<proc_macro2::TokenStream>, DeviceError> {
+    let mut fields = vec![];
+    for TypedField {
+        ident,
+        ty,
+        name,
+        dev,
+    } in typed_fields(devices, optional)?
+    {
+        let doc = field_doc(name, dev, optional.contains(name));
+        fields.push(quote! {
+            #(#[doc = #doc])*
+            pub #ident: #ty
+        });
+    }
+    Ok(fields)
 }
 
-pub(crate) fn generate_field_names(devices: &HashMap<KString, Device>) -> Vec<Ident> {
-    devices
-        .iter()
-        .filter_map(|(name, dev)| {
-            kind_to_type(dev).map(|_| {
-                syn::parse_str::<Ident>(name.as_str()).unwrap_or_else(|_| {
-                    panic!("FPGA register name `{name}` is not a valid rust identifier")
-                })
-            })
-        })
-        .collect()
+/// The generated field of a device with a yellow block
+struct TypedField<'a> {
+    ident: Ident,
+    ty: proc_macro2::TokenStream,
+    name: &'a str,
+    dev: &'a Device,
+}
+
+fn typed_fields<'a>(
+    devices: &[(&'a KString, &'a Device)],
+    optional: &Optional,
+) -> Result<Vec<TypedField<'a>>, DeviceError> {
+    let platform = xsg_device(devices.iter().copied()).map(|(platform, _)| platform.as_str());
+    let mut fields: Vec<TypedField> = vec![];
+    for (name, dev) in devices {
+        if let Some(ty) = kind_to_type(name, dev)? {
+            let ty = if optional.contains(name) {
+                quote!(Option<#ty>)
+            } else {
+                ty
+            };
+            let ident = device_ident(name, platform)?;
+            // i.e. a device named `platform` alongside the platform block
+            if let Some(other) = fields.iter().find(|field| field.ident == ident) {
+                return Err(device_error(
+                    name,
+                    format!(
+                        "its field `{ident}` clashes with the field of `{}`, rename one of them \
+                         in the design",
+                        other.name
+                    ),
+                ));
+            }
+            fields.push(TypedField {
+                ident,
+                ty,
+                name,
+                dev,
+            });
+        }
+    }
+    Ok(fields)
+}
+
+pub(crate) fn generate_field_types(
+    devices: &[(&KString, &Device)],
+    optional: &Optional,
+) -> Result<Vec<(Ident, proc_macro2::TokenStream)>, DeviceError> {
+    Ok(typed_fields(devices, optional)?
+        .into_iter()
+        .map(|field| (field.ident, field.ty))
+        .collect())
+}
+
+pub(crate) fn generate_field_names(
+    devices: &[(&KString, &Device)],
+) -> Result<Vec<Ident>, DeviceError> {
+    Ok(generate_field_types(devices, &Optional::None)?
+        .into_iter()
+        .map(|(ident, _)| ident)
+        .collect())
 }
 
+/// The constructors of every field, preceded by the listing of the board's devices if any of them
+/// are optional
 pub(crate) fn generate_constructors(
+    ordered: &[(&KString, &Device)],
     devices: &HashMap<KString, Device>,
-) -> Vec<proc_macro2::TokenStream> {
-    devices
-        .iter()
-        .filter_map(|(name, _)| dev_to_constructor(name, devices))
-        .collect()
+    optional: &Optional,
+) -> Result<Vec<proc_macro2::TokenStream>, DeviceError> {
+    let mut constructors = vec![];
+    let mut needs_listing = false;
+    for (name, dev) in ordered {
+        let optional = optional.contains(name);
+        if let Some(constructor) = dev_to_constructor(name, devices, optional)? {
+            needs_listing |= optional && dev.register.is_some();
+            constructors.push(constructor);
+        }
+    }
+    if needs_listing {
+        constructors.insert(
+            0,
+            quote! {
+                let live = tarc
+                    .lock()
+                    .map_err(|_| casperfpga::transport::Error::TransportPoisoned)?
+                    .listdev()?;
+            },
+        );
+    }
+    Ok(constructors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use casper_utils::design_sources::fpg::read_fpg_file;
+
+    fn devices() -> HashMap<KString, Device> {
+        read_fpg_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../casperfpga/tests/synthetic.fpg"
+        ))
+        .unwrap()
+        .devices
+    }
+
+    #[test]
+    fn test_unknown_optional_device() {
+        let parse = |tokens| syn::parse_str::<FpgFpga>(tokens).unwrap().optional;
+        let devices = devices();
+        assert!(parse(r#"Fpga, "synthetic.fpg", optional(gbe0, fft_shift)"#)
+            .check(&devices)
+            .is_ok());
+        assert!(parse(r#"Fpga, "synthetic.fpg", optional"#)
+            .check(&devices)
+            .is_ok());
+        // A misspelled device is a compile error naming it
+        let err = parse(r#"Fpga, "synthetic.fpg", optional(gbe0, fft_shfit)"#)
+            .check(&devices)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The design has no device named `fft_shfit`"
+        );
+    }
+
+    #[test]
+    fn test_platform_field() {
+        let mut devices = devices();
+        let idents = |devices: &HashMap<KString, Device>| -> Result<Vec<String>, DeviceError> {
+            Ok(
+                typed_fields(&ordered_devices(devices, false), &Optional::None)?
+                    .into_iter()
+                    .filter(|field| field.dev.kind == DeviceKind::Xsg)
+                    .map(|field| format!("{} {}", field.name, field.ident))
+                    .collect(),
+            )
+        };
+        assert_eq!(idents(&devices).unwrap(), vec!["SNAP platform"]);
+
+        // Only the platform block picked for the design is `platform`, any others keep their name
+        let copy = |dev: &Device| Device {
+            kind: dev.kind.clone(),
+            register: dev.register,
+            metadata: dev.metadata.clone(),
+        };
+        devices.insert("ZCU216".into(), copy(&devices["SNAP"]));
+        devices.insert("AAA".into(), copy(&devices["SNAP"]));
+        assert_eq!(
+            idents(&devices).unwrap(),
+            vec!["AAA platform", "SNAP SNAP", "ZCU216 ZCU216"]
+        );
+
+        // And a device that would take the same field is an error
+        devices.insert("platform".into(), copy(&devices["arm"]));
+        let err = idents(&devices).unwrap_err();
+        assert_eq!(err.device, "platform");
+        assert_eq!(
+            err.to_string(),
+            "Malformed FPG entry for `platform` - its field `platform` clashes with the field of \
+             `AAA`, rename one of them in the design"
+        );
+    }
 }
@@ -3,26 +3,72 @@
 
 mod fpg;
 
-use casper_utils::design_sources::fpg::read_fpg_file;
+use casper_utils::design_sources::{
+    fpg::read_fpg_file,
+    FpgaDesign,
+};
 use fpg::{
     generate_constructors,
+    generate_design_metadata,
     generate_field_names,
+    generate_field_types,
     generate_struct_fields,
+    ordered_devices,
+    DeviceError,
     FpgFpga,
+    FpgPersonalities,
+    Optional,
 };
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{
+    format_ident,
+    quote,
+};
 use std::path::PathBuf;
 use syn::{
     parse_macro_input,
     DeriveInput,
+    Ident,
+    LitStr,
 };
 
-#[proc_macro_derive(CasperSerde)]
+#[proc_macro_derive(CasperSerde, attributes(casper_serde))]
 /// Derived on a [`PackedStruct`] to shim in our serde methods on packed structs
+///
+/// Packing mistakes (i.e. `msb0` vs `lsb0` bit numbering) compile fine, so the layout can also be
+/// checked with a `#[casper_serde(..)]` attribute below the derive, with any of
+/// - `layout_tests` to generate a test that packing and unpacking are inverses, for a handful of
+///   byte patterns and every single set bit
+/// - `size = <bytes>` to check at compile time that the packed struct is the size of its register
+/// - `golden(value = "<expr>", bytes = "[<bytes>]")` to generate a test that the value packs to
+///   exactly the bytes and back, which can be repeated. This implies `layout_tests`.
+///
+/// The expressions are strings as the `PackedStruct` derive also parses this attribute, and it only
+/// understands literals.
 pub fn derive_casper_serde(tokens: TokenStream) -> TokenStream {
     let input = parse_macro_input!(tokens as DeriveInput);
+    let mut layout = LayoutAttr::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("casper_serde"))
+    {
+        if let Err(e) = attr.parse_nested_meta(|meta| layout.parse(&meta)) {
+            return e.to_compile_error().into();
+        }
+    }
     let block_name = input.ident;
+    let size_check = layout.size.take().map(|size| {
+        let size_msg = format!("The packed size of `{block_name}` doesn't match its declared size");
+        quote! {
+            const _: () = assert!(
+                core::mem::size_of::<<#block_name as packed_struct::PackedStruct>::ByteArray>()
+                    == (#size) as usize,
+                #size_msg
+            );
+        }
+    });
+    let layout_tests = layout.generate_tests(&block_name);
     let generated = quote! {
         impl Serialize for #block_name {
             type Chunk = <Self as PackedStruct>::ByteArray;
@@ -40,31 +86,181 @@ pub fn derive_casper_serde(tokens: TokenStream) -> TokenStream {
                 Self::unpack(&chunk)
             }
         }
+
+        #size_check
+        #layout_tests
     };
     TokenStream::from(generated)
 }
 
+/// The options of the `casper_serde` attribute, see [`derive_casper_serde`]
+#[derive(Default)]
+struct LayoutAttr {
+    tests: bool,
+    size: Option<syn::Expr>,
+    golden: Vec<(syn::Expr, syn::Expr)>,
+}
+
+impl LayoutAttr {
+    /// The layout tests of the struct `block_name`, if there are any
+    fn generate_tests(self, block_name: &Ident) -> Option<proc_macro2::TokenStream> {
+        if !self.tests && self.golden.is_empty() {
+            return None;
+        }
+        let mod_name = format_ident!("__casper_serde_layout_{block_name}");
+        let round_trip_msg = format!("Packing and unpacking `{block_name}` aren't inverses");
+        let golden_msg = format!("`{block_name}` doesn't pack to its golden bytes");
+        let (values, bytes): (Vec<_>, Vec<_>) = self.golden.into_iter().unzip();
+        let golden_test = (!values.is_empty()).then(|| {
+            quote! {
+                #[test]
+                fn golden() {
+                    #({
+                        let value: #block_name = #values;
+                        let bytes: Chunk = #bytes;
+                        assert_eq!(
+                            value.serialize().as_bytes_slice(),
+                            bytes.as_bytes_slice(),
+                            #golden_msg
+                        );
+                        let unpacked =
+                            <#block_name as Deserialize>::deserialize(bytes).expect(#golden_msg);
+                        assert_eq!(
+                            unpacked.serialize().as_bytes_slice(),
+                            bytes.as_bytes_slice(),
+                            #golden_msg
+                        );
+                    })*
+                }
+            }
+        });
+        Some(quote! {
+            #[cfg(test)]
+            #[allow(non_snake_case)]
+            mod #mod_name {
+                use super::*;
+                use packed_struct::types::bits::ByteArray;
+
+                type Chunk = <#block_name as packed_struct::PackedStruct>::ByteArray;
+
+                #[test]
+                fn round_trip() {
+                    let len = <Chunk as ByteArray>::len();
+                    let mut patterns: Vec<Chunk> =
+                        [0x00, 0xFF, 0x55, 0xAA].into_iter().map(Chunk::new).collect();
+                    for bit in 0..len * 8 {
+                        let mut chunk = Chunk::new(0);
+                        chunk.as_mut_bytes_slice()[bit / 8] = 1 << (bit % 8);
+                        patterns.push(chunk);
+                    }
+                    let mut valid = 0;
+                    for pattern in patterns {
+                        // Patterns that aren't a value (i.e. an enum field out of range) are skipped
+                        let Ok(value) = <#block_name as Deserialize>::deserialize(pattern) else {
+                            continue;
+                        };
+                        let packed = value.serialize();
+                        let repacked = <#block_name as Deserialize>::deserialize(packed)
+                            .expect(#round_trip_msg)
+                            .serialize();
+                        assert_eq!(
+                            packed.as_bytes_slice(),
+                            repacked.as_bytes_slice(),
+                            #round_trip_msg
+                        );
+                        valid += 1;
+                    }
+                    assert!(valid > 0, #round_trip_msg);
+                }
+
+                #golden_test
+            }
+        })
+    }
+
+    fn parse(&mut self, meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("layout_tests") {
+            self.tests = true;
+        } else if meta.path.is_ident("size") {
+            self.size = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("golden") {
+            let (mut value, mut bytes) = (None, None);
+            meta.parse_nested_meta(|entry| {
+                let expr = entry.value()?.parse::<LitStr>()?.parse()?;
+                if entry.path.is_ident("value") {
+                    value = Some(expr);
+                } else if entry.path.is_ident("bytes") {
+                    bytes = Some(expr);
+                } else {
+                    return Err(entry.error("Expected `value = \"..\"` or `bytes = \"..\"`"));
+                }
+                Ok(())
+            })?;
+            match (value, bytes) {
+                (Some(value), Some(bytes)) => self.golden.push((value, bytes)),
+                _ => return Err(meta.error("Golden layouts need both a `value` and `bytes`")),
+            }
+        } else {
+            return Err(meta.error("Expected `layout_tests`, `size = <bytes>`, or `golden(..)`"));
+        }
+        Ok(())
+    }
+}
+
 #[proc_macro_attribute]
 /// Implement the Address trait on this struct, allowing for automatic addressing when reading and
-/// writing
-/// # Panics
-/// Panics on bad address literals
-#[allow(clippy::manual_flatten)]
-#[allow(clippy::manual_let_else)]
+/// writing.
+///
+/// The address can be any constant expression (literals, named constants, arithmetic) that fits in
+/// the 16-bit address space. Optionally, `size = <bytes>` checks at compile time that the packed
+/// representation of the struct is exactly that many bytes, i.e. `#[address(0x34, size = 8)]`.
+/// Structs wider than a 4-byte word are also checked to be whole words at a word-aligned address,
+/// as they are transferred a word at a time.
+///
+/// Attributes below `#[derive(PackedStruct)]` are also seen by its parser, which only understands
+/// literals, so place `#[address]` above the derive when using anything but a literal address.
 pub fn address(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let attr = match syn::parse::<syn::Lit>(attr).expect("Error parsing attribute") {
-        syn::Lit::Int(v) => v,
-        _ => panic!("The address must be a literal integer (hopefully a u8)"),
-    };
-    let num = attr;
+    let AddressAttr { addr, size } = parse_macro_input!(attr as AddressAttr);
     // Get the struct name this address is for
     let item = parse_macro_input!(item as DeriveInput);
     let ident = item.clone().ident;
 
+    // Literals we can check right here, everything else is checked when the constant is evaluated
+    if let syn::Expr::Lit(syn::ExprLit {
+        lit: syn::Lit::Int(v),
+        ..
+    }) = &addr
+    {
+        if v.base10_parse::<u16>().is_err() {
+            return syn::Error::new_spanned(v, "Address doesn't fit in the 16-bit address space")
+                .to_compile_error()
+                .into();
+        }
+    }
+
+    let addr_msg = format!("The address of `{ident}` doesn't fit in the 16-bit address space");
+    let size_check = size.map(|size| {
+        let size_msg = format!("The packed size of `{ident}` doesn't match its declared size");
+        quote! {
+            const _: () = assert!(
+                core::mem::size_of::<<#ident as packed_struct::PackedStruct>::ByteArray>()
+                    == (#size) as usize,
+                #size_msg
+            );
+        }
+    });
+
+    let align_msg = format!("`{ident}` spans multiple words, so it must be whole, aligned words");
     let generated = quote! {
+        const _: () = assert!((#addr) as u128 <= u16::MAX as u128, #addr_msg);
+        const _: () = {
+            let size = core::mem::size_of::<<#ident as packed_struct::PackedStruct>::ByteArray>();
+            assert!(size <= 4 || ((#addr) as usize % 4 == 0 && size % 4 == 0), #align_msg);
+        };
+        #size_check
         impl Address for #ident {
             fn addr() -> u16 {
-                #num as u16
+                (#addr) as u16
             }
         }
         #item
@@ -72,19 +268,93 @@ pub fn address(attr: TokenStream, item: TokenStream) -> TokenStream {
     TokenStream::from(generated)
 }
 
-#[proc_macro]
-/// Generates a fully-typed and specified FPGA instance using the object definitions from a given
-/// fpg file.
-#[allow(clippy::missing_panics_doc)]
-pub fn fpga_from_fpg(tokens: TokenStream) -> TokenStream {
-    let FpgFpga { name, filename } = parse_macro_input!(tokens as FpgFpga);
-    let filename = filename.value();
+/// The arguments to the `address` attribute - an address expression and optional size
+struct AddressAttr {
+    addr: syn::Expr,
+    size: Option<syn::Expr>,
+}
+
+impl syn::parse::Parse for AddressAttr {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let addr = input.parse()?;
+        let mut size = None;
+        if input.parse::<Option<syn::Token![,]>>()?.is_some() && !input.is_empty() {
+            let key: syn::Ident = input.parse()?;
+            if key != "size" {
+                return Err(syn::Error::new_spanned(key, "Expected `size = <bytes>`"));
+            }
+            input.parse::<syn::Token![=]>()?;
+            size = Some(input.parse()?);
+            input.parse::<Option<syn::Token![,]>>()?;
+        }
+        Ok(Self { addr, size })
+    }
+}
+
+/// Find the fpg file `filename` as described in [`fpga_from_fpg`]
+fn resolve_fpg_path(filename: &str) -> PathBuf {
+    let path = PathBuf::from(filename);
+    if path.is_absolute() {
+        return path;
+    }
+    if let Some(dir) = std::env::var_os("CASPERFPGA_FPG_DIR") {
+        return PathBuf::from(dir).join(path);
+    }
+    match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) if PathBuf::from(&dir).join(&path).exists() => PathBuf::from(dir).join(path),
+        _ => path,
+    }
+}
 
-    let fpg = read_fpg_file(PathBuf::from(filename)).expect("Couldn't read FPG file");
+/// Build the struct for a single design, returning the generated code and the name and type of
+/// every typed field, or the `compile_error!` if the fpg file couldn't be read
+fn fpga_struct(
+    name: &Ident,
+    filename_lit: &LitStr,
+    group_by_kind: bool,
+    optional: &Optional,
+) -> Result<
+    (
+        proc_macro2::TokenStream,
+        Vec<(Ident, proc_macro2::TokenStream)>,
+    ),
+    TokenStream,
+> {
+    let filename = filename_lit.value();
+
+    let path = resolve_fpg_path(&filename);
+    let fpg = match read_fpg_file(&path) {
+        Ok(fpg) => fpg,
+        Err(e) => {
+            let shown = std::env::current_dir().map_or(path.clone(), |dir| dir.join(&path));
+            return Err(syn::Error::new(
+                filename_lit.span(),
+                format!("Couldn't read FPG file `{}` - {e}", shown.display()),
+            )
+            .to_compile_error()
+            .into());
+        }
+    };
+    optional
+        .check(&fpg.devices)
+        .map_err(|e| TokenStream::from(e.to_compile_error()))?;
+    let devices = ordered_devices(&fpg.devices, group_by_kind);
+    let md5 = fpg.md5_string();
+    let device_names: Vec<_> = devices.iter().map(|(name, _)| name.as_str()).collect();
+    let device_kinds: Vec<_> = devices.iter().map(|(_, dev)| dev.kind.as_str()).collect();
 
-    let struct_fields = generate_struct_fields(&fpg.devices);
-    let field_names = generate_field_names(&fpg.devices);
-    let constructors = generate_constructors(&fpg.devices);
+    // Malformed device entries are reported against the fpg file instead of panicking
+    let device_error = |e: DeviceError| -> TokenStream {
+        syn::Error::new(filename_lit.span(), e.to_string())
+            .to_compile_error()
+            .into()
+    };
+    let struct_fields = generate_struct_fields(&devices, optional).map_err(device_error)?;
+    let field_names = generate_field_names(&devices).map_err(device_error)?;
+    let constructors =
+        generate_constructors(&devices, &fpg.devices, optional).map_err(device_error)?;
+    let field_types = generate_field_types(&devices, optional).map_err(device_error)?;
+    let design_metadata = generate_design_metadata(&fpg.devices).map_err(device_error)?;
 
     // For every device in the fpg file, create a typed entry in the struct
     let generated = quote! {
@@ -98,17 +368,252 @@ pub fn fpga_from_fpg(tokens: TokenStream) -> TokenStream {
         where
             T: casperfpga::transport::Transport
         {
+            #design_metadata
+
             pub fn new(transport: T) -> Result<Self, casperfpga::yellow_blocks::Error> {
                 // Create the Arc Mutex for the transport
-                let tarc = std::sync::Arc::new(std::sync::Mutex::new(transport));
-                // And create the weak to pass to the yellow blocks
+                Self::from_shared(std::sync::Arc::new(std::sync::Mutex::new(transport)))
+            }
+
+            /// Build the FPGA around an already shared transport
+            pub fn from_shared(
+                tarc: std::sync::Arc<std::sync::Mutex<T>>,
+            ) -> Result<Self, casperfpga::yellow_blocks::Error> {
+                // Create the weak to pass to the yellow blocks
                 let tweak = std::sync::Arc::downgrade(&tarc);
-                // For every fpg device, run its `from_fpg` method
+                // Build every block through its `FromFpg` implementation
                 #(#constructors)*
                 // We probably want to actualy enforce that we program the FPGA at some point
                 Ok(Self {transport: tarc, #(#field_names,)*})
             }
+
+            /// A typed handle on the register `name`, looked up in the board's `listdev`, for
+            /// registers without a yellow block of their own
+            pub fn register<V>(
+                &self,
+                name: &str,
+            ) -> Result<
+                casperfpga::register::TypedRegister<T, V>,
+                casperfpga::transport::Error,
+            >
+            where
+                V: casperfpga::register::RegisterValue,
+            {
+                casperfpga::register::TypedRegister::new(&self.transport, name, 0)
+            }
+
+            /// Start tracking the bringup of this FPGA, so its steps can't run out of order
+            #[must_use]
+            pub fn bringup(self) -> casperfpga::bringup::Bringup<Self> {
+                casperfpga::bringup::Bringup::new(self)
+            }
+
+            /// The names of every device in the design this struct was generated from
+            #[must_use]
+            pub fn device_names() -> &'static [&'static str] {
+                &[#(#device_names),*]
+            }
+
+            /// The map of device name to yellow block kind (i.e. `xps:sw_reg`) of every device in
+            /// the design this struct was generated from
+            #[must_use]
+            pub fn devices() -> std::collections::HashMap<&'static str, &'static str> {
+                std::collections::HashMap::from([#((#device_names, #device_kinds)),*])
+            }
+
+            /// The md5 of the design this struct was generated from
+            #[must_use]
+            pub fn design_md5() -> &'static str {
+                #md5
+            }
+
+            /// The path of the fpg file this struct was generated from
+            #[must_use]
+            pub fn fpg_filename() -> &'static str {
+                #filename
+            }
+        }
+    };
+    Ok((generated, field_types))
+}
+
+#[proc_macro]
+/// Generates a fully-typed and specified FPGA instance using the object definitions from a given
+/// fpg file.
+///
+/// Fields are ordered by device name, or by yellow block kind and then name when the
+/// `group_by_kind` option is given, i.e. `fpga_from_fpg!(MyFpga, "my.fpg", group_by_kind)`.
+///
+/// Devices that may be missing from the live board (i.e. on older gateware) can be made `Option`
+/// fields with the `optional` option, either for every device or just the listed ones, i.e.
+/// `fpga_from_fpg!(MyFpga, "my.fpg", optional(gbe0, adc_snap))`. Those fields are `None` when the
+/// board doesn't list the device, so build the struct after programming the board. Devices
+/// without a register of their own (i.e. the SNAP ADC) can't be looked for and are always `Some`.
+///
+/// The design-level metadata of the platform (`xps:xsg`) block is available without the fpg file
+/// through `design_metadata()`, along with the `CLOCK_RATE_MHZ`, `CLOCK_SOURCE`, and `PLATFORM`
+/// constants for the entries the design has.
+///
+/// Relative paths are resolved against the directory in the `CASPERFPGA_FPG_DIR` environment
+/// variable if it is set (useful for build farms that keep gateware elsewhere), otherwise against
+/// the crate's `CARGO_MANIFEST_DIR`, falling back to the current directory.
+pub fn fpga_from_fpg(tokens: TokenStream) -> TokenStream {
+    let FpgFpga {
+        name,
+        filename,
+        group_by_kind,
+        optional,
+    } = parse_macro_input!(tokens as FpgFpga);
+    match fpga_struct(&name, &filename, group_by_kind, &optional) {
+        Ok((generated, _)) => generated.into(),
+        Err(e) => e,
+    }
+}
+
+#[proc_macro]
+/// Generates an FPGA type that can switch between several designs ("personalities") at runtime.
+///
+/// `fpga_personalities!(Instrument, SpecFpga => "spec.fpg", VoltFpga => "volt.fpg")` generates
+/// the `SpecFpga` and `VoltFpga` structs exactly as [`fpga_from_fpg`] would, an `Instrument<T>`
+/// enum with a variant holding each, and an `InstrumentPersonality` enum naming the designs.
+///
+/// Every device that exists with the same type in all of the designs gets an accessor method on
+/// the enum, so shared registers can be used without matching on the personality.
+pub fn fpga_personalities(tokens: TokenStream) -> TokenStream {
+    let FpgPersonalities { name, designs } = parse_macro_input!(tokens as FpgPersonalities);
+    let personality = format_ident!("{name}Personality");
+
+    let mut structs = vec![];
+    let mut shared: Option<Vec<(Ident, proc_macro2::TokenStream)>> = None;
+    for (design, filename) in &designs {
+        let (generated, fields) = match fpga_struct(design, filename, false, &Optional::None) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        structs.push(generated);
+        // Only keep the fields that are typed identically across every design
+        shared = Some(match shared {
+            None => fields,
+            Some(shared) => shared
+                .into_iter()
+                .filter(|(ident, ty)| {
+                    fields
+                        .iter()
+                        .any(|(i, t)| i == ident && t.to_string() == ty.to_string())
+                })
+                .collect(),
+        });
+    }
+    let shared = shared.unwrap_or_default();
+    let variants: Vec<_> = designs.iter().map(|(design, _)| design).collect();
+    let accessors = shared.iter().map(|(ident, ty)| {
+        let doc = format!("The `{ident}` block, which is shared by every personality");
+        quote! {
+            #[doc = #doc]
+            #[must_use]
+            pub fn #ident(&self) -> &#ty {
+                match self {
+                    #(Self::#variants(fpga) => &fpga.#ident,)*
+                }
+            }
+        }
+    });
+
+    let personality_enum = generate_personality_enum(&personality, &variants);
+    let generated = quote! {
+        #(#structs)*
+
+        #personality_enum
+
+        #[derive(Debug)]
+        pub enum #name<T> {
+            #(#variants(#variants<T>),)*
+        }
+
+        impl<T> #name<T>
+        where
+            T: casperfpga::transport::Transport
+        {
+            /// Build the FPGA as the `personality` design
+            pub fn new(
+                transport: T,
+                personality: #personality,
+            ) -> Result<Self, casperfpga::yellow_blocks::Error> {
+                Self::from_shared(std::sync::Arc::new(std::sync::Mutex::new(transport)), personality)
+            }
+
+            /// Build the FPGA as the `personality` design around an already shared transport
+            pub fn from_shared(
+                tarc: std::sync::Arc<std::sync::Mutex<T>>,
+                personality: #personality,
+            ) -> Result<Self, casperfpga::yellow_blocks::Error> {
+                Ok(match personality {
+                    #(#personality::#variants => Self::#variants(#variants::from_shared(tarc)?),)*
+                })
+            }
+
+            /// Rebuild the FPGA as a different personality on the same transport. This doesn't
+            /// program the FPGA, that's up to the caller.
+            pub fn switch(self, personality: #personality) -> Result<Self, casperfpga::yellow_blocks::Error> {
+                let tarc = self.transport().clone();
+                // Drop the old blocks before building the new ones
+                drop(self);
+                Self::from_shared(tarc, personality)
+            }
+
+            /// The personality this FPGA is currently using
+            #[must_use]
+            pub fn personality(&self) -> #personality {
+                match self {
+                    #(Self::#variants(_) => #personality::#variants,)*
+                }
+            }
+
+            /// The transport shared by every personality
+            #[must_use]
+            pub fn transport(&self) -> &std::sync::Arc<std::sync::Mutex<T>> {
+                match self {
+                    #(Self::#variants(fpga) => &fpga.transport,)*
+                }
+            }
+
+            #(#accessors)*
         }
     };
     TokenStream::from(generated)
 }
+
+/// The plain enum naming each personality of a [`fpga_personalities`] FPGA
+fn generate_personality_enum(personality: &Ident, variants: &[&Ident]) -> proc_macro2::TokenStream {
+    quote! {
+        /// The designs this FPGA can be switched between
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum #personality {
+            #(#variants,)*
+        }
+
+        impl #personality {
+            /// Every personality
+            #[must_use]
+            pub fn all() -> &'static [Self] {
+                &[#(Self::#variants,)*]
+            }
+
+            /// The path of the fpg file of this personality
+            #[must_use]
+            pub fn fpg_filename(self) -> &'static str {
+                match self {
+                    #(Self::#variants => #variants::<casperfpga::transport::mock::Mock>::fpg_filename(),)*
+                }
+            }
+
+            /// The md5 of the design of this personality
+            #[must_use]
+            pub fn design_md5(self) -> &'static str {
+                match self {
+                    #(Self::#variants => #variants::<casperfpga::transport::mock::Mock>::design_md5(),)*
+                }
+            }
+        }
+    }
+}
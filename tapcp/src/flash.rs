@@ -0,0 +1,89 @@
+//! Addressing of the onboard flash
+//!
+//! Flash locations are byte addresses in a 32-bit space, but the `/flash` files take offsets in
+//! 4 byte words and transfers are measured in `usize` bytes. [`FlashAddr`] keeps the byte address
+//! and does the conversions, checked, so a misaligned address or one pushed past the end of the
+//! address space is an error instead of a silently truncated offset.
+use crate::{
+    Error,
+    FLASH_SECTOR_SIZE,
+};
+use std::fmt::Display;
+
+/// A byte address in the onboard flash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FlashAddr(u32);
+
+impl FlashAddr {
+    /// The flash address `bytes` bytes from the start of flash
+    #[must_use]
+    pub const fn new(bytes: u32) -> Self {
+        Self(bytes)
+    }
+
+    /// The flash address of the word offset `word`, as used by the `/flash` files
+    /// # Errors
+    /// Returns an error if the word is past the end of the 32-bit address space
+    pub fn from_word(word: usize) -> Result<Self, Error> {
+        u32::try_from(word)
+            .ok()
+            .and_then(|word| word.checked_mul(4))
+            .map(Self)
+            .ok_or(Error::FlashOverflow {
+                addr: Self(0),
+                offset: word.saturating_mul(4),
+            })
+    }
+
+    /// The address in bytes
+    #[must_use]
+    pub const fn bytes(self) -> u32 {
+        self.0
+    }
+
+    /// The word offset of the address, as used by the `/flash` files
+    /// # Errors
+    /// Returns an error if the address isn't word aligned
+    pub fn word(self) -> Result<usize, Error> {
+        if self.0 % 4 == 0 {
+            Ok(self.0 as usize / 4)
+        } else {
+            Err(Error::MisalignedFlash(self))
+        }
+    }
+
+    /// The address `offset` bytes past this one
+    /// # Errors
+    /// Returns an error if that's past the end of the 32-bit address space
+    pub fn offset(self, offset: usize) -> Result<Self, Error> {
+        u32::try_from(offset)
+            .ok()
+            .and_then(|n| self.0.checked_add(n))
+            .map(Self)
+            .ok_or(Error::FlashOverflow { addr: self, offset })
+    }
+
+    /// The number of bytes from this address to the end of its flash sector
+    #[must_use]
+    pub const fn sector_room(self) -> usize {
+        (FLASH_SECTOR_SIZE - self.0 % FLASH_SECTOR_SIZE) as usize
+    }
+}
+
+impl From<u32> for FlashAddr {
+    fn from(bytes: u32) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<FlashAddr> for u32 {
+    fn from(addr: FlashAddr) -> Self {
+        addr.0
+    }
+}
+
+impl Display for FlashAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010x}", self.0)
+    }
+}
@@ -5,21 +5,40 @@ use casper_utils::csl;
 use kstring::KString;
 use std::{
     self,
-    collections::HashMap,
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
     fmt::Write,
     net::UdpSocket,
-    time::Duration,
-};
-use tftp_client::{
-    download,
-    upload,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 use thiserror::Error;
 use tracing::debug;
 
+pub mod flash;
+pub mod negotiate;
+pub mod stats;
+pub use flash::FlashAddr;
+pub use negotiate::TransferOptions;
+pub use stats::SocketStats;
+
 pub const FLASH_SECTOR_SIZE: u32 = 0x10000;
+/// The metadata dictionary is read and written in chunks of this many bytes
+pub const METADATA_CHUNK_SIZE: usize = 1024;
+/// The most chunks a metadata dictionary can span
+pub const MAX_METADATA_CHUNKS: usize = 128;
+/// The marker at the end of a metadata dictionary
+const METADATA_END: &[u8] = b"?end";
+/// The default number of words read per request by the chunked reads, the size of a flash sector
+pub const DEFAULT_READ_CHUNK_WORDS: usize = FLASH_SECTOR_SIZE as usize / 4;
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
 pub const MAX_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long [`progdev`] usually waits for the FPGA to reboot
+pub const DEFAULT_REBOOT_WAIT: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -29,88 +48,245 @@ pub enum Error {
     Incomplete,
     #[error("While trying to parse a string from a response, we received invalid UTF8")]
     Utf8(#[from] std::str::Utf8Error),
+    #[error("Expected {expected} bytes but the reads only returned {got}")]
+    ShortRead { expected: usize, got: usize },
     #[error("No metadata returned when we requested metadata")]
     MissingMetadata,
+    #[error("The metadata dictionary is {len} bytes, more than the maximum of {max}")]
+    MetadataTooLarge { len: usize, max: usize },
+    #[error("Flash address {0} isn't word aligned")]
+    MisalignedFlash(FlashAddr),
+    #[error("Flash address {addr} plus {offset} bytes is past the end of the address space")]
+    FlashOverflow { addr: FlashAddr, offset: usize },
     #[error(transparent)]
     Csl(#[from] csl::Error),
+    #[error("`{operation}` gave up after {attempts} attempts over {elapsed:?}")]
+    Timeout {
+        /// The file that was being transferred
+        operation: String,
+        /// The number of requests made, not counting the retransmits within each request
+        attempts: usize,
+        /// How long we spent trying
+        elapsed: Duration,
+        /// The error from the final attempt
+        #[source]
+        last: Box<tftp_client::Error>,
+    },
 }
 
-// The FPGA handles errors poorly, so when we try to move to quick (esp with sequential commands),
-// we want to retry. We'll create wrappers around the tftp functions to retry on procotol errors,
-// but bail on all others
-fn retrying_download(
-    filename: &str,
-    socket: &UdpSocket,
-    timeout: Duration,
-    max_timeout: Duration,
-    retries: usize,
-) -> Result<Vec<u8>, Error> {
-    let mut local_retries = 0;
-    let mut this_timeout = timeout;
-    loop {
-        if local_retries == retries {
-            return Err(Error::Tftp(tftp_client::Error::Timeout));
+impl Error {
+    /// The underlying TFTP error, looking through [`Error::Timeout`] to the final attempt
+    #[must_use]
+    pub fn tftp(&self) -> Option<&tftp_client::Error> {
+        match self {
+            Error::Tftp(e) => Some(e),
+            Error::Timeout { last, .. } => Some(last),
+            _ => None,
         }
-        let res = download(filename, socket, timeout, max_timeout, retries);
-        match res {
-            Ok(v) => return Ok(v),
-            Err(tftp_client::Error::Protocol { code, msg }) => {
-                debug!("Protocol error: {:?} {msg}", code);
-                std::thread::sleep(this_timeout);
-                local_retries += 1;
-                this_timeout += this_timeout / 2;
-                if this_timeout > MAX_TIMEOUT {
-                    this_timeout = MAX_TIMEOUT;
-                }
-                continue;
-            }
-            Err(e) => {
-                return Err(Error::Tftp(e));
-            }
+    }
+}
+
+/// How the requests of every operation are retried
+///
+/// Each operation is a single TFTP transfer (which retransmits lost packets on its own, up to
+/// [`RetryPolicy::attempts`] times). When the whole transfer fails with an error accepted by
+/// [`RetryPolicy::retry_on`], we wait a backoff and make the request again. The backoff starts at
+/// [`RetryPolicy::initial_backoff`] and grows by [`RetryPolicy::multiplier`] after every attempt,
+/// up to [`RetryPolicy::max_backoff`].
+///
+/// Every function in this crate takes anything that converts into a policy, so a plain `usize` is
+/// the default policy with that number of attempts.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// The maximum number of requests, also used as the retransmit limit within each request
+    pub attempts: usize,
+    /// The timeout of the first attempt and the first backoff. If `None`, this is the socket's
+    /// read timeout, falling back to [`DEFAULT_TIMEOUT`] if it has none, so callers can tune it
+    /// per operation.
+    pub initial_backoff: Option<Duration>,
+    /// The growth of the backoff after every failed attempt
+    pub multiplier: f64,
+    /// The largest backoff (and per-packet timeout) to ever use
+    pub max_backoff: Duration,
+    /// The fraction of every backoff that is randomized, from 0 (none) to 1 (anywhere between zero
+    /// and the full backoff), to keep several clients from retrying in lockstep
+    pub jitter: f64,
+    /// Give up once this much time has passed since the first attempt, even if there are attempts
+    /// left
+    pub max_elapsed: Option<Duration>,
+    /// Which errors are worth another attempt, by default only protocol errors
+    pub retry_on: fn(&tftp_client::Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            initial_backoff: None,
+            multiplier: 1.5,
+            max_backoff: MAX_TIMEOUT,
+            jitter: 0.0,
+            max_elapsed: None,
+            retry_on: |e| matches!(e, tftp_client::Error::Protocol { .. }),
         }
     }
 }
 
-fn retrying_upload(
-    filename: &str,
-    data: &[u8],
-    socket: &UdpSocket,
-    timeout: Duration,
-    max_timeout: Duration,
-    retries: usize,
-) -> Result<(), Error> {
-    let mut local_retries = 0;
-    let mut this_timeout = timeout;
-    loop {
-        if local_retries == retries {
-            return Err(Error::Tftp(tftp_client::Error::Timeout));
+impl From<usize> for RetryPolicy {
+    fn from(attempts: usize) -> Self {
+        Self::with_attempts(attempts)
+    }
+}
+
+impl RetryPolicy {
+    /// The default policy making at most `attempts` requests
+    #[must_use]
+    pub fn with_attempts(attempts: usize) -> Self {
+        Self {
+            attempts,
+            ..Self::default()
         }
-        let res = upload(filename, data, socket, timeout, max_timeout, retries);
-        match res {
-            Ok(()) => return Ok(()),
-            Err(tftp_client::Error::Protocol { code, msg }) => {
-                debug!("Protocol error: {:?} {msg}", code);
-                local_retries += 1;
-                std::thread::sleep(this_timeout);
-                local_retries += 1;
-                this_timeout += this_timeout / 2;
-                if this_timeout > MAX_TIMEOUT {
-                    this_timeout = MAX_TIMEOUT;
+    }
+
+    /// The timeout of the first attempt when using `socket`
+    #[must_use]
+    pub fn initial_timeout(&self, socket: &UdpSocket) -> Duration {
+        self.initial_backoff.unwrap_or_else(|| {
+            socket
+                .read_timeout()
+                .ok()
+                .flatten()
+                .unwrap_or(DEFAULT_TIMEOUT)
+        })
+    }
+
+    /// The backoff after `backoff`, without jitter
+    #[must_use]
+    pub fn next_backoff(&self, backoff: Duration) -> Duration {
+        backoff
+            .mul_f64(self.multiplier.max(1.0))
+            .min(self.max_backoff)
+    }
+
+    /// The time actually slept for a `backoff`, with the jitter applied
+    fn jittered(&self, backoff: Duration) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        // This only has to decorrelate clients, so the clock's sub-second noise is random enough
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let unit = f64::from(nanos.wrapping_mul(2_654_435_761)) / f64::from(u32::MAX);
+        backoff.mul_f64(1.0 - jitter * unit)
+    }
+
+    /// Run the request `f` (given the timeout to use for the transfer) for `operation` until it
+    /// succeeds, fails with an error not accepted by [`RetryPolicy::retry_on`], or we run out of
+    /// attempts or time
+    /// # Errors
+    /// Returns [`Error::Timeout`] if we gave up on a retryable (or timeout) error and
+    /// [`Error::Tftp`] on any other TFTP error
+    pub fn run<T, F>(&self, operation: &str, socket: &UdpSocket, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(Duration) -> Result<T, tftp_client::Error>,
+    {
+        let start = Instant::now();
+        let timeout = self.initial_timeout(socket);
+        let mut backoff = timeout;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let e = match f(timeout) {
+                Ok(v) => {
+                    if attempts > 1 {
+                        debug!("{operation} succeeded after {attempts} attempts");
+                    }
+                    return Ok(v);
                 }
-                continue;
-            }
-            Err(e) => {
+                Err(e) => e,
+            };
+            let retryable = (self.retry_on)(&e);
+            let sleep = self.jittered(backoff);
+            let out_of_time = self
+                .max_elapsed
+                .is_some_and(|max| start.elapsed() + sleep > max);
+            if retryable && attempts < self.attempts && !out_of_time {
+                // Under packet loss this can happen a lot, so only report on the first retry and
+                // then on every power of two after that
+                if attempts.is_power_of_two() {
+                    debug!("{operation} failed on attempt {attempts}: {e}");
+                }
+                std::thread::sleep(sleep);
+                backoff = self.next_backoff(backoff);
+            } else if retryable
+                || matches!(
+                    e,
+                    tftp_client::Error::Protocol { .. } | tftp_client::Error::Timeout
+                )
+            {
+                return Err(Error::Timeout {
+                    operation: operation.to_string(),
+                    attempts,
+                    elapsed: start.elapsed(),
+                    last: Box::new(e),
+                });
+            } else {
                 return Err(Error::Tftp(e));
             }
         }
     }
+
+    // The FPGA handles errors poorly, so when we try to move to quick (esp with sequential
+    // commands), we want to retry. These wrap the tftp functions with the retry engine.
+    fn download(&self, filename: &str, socket: &UdpSocket) -> Result<Vec<u8>, Error> {
+        self.negotiated_download(filename, socket, TransferOptions::default(), |_, _| {})
+    }
+
+    fn negotiated_download<P>(
+        &self,
+        filename: &str,
+        socket: &UdpSocket,
+        options: TransferOptions,
+        mut progress: P,
+    ) -> Result<Vec<u8>, Error>
+    where
+        P: FnMut(usize, Option<usize>),
+    {
+        self.run(filename, socket, |timeout| {
+            negotiate::download(
+                filename,
+                socket,
+                timeout,
+                self.max_backoff,
+                self.attempts,
+                options,
+                &mut progress,
+            )
+            .map(|(data, _)| data)
+        })
+    }
+
+    fn upload(&self, filename: &str, data: &[u8], socket: &UdpSocket) -> Result<(), Error> {
+        self.run(filename, socket, |timeout| {
+            negotiate::upload(
+                filename,
+                data,
+                socket,
+                timeout,
+                self.max_backoff,
+                self.attempts,
+            )
+        })
+    }
 }
 
 /// Gets the temperature of the remote device in Celsius
 /// # Errors
 /// Returns an error on TFTP errors
-pub fn temp(socket: &UdpSocket, retries: usize) -> Result<f32, Error> {
-    let bytes = retrying_download("/temp", socket, DEFAULT_TIMEOUT, MAX_TIMEOUT, retries)?;
+pub fn temp(socket: &UdpSocket, retries: impl Into<RetryPolicy>) -> Result<f32, Error> {
+    let bytes = retries.into().download("/temp", socket)?;
     let four_bytes = bytes.get(..4).ok_or(Error::Incomplete)?;
     Ok(f32::from_be_bytes(
         four_bytes.try_into().map_err(|_| Error::Incomplete)?,
@@ -120,30 +296,84 @@ pub fn temp(socket: &UdpSocket, retries: usize) -> Result<f32, Error> {
 /// Gets the list of top level commands (as a string)
 /// # Errors
 /// Returns an error on TFTP errors
-pub fn help(socket: &UdpSocket, retries: usize) -> Result<String, Error> {
-    let bytes = retrying_download("/help", socket, DEFAULT_TIMEOUT, MAX_TIMEOUT, retries)?;
+pub fn help(socket: &UdpSocket, retries: impl Into<RetryPolicy>) -> Result<String, Error> {
+    let bytes = retries.into().download("/help", socket)?;
     Ok(std::str::from_utf8(&bytes)?.to_string())
 }
 
+/// Gets the names of the top level commands the server lists in `/help`, i.e. `/listdev` and
+/// `/progdev`. These are what the server knows how to handle, not files stored on the board.
+/// # Errors
+/// Returns an error on TFTP errors
+pub fn list_commands(
+    socket: &UdpSocket,
+    retries: impl Into<RetryPolicy>,
+) -> Result<Vec<String>, Error> {
+    Ok(help(socket, retries)?
+        .split_whitespace()
+        .filter(|s| s.starts_with('/'))
+        .map(ToString::to_string)
+        .collect())
+}
+
+/// The TFTP filename of `name`, which may leave off the leading slash
+fn file_path(name: &str) -> String {
+    if name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("/{name}")
+    }
+}
+
+/// Read the entire contents of an arbitrary file `name` on the server, i.e. a calibration table
+/// stored on the board
+/// # Errors
+/// Returns an error on TFTP errors, including if the file doesn't exist
+pub fn read_file(
+    name: &str,
+    socket: &UdpSocket,
+    retries: impl Into<RetryPolicy>,
+) -> Result<Vec<u8>, Error> {
+    retries.into().download(&file_path(name), socket)
+}
+
+/// Write `data` to an arbitrary file `name` on the server
+/// # Errors
+/// Returns an error on TFTP errors
+pub fn write_file(
+    name: &str,
+    data: &[u8],
+    socket: &UdpSocket,
+    retries: impl Into<RetryPolicy>,
+) -> Result<(), Error> {
+    retries.into().upload(&file_path(name), data, socket)
+}
+
 /// Gets the list of all devices supported by the currently running gateware
 /// Returns a hash map from device name to (addr,length)
 /// # Errors
 /// Returns an error on TFTP errors
-pub fn listdev(socket: &UdpSocket, retries: usize) -> Result<HashMap<String, (u32, u32)>, Error> {
+pub fn listdev(
+    socket: &UdpSocket,
+    retries: impl Into<RetryPolicy>,
+) -> Result<HashMap<String, (u32, u32)>, Error> {
     // Grab CSL bytes
-    let bytes = retrying_download("/listdev", socket, DEFAULT_TIMEOUT, MAX_TIMEOUT, retries)?;
-    // Unpack CSL
-    let csl = csl::from_bytes(&bytes)?;
-    // Translate into our device map
-    csl.into_iter()
-        .map(|(k, v)| {
-            // Value should be exactly 8 bytes
-            // First 4 is offset, second is length
-            let addr = u32::from_be_bytes(v[..4].try_into().map_err(|_| Error::Incomplete)?);
-            let length = u32::from_be_bytes(v[4..].try_into().map_err(|_| Error::Incomplete)?);
-            Ok((k, (addr, length)))
-        })
-        .collect()
+    let bytes = retries.into().download("/listdev", socket)?;
+    // Unpack CSL straight into our device map
+    let mut entries = csl::Entries::new(&bytes)?;
+    let mut devices = HashMap::new();
+    while let Some(entry) = entries.next_entry() {
+        let (k, v) = entry?;
+        // Value should be exactly 8 bytes
+        // First 4 is offset, second is length
+        if v.len() != 8 {
+            return Err(Error::Incomplete);
+        }
+        let addr = u32::from_be_bytes(v[..4].try_into().map_err(|_| Error::Incomplete)?);
+        let length = u32::from_be_bytes(v[4..].try_into().map_err(|_| Error::Incomplete)?);
+        devices.insert(k.to_string(), (addr, length));
+    }
+    Ok(devices)
 }
 
 /// Read memory associated with the gateware device `device`
@@ -156,12 +386,12 @@ pub fn read_device(
     offset: usize,
     n: usize,
     socket: &UdpSocket,
-    retries: usize,
+    retries: impl Into<RetryPolicy>,
 ) -> Result<Vec<u8>, Error> {
     // To start the request, we need to form the filename string, defined by the TAPCP
     // spec as - `/dev/DEV_NAME[.WORD_OFFSET[.NWORDS]]` with WORD_OFFSET and NWORDs in hexadecimal
     let filename = format!("/dev/{device}.{offset:x}.{n:x}");
-    let bytes = retrying_download(&filename, socket, DEFAULT_TIMEOUT, MAX_TIMEOUT, retries)?;
+    let bytes = retries.into().download(&filename, socket)?;
     if n != 0 && bytes.len() != n * 4 {
         Err(Error::Incomplete)
     } else {
@@ -169,6 +399,56 @@ pub fn read_device(
     }
 }
 
+/// Read `n` words of the gateware device `device` like [`read_device`], but in requests of at most
+/// `chunk` words, so reads of big devices don't depend on a single huge transfer. Every chunk is
+/// retried on its own by `retries`, so a failure only repeats that chunk.
+/// # Errors
+/// Returns an error on TFTP errors or if the device returned fewer than `n` words
+pub fn read_device_chunked(
+    device: &str,
+    offset: usize,
+    n: usize,
+    chunk: usize,
+    socket: &UdpSocket,
+    retries: impl Into<RetryPolicy>,
+) -> Result<Vec<u8>, Error> {
+    let retries = retries.into();
+    read_chunked(offset, n, chunk, |offset, n| {
+        retries.download(&format!("/dev/{device}.{offset:x}.{n:x}"), socket)
+    })
+}
+
+/// Read `n` words starting at the word `offset` with `read(offset, n)` in chunks of at most
+/// `chunk` words, reassembling them into one buffer
+/// # Errors
+/// Returns the first error from `read`, or [`Error::ShortRead`] if a chunk came back short
+pub fn read_chunked<F>(offset: usize, n: usize, chunk: usize, mut read: F) -> Result<Vec<u8>, Error>
+where
+    F: FnMut(usize, usize) -> Result<Vec<u8>, Error>,
+{
+    let chunk = chunk.max(1);
+    let mut bytes = Vec::with_capacity(4 * n);
+    let mut done = 0;
+    while done < n {
+        let words = chunk.min(n - done);
+        let data = read(offset + done, words)?;
+        bytes.extend_from_slice(&data);
+        // Anything after a short chunk would be in the wrong place
+        if data.len() != 4 * words {
+            break;
+        }
+        done += words;
+    }
+    if bytes.len() == 4 * n {
+        Ok(bytes)
+    } else {
+        Err(Error::ShortRead {
+            expected: 4 * n,
+            got: bytes.len(),
+        })
+    }
+}
+
 /// Write bytes to the device named `device`
 /// # Errors
 /// Returns an error on TFTP errors
@@ -177,20 +457,13 @@ pub fn write_device(
     offset: usize,
     data: &[u8],
     socket: &UdpSocket,
-    retries: usize,
+    retries: impl Into<RetryPolicy>,
 ) -> Result<(), Error> {
     // To start the request, we need to form the filename string, defined by the TAPCP
     // spec as - `/dev/DEV_NAME[.WORD_OFFSET]` with WORD_OFFSET and NWORDs in hexadecimal
     let filename = format!("/dev/{device}.{offset:x}");
     // Then do it
-    retrying_upload(
-        &filename,
-        data,
-        socket,
-        DEFAULT_TIMEOUT,
-        MAX_TIMEOUT,
-        retries,
-    )
+    retries.into().upload(&filename, data, socket)
 }
 
 /// Read memory from the onboard flash
@@ -201,14 +474,38 @@ pub fn read_flash(
     offset: usize,
     n: usize,
     socket: &UdpSocket,
-    retries: usize,
+    retries: impl Into<RetryPolicy>,
 ) -> Result<Vec<u8>, Error> {
     // spec as - `/flash.WORD_OFFSET[.NWORDS]` with WORD_OFFSET and NWORDs in hexadecimal
     let filename = format!("/flash.{offset:x}.{n:x}");
-    let bytes = retrying_download(&filename, socket, DEFAULT_TIMEOUT, MAX_TIMEOUT, retries)?;
+    let bytes = retries.into().download(&filename, socket)?;
     Ok(bytes)
 }
 
+/// Read memory from the onboard flash like [`read_flash`], negotiating `options` with the board
+/// (see [`negotiate`]). `progress` is called with the number of bytes read so far and the total
+/// after every block, where the total comes from `tsize` if the board sent it.
+/// # Errors
+/// Returns an error on TFTP errors
+pub fn read_flash_with_progress<P>(
+    offset: usize,
+    n: usize,
+    socket: &UdpSocket,
+    retries: impl Into<RetryPolicy>,
+    options: TransferOptions,
+    mut progress: P,
+) -> Result<Vec<u8>, Error>
+where
+    P: FnMut(usize, usize),
+{
+    let filename = format!("/flash.{offset:x}.{n:x}");
+    retries
+        .into()
+        .negotiated_download(&filename, socket, options, |read, total| {
+            progress(read, total.unwrap_or(4 * n));
+        })
+}
+
 /// Writes data to the onboard flash
 /// `offset` are in increments of 4 byte words, just like `read_device`
 /// # Errors
@@ -217,98 +514,230 @@ pub fn write_flash(
     offset: usize,
     data: &[u8],
     socket: &UdpSocket,
-    retries: usize,
+    retries: impl Into<RetryPolicy>,
 ) -> Result<(), Error> {
     let filename = format!("/flash.{offset:x}");
-    retrying_upload(
-        &filename,
-        data,
-        socket,
-        DEFAULT_TIMEOUT,
-        MAX_TIMEOUT,
-        retries,
-    )
+    retries.into().upload(&filename, data, socket)
 }
 
-/// Reboot the FPGA from the bitstream program at the 32-bit address `addr`.
+/// A platform control command, which servers that support it expose as a write-only file listed
+/// in `/help`. Only the commands of the TAPCP server itself are here, the rest of what `/help`
+/// lists is read with the other functions of this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PlatformCommand {
+    /// `/progdev`, reboot the FPGA from a flash address (see [`progdev`])
+    Progdev,
+}
+
+impl PlatformCommand {
+    /// Every command we know how to send
+    pub const ALL: [Self; 1] = [Self::Progdev];
+
+    /// The file the command is written to
+    #[must_use]
+    pub const fn path(self) -> &'static str {
+        match self {
+            Self::Progdev => "/progdev",
+        }
+    }
+
+    /// The command written to the file at `path`, if it's one we know
+    #[must_use]
+    pub fn from_path(path: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.path() == path)
+    }
+}
+
+impl std::fmt::Display for PlatformCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.path())
+    }
+}
+
+/// Gets the platform commands the server advertises in `/help`
+/// # Errors
+/// Returns an error on TFTP errors
+pub fn platform_commands(
+    socket: &UdpSocket,
+    retries: impl Into<RetryPolicy>,
+) -> Result<Vec<PlatformCommand>, Error> {
+    Ok(list_commands(socket, retries)?
+        .iter()
+        .filter_map(|f| PlatformCommand::from_path(f))
+        .collect())
+}
+
+/// Reboot the FPGA from the bitstream program at the 32-bit address `addr`, then wait
+/// `reboot_wait` (usually [`DEFAULT_REBOOT_WAIT`]) for it to come back.
 /// No validation is performed to ensure a program actually exists there
 /// # Errors
 /// Returns an error on TFTP errors
-pub fn progdev(addr: u32, socket: &UdpSocket) -> Result<(), Error> {
-    match upload(
+pub fn progdev(addr: u32, socket: &UdpSocket, reboot_wait: Duration) -> Result<(), Error> {
+    match negotiate::upload(
         "/progdev",
         &addr.to_be_bytes(),
         socket,
-        DEFAULT_TIMEOUT,
+        RetryPolicy::default().initial_timeout(socket),
         MAX_TIMEOUT,
         0,
     ) {
         Ok(()) | Err(_) => (),
     }
     // Then wait as the FPGA takes a while to reboot
-    std::thread::sleep(Duration::from_secs(10));
+    std::thread::sleep(reboot_wait);
     Ok(())
 }
 
-/// Retrieves the most recent metadata (stored at the 32-bit `user_flash_loc` address)
-/// # Errors
-/// Returns an error on TFTP errors or if the metadata couldn't be found
-pub fn get_metadata(
+/// A decoded metadata dictionary
+pub type Metadata = HashMap<KString, String>;
+
+/// Read the raw metadata dictionary at `user_flash_loc`, up to (but not including) its `?end`, or
+/// `None` if there's no end within [`MAX_METADATA_CHUNKS`]
+fn read_dict(
     socket: &UdpSocket,
-    user_flash_loc: u32,
-    retries: usize,
-) -> Result<HashMap<KString, String>, Error> {
-    let mut dict_str = String::new();
-    let mut chunks = 0;
-    let chunk_size = 1024 / 4;
-    loop {
-        if chunks > 128 {
-            return Err(Error::MissingMetadata);
-        }
-        let raw = read_flash(
-            (user_flash_loc / 4 + chunks * chunk_size) as usize,
-            chunk_size as usize,
+    user_flash_loc: FlashAddr,
+    retries: RetryPolicy,
+) -> Result<Option<Vec<u8>>, Error> {
+    let chunk_words = METADATA_CHUNK_SIZE / 4;
+    let mut raw = vec![];
+    for chunk in 0..MAX_METADATA_CHUNKS {
+        raw.extend(read_flash(
+            user_flash_loc.offset(chunk * METADATA_CHUNK_SIZE)?.word()?,
+            chunk_words,
             socket,
             retries,
-        )?;
-        dict_str.push_str(std::str::from_utf8(&raw)?);
-        match dict_str.find("?end") {
-            Some(idx) => {
-                dict_str = dict_str.split_at(idx).0.to_string();
-                break;
-            }
-            None => chunks += 1,
+        )?);
+        // The end marker can straddle two chunks
+        let from = raw
+            .len()
+            .saturating_sub(METADATA_CHUNK_SIZE + METADATA_END.len());
+        if let Some(idx) = raw[from..]
+            .windows(METADATA_END.len())
+            .position(|w| w == METADATA_END)
+        {
+            raw.truncate(from + idx);
+            return Ok(Some(raw));
         }
     }
-    Ok(dict_str
+    Ok(None)
+}
+
+/// Parse the `?<key>\t<value>` pairs of a raw dictionary (without its `?end`)
+/// # Errors
+/// Returns an error if the dictionary isn't valid UTF-8
+pub fn decode_metadata(dict: &[u8]) -> Result<HashMap<KString, String>, Error> {
+    Ok(std::str::from_utf8(dict)?
         .split('?')
         .filter_map(|kv| kv.split_once('\t'))
         .map(|(k, v)| (k.to_string().into(), v.to_string()))
         .collect())
 }
 
-/// Program arbitrary metadata (stored at the 32-bit `user_flash_loc` address)
+/// Encode `data` as a dictionary of `?<key>\t<value>` pairs followed by `?end`, padded out to
+/// whole chunks covering at least `extent` bytes, so a previous (longer) dictionary of that many
+/// bytes is overwritten entirely
 /// # Errors
-/// Returns an error on TFTP errors or if the metadata couldn't be found
+/// Returns an error if the dictionary is longer than [`MAX_METADATA_CHUNKS`] chunks
 #[allow(clippy::implicit_hasher)]
-pub fn set_metadata(
-    data: &HashMap<KString, String>,
-    socket: &UdpSocket,
-    user_flash_loc: u32,
-    retries: usize,
-) -> Result<(), Error> {
-    // Dict is written as ?<key>\t<value> pairs followed by ?end
-    // It must be padded with zeros to be a multiple of 1024
-    let mut dict_str = data.iter().fold(String::new(), |mut output, (k, v)| {
+pub fn encode_metadata(data: &HashMap<KString, String>, extent: usize) -> Result<Vec<u8>, Error> {
+    // Sorted, so the same dictionary is always written the same way
+    let sorted: BTreeMap<_, _> = data.iter().collect();
+    let mut dict_str = sorted.iter().fold(String::new(), |mut output, (k, v)| {
         let _ = write!(output, "?{k}\t{v}");
         output
     });
     dict_str.push_str("?end");
-    let mut bytes = dict_str.as_bytes().to_vec();
-    // Padding
-    if bytes.len() % 1024 != 0 {
-        bytes.append(&mut vec![b'0'; 1024 - bytes.len() % 1024]);
+    let mut bytes = dict_str.into_bytes();
+    let max = MAX_METADATA_CHUNKS * METADATA_CHUNK_SIZE;
+    if bytes.len() > max {
+        return Err(Error::MetadataTooLarge {
+            len: bytes.len(),
+            max,
+        });
+    }
+    // Padding with zeros (the character) to whole chunks
+    let len = bytes.len().max(extent.min(max));
+    let padded = (len + METADATA_CHUNK_SIZE - 1) / METADATA_CHUNK_SIZE * METADATA_CHUNK_SIZE;
+    bytes.resize(padded, b'0');
+    Ok(bytes)
+}
+
+/// Retrieves the most recent metadata (stored at the flash address `user_flash_loc`)
+/// # Errors
+/// Returns an error on TFTP errors, if the metadata couldn't be found, or if `user_flash_loc`
+/// isn't word aligned
+pub fn get_metadata(
+    socket: &UdpSocket,
+    user_flash_loc: impl Into<FlashAddr>,
+    retries: impl Into<RetryPolicy>,
+) -> Result<HashMap<KString, String>, Error> {
+    let (meta, _) =
+        read_metadata(socket, user_flash_loc, retries)?.ok_or(Error::MissingMetadata)?;
+    Ok(meta)
+}
+
+/// Retrieves the most recent metadata (stored at the flash address `user_flash_loc`) along with
+/// the number of bytes of flash it covers, end marker included, or `None` if there isn't any.
+/// The extent can be handed to [`replace_metadata`] so it doesn't have to be read again.
+/// # Errors
+/// Returns an error on TFTP errors or if `user_flash_loc` isn't word aligned
+pub fn read_metadata(
+    socket: &UdpSocket,
+    user_flash_loc: impl Into<FlashAddr>,
+    retries: impl Into<RetryPolicy>,
+) -> Result<Option<(Metadata, usize)>, Error> {
+    read_dict(socket, user_flash_loc.into(), retries.into())?
+        .map(|dict| Ok((decode_metadata(&dict)?, dict.len() + METADATA_END.len())))
+        .transpose()
+}
+
+/// Program arbitrary metadata (stored at the flash address `user_flash_loc`)
+///
+/// The whole extent of the previous dictionary is overwritten, so a shorter dictionary doesn't
+/// leave stale entries behind it. Dictionaries spanning several flash sectors are written a sector
+/// at a time.
+/// # Errors
+/// Returns an error on TFTP errors, if the dictionary is too large, or if `user_flash_loc` isn't
+/// word aligned
+#[allow(clippy::implicit_hasher)]
+pub fn set_metadata(
+    data: &HashMap<KString, String>,
+    socket: &UdpSocket,
+    user_flash_loc: impl Into<FlashAddr>,
+    retries: impl Into<RetryPolicy>,
+) -> Result<(), Error> {
+    let retries = retries.into();
+    let addr = user_flash_loc.into();
+    let extent =
+        read_dict(socket, addr, retries)?.map_or(0, |dict| dict.len() + METADATA_END.len());
+    replace_metadata(data, extent, socket, addr, retries)
+}
+
+/// Like [`set_metadata`], but overwriting `extent` bytes of a previous dictionary (as returned by
+/// [`read_metadata`]) instead of reading it back first
+/// # Errors
+/// Returns an error on TFTP errors, if the dictionary is too large, or if `user_flash_loc` isn't
+/// word aligned
+#[allow(clippy::implicit_hasher)]
+pub fn replace_metadata(
+    data: &HashMap<KString, String>,
+    extent: usize,
+    socket: &UdpSocket,
+    user_flash_loc: impl Into<FlashAddr>,
+    retries: impl Into<RetryPolicy>,
+) -> Result<(), Error> {
+    let retries = retries.into();
+    let mut addr = user_flash_loc.into();
+    let bytes = encode_metadata(data, extent)?;
+    // Every write has to stay within a sector
+    let mut rest = bytes.as_slice();
+    while !rest.is_empty() {
+        let (piece, tail) = rest.split_at(addr.sector_room().min(rest.len()));
+        write_flash(addr.word()?, piece, socket, retries)?;
+        rest = tail;
+        if !rest.is_empty() {
+            addr = addr.offset(piece.len())?;
+        }
     }
-    // Write
-    write_flash((user_flash_loc / 4) as usize, &bytes, socket, retries)
+    Ok(())
 }
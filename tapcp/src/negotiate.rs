@@ -0,0 +1,425 @@
+//! Downloads with TFTP option negotiation (RFC 2347), for the `tsize` and `timeout` options of
+//! RFC 2349 and the `blksize` option of RFC 2348
+//!
+//! `tftp_client` only speaks plain RFC 1350, so this is a small client that asks for the
+//! [`TransferOptions`] in its read requests. A board that supports them acknowledges the ones it
+//! accepts with an OACK, and we use the size to pre-allocate the download and report progress, and
+//! the timeout as the retransmit timeout. A board that doesn't simply answers with the first block
+//! of data (or refuses with a "bad option" error, in which case we ask again without options), so
+//! falling back is silent.
+//!
+//! Every transfer of this crate goes through [`download`] and [`upload`] (plain transfers are just
+//! ones without options), so they are also where datagrams are sized and counted (see
+//! [`stats`](crate::stats)). Nothing bigger than [`MAX_DATAGRAM`] is ever asked for, and a
+//! datagram bigger than the block size of its transfer fails the transfer instead of being
+//! silently truncated.
+use crate::stats;
+use std::{
+    ffi::CString,
+    io,
+    net::UdpSocket,
+    time::Duration,
+};
+use tftp_client::{
+    parser::{
+        self,
+        ErrorCode,
+        Packet,
+    },
+    Error,
+};
+use tracing::debug;
+
+/// The block size of plain transfers
+const BLOCK_SIZE: usize = 512;
+/// The largest datagram we ask for, the UDP payload of a standard 1500 byte Ethernet MTU, so
+/// nothing is fragmented on the way
+pub const MAX_DATAGRAM: usize = 1472;
+/// The largest block size we ask for, the data that fits in a [`MAX_DATAGRAM`] after its header
+pub const MAX_BLOCK_SIZE: usize = MAX_DATAGRAM - 4;
+/// The smallest block size of RFC 2348
+const MIN_BLOCK_SIZE: usize = 8;
+const OP_RRQ: u16 = 1;
+const OP_WRQ: u16 = 2;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+/// The options to ask for on a download, none by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferOptions {
+    /// Ask for the size of the file with `tsize`
+    pub tsize: bool,
+    /// Ask for a retransmit timeout of this many seconds (1 to 255) with `timeout`
+    pub timeout: Option<u8>,
+    /// Ask for blocks of this many bytes with `blksize`, clamped to at most [`MAX_BLOCK_SIZE`] so
+    /// every block fits in one datagram
+    pub blksize: Option<u16>,
+}
+
+impl TransferOptions {
+    /// Ask for both the size and a retransmit timeout of `timeout` seconds
+    #[must_use]
+    pub fn with_timeout(timeout: u8) -> Self {
+        Self {
+            tsize: true,
+            timeout: Some(timeout),
+            blksize: None,
+        }
+    }
+
+    /// True if there is nothing to negotiate
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        !self.tsize && self.timeout.is_none() && self.blksize.is_none()
+    }
+
+    /// The block size we actually ask for, if any
+    fn requested_blksize(self) -> Option<usize> {
+        self.blksize
+            .map(|size| usize::from(size).clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE))
+    }
+}
+
+/// The options the board accepted, all `None` if it ignored them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Negotiated {
+    /// The size of the file in bytes
+    pub tsize: Option<usize>,
+    /// The retransmit timeout both sides use
+    pub timeout: Option<Duration>,
+    /// The size of every block but the last
+    pub blksize: Option<usize>,
+}
+
+/// Download `filename` like [`tftp_client::download`], negotiating `options` with the server and
+/// calling `progress` with the bytes received so far and the size of the file (if the server told
+/// us) after every block
+/// # Errors
+/// Returns an error on socket errors, timeouts, protocol errors, malformed packets, or a datagram
+/// larger than the block size
+pub fn download<P>(
+    filename: &str,
+    socket: &UdpSocket,
+    timeout: Duration,
+    max_timeout: Duration,
+    retries: usize,
+    options: TransferOptions,
+    mut progress: P,
+) -> Result<(Vec<u8>, Negotiated), Error>
+where
+    P: FnMut(usize, Option<usize>),
+{
+    let old_read_timeout = socket.read_timeout().map_err(Error::SocketIo)?;
+    let result = transfer(
+        filename,
+        socket,
+        timeout,
+        max_timeout,
+        retries,
+        options,
+        &mut progress,
+    );
+    socket
+        .set_read_timeout(old_read_timeout)
+        .map_err(Error::SocketIo)?;
+    result
+}
+
+fn transfer(
+    filename: &str,
+    socket: &UdpSocket,
+    timeout: Duration,
+    max_timeout: Duration,
+    retries: usize,
+    options: TransferOptions,
+    progress: &mut dyn FnMut(usize, Option<usize>),
+) -> Result<(Vec<u8>, Negotiated), Error> {
+    debug!("┌── GET {filename} with {options:?}");
+    let mut options = options;
+    let mut negotiated = Negotiated::default();
+    let mut data = vec![];
+    // The last block we received, zero until the first
+    let mut block = 0u16;
+    let mut local_timeout = timeout;
+    let mut local_retries = retries;
+    let mut send_pkt = read_request(filename, options)?;
+    socket
+        .set_read_timeout(Some(local_timeout))
+        .map_err(Error::SocketIo)?;
+    stats::send(socket, &send_pkt).map_err(Error::SocketIo)?;
+    // One byte more than we'd ever take, so oversized datagrams can't pass as truncated ones
+    let mut buf = vec![0; MAX_DATAGRAM + 1];
+    loop {
+        let n = match stats::recv(socket, &mut buf) {
+            Ok(n) => n,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                debug!("│ Timeout");
+                local_retries = local_retries.saturating_sub(1);
+                if local_retries == 0 {
+                    return Err(Error::Timeout);
+                }
+                // A negotiated timeout is fixed, otherwise back off like `tftp_client`
+                if negotiated.timeout.is_none() {
+                    local_timeout = (local_timeout + local_timeout / 2).min(max_timeout);
+                    socket
+                        .set_read_timeout(Some(local_timeout))
+                        .map_err(Error::SocketIo)?;
+                }
+                stats::send(socket, &send_pkt).map_err(Error::SocketIo)?;
+                continue;
+            }
+            Err(e) => return Err(Error::SocketIo(e)),
+        };
+        let packet = &buf[..n];
+        if n < 4 {
+            return Err(Error::Parse(parser::Error::Incomplete(n)));
+        }
+        match u16::from_be_bytes([packet[0], packet[1]]) {
+            // The OACK is resent until we acknowledge it, so take it as long as we have no data
+            OP_OACK if block == 0 => {
+                negotiated = parse_oack(&packet[2..], options)?;
+                debug!("│ RX - OACK {negotiated:?}");
+                if let Some(size) = negotiated.tsize {
+                    // Just a hint, so a bogus size shouldn't stop the download
+                    let _ = data.try_reserve_exact(size);
+                }
+                if let Some(t) = negotiated.timeout {
+                    local_timeout = t;
+                }
+                send_pkt = ack(0);
+            }
+            OP_DATA => {
+                let block_size = negotiated.blksize.unwrap_or(BLOCK_SIZE);
+                if n > block_size + 4 {
+                    stats::oversized(socket);
+                    return Err(too_big(n, block_size + 4));
+                }
+                let block_n = u16::from_be_bytes([packet[2], packet[3]]);
+                if block_n != block.wrapping_add(1) {
+                    // A retransmit of a block we already have because our ACK was lost, so ACK it
+                    // again
+                    debug!("│ RX - DATA {block_n} (Duplicate)");
+                    if block_n == block {
+                        stats::send(socket, &send_pkt).map_err(Error::SocketIo)?;
+                    }
+                    continue;
+                }
+                block = block_n;
+                data.extend_from_slice(&packet[4..]);
+                progress(data.len(), negotiated.tsize);
+                send_pkt = ack(block);
+                if n - 4 < block_size {
+                    stats::send(socket, &send_pkt).map_err(Error::SocketIo)?;
+                    debug!("└");
+                    return Ok((data, negotiated));
+                }
+            }
+            OP_ERROR
+                if block == 0
+                    && !options.is_empty()
+                    && packet[2..4] == (ErrorCode::BadOpt as u16).to_be_bytes() =>
+            {
+                debug!("│ RX - Options refused, asking again without them");
+                options = TransferOptions::default();
+                negotiated = Negotiated::default();
+                send_pkt = read_request(filename, options)?;
+            }
+            _ => return Err(unexpected(packet)),
+        }
+        // We made progress, so start over on the retries
+        local_retries = retries;
+        if negotiated.timeout.is_none() {
+            local_timeout = timeout;
+        }
+        socket
+            .set_read_timeout(Some(local_timeout))
+            .map_err(Error::SocketIo)?;
+        stats::send(socket, &send_pkt).map_err(Error::SocketIo)?;
+    }
+}
+
+/// Upload `data` to `filename` like [`tftp_client::upload`], sending it in plain blocks of 512
+/// bytes and ending with the block that completes the data
+/// # Errors
+/// Returns an error on socket errors, timeouts, protocol errors, or malformed packets
+pub fn upload(
+    filename: &str,
+    data: &[u8],
+    socket: &UdpSocket,
+    timeout: Duration,
+    max_timeout: Duration,
+    retries: usize,
+) -> Result<(), Error> {
+    let old_read_timeout = socket.read_timeout().map_err(Error::SocketIo)?;
+    let result = send_file(filename, data, socket, timeout, max_timeout, retries);
+    socket
+        .set_read_timeout(old_read_timeout)
+        .map_err(Error::SocketIo)?;
+    result
+}
+
+fn send_file(
+    filename: &str,
+    data: &[u8],
+    socket: &UdpSocket,
+    timeout: Duration,
+    max_timeout: Duration,
+    retries: usize,
+) -> Result<(), Error> {
+    debug!("┌── PUT {filename}");
+    let chunks: Vec<_> = data.chunks(BLOCK_SIZE).collect();
+    let filename = CString::new(filename).map_err(|_| Error::BadFilename)?;
+    let mut send_pkt = OP_WRQ.to_be_bytes().to_vec();
+    send_pkt.extend_from_slice(filename.as_bytes_with_nul());
+    send_pkt.extend_from_slice(b"octet\0");
+    // The block the next ACK has to be for
+    let mut block = 0u16;
+    let mut local_timeout = timeout;
+    let mut local_retries = retries;
+    socket
+        .set_read_timeout(Some(local_timeout))
+        .map_err(Error::SocketIo)?;
+    stats::send(socket, &send_pkt).map_err(Error::SocketIo)?;
+    let mut buf = vec![0; MAX_DATAGRAM + 1];
+    loop {
+        let n = match stats::recv(socket, &mut buf) {
+            Ok(n) => n,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                debug!("│ Timeout");
+                local_retries = local_retries.saturating_sub(1);
+                if local_retries == 0 {
+                    return Err(Error::Timeout);
+                }
+                local_timeout = (local_timeout + local_timeout / 2).min(max_timeout);
+                socket
+                    .set_read_timeout(Some(local_timeout))
+                    .map_err(Error::SocketIo)?;
+                stats::send(socket, &send_pkt).map_err(Error::SocketIo)?;
+                continue;
+            }
+            Err(e) => return Err(Error::SocketIo(e)),
+        };
+        let packet = &buf[..n];
+        if n < 4 {
+            return Err(Error::Parse(parser::Error::Incomplete(n)));
+        }
+        match u16::from_be_bytes([packet[0], packet[1]]) {
+            OP_ACK => {
+                // Don't resend on duplicate ACKs (the Sorcerer's Apprentice bug), just wait for
+                // the next one
+                if u16::from_be_bytes([packet[2], packet[3]]) != block {
+                    debug!("│ RX - ACK (Duplicate)");
+                    continue;
+                }
+                let Some(chunk) = chunks.get(usize::from(block)) else {
+                    debug!("└");
+                    return Ok(());
+                };
+                block = block.wrapping_add(1);
+                send_pkt = [&OP_DATA.to_be_bytes()[..], &block.to_be_bytes(), chunk].concat();
+            }
+            _ => return Err(unexpected(packet)),
+        }
+        local_retries = retries;
+        local_timeout = timeout;
+        socket
+            .set_read_timeout(Some(local_timeout))
+            .map_err(Error::SocketIo)?;
+        stats::send(socket, &send_pkt).map_err(Error::SocketIo)?;
+    }
+}
+
+/// The error for a datagram of `n` bytes when we take at most `limit`
+fn too_big(n: usize, limit: usize) -> Error {
+    let size = if n > MAX_DATAGRAM {
+        format!("more than {MAX_DATAGRAM}")
+    } else {
+        n.to_string()
+    };
+    Error::SocketIo(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Received a datagram of {size} bytes, larger than the {limit} bytes we take"),
+    ))
+}
+
+/// The error to return for an ERROR or otherwise unexpected packet
+fn unexpected(packet: &[u8]) -> Error {
+    match Packet::from_bytes(packet) {
+        Ok(Packet::Error { code, msg }) => Error::Protocol {
+            code,
+            msg: msg.to_string_lossy().into_owned(),
+        },
+        Ok(other) => Error::UnexpectedPacket(other),
+        Err(e) => Error::Parse(e),
+    }
+}
+
+/// The bytes of a read request for `filename` with `options`
+fn read_request(filename: &str, options: TransferOptions) -> Result<Vec<u8>, Error> {
+    let filename = CString::new(filename).map_err(|_| Error::BadFilename)?;
+    let mut bytes = OP_RRQ.to_be_bytes().to_vec();
+    bytes.extend_from_slice(filename.as_bytes_with_nul());
+    bytes.extend_from_slice(b"octet\0");
+    if options.tsize {
+        bytes.extend_from_slice(b"tsize\x000\0");
+    }
+    if let Some(t) = options.timeout {
+        bytes.extend_from_slice(format!("timeout\0{t}\0").as_bytes());
+    }
+    if let Some(size) = options.requested_blksize() {
+        bytes.extend_from_slice(format!("blksize\0{size}\0").as_bytes());
+    }
+    Ok(bytes)
+}
+
+fn ack(block_n: u16) -> Vec<u8> {
+    [OP_ACK.to_be_bytes(), block_n.to_be_bytes()].concat()
+}
+
+/// Parse the body of an OACK, only keeping the options we asked for
+fn parse_oack(body: &[u8], asked: TransferOptions) -> Result<Negotiated, Error> {
+    let body = body.strip_suffix(&[0]).unwrap_or(body);
+    let mut fields = body.split(|b| *b == 0);
+    let mut negotiated = Negotiated::default();
+    while let Some(name) = fields.next() {
+        let value = fields
+            .next()
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .ok_or(Error::Parse(parser::Error::BadString))?;
+        if name.eq_ignore_ascii_case(b"tsize") && asked.tsize {
+            negotiated.tsize = Some(
+                value
+                    .parse()
+                    .map_err(|_| Error::Parse(parser::Error::BadString))?,
+            );
+        } else if name.eq_ignore_ascii_case(b"timeout") && asked.timeout.is_some() {
+            let secs: u8 = value
+                .parse()
+                .map_err(|_| Error::Parse(parser::Error::BadString))?;
+            negotiated.timeout = (secs > 0).then(|| Duration::from_secs(secs.into()));
+        } else if name.eq_ignore_ascii_case(b"blksize") && asked.blksize.is_some() {
+            let max = asked.requested_blksize().unwrap_or(BLOCK_SIZE);
+            let size: usize = value
+                .parse()
+                .map_err(|_| Error::Parse(parser::Error::BadString))?;
+            // The server may only lower the size, anything else could be fragmented
+            if !(MIN_BLOCK_SIZE..=max).contains(&size) {
+                return Err(Error::Parse(parser::Error::BadString));
+            }
+            negotiated.blksize = Some(size);
+        }
+    }
+    Ok(negotiated)
+}
@@ -0,0 +1,110 @@
+//! Datagram statistics of the sockets we talk TFTP over, for debugging lossy links
+//!
+//! Every datagram sent or received by the clients in [`negotiate`](crate::negotiate) (which every
+//! transfer in this crate goes through) is counted against the local address of its socket, along
+//! with every receive that timed out and every datagram that was too big to take. The counts are
+//! kept until [`reset`] and are shared by every socket bound to the same address over time.
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{
+        SocketAddr,
+        UdpSocket,
+    },
+    sync::{
+        Mutex,
+        PoisonError,
+    },
+};
+
+/// The counts of one socket, see [`get`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketStats {
+    /// Datagrams sent, including retransmits
+    pub datagrams_sent: u64,
+    /// Datagrams received, including duplicates and the ones too big to take
+    pub datagrams_received: u64,
+    /// Bytes sent in all the datagrams
+    pub bytes_sent: u64,
+    /// Bytes received in all the datagrams
+    pub bytes_received: u64,
+    /// Receives that timed out waiting for the other side
+    pub timeouts: u64,
+    /// Datagrams larger than the block size of their transfer (see [`MAX_DATAGRAM`]), which would
+    /// have been fragmented on the way
+    ///
+    /// [`MAX_DATAGRAM`]: crate::negotiate::MAX_DATAGRAM
+    pub oversized: u64,
+}
+
+static STATS: Mutex<BTreeMap<SocketAddr, SocketStats>> = Mutex::new(BTreeMap::new());
+
+/// Update the counts of `socket` with `f`. Sockets without a local address aren't counted.
+fn update<F>(socket: &UdpSocket, f: F)
+where
+    F: FnOnce(&mut SocketStats),
+{
+    let Ok(addr) = socket.local_addr() else {
+        return;
+    };
+    // The counts are only ever added to, so they're fine after a panic
+    let mut stats = STATS.lock().unwrap_or_else(PoisonError::into_inner);
+    f(stats.entry(addr).or_default());
+}
+
+/// The counts of `socket` since it (or an earlier socket at the same address) was first used or
+/// last [`reset`]
+#[must_use]
+pub fn get(socket: &UdpSocket) -> SocketStats {
+    let Ok(addr) = socket.local_addr() else {
+        return SocketStats::default();
+    };
+    STATS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&addr)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Start the counts of `socket` over
+pub fn reset(socket: &UdpSocket) {
+    update(socket, |stats| *stats = SocketStats::default());
+}
+
+/// Send `bytes` on the connected `socket`, counting it
+pub(crate) fn send(socket: &UdpSocket, bytes: &[u8]) -> io::Result<usize> {
+    let n = socket.send(bytes)?;
+    update(socket, |stats| {
+        stats.datagrams_sent += 1;
+        stats.bytes_sent += n as u64;
+    });
+    Ok(n)
+}
+
+/// Receive a datagram on the connected `socket` into `buf`, counting it or the timeout
+pub(crate) fn recv(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<usize> {
+    match socket.recv(buf) {
+        Ok(n) => {
+            update(socket, |stats| {
+                stats.datagrams_received += 1;
+                stats.bytes_received += n as u64;
+            });
+            Ok(n)
+        }
+        Err(e) => {
+            if matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) {
+                update(socket, |stats| stats.timeouts += 1);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Count a datagram on `socket` that was too big to take
+pub(crate) fn oversized(socket: &UdpSocket) {
+    update(socket, |stats| stats.oversized += 1);
+}